@@ -0,0 +1,73 @@
+//! Benchmarks for rewriting packages: one large model, and a batch of small
+//! ones the way the CLI walks a directory of files.
+//!
+//! Run with `cargo bench`, or `cargo bench -- large` to pick a group.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use metadata_3mf::{add_metadata_to_vec, read_metadata_from_bytes, MetadataSet};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+/// Vertices laid out on a grid, so the model text looks like a real mesh
+/// instead of the same line repeated.
+fn grid(count: usize) -> Vec<[f64; 3]> {
+    let side = (count as f64).sqrt().ceil() as usize;
+    (0..count)
+        .map(|index| {
+            let x = (index % side) as f64 * 0.25;
+            let y = (index / side) as f64 * 0.25;
+            [x, y, (x * y).sin()]
+        })
+        .collect()
+}
+
+fn metadata() -> MetadataSet {
+    MetadataSet::builder()
+        .title("Benchmark")
+        .designer("metadata_3mf")
+        .license_terms("CC0")
+        .build()
+        .unwrap()
+}
+
+fn large_model(c: &mut Criterion) {
+    let package = common::generated_package(&[], &grid(100_000));
+    let metadata = metadata();
+
+    let mut group = c.benchmark_group("large");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(package.len() as u64));
+    group.bench_function("read_metadata", |b| {
+        b.iter(|| read_metadata_from_bytes(&package).unwrap())
+    });
+    group.bench_function("add_metadata", |b| {
+        b.iter(|| add_metadata_to_vec(&package, &metadata, false).unwrap())
+    });
+    group.finish();
+}
+
+fn batch(c: &mut Criterion) {
+    let packages: Vec<Vec<u8>> = (0..50)
+        .map(|index| common::generated_package(&[], &grid(100 + index * 10)))
+        .collect();
+    let metadata = metadata();
+
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(packages.len() as u64));
+    group.bench_function("add_metadata", |b| {
+        b.iter_batched(
+            || packages.clone(),
+            |packages| {
+                for package in packages {
+                    add_metadata_to_vec(&package, &metadata, false).unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, large_model, batch);
+criterion_main!(benches);
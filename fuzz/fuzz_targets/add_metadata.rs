@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metadata_3mf::MetadataSet;
+
+fuzz_target!(|data: &[u8]| {
+    let metadata = MetadataSet::builder()
+        .title("fuzz")
+        .copyright("Copyright (c) fuzz")
+        .build()
+        .unwrap();
+    let _ = metadata_3mf::add_metadata_to_vec(data, &metadata, false);
+    let _ = metadata_3mf::add_metadata_to_vec(data, &metadata, true);
+});
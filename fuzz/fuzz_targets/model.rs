@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metadata_3mf::{model, MetadataSet, Unit};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut xml) = model::read_model(data) {
+        let metadata = MetadataSet::from_model(&xml);
+        let _ = metadata.rating();
+        let _ = metadata.creation_date();
+        let _ = model::unit(&xml);
+        for (object_id, _) in model::build_items_by_object(&xml) {
+            let _ = model::object_name(&xml, &object_id);
+        }
+        let _ = model::convert_units(&mut xml, Unit::Inch);
+        model::merge_metadata(&mut xml, &metadata, false);
+        let _ = model::write_model(&xml, std::io::sink());
+    }
+});
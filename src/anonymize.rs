@@ -0,0 +1,109 @@
+//! Stripping personal information from a package before it is shared.
+//!
+//! That means the metadata entries that name people, organizations or
+//! their tools, the OPC core properties part, and the settings that
+//! slicers store alongside the model, which name the user's printers,
+//! profiles and print hosts.
+
+use xmltree::{Element, XMLNode};
+
+use crate::{MetadataEntry, MetadataSet};
+
+/// The metadata entries removed unless told otherwise.
+pub const DEFAULT_KEYS: [&str; 6] = [
+    "Designer",
+    "DesignerUserId",
+    "DesignerCover",
+    "Copyright",
+    "Application",
+    "PartNumber",
+];
+
+/// Parts in which slicers store their settings. A name ending in a slash
+/// stands for everything in that directory.
+pub const SLICER_PARTS: [&str; 4] = [
+    // PrusaSlicer, the complete configuration including physical printers
+    "Metadata/Slic3r_PE.config",
+    // Bambu Studio and OrcaSlicer, the printer and filament presets
+    "Metadata/project_settings.config",
+    "Metadata/slice_info.config",
+    // Cura, the machine definitions and user preferences
+    "Cura/",
+];
+
+/// Whether the metadata entry `name` is meant by `key`. A key without a
+/// namespace prefix also matches prefixed names with the same local name,
+/// so that `PartNumber` covers `bast:PartNumber`.
+pub fn key_matches(key: &str, name: &str) -> bool {
+    if key.contains(':') {
+        return key == name;
+    }
+    match name.split_once(':') {
+        Some((_, local)) => local == key,
+        None => name == key,
+    }
+}
+
+/// Whether the entry is one of the [`SLICER_PARTS`].
+pub fn is_slicer_part(name: &str) -> bool {
+    SLICER_PARTS.iter().any(|part| {
+        if part.ends_with('/') {
+            name.get(..part.len())
+                .is_some_and(|dir| dir.eq_ignore_ascii_case(part))
+        } else {
+            name.eq_ignore_ascii_case(part)
+        }
+    })
+}
+
+/// Removes the metadata entries of the model matched by any of `keys` but
+/// none of `except`, see [`key_matches`], and returns them.
+pub fn remove_metadata(
+    xml: &mut Element,
+    keys: &[String],
+    except: &[String],
+) -> Vec<MetadataEntry> {
+    let matches = |keys: &[String], name: &str| keys.iter().any(|key| key_matches(key, name));
+    let metadata = MetadataSet::from_model(xml);
+    let (removed, kept): (Vec<_>, Vec<_>) = metadata
+        .into_iter()
+        .partition(|entry| matches(keys, &entry.name) && !matches(except, &entry.name));
+    if !removed.is_empty() {
+        crate::model::set_metadata(xml, &kept.into_iter().collect());
+    }
+    removed
+}
+
+/// Removes the relationships to the given parts from a relationships part
+/// named `rels_name`, or their content type overrides from
+/// `[Content_Types].xml`. Part names are entry names, compared without
+/// regard to case as OPC does. Returns whether anything was removed.
+pub fn remove_part_references(xml: &mut Element, rels_name: &str, parts: &[String]) -> bool {
+    // Relative targets are relative to the part the relationships are of,
+    // which is in the directory above `_rels/`
+    let base = match rels_name.rsplit_once("_rels/") {
+        Some((base, _)) => base,
+        None => "",
+    };
+    let is_removed = |element: &Element| {
+        let reference = match element.name.as_str() {
+            "Relationship" => element.attributes.get("Target"),
+            "Override" => element.attributes.get("PartName"),
+            _ => None,
+        };
+        let Some(reference) = reference else {
+            return false;
+        };
+        let name = match reference.strip_prefix('/') {
+            Some(absolute) => absolute.to_string(),
+            None => format!("{}{}", base, reference),
+        };
+        parts.iter().any(|part| part.eq_ignore_ascii_case(&name))
+    };
+    let before = xml.children.len();
+    xml.children.retain(|child| match child {
+        XMLNode::Element(element) => !is_removed(element),
+        _ => true,
+    });
+    xml.children.len() != before
+}
@@ -0,0 +1,471 @@
+//! Updating the root model part of a package without copying the rest.
+//!
+//! Rewriting a package copies every entry, which takes a while for packages
+//! with gigabytes of textures when only the metadata of a small root model
+//! part changes. An append instead writes the new model part where the
+//! central directory started, followed by a new central directory that
+//! lists it in place of the old one. The old model part is superseded: it
+//! stays in the file, but nothing refers to it anymore. Rewriting the
+//! package in any other way drops it.
+//!
+//! The old model part can be kept as a backup entry under
+//! [`BACKUP_DIRECTORY`], named after the time of the append, which [`undo`]
+//! puts back in the same way. A backup is not a model part; rewriting the
+//! package copies it as it is.
+//!
+//! The file is read back afterwards. Should that check, or anything before
+//! it, fail, the original central directory is put back, so that the file
+//! is as it was.
+//!
+//! ```no_run
+//! use std::fs::OpenOptions;
+//!
+//! use metadata_3mf::{append, MetadataSet};
+//!
+//! let metadata = MetadataSet::builder().title("Benchy").build()?;
+//! let mut file = OpenOptions::new().read(true).write(true).open("textured.3mf")?;
+//! let appended = append::append_metadata(&mut file, &metadata, false)?;
+//! println!("{} bytes of the old {} are unused", appended.superseded, appended.part);
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use zip::ZipArchive;
+
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+pub use crate::package::BACKUP_DIRECTORY;
+use crate::package::{self, Package, COMPRESSION_LEVEL, MAX_COMMENT_LENGTH};
+use crate::zip_headers::{DeflatedEntry, CENTRAL_HEADER_LENGTH, ZIP64_THRESHOLD};
+use crate::{model, Error, MetadataSet, Result};
+
+/// The most entries the end of central directory record can count.
+const MAX_ENTRIES: usize = 0xFFFF;
+
+/// The UTF-8 bit of the general purpose flags.
+const UTF8_FLAG: u16 = 1 << 11;
+
+/// What an append or [`undo`] did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Appended {
+    /// The entry name of the model part that was replaced.
+    pub part: String,
+    /// The bytes of the file that the old model part takes up, which
+    /// nothing refers to anymore.
+    pub superseded: u64,
+    /// The entry name of the backup that was kept of the old model part,
+    /// or for an undo, that was put back.
+    pub backup: Option<String>,
+}
+
+/// Merges `metadata` into the root model part as
+/// [`model::merge_metadata`] does, by appending the changed part to the
+/// file, see the [module documentation](self).
+pub fn append_metadata(
+    file: &mut File,
+    metadata: &MetadataSet,
+    keep_existing: bool,
+) -> Result<Appended> {
+    for entry in metadata {
+        entry.validate_characters()?;
+    }
+    append_root_model(file, None, false, |bytes| {
+        let mut edit = model::MetadataEdit::new(bytes)?;
+        model::merge_metadata(edit.xml_mut(), metadata, keep_existing);
+        let mut xml = Vec::new();
+        edit.write(&mut xml)?;
+        Ok(xml)
+    })
+}
+
+/// Replaces the root model part of the package in `file` with what `update`
+/// makes of its contents, by appending it. The archive comment is kept, or
+/// replaced with `comment` when given. With `backup`, the old contents are
+/// kept as a backup entry as well.
+///
+/// Fails with [`Error::NoRootModel`] when the package has no root model
+/// part, and with [`Error::Encrypted`] when that part is encrypted.
+pub fn append_root_model<F>(
+    file: &mut File,
+    comment: Option<&str>,
+    backup: bool,
+    update: F,
+) -> Result<Appended>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+{
+    let layout = Layout::read(file)?;
+    let contents = update(&layout.root_contents)?;
+    let backup = match backup {
+        true => Some(layout.backup_name()?),
+        false => None,
+    };
+    let changes = Changes {
+        root: &contents,
+        added: backup
+            .as_deref()
+            .map(|name| (name, layout.root_contents.as_slice())),
+        dropped: None,
+        comment,
+    };
+    layout.apply(file, &changes)?;
+    Ok(Appended {
+        part: layout.root_name.clone(),
+        superseded: layout.root_length,
+        backup,
+    })
+}
+
+/// Puts the latest backup of the root model part back, by appending it, and
+/// drops that backup. The archive comment is kept, or replaced with
+/// `comment` when given.
+///
+/// Fails with [`Error::NoBackup`] when the package has no backup of its
+/// root model part.
+pub fn undo(file: &mut File, comment: Option<&str>) -> Result<Appended> {
+    let layout = Layout::read(file)?;
+    let suffix = format!("/{}", file_name(&layout.root_name));
+    let (index, name) = layout
+        .entries
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, (name, _))| name.starts_with(BACKUP_DIRECTORY) && name.ends_with(&suffix))
+        .map(|(index, (name, _))| (index, name.clone()))
+        .ok_or_else(|| Error::NoBackup(layout.root_name.clone()))?;
+    let contents = {
+        let mut package = Package::new(&mut *file)?;
+        let mut entry = package.by_index(index)?;
+        package::read_model_part(&mut entry)?
+    };
+    let changes = Changes {
+        root: &contents,
+        added: None,
+        dropped: Some(index),
+        comment,
+    };
+    layout.apply(file, &changes)?;
+    Ok(Appended {
+        part: layout.root_name.clone(),
+        superseded: layout.root_length,
+        backup: Some(name),
+    })
+}
+
+/// What to write in place of the old central directory.
+struct Changes<'a> {
+    /// The new contents of the root model part.
+    root: &'a [u8],
+    /// An entry to add after the others, by name and contents.
+    added: Option<(&'a str, &'a [u8])>,
+    /// An entry to leave out of the central directory.
+    dropped: Option<usize>,
+    comment: Option<&'a str>,
+}
+
+/// Where things are in the archive, as far as an append needs to know.
+struct Layout {
+    /// The number of bytes before the archive, such as a self-extractor,
+    /// which offsets in the archive don't count.
+    archive_offset: u64,
+    central_directory_start: u64,
+    /// The position of the central directory header of each entry, in
+    /// central directory order.
+    central_headers: Vec<u64>,
+    /// The name and local header position of each entry, to check against.
+    entries: Vec<(String, u64)>,
+    root_index: usize,
+    root_name: String,
+    /// The bytes from the local header of the root model part up to the
+    /// next entry or the central directory.
+    root_length: u64,
+    root_contents: Vec<u8>,
+    comment: Vec<u8>,
+}
+
+impl Layout {
+    /// Writes `changes`, and reads the file back. Should either fail, the
+    /// original central directory is put back.
+    fn apply(&self, file: &mut File, changes: &Changes) -> Result<()> {
+        if let Some(comment) = changes.comment {
+            if comment.len() > MAX_COMMENT_LENGTH {
+                return Err(Error::InvalidValue {
+                    name: "archive comment".to_string(),
+                    value: format!("of {} bytes", comment.len()),
+                });
+            }
+        }
+        let original_length = file.metadata()?.len();
+        file.seek(SeekFrom::Start(self.central_directory_start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        let result = self
+            .write(file, &tail, changes)
+            .and_then(|()| self.check(file, changes));
+        if let Err(error) = result {
+            // Put the original central directory back, leaving the entries
+            // before it as they always were
+            file.seek(SeekFrom::Start(self.central_directory_start))?;
+            file.write_all(&tail)?;
+            file.set_len(original_length)?;
+            file.flush()?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn read(file: &mut File) -> Result<Self> {
+        let mut package = Package::new(&mut *file)?;
+        let root_name = package.root_model_name()?;
+        let root_index = package.index_of(&root_name).ok_or(Error::NoRootModel)?;
+        let root_contents = package::read_model_part(&mut package.by_index(root_index)?)?;
+        let archive = package.archive();
+        let mut central_headers = Vec::with_capacity(archive.len());
+        let mut entries = Vec::with_capacity(archive.len());
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            central_headers.push(file.central_header_start());
+            entries.push((file.name().to_string(), file.header_start()));
+        }
+        let central_directory_start = central_headers.iter().copied().min().unwrap_or_default();
+        let root_start = entries[root_index].1;
+        let root_end = entries
+            .iter()
+            .map(|(_, start)| *start)
+            .filter(|start| *start > root_start)
+            .min()
+            .unwrap_or(central_directory_start);
+        Ok(Layout {
+            archive_offset: archive.offset(),
+            central_directory_start,
+            central_headers,
+            entries,
+            root_index,
+            root_name,
+            root_length: root_end - root_start,
+            root_contents,
+            comment: archive.comment().to_vec(),
+        })
+    }
+
+    /// The name of a new backup of the root model part, after the time of
+    /// the entries this crate writes and unlike any existing entry.
+    fn backup_name(&self) -> Result<String> {
+        let time = package::source_date_epoch()?.unwrap_or_else(OffsetDateTime::now_utc);
+        let directory = time
+            .format(format_description!(
+                "[year][month][day]T[hour][minute][second]Z"
+            ))
+            .map_err(|error| Error::InvalidValue {
+                name: "backup time".to_string(),
+                value: error.to_string(),
+            })?;
+        let file_name = file_name(&self.root_name);
+        let mut name = format!("{}{}/{}", BACKUP_DIRECTORY, directory, file_name);
+        let mut number = 1;
+        while self.entries.iter().any(|(entry, _)| *entry == name) {
+            number += 1;
+            name = format!("{}{}-{}/{}", BACKUP_DIRECTORY, directory, number, file_name);
+        }
+        Ok(name)
+    }
+
+    /// Writes the new entries and the central directory from the start of
+    /// the old central directory, of which `tail` holds the bytes up to the
+    /// end of the file.
+    fn write(&self, file: &mut File, tail: &[u8], changes: &Changes) -> Result<()> {
+        let old_header = self.central_header(tail, self.root_index)?;
+        let name =
+            &old_header[CENTRAL_HEADER_LENGTH..CENTRAL_HEADER_LENGTH + field(old_header, 28)];
+        let flags = u16::from_le_bytes([old_header[8], old_header[9]]) & UTF8_FLAG;
+        let offset = self.central_directory_start - self.archive_offset;
+        let mut output = Vec::with_capacity(changes.root.len() / 4 + tail.len() + 256);
+        let root_record = new_entry(&mut output, offset, name, flags, changes.root)?;
+        let added_record = match changes.added {
+            Some((name, contents)) => Some(new_entry(
+                &mut output,
+                offset,
+                name.as_bytes(),
+                UTF8_FLAG,
+                contents,
+            )?),
+            None => None,
+        };
+
+        let central_directory_start = offset + output.len() as u64;
+        for index in 0..self.central_headers.len() {
+            if changes.dropped == Some(index) {
+                continue;
+            }
+            if index == self.root_index {
+                output.extend_from_slice(&root_record);
+            } else {
+                output.extend_from_slice(self.central_header(tail, index)?);
+            }
+        }
+        if let Some(record) = &added_record {
+            output.extend_from_slice(record);
+        }
+        let central_directory_end = offset + output.len() as u64;
+        let central_directory_size = central_directory_end - central_directory_start;
+        let count = self.central_headers.len() + usize::from(changes.added.is_some())
+            - usize::from(changes.dropped.is_some());
+
+        if count >= MAX_ENTRIES
+            || central_directory_size >= ZIP64_THRESHOLD
+            || central_directory_start >= ZIP64_THRESHOLD
+        {
+            // ZIP64 end of central directory record, and its locator
+            output.extend_from_slice(&0x0606_4b50u32.to_le_bytes());
+            output.extend_from_slice(&44u64.to_le_bytes());
+            output.extend_from_slice(&(3 << 8 | 45u16).to_le_bytes());
+            output.extend_from_slice(&45u16.to_le_bytes());
+            output.extend_from_slice(&0u32.to_le_bytes()); // disk number
+            output.extend_from_slice(&0u32.to_le_bytes()); // disk with central directory
+            output.extend_from_slice(&(count as u64).to_le_bytes());
+            output.extend_from_slice(&(count as u64).to_le_bytes());
+            output.extend_from_slice(&central_directory_size.to_le_bytes());
+            output.extend_from_slice(&central_directory_start.to_le_bytes());
+            output.extend_from_slice(&0x0706_4b50u32.to_le_bytes());
+            output.extend_from_slice(&0u32.to_le_bytes()); // disk with the record
+            output.extend_from_slice(&central_directory_end.to_le_bytes());
+            output.extend_from_slice(&1u32.to_le_bytes()); // number of disks
+        }
+        let comment = changes.comment.map_or(&self.comment[..], str::as_bytes);
+        output.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        output.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        output.extend_from_slice(&0u16.to_le_bytes()); // disk with central directory
+        output.extend_from_slice(&(count.min(MAX_ENTRIES) as u16).to_le_bytes());
+        output.extend_from_slice(&(count.min(MAX_ENTRIES) as u16).to_le_bytes());
+        output
+            .extend_from_slice(&(central_directory_size.min(ZIP64_THRESHOLD) as u32).to_le_bytes());
+        output.extend_from_slice(
+            &(central_directory_start.min(ZIP64_THRESHOLD) as u32).to_le_bytes(),
+        );
+        output.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        output.extend_from_slice(comment);
+
+        file.seek(SeekFrom::Start(self.central_directory_start))?;
+        file.write_all(&output)?;
+        file.set_len(self.central_directory_start + output.len() as u64)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// The central directory header of an entry, from `tail`.
+    fn central_header<'a>(&self, tail: &'a [u8], index: usize) -> Result<&'a [u8]> {
+        let start = (self.central_headers[index] - self.central_directory_start) as usize;
+        let header = tail
+            .get(start..start + CENTRAL_HEADER_LENGTH)
+            .ok_or_else(|| truncated(&self.entries[index].0))?;
+        let length =
+            CENTRAL_HEADER_LENGTH + field(header, 28) + field(header, 30) + field(header, 32);
+        tail.get(start..start + length)
+            .ok_or_else(|| truncated(&self.entries[index].0))
+    }
+
+    /// Reads the archive back: it must have the same entries at the same
+    /// places, but for the changed ones, which must read as written.
+    fn check(&self, file: &mut File, changes: &Changes) -> Result<()> {
+        let mut expected: Vec<_> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| changes.dropped != Some(*index))
+            .map(|(index, (name, start))| {
+                let start = (index != self.root_index).then_some(*start);
+                (name.as_str(), start)
+            })
+            .collect();
+        if let Some((name, _)) = changes.added {
+            expected.push((name, None));
+        }
+        let root_index = self.root_index
+            - usize::from(changes.dropped.is_some_and(|index| index < self.root_index));
+
+        file.seek(SeekFrom::Start(0))?;
+        let mut archive = ZipArchive::new(&mut *file)?;
+        if archive.len() != expected.len() {
+            return Err(Error::AppendCheck(format!(
+                "{} entries instead of {}",
+                archive.len(),
+                expected.len()
+            )));
+        }
+        for (index, (name, start)) in expected.iter().enumerate() {
+            let entry = archive.by_index_raw(index)?;
+            if entry.name() != *name || start.is_some_and(|start| entry.header_start() != start) {
+                return Err(Error::AppendCheck(format!("entry {} moved", name)));
+            }
+        }
+        let mut written = vec![(root_index, changes.root)];
+        if let Some((_, contents)) = changes.added {
+            written.push((expected.len() - 1, contents));
+        }
+        for (index, contents) in written {
+            // Reading to the end checks the CRC as well
+            let mut read = Vec::with_capacity(contents.len());
+            let mut entry = archive.by_index(index)?;
+            entry.read_to_end(&mut read)?;
+            if read != contents {
+                return Err(Error::AppendCheck(format!(
+                    "entry {} reads back differently",
+                    entry.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the local header and the deflated `contents` of a new entry to
+/// `output`, which starts at `offset` in the archive, and returns its central
+/// directory header.
+fn new_entry(
+    output: &mut Vec<u8>,
+    offset: u64,
+    name: &[u8],
+    flags: u16,
+    contents: &[u8],
+) -> Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(COMPRESSION_LEVEL as u32));
+    encoder.write_all(contents)?;
+    let compressed = encoder.finish()?;
+    if compressed.len() as u64 >= ZIP64_THRESHOLD || contents.len() as u64 >= ZIP64_THRESHOLD {
+        return Err(Error::TooLarge {
+            part: String::from_utf8_lossy(name).to_string(),
+            limit: ZIP64_THRESHOLD,
+        });
+    }
+    let entry = DeflatedEntry {
+        name,
+        flags,
+        modified: package::entry_time()?,
+        crc: crc32fast::hash(contents),
+        compressed_size: compressed.len() as u64,
+        size: contents.len() as u64,
+        offset: offset + output.len() as u64,
+    };
+    entry.write_local_header(output);
+    output.extend_from_slice(&compressed);
+    Ok(entry.central_header())
+}
+
+/// The last path segment of an entry name.
+fn file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// A little-endian 16 bit length field of a header.
+fn field(header: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([header[offset], header[offset + 1]]) as usize
+}
+
+fn truncated(name: &str) -> Error {
+    Error::AppendCheck(format!("central directory header of {} is cut off", name))
+}
@@ -0,0 +1,61 @@
+//! Async variants of the package functions, for use with tokio.
+//!
+//! The ZIP and XML handling underneath is synchronous, so these read the
+//! whole package into memory without blocking, and then parse and compress
+//! that buffer on the blocking thread pool of the runtime, where the
+//! CPU-bound work doesn't hold up other tasks. Writing always produces an
+//! in-memory buffer, which the caller can store wherever it likes.
+
+use std::io::SeekFrom;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+use crate::{package, MetadataSet, Result};
+
+async fn read_all<R>(reader: &mut R) -> Result<Vec<u8>>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    reader.seek(SeekFrom::Start(0)).await?;
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).await?;
+    Ok(buffer)
+}
+
+/// Runs `work` on the blocking thread pool and waits for it.
+async fn blocking<T, F>(work: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        // A panic goes on in the caller, as if the work had run there
+        Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+        Err(error) => Err(std::io::Error::other(error).into()),
+    }
+}
+
+/// Async version of [`package::read_metadata`].
+pub async fn read_metadata<R>(reader: &mut R) -> Result<Vec<(String, MetadataSet)>>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let buffer = read_all(reader).await?;
+    blocking(move || package::read_metadata_from_bytes(&buffer)).await
+}
+
+/// Async version of [`package::add_metadata`], returning the rewritten
+/// package as bytes.
+pub async fn add_metadata<R>(
+    reader: &mut R,
+    metadata: &MetadataSet,
+    keep_existing: bool,
+) -> Result<Vec<u8>>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let buffer = read_all(reader).await?;
+    let metadata = metadata.clone();
+    blocking(move || package::add_metadata_to_vec(&buffer, &metadata, keep_existing)).await
+}
@@ -0,0 +1,179 @@
+//! Attributions of models in the form Creative Commons asks for, for
+//! `attribution`: the title, the author, the source and the license, with
+//! links to where the model was published and to the license.
+//!
+//! The source is the URL of a listing that `publish` added to the file, if
+//! there is one. Works dedicated to the public domain with CC0 are marked
+//! with it rather than licensed under it, as Creative Commons words it.
+//!
+//! ```
+//! use metadata_3mf::attribution::{Attribution, Format};
+//! use metadata_3mf::{MetadataSet, Spdx};
+//!
+//! let metadata = MetadataSet::builder()
+//!     .title("Cable clip")
+//!     .designer("Jane Doe")
+//!     .license(Spdx::CcBySa40)
+//!     .build()?;
+//! let attribution = Attribution::new(&metadata, "clip.3mf")?;
+//! assert_eq!(
+//!     attribution.render(Format::Text),
+//!     "Cable clip by Jane Doe is licensed under CC BY-SA 4.0 (https://creativecommons.org/licenses/by-sa/4.0/)"
+//! );
+//! assert_eq!(
+//!     attribution.render(Format::Markdown),
+//!     "Cable clip by Jane Doe is licensed under [CC BY-SA 4.0](https://creativecommons.org/licenses/by-sa/4.0/)"
+//! );
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+
+use std::str::FromStr;
+
+use crate::catalog::escape;
+use crate::license::{LicenseInfo, Spdx};
+use crate::{Error, MetadataSet, Result};
+
+/// The namespace prefix `publish` adds the URLs of listings under.
+const SOURCE_PREFIX: &str = "publish:";
+
+/// How an attribution is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Plain text, with the URLs in parentheses.
+    Text,
+    Markdown,
+    Html,
+}
+
+impl Format {
+    /// The names of all formats, as given on the command line.
+    pub const NAMES: [&'static str; 3] = ["text", "markdown", "html"];
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => Err(Error::InvalidValue {
+                name: "attribution format".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// The short name Creative Commons gives its licenses in attributions.
+pub fn cc_name(license: Spdx) -> Option<&'static str> {
+    match license {
+        Spdx::Cc010 => Some("CC0 1.0"),
+        Spdx::CcBy40 => Some("CC BY 4.0"),
+        Spdx::CcBySa40 => Some("CC BY-SA 4.0"),
+        Spdx::CcByNd40 => Some("CC BY-ND 4.0"),
+        Spdx::CcByNc40 => Some("CC BY-NC 4.0"),
+        Spdx::CcByNcSa40 => Some("CC BY-NC-SA 4.0"),
+        Spdx::CcByNcNd40 => Some("CC BY-NC-ND 4.0"),
+        Spdx::Mit | Spdx::Apache20 | Spdx::Gpl30OrLater => None,
+    }
+}
+
+/// Who made a model, and under which license it may be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribution {
+    pub title: String,
+    pub author: String,
+    /// Where the model was published.
+    pub source: Option<String>,
+    /// The short name of the license, or the SPDX id of licenses other
+    /// than those of Creative Commons.
+    pub license: String,
+    pub license_url: String,
+    /// Whether the license dedicates the model to the public domain.
+    pub dedication: bool,
+}
+
+impl Attribution {
+    /// The attribution of a model with the metadata, which needs a
+    /// `Designer` and a `LicenseTerms` that is the SPDX id of a known
+    /// license. The title falls back to `file_name` without a `Title`.
+    pub fn new(metadata: &MetadataSet, file_name: &str) -> Result<Attribution> {
+        let text = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let author = text(metadata.designer()).ok_or_else(|| {
+            Error::Attribution("no Designer to attribute the model to".to_string())
+        })?;
+        let terms = text(metadata.license_terms()).unwrap_or_default();
+        let info = LicenseInfo::lookup(&terms).ok_or_else(|| {
+            Error::Attribution(format!(
+                "LicenseTerms \"{}\" is not the SPDX id of a license with a URL",
+                terms
+            ))
+        })?;
+        let spdx = terms.parse::<Spdx>().ok();
+        let source = metadata
+            .iter()
+            .filter(|entry| entry.name.starts_with(SOURCE_PREFIX))
+            .map(|entry| entry.value.trim())
+            .find(|value| value.starts_with("https://") || value.starts_with("http://"))
+            .map(str::to_string);
+        Ok(Attribution {
+            title: text(metadata.title()).unwrap_or_else(|| file_name.to_string()),
+            author,
+            source,
+            license: spdx.and_then(cc_name).unwrap_or(info.id).to_string(),
+            license_url: info.url,
+            dedication: spdx == Some(Spdx::Cc010),
+        })
+    }
+
+    /// The attribution as one line, such as `Cable clip by Jane Doe is
+    /// licensed under CC BY 4.0`, with the title linked to the source and
+    /// the license to its text.
+    pub fn render(&self, format: Format) -> String {
+        let verb = match self.dedication {
+            true => "is marked with",
+            false => "is licensed under",
+        };
+        let link = |text: &str, url: Option<&str>| match (format, url) {
+            (Format::Text, Some(url)) => format!("{} ({})", text, url),
+            (Format::Text, None) => text.to_string(),
+            (Format::Markdown, Some(url)) => format!("[{}]({})", markdown(text), url),
+            (Format::Markdown, None) => markdown(text),
+            (Format::Html, Some(url)) => {
+                format!("<a href=\"{}\">{}</a>", escape(url), escape(text))
+            }
+            (Format::Html, None) => escape(text),
+        };
+        let author = match format {
+            Format::Text => self.author.clone(),
+            Format::Markdown => markdown(&self.author),
+            Format::Html => escape(&self.author),
+        };
+        format!(
+            "{} by {} {} {}",
+            link(&self.title, self.source.as_deref()),
+            author,
+            verb,
+            link(&self.license, Some(&self.license_url))
+        )
+    }
+}
+
+/// Escapes the characters that Markdown would take for formatting.
+fn markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '*' | '_' | '`' | '<') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,80 @@
+//! Stopping long operations from another thread, such as a Ctrl-C handler
+//! or the cancel button of a window.
+//!
+//! Library functions that copy packages run inside
+//! [`CancellationToken::run`] look at the token before each part, and fail
+//! with [`Error::Cancelled`] once it is cancelled. What was written to the
+//! output by then stays there, so write to a temporary file and only keep
+//! it when the work succeeds.
+//!
+//! ```
+//! use metadata_3mf::cancel::CancellationToken;
+//! use metadata_3mf::{Error, MetadataSet};
+//!
+//! let token = CancellationToken::new();
+//! let button = token.clone();
+//! button.cancel();
+//! let result = token.run(|| {
+//!     metadata_3mf::add_metadata_to_vec(&[], &MetadataSet::new(), false)
+//! });
+//! assert!(matches!(result, Err(Error::Cancelled)));
+//! ```
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{Error, Result};
+
+/// A flag that is shared by its clones, to cancel work on other threads.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+thread_local! {
+    // The token of the work that `run` is doing on this thread
+    static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the work of this token and all its clones. This only stores
+    /// a flag, so it is safe to call from a signal handler.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with [`Error::Cancelled`] once the token is cancelled.
+    pub fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /// Does `work`, with the library functions it calls on this thread
+    /// stopping once the token is cancelled.
+    pub fn run<T>(&self, work: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.check()?;
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = work();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+}
+
+/// Fails when the token of the work on this thread is cancelled.
+pub(crate) fn check() -> Result<()> {
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(token) => token.check(),
+        None => Ok(()),
+    })
+}
@@ -0,0 +1,179 @@
+//! A static HTML page that lists 3MF files as cards with their title,
+//! designer, license and thumbnail, to browse a library of models on a web
+//! share.
+//!
+//! Thumbnails are embedded in the page, so it is a single file that works
+//! wherever it is put, as long as the links to the models do.
+//!
+//! ```
+//! use metadata_3mf::catalog::{self, Card};
+//!
+//! let card = Card {
+//!     path: "boats/benchy #2.3mf".to_string(),
+//!     file_name: "benchy #2.3mf".to_string(),
+//!     title: Some("3DBenchy".to_string()),
+//!     designer: Some("Creative Tools".to_string()),
+//!     license: Some("CC-BY-4.0".to_string()),
+//!     thumbnail: None,
+//! };
+//! let page = catalog::render("Models", &[card]);
+//! assert!(page.contains("<a href=\"boats/benchy%20%232.3mf\">3DBenchy</a>"));
+//! assert!(page.contains("https://creativecommons.org/licenses/by/4.0/"));
+//! ```
+
+use std::fmt::Write;
+use std::io::{Read, Seek};
+
+use crate::package::{self, Package};
+use crate::{diagnostics, Result, Spdx};
+
+/// What the catalog shows of one file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Card {
+    /// Where the file is, relative to the page, with `/` or `\` between
+    /// directories.
+    pub path: String,
+    pub file_name: String,
+    pub title: Option<String>,
+    pub designer: Option<String>,
+    /// The `LicenseTerms` of the file.
+    pub license: Option<String>,
+    /// The media type and contents of the thumbnail.
+    pub thumbnail: Option<(String, Vec<u8>)>,
+}
+
+impl Card {
+    /// Reads the card of a package from the metadata of its root model part
+    /// and its thumbnail. `path` and `file_name` are left empty.
+    pub fn read<R: Read + Seek>(package: &mut Package<R>) -> Result<Self> {
+        let metadata = package::read_root_metadata(package)?;
+        let text = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Ok(Card {
+            path: String::new(),
+            file_name: String::new(),
+            title: text(metadata.title()),
+            designer: text(metadata.designer()),
+            license: text(metadata.license_terms()),
+            thumbnail: read_thumbnail(package)?,
+        })
+    }
+}
+
+fn read_thumbnail<R: Read + Seek>(package: &mut Package<R>) -> Result<Option<(String, Vec<u8>)>> {
+    let targets = package.relationship_targets(package::THUMBNAIL_RELATIONSHIP_TYPE)?;
+    let Some((name, index)) = targets
+        .into_iter()
+        .find_map(|name| package.index_of(&name).map(|index| (name, index)))
+    else {
+        return Ok(None);
+    };
+    let media_type = match name.rsplit('.').next().map(str::to_ascii_lowercase) {
+        Some(extension) if extension == "png" => "image/png",
+        Some(extension) if extension == "jpg" || extension == "jpeg" => "image/jpeg",
+        // Browsers can't show other formats anyway
+        _ => return Ok(None),
+    };
+    let mut bytes = Vec::new();
+    package.by_index(index)?.read_to_end(&mut bytes)?;
+    Ok(Some((media_type.to_string(), bytes)))
+}
+
+/// Renders the page with a card for each file, in the given order.
+pub fn render(title: &str, cards: &[Card]) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n\
+         <p>{} files</p>\n<main>\n",
+        escape(title),
+        STYLE,
+        escape(title),
+        cards.len()
+    );
+    for card in cards {
+        html.push_str("<article>\n");
+        match &card.thumbnail {
+            Some((media_type, bytes)) => {
+                let _ = writeln!(
+                    html,
+                    "<img src=\"data:{};base64,{}\" alt=\"\">",
+                    media_type,
+                    base64(bytes)
+                );
+            }
+            None => html.push_str("<div class=\"none\">no thumbnail</div>\n"),
+        }
+        let _ = writeln!(
+            html,
+            "<h2><a href=\"{}\">{}</a></h2>",
+            escape(&diagnostics::file_uri(&card.path)),
+            escape(card.title.as_deref().unwrap_or(&card.file_name))
+        );
+        html.push_str("<dl>\n");
+        let _ = writeln!(html, "<dt>File</dt><dd>{}</dd>", escape(&card.file_name));
+        if let Some(designer) = &card.designer {
+            let _ = writeln!(html, "<dt>Designer</dt><dd>{}</dd>", escape(designer));
+        }
+        if let Some(license) = &card.license {
+            let license = match license.parse::<Spdx>() {
+                Ok(spdx) => format!("<a href=\"{}\">{}</a>", spdx.url(), spdx.id()),
+                Err(_) => escape(license),
+            };
+            let _ = writeln!(html, "<dt>License</dt><dd>{}</dd>", license);
+        }
+        html.push_str("</dl>\n</article>\n");
+    }
+    html.push_str("</main>\n</body>\n</html>\n");
+    html
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+main{display:grid;grid-template-columns:repeat(auto-fill,minmax(14em,1fr));gap:1em}\
+article{border:1px solid #ccc;border-radius:.5em;padding:.5em}\
+img,.none{width:100%;aspect-ratio:1;object-fit:contain;background:#f4f4f4}\
+.none{display:flex;align-items:center;justify-content:center;color:#888}\
+h2{font-size:1.1em;overflow-wrap:anywhere}\
+dt{font-weight:bold}dd{margin:0 0 .3em;overflow-wrap:anywhere}";
+
+/// Escapes text for HTML content and attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
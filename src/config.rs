@@ -0,0 +1,165 @@
+//! The config file, which names metadata presets and picks one for each
+//! file by its location.
+//!
+//! The file has one statement per line; empty lines and lines starting
+//! with `#` are skipped:
+//!
+//! ```text
+//! # Presets name metadata files, relative to the config file
+//! preset commercial-license = presets/commercial.xml
+//! preset personal = presets/personal.xml
+//!
+//! # Rules pick a preset by path; the first rule that matches wins
+//! path:~/printing/commercial/** -> preset commercial-license
+//! path:~/printing/** -> preset personal
+//! ```
+//!
+//! Path patterns are globs, where `*` stays within a directory and `**`
+//! matches any number of them. A leading `~` stands for the home
+//! directory, and relative patterns are relative to the config file.
+
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::{Error, Result};
+
+/// A parsed config file.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    presets: Vec<(String, PathBuf)>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Pattern,
+    preset: String,
+}
+
+impl Config {
+    /// Reads a config file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|error| {
+            Error::Config(format!(
+                "could not read config file {}: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        // Rules are matched against absolute paths
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        Config::parse(&text, &dir)
+            .map_err(|error| Error::Config(format!("config file {}, {}", path.display(), error)))
+    }
+
+    /// Parses the text of a config file. Relative paths in it are taken
+    /// relative to `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: &str| Error::Config(format!("line {}: {}", number + 1, message));
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(preset) = line.strip_prefix("preset ") {
+                let (name, file) = preset
+                    .split_once('=')
+                    .ok_or_else(|| error("expected `preset NAME = FILE`"))?;
+                let name = name.trim();
+                if config.preset(name).is_some() {
+                    return Err(error(&format!("preset {} is defined twice", name)));
+                }
+                let file = expand_home(file.trim()).ok_or_else(|| error("no home directory"))?;
+                config.presets.push((name.to_string(), dir.join(file)));
+            } else if let Some(rule) = line.strip_prefix("path:") {
+                let (pattern, action) = rule
+                    .split_once("->")
+                    .ok_or_else(|| error("expected `path:PATTERN -> preset NAME`"))?;
+                let preset = action
+                    .trim()
+                    .strip_prefix("preset ")
+                    .ok_or_else(|| error("expected `preset NAME` after `->`"))?
+                    .trim();
+                if config.preset(preset).is_none() {
+                    return Err(error(&format!("unknown preset {}", preset)));
+                }
+                let pattern = absolute_pattern(pattern.trim(), dir)
+                    .ok_or_else(|| error("no home directory"))?;
+                let pattern = Pattern::new(&pattern)
+                    .map_err(|pattern_error| error(&pattern_error.to_string()))?;
+                config.rules.push(Rule {
+                    pattern,
+                    preset: preset.to_string(),
+                });
+            } else {
+                return Err(error("expected a `preset` or `path:` line"));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The metadata file of the preset with the given name.
+    pub fn preset(&self, name: &str) -> Option<&Path> {
+        self.presets
+            .iter()
+            .find(|(preset, _)| preset == name)
+            .map(|(_, path)| path.as_path())
+    }
+
+    /// The name of the preset for the file at `path`, from the first rule
+    /// that matches it. `path` should be absolute.
+    pub fn preset_for(&self, path: &Path) -> Option<&str> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        self.rules
+            .iter()
+            .find(|rule| rule.pattern.matches_path_with(path, options))
+            .map(|rule| rule.preset.as_str())
+    }
+}
+
+/// The directory metadata_3mf keeps its files in, such as the config
+/// file: `~/.config/metadata_3mf`, or `%APPDATA%\metadata_3mf` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    Some(dir?.join("metadata_3mf"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Replaces a leading `~` with the home directory.
+fn expand_home(path: &str) -> Option<PathBuf> {
+    match path.strip_prefix('~') {
+        Some(rest) => Some(home_dir()?.join(rest.trim_start_matches(['/', '\\']))),
+        None => Some(PathBuf::from(path)),
+    }
+}
+
+/// Makes a path pattern absolute, the way [`expand_home`] does for paths,
+/// without taking glob characters in the home directory or `dir` as such.
+fn absolute_pattern(pattern: &str, dir: &Path) -> Option<String> {
+    let (base, rest) = match pattern.strip_prefix('~') {
+        Some(rest) => (home_dir()?, rest.trim_start_matches(['/', '\\'])),
+        None if Path::new(pattern).is_absolute() => return Some(pattern.to_string()),
+        None => (dir.to_path_buf(), pattern),
+    };
+    let base = Pattern::escape(&base.to_string_lossy());
+    Some(format!("{}/{}", base.trim_end_matches(['/', '\\']), rest))
+}
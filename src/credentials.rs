@@ -0,0 +1,342 @@
+//! API tokens for the services metadata_3mf can talk to, kept out of the
+//! command line and with it out of shell history.
+//!
+//! A token is looked for in this order:
+//!
+//! 1. the environment variable of the service, such as
+//!    `METADATA_3MF_THINGIVERSE_TOKEN`
+//! 2. the credentials file, `credentials` in the
+//!    [config directory](crate::config::config_dir), or the file that
+//!    `METADATA_3MF_CREDENTIALS` names
+//! 3. the system keyring, when built with the `keyring` feature
+//!
+//! The credentials file has a `service = token` line for each service;
+//! empty lines and lines starting with `#` are skipped:
+//!
+//! ```text
+//! thingiverse = 0123456789abcdef
+//! ```
+//!
+//! On Unix, it is written readable by its owner only, and refused when
+//! others can read it, like SSH does with keys.
+//!
+//! ```
+//! use metadata_3mf::credentials::{self, Service, Source};
+//!
+//! let dir = tempfile::tempdir()?;
+//! let path = dir.path().join("credentials");
+//! credentials::store_in(&path, Service::Thingiverse, "0123456789abcdef")?;
+//! let token = credentials::read_file(&path, Service::Thingiverse)?.unwrap();
+//! assert_eq!(token.value(), "0123456789abcdef");
+//! assert_eq!(token.source, Source::File(path));
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::{Error, Result};
+
+/// A service with an API that takes a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Service {
+    Thingiverse,
+    Printables,
+    /// The MQTT broker of `--notify-mqtt`, whose token is the password.
+    Mqtt,
+}
+
+impl Service {
+    /// The names of the services, as given on the command line.
+    pub const NAMES: [&'static str; 3] = ["thingiverse", "printables", "mqtt"];
+
+    /// Every service, in the order of [`Service::NAMES`].
+    pub const ALL: [Service; 3] = [Service::Thingiverse, Service::Printables, Service::Mqtt];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Service::Thingiverse => "thingiverse",
+            Service::Printables => "printables",
+            Service::Mqtt => "mqtt",
+        }
+    }
+
+    /// The environment variable with the token of the service.
+    pub fn env_var(self) -> String {
+        format!("METADATA_3MF_{}_TOKEN", self.as_str().to_uppercase())
+    }
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Service {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "thingiverse" => Ok(Service::Thingiverse),
+            "printables" => Ok(Service::Printables),
+            "mqtt" => Ok(Service::Mqtt),
+            _ => Err(Error::InvalidValue {
+                name: "service".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Where a token was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Given on the command line.
+    CommandLine,
+    /// The environment variable with this name.
+    Environment(String),
+    /// The credentials file at this path.
+    File(PathBuf),
+    /// The system keyring.
+    Keyring,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::CommandLine => write!(f, "the command line"),
+            Source::Environment(name) => write!(f, "environment variable {}", name),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Keyring => write!(f, "the system keyring"),
+        }
+    }
+}
+
+/// A token, with where it was found. Its `Debug` output leaves the value
+/// out, so that it doesn't end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token {
+    value: String,
+    pub source: Source,
+}
+
+impl Token {
+    pub fn new(value: impl Into<String>, source: Source) -> Self {
+        Token {
+            value: value.into(),
+            source,
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("value", &"<hidden>")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+/// The credentials file: the one `METADATA_3MF_CREDENTIALS` names, or else
+/// `credentials` in the config directory.
+pub fn credentials_path() -> Option<PathBuf> {
+    match std::env::var_os("METADATA_3MF_CREDENTIALS") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(crate::config::config_dir()?.join("credentials")),
+    }
+}
+
+/// Looks for the token of `service` in the environment, the credentials
+/// file and, with the `keyring` feature, the system keyring.
+pub fn find(service: Service) -> Result<Option<Token>> {
+    let name = service.env_var();
+    if let Some(value) = std::env::var(&name).ok().filter(|value| !value.is_empty()) {
+        return Ok(Some(Token::new(value, Source::Environment(name))));
+    }
+    if let Some(path) = credentials_path() {
+        if let Some(token) = read_file(&path, service)? {
+            return Ok(Some(token));
+        }
+    }
+    #[cfg(feature = "keyring")]
+    if let Some(value) = keyring_get(service)? {
+        return Ok(Some(Token::new(value, Source::Keyring)));
+    }
+    Ok(None)
+}
+
+/// Reads the token of `service` from the credentials file at `path`, if
+/// it exists.
+pub fn read_file(path: &Path, service: Service) -> Result<Option<Token>> {
+    let Some(lines) = read_lines(path)? else {
+        return Ok(None);
+    };
+    for (number, line) in lines.iter().enumerate() {
+        let Some((name, value)) = parse_line(line) else {
+            continue;
+        };
+        let name = name.parse::<Service>().map_err(|_| {
+            Error::Credentials(format!(
+                "{} line {}: unknown service {}, expected one of {}",
+                path.display(),
+                number + 1,
+                name,
+                Service::NAMES.join(", ")
+            ))
+        })?;
+        if name == service {
+            return Ok(Some(Token::new(value, Source::File(path.to_path_buf()))));
+        }
+    }
+    Ok(None)
+}
+
+/// The lines of the credentials file at `path`, or `None` when there is
+/// none.
+fn read_lines(path: &Path) -> Result<Option<Vec<String>>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(Error::Credentials(format!(
+                "could not read credentials file {}: {}",
+                path.display(),
+                error
+            )))
+        }
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(Error::Credentials(format!(
+                "credentials file {} can be read by other users, make it private with chmod 600",
+                path.display()
+            )));
+        }
+    }
+    Ok(Some(text.lines().map(str::to_string).collect()))
+}
+
+/// The service and token of a line, or `None` for empty lines and
+/// comments.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (name, value) = line.split_once('=').unwrap_or((line, ""));
+    Some((name.trim(), value.trim()))
+}
+
+/// Stores the token of `service` in the credentials file, replacing any
+/// it has, and returns where it went.
+pub fn store(service: Service, token: &str) -> Result<Source> {
+    let path = credentials_path().ok_or_else(|| {
+        Error::Credentials("no home directory for the credentials file".to_string())
+    })?;
+    store_in(&path, service, token)?;
+    Ok(Source::File(path))
+}
+
+/// Stores the token of `service` in the credentials file at `path`,
+/// creating the file readable by its owner only.
+pub fn store_in(path: &Path, service: Service, token: &str) -> Result<()> {
+    if token.is_empty() || token.contains(['\n', '\r']) {
+        return Err(Error::Credentials(
+            "a token must be a single, non-empty line".to_string(),
+        ));
+    }
+    let mut lines = read_lines(path)?.unwrap_or_default();
+    lines.retain(|line| parse_line(line).is_none_or(|(name, _)| name != service.as_str()));
+    lines.push(format!("{} = {}", service, token));
+    write_lines(path, &lines)
+}
+
+/// Removes the token of `service` from the credentials file and, with the
+/// `keyring` feature, the system keyring. Returns whether there was one.
+pub fn remove(service: Service) -> Result<bool> {
+    let mut removed = false;
+    if let Some(path) = credentials_path() {
+        if let Some(mut lines) = read_lines(&path)? {
+            let count = lines.len();
+            lines.retain(|line| parse_line(line).is_none_or(|(name, _)| name != service.as_str()));
+            if lines.len() < count {
+                write_lines(&path, &lines)?;
+                removed = true;
+            }
+        }
+    }
+    #[cfg(feature = "keyring")]
+    {
+        removed |= keyring_delete(service)?;
+    }
+    Ok(removed)
+}
+
+/// Writes the credentials file, readable by its owner only, through a
+/// temporary file so that it is never left half written.
+fn write_lines(path: &Path, lines: &[String]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    // Temporary files are only readable by their owner already
+    let mut file = tempfile::Builder::new()
+        .prefix(".credentials")
+        .tempfile_in(dir)?;
+    for line in lines {
+        io::Write::write_all(&mut file, line.as_bytes())?;
+        io::Write::write_all(&mut file, b"\n")?;
+    }
+    file.persist(path).map_err(|error| error.error)?;
+    Ok(())
+}
+
+/// The name tokens are stored under in the system keyring.
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "metadata_3mf";
+
+#[cfg(feature = "keyring")]
+fn keyring_error(error: keyring::Error) -> Error {
+    Error::Credentials(format!("system keyring: {}", error))
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_get(service: Service) -> Result<Option<String>> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, service.as_str()).map_err(keyring_error)?;
+    match entry.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(error) => Err(keyring_error(error)),
+    }
+}
+
+/// Stores the token of `service` in the system keyring.
+#[cfg(feature = "keyring")]
+pub fn store_in_keyring(service: Service, token: &str) -> Result<Source> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, service.as_str()).map_err(keyring_error)?;
+    entry.set_password(token).map_err(keyring_error)?;
+    Ok(Source::Keyring)
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_delete(service: Service) -> Result<bool> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, service.as_str()).map_err(keyring_error)?;
+    match entry.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(error) => Err(keyring_error(error)),
+    }
+}
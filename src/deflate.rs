@@ -0,0 +1,159 @@
+//! Deflating large model parts on several threads.
+//!
+//! The input is cut into chunks that are compressed independently, each
+//! ending in a sync flush so that the concatenation is a single valid
+//! deflate stream, the way pigz does it. The zip crate can only take
+//! compressed data from another archive, so the result is wrapped in a
+//! one-entry archive in memory and raw copied from there.
+
+use std::io::{self, Cursor, Write};
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::zip_headers::{DeflatedEntry, ZIP64_THRESHOLD};
+use crate::Result;
+
+/// Size of the chunks compressed on their own. Big enough that losing the
+/// history at the chunk boundaries costs next to nothing.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// A writer that deflates what is written to it on up to `threads` threads
+/// at a time. Holds one chunk per thread of uncompressed data, and the
+/// compressed data written so far.
+pub(crate) struct ParallelDeflate {
+    level: Compression,
+    threads: usize,
+    chunks: Vec<Vec<u8>>,
+    compressed: Vec<u8>,
+    crc: crc32fast::Hasher,
+    size: u64,
+}
+
+impl ParallelDeflate {
+    pub(crate) fn new(level: u32, threads: usize) -> Self {
+        ParallelDeflate {
+            level: Compression::new(level),
+            threads: threads.max(1),
+            chunks: vec![Vec::with_capacity(CHUNK_SIZE)],
+            compressed: Vec::new(),
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+        }
+    }
+
+    /// Compresses the full chunks, all but the last of which end in a sync
+    /// flush. The last one is only finished when `last` is set.
+    fn compress_chunks(&mut self, last: bool) -> io::Result<()> {
+        let level = self.level;
+        let count = self.chunks.len();
+        let compressed = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .chunks
+                .drain(..)
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let finish = last && index + 1 == count;
+                    scope.spawn(move || compress_chunk(&chunk, level, finish))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("deflate thread panicked"))
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+        for chunk in compressed {
+            self.compressed.extend_from_slice(&chunk);
+        }
+        Ok(())
+    }
+
+    /// Finishes the stream and adds it to `output` as the entry `name`.
+    pub(crate) fn finish<W>(mut self, output: &mut ZipWriter<W>, name: &str) -> Result<()>
+    where
+        W: Write + io::Seek,
+    {
+        self.compress_chunks(true)?;
+        let modified = crate::package::entry_time()?;
+        let archive =
+            single_entry_archive(&self.compressed, self.crc.finalize(), self.size, modified)?;
+        let mut archive = ZipArchive::new(Cursor::new(archive))?;
+        output.raw_copy_file_rename(archive.by_index_raw(0)?, name)?;
+        Ok(())
+    }
+}
+
+impl Write for ParallelDeflate {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = self.chunks.last_mut().expect("there is always a chunk");
+        let length = buf.len().min(CHUNK_SIZE - chunk.len());
+        chunk.extend_from_slice(&buf[..length]);
+        self.crc.update(&buf[..length]);
+        self.size += length as u64;
+        if chunk.len() == CHUNK_SIZE {
+            if self.chunks.len() == self.threads {
+                self.compress_chunks(false)?;
+            }
+            self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn compress_chunk(chunk: &[u8], level: Compression, finish: bool) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), level);
+    encoder.write_all(chunk)?;
+    if finish {
+        encoder.finish()
+    } else {
+        // A sync flush ends on a byte boundary without marking the last
+        // block, so the next chunk can simply follow.
+        encoder.flush()?;
+        Ok(std::mem::take(encoder.get_mut()))
+    }
+}
+
+/// Builds a ZIP archive holding just the given deflated data.
+fn single_entry_archive(
+    compressed: &[u8],
+    crc: u32,
+    size: u64,
+    modified: zip::DateTime,
+) -> io::Result<Vec<u8>> {
+    if compressed.len() as u64 >= ZIP64_THRESHOLD {
+        return Err(io::Error::other(
+            "compressed model part is larger than 4 GiB",
+        ));
+    }
+    let entry = DeflatedEntry {
+        name: b"part",
+        flags: 0,
+        modified,
+        crc,
+        compressed_size: compressed.len() as u64,
+        size,
+        offset: 0,
+    };
+    let mut archive = Vec::with_capacity(compressed.len() + 128);
+    entry.write_local_header(&mut archive);
+    archive.extend_from_slice(compressed);
+    let central_directory_start = archive.len() as u32;
+    archive.extend_from_slice(&entry.central_header());
+    let central_directory_size = archive.len() as u32 - central_directory_start;
+
+    // End of central directory record
+    archive.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes()); // disk number
+    archive.extend_from_slice(&0u16.to_le_bytes()); // disk with central directory
+    archive.extend_from_slice(&1u16.to_le_bytes());
+    archive.extend_from_slice(&1u16.to_le_bytes());
+    archive.extend_from_slice(&central_directory_size.to_le_bytes());
+    archive.extend_from_slice(&central_directory_start.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    Ok(archive)
+}
@@ -0,0 +1,226 @@
+//! Stable codes for the problems and warnings that are reported about
+//! packages, and the SARIF log format that code review and CI tools read
+//! them in.
+//!
+//! The codes never change meaning, so they can be filtered on and looked
+//! up. Codes from `M3MF001` are problems that `validate`, `check` and
+//! `doctor` report, and codes from `M3MF101` are the [warnings](crate::warnings).
+//!
+//! ```
+//! use metadata_3mf::diagnostics::{self, Diagnostic, Severity};
+//!
+//! let diagnostic = Diagnostic {
+//!     code: diagnostics::DUPLICATE_METADATA,
+//!     severity: Severity::Error,
+//!     file: "models/boat.3mf".to_string(),
+//!     message: "3D/3dmodel.model: metadata name Title appears 2 times".to_string(),
+//! };
+//! let log = diagnostics::sarif(&[diagnostic]);
+//! assert_eq!(log["runs"][0]["results"][0]["ruleId"], "M3MF001");
+//! ```
+
+use serde_json::{json, Value};
+
+pub const DUPLICATE_METADATA: &str = "M3MF001";
+pub const MISSING_ROOT_MODEL: &str = "M3MF002";
+pub const MALFORMED_XML: &str = "M3MF003";
+pub const SCHEMA: &str = "M3MF004";
+pub const INVALID_VALUE: &str = "M3MF005";
+pub const MISSING_METADATA: &str = "M3MF006";
+pub const MISMATCHED_METADATA: &str = "M3MF007";
+pub const DAMAGED_ENTRY: &str = "M3MF008";
+pub const CONTENT_TYPE: &str = "M3MF009";
+pub const NON_CONFORMANT_NAME: &str = "M3MF101";
+pub const DEPRECATED_KEY: &str = "M3MF102";
+pub const MISSING_THUMBNAIL: &str = "M3MF103";
+pub const HUGE_UNCOMPRESSED_PART: &str = "M3MF104";
+pub const DUPLICATE_ENTRY: &str = "M3MF105";
+pub const THUMBNAIL_FORMAT: &str = "M3MF106";
+pub const CONFLICTING_METADATA: &str = "M3MF107";
+
+/// What a code stands for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub code: &'static str,
+    /// A short name, the same as the warning name for warnings.
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// All codes, in order.
+pub const RULES: [Rule; 16] = [
+    Rule {
+        code: DUPLICATE_METADATA,
+        name: "duplicate-metadata",
+        description: "A metadata name appears more than once in a model part.",
+    },
+    Rule {
+        code: MISSING_ROOT_MODEL,
+        name: "missing-root-model",
+        description:
+            "The package relationships point at no root model part, or at one that isn't there.",
+    },
+    Rule {
+        code: MALFORMED_XML,
+        name: "malformed-xml",
+        description: "A part is not well-formed XML.",
+    },
+    Rule {
+        code: SCHEMA,
+        name: "schema",
+        description: "A model part breaks the schema of the 3MF core specification, or of one of its common extensions.",
+    },
+    Rule {
+        code: INVALID_VALUE,
+        name: "invalid-value",
+        description: "The value of a well-known metadata name is not in the format of the name.",
+    },
+    Rule {
+        code: MISSING_METADATA,
+        name: "missing-metadata",
+        description: "An entry of the expected metadata is missing.",
+    },
+    Rule {
+        code: MISMATCHED_METADATA,
+        name: "mismatched-metadata",
+        description: "An entry of the expected metadata has another value.",
+    },
+    Rule {
+        code: DAMAGED_ENTRY,
+        name: "damaged-entry",
+        description: "An entry of the archive can't be read, or its CRC-32 checksum doesn't match.",
+    },
+    Rule {
+        code: CONTENT_TYPE,
+        name: "content-type",
+        description: "A part has no content type, or not the one of its kind.",
+    },
+    Rule {
+        code: NON_CONFORMANT_NAME,
+        name: "non-conformant-name",
+        description: "A metadata name is neither well-known nor namespaced.",
+    },
+    Rule {
+        code: DEPRECATED_KEY,
+        name: "deprecated-key",
+        description: "A metadata name is an old spelling of a well-known name.",
+    },
+    Rule {
+        code: MISSING_THUMBNAIL,
+        name: "missing-thumbnail",
+        description: "The package has no thumbnail.",
+    },
+    Rule {
+        code: HUGE_UNCOMPRESSED_PART,
+        name: "huge-uncompressed-part",
+        description: "A large part is stored without compression.",
+    },
+    Rule {
+        code: DUPLICATE_ENTRY,
+        name: "duplicate-entry",
+        description: "The archive has more than one entry with the same name.",
+    },
+    Rule {
+        code: THUMBNAIL_FORMAT,
+        name: "thumbnail-format",
+        description:
+            "A thumbnail is not a PNG or JPEG image, or its content type names another format.",
+    },
+    Rule {
+        code: CONFLICTING_METADATA,
+        name: "conflicting-metadata",
+        description: "A metadata name has different values in the model parts of a package.",
+    },
+];
+
+/// Looks up a code.
+pub fn rule(code: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.code == code)
+}
+
+/// How bad a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    /// The name of the level in SARIF.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A problem or warning in one file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    /// The input file, as it was given.
+    pub file: String,
+    /// The message, starting with the part and position inside the package
+    /// when they are known.
+    pub message: String,
+}
+
+/// A SARIF 2.1.0 log with the diagnostics as the results of one run.
+pub fn sarif(diagnostics: &[Diagnostic]) -> Value {
+    let rules: Vec<_> = RULES
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.code,
+                "name": rule.name,
+                "shortDescription": { "text": rule.description },
+            })
+        })
+        .collect();
+    let results: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            json!({
+                "ruleId": diagnostic.code,
+                "ruleIndex": RULES.iter().position(|rule| rule.code == diagnostic.code),
+                "level": diagnostic.severity.name(),
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": file_uri(&diagnostic.file) },
+                    },
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "metadata_3mf",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// A relative URI reference for a file path, with forward slashes and
+/// everything but unreserved characters percent-encoded.
+pub(crate) fn file_uri(path: &str) -> String {
+    let mut uri = String::new();
+    for byte in path.replace('\\', "/").bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
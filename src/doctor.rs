@@ -0,0 +1,327 @@
+//! Everything that is wrong with a package in one place, with what to do
+//! about each of it, for `doctor`.
+//!
+//! Besides the checks of `validate` and `check` and the
+//! [warnings](crate::warnings), `doctor` reads every entry to verify its
+//! checksum, and looks for parts without the content type they should have.
+//! Each finding comes with a remedy. Some fixes are safe to apply without
+//! looking, which `doctor --fix` does; the rest need a decision, and are
+//! only described:
+//!
+//! | Finding | Fix |
+//! | --- | --- |
+//! | `duplicate-entry` | keep the last of the entries, which most readers read |
+//! | `content-type` | give the part the content type of its kind in `[Content_Types].xml` |
+//! | `thumbnail-format` | give the thumbnail the content type of its format |
+//! | `deprecated-key` | rename the entry to the well-known name, unless that is set already |
+//! | `huge-uncompressed-part` | compress the part |
+//!
+//! ```
+//! use metadata_3mf::diagnostics::{self, Severity};
+//! use metadata_3mf::doctor::{Finding, Fix};
+//!
+//! let finding = Finding {
+//!     code: diagnostics::DEPRECATED_KEY,
+//!     severity: Severity::Warning,
+//!     message: "metadata name \"Author\" is deprecated, use Designer".to_string(),
+//!     fix: Some(Fix::RenameKey {
+//!         from: "Author".to_string(),
+//!         to: "Designer".to_string(),
+//!     }),
+//! };
+//! assert_eq!(finding.remedy(), "rename Author to Designer");
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use xmltree::{Element, XMLNode};
+use zip::read::ZipFile;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::diagnostics::{self, Severity};
+use crate::model::{self, MetadataEdit, RenameConflict};
+use crate::package::{self, KeepEntry, Package, RawNames, UnsafeNames};
+use crate::{Error, Result};
+
+/// A change that is safe to make without asking, and that `doctor --fix`
+/// makes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fix {
+    /// Keep the last of the entries that share a name.
+    DedupeEntries,
+    /// Give the part the content type, with an override in
+    /// `[Content_Types].xml`.
+    ContentType { part: String, content_type: String },
+    /// Rename a metadata entry of the root model part.
+    RenameKey { from: String, to: String },
+    /// Compress the part.
+    Compress(String),
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fix::DedupeEntries => write!(f, "keep the last of the entries with the same name"),
+            Fix::ContentType { part, content_type } => {
+                write!(f, "give {} the content type {}", part, content_type)
+            }
+            Fix::RenameKey { from, to } => write!(f, "rename {} to {}", from, to),
+            Fix::Compress(part) => write!(f, "compress {}", part),
+        }
+    }
+}
+
+/// Something wrong with a package, with its [diagnostics] code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub code: &'static str,
+    /// Warnings that aren't denied are only warnings, problems are errors.
+    pub severity: Severity,
+    pub message: String,
+    /// The safe fix, if there is one.
+    pub fix: Option<Fix>,
+}
+
+impl Finding {
+    /// What to do about the finding: the safe fix, or else what to do by
+    /// hand for its code.
+    pub fn remedy(&self) -> String {
+        match &self.fix {
+            Some(fix) => fix.to_string(),
+            None => advice(self.code).to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.code)
+    }
+}
+
+/// What to do by hand about a problem with the code, when there is no safe
+/// fix for it.
+pub fn advice(code: &str) -> &'static str {
+    match code {
+        diagnostics::DUPLICATE_METADATA => {
+            "keep one of the entries, by removing the name with remove and adding it again with add"
+        }
+        diagnostics::MISSING_ROOT_MODEL | diagnostics::MALFORMED_XML | diagnostics::SCHEMA => {
+            "export the file again from the program that made it"
+        }
+        diagnostics::INVALID_VALUE => {
+            "set a value in the format of the name with add, see explain for the format"
+        }
+        diagnostics::MISSING_METADATA | diagnostics::MISMATCHED_METADATA => {
+            "add the expected metadata with add"
+        }
+        diagnostics::DAMAGED_ENTRY => "restore the file from a backup, or export it again",
+        diagnostics::CONTENT_TYPE => "add a content type for the part to [Content_Types].xml",
+        diagnostics::NON_CONFORMANT_NAME => {
+            "rename the entry to a well-known or namespaced name with rename-key"
+        }
+        diagnostics::DEPRECATED_KEY => {
+            "remove the entry with remove, the well-known name is set already"
+        }
+        diagnostics::MISSING_THUMBNAIL => "save the file from a program that writes a thumbnail",
+        diagnostics::THUMBNAIL_FORMAT => "replace the thumbnail with a PNG or JPEG image",
+        diagnostics::CONFLICTING_METADATA => {
+            "add the value to the root model part with add, which gives the other model parts the same value"
+        }
+        _ => "see explain for the code",
+    }
+}
+
+/// Reads every entry of the package, which checks their CRC-32 checksums,
+/// and reports the entries that can't be read.
+pub fn verify_entries<R: Read + Seek>(package: &mut Package<R>) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    for index in 0..package.len() {
+        let name = package.archive().by_index_raw(index)?.name().to_string();
+        let verified = package
+            .by_index(index)
+            .and_then(|mut file| Ok(io::copy(&mut file, &mut io::sink())?));
+        match verified {
+            Ok(_) => (),
+            Err(error @ (Error::Io(_) | Error::Zip(_))) => findings.push(Finding {
+                code: diagnostics::DAMAGED_ENTRY,
+                severity: Severity::Error,
+                message: format!("entry {} can't be read: {}", name, error),
+                fix: None,
+            }),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(findings)
+}
+
+/// The content type a part should have, going by its name, if that says
+/// enough.
+fn expected_content_type(name: &str) -> Option<&'static str> {
+    let path = Path::new(name);
+    if package::is_model_part(path) {
+        return Some(package::MODEL_CONTENT_TYPE);
+    }
+    let extension = package::part_extension(name)?.to_lowercase();
+    match extension.as_str() {
+        "rels" => Some(package::RELATIONSHIPS_CONTENT_TYPE),
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        _ => None,
+    }
+}
+
+/// Looks for parts that have no content type, and for model and
+/// relationships parts with another content type than those have.
+/// Thumbnails are left to the `thumbnail-format` warning, which looks at
+/// their data. Slicers commonly leave their settings parts without a
+/// content type, so parts of other kinds only get a warning.
+pub fn check_content_types<R: Read + Seek>(package: &mut Package<R>) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    let thumbnails = match package.relationship_targets(package::THUMBNAIL_RELATIONSHIP_TYPE) {
+        Ok(thumbnails) => thumbnails,
+        // Broken relationships are a problem of their own
+        Err(Error::XmlParse(_)) => Vec::new(),
+        Err(error) => return Err(error),
+    };
+    let mut seen: HashSet<_> = thumbnails.into_iter().collect();
+    for index in 0..package.len() {
+        let name = package.archive().by_index_raw(index)?.name().to_string();
+        if name.ends_with('/') || name == package::CONTENT_TYPES_PART || !seen.insert(name.clone())
+        {
+            continue;
+        }
+        let expected = expected_content_type(&name);
+        let fix = expected.map(|content_type| Fix::ContentType {
+            part: name.clone(),
+            content_type: content_type.to_string(),
+        });
+        match (package.content_types().get(&name), expected) {
+            (None, _) => findings.push(Finding {
+                code: diagnostics::CONTENT_TYPE,
+                severity: match expected {
+                    Some(_) => Severity::Error,
+                    None => Severity::Warning,
+                },
+                message: format!("part {} has no content type", name),
+                fix,
+            }),
+            (Some(content_type), Some(expected))
+                if !expected.starts_with("image/")
+                    && !content_type.eq_ignore_ascii_case(expected) =>
+            {
+                findings.push(Finding {
+                    code: diagnostics::CONTENT_TYPE,
+                    severity: Severity::Error,
+                    message: format!(
+                        "part {} has the content type {}, instead of {}",
+                        name, content_type, expected
+                    ),
+                    fix,
+                })
+            }
+            _ => (),
+        }
+    }
+    Ok(findings)
+}
+
+/// What a package that has no `[Content_Types].xml` gets.
+const EMPTY_CONTENT_TYPES: &str =
+    r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"/>"#;
+
+/// Adds an override to the `[Content_Types].xml` part for each part and
+/// content type, replacing any it has for the part.
+fn override_content_types(xml: &mut Element, overrides: &[(&str, &str)]) {
+    for (part, content_type) in overrides {
+        let part_name = format!("/{}", part.trim_start_matches('/'));
+        xml.children.retain(|child| match child {
+            XMLNode::Element(element) if element.name == "Override" => element
+                .attributes
+                .get("PartName")
+                .is_none_or(|name| !name.eq_ignore_ascii_case(&part_name)),
+            _ => true,
+        });
+        let mut element = Element::new("Override");
+        element.attributes.insert("PartName".to_string(), part_name);
+        element
+            .attributes
+            .insert("ContentType".to_string(), content_type.to_string());
+        xml.children.push(XMLNode::Element(element));
+    }
+}
+
+/// Copies the package to `output` with the fixes applied. Without a
+/// [`Fix::DedupeEntries`], every entry is copied, duplicates included.
+pub fn apply_fixes<R, W>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    unsafe_names: UnsafeNames,
+    fixes: &[Fix],
+) -> Result<RawNames>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut overrides = Vec::new();
+    let mut renames = Vec::new();
+    let mut compress = Vec::new();
+    for fix in fixes {
+        match fix {
+            Fix::DedupeEntries => (),
+            Fix::ContentType { part, content_type } => {
+                overrides.push((part.as_str(), content_type.as_str()))
+            }
+            Fix::RenameKey { from, to } => renames.push((from.as_str(), to.as_str())),
+            Fix::Compress(part) => compress.push(part.as_str()),
+        }
+    }
+    let root = match renames.is_empty() {
+        true => None,
+        false => Some(input.root_model_name()?),
+    };
+    let mut wrote_content_types = false;
+    let update = |mut file: ZipFile, name: &str, output: &mut ZipWriter<W>| {
+        if !overrides.is_empty() && file.name() == package::CONTENT_TYPES_PART {
+            let mut xml = Element::parse(&mut file)?;
+            override_content_types(&mut xml, &overrides);
+            package::write_xml_part_as(output, name, file.compression(), &xml)?;
+            wrote_content_types = true;
+            return Ok(true);
+        }
+        if root.as_deref() == Some(file.name()) {
+            let bytes = package::read_model_part(&mut file)?;
+            let mut edit = MetadataEdit::new(&bytes)?;
+            for (from, to) in &renames {
+                model::rename_metadata(edit.xml_mut(), from, to, RenameConflict::Skip);
+            }
+            let method = file.compression();
+            package::write_model_part_as(output, name, method, 1, |writer| edit.write(writer))?;
+            return Ok(true);
+        }
+        if compress.contains(&file.name()) {
+            let options = FileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(Some(package::COMPRESSION_LEVEL))
+                .last_modified_time(package::entry_time()?);
+            output.start_file(name, options)?;
+            io::copy(&mut file, output)?;
+            return Ok(true);
+        }
+        Ok(false)
+    };
+    let raw_names = match fixes.contains(&Fix::DedupeEntries) {
+        true => package::dedupe_entries_with(input, output, unsafe_names, KeepEntry::Last, update)?,
+        false => package::copy_entries(input, output, unsafe_names, update)?,
+    };
+    if !overrides.is_empty() && !wrote_content_types {
+        let mut xml = Element::parse(EMPTY_CONTENT_TYPES.as_bytes())?;
+        override_content_types(&mut xml, &overrides);
+        package::write_xml_part(output, package::CONTENT_TYPES_PART, &xml)?;
+    }
+    Ok(raw_names)
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The ZIP container could not be read or written.
+    Zip(zip::result::ZipError),
+    /// An XML part could not be parsed.
+    XmlParse(xmltree::ParseError),
+    /// A model part or metadata file is not well-formed XML, at a place
+    /// that is known.
+    Syntax(SyntaxError),
+    /// An XML part could not be written.
+    XmlWrite(xmltree::Error),
+    /// The metadata file does not have the expected structure.
+    MetadataFile(String),
+    /// A value could not be parsed as the type its name calls for.
+    InvalidValue { name: String, value: String },
+    /// A value is not a valid SPDX license expression, see
+    /// [`LicenseExpression`](crate::license::LicenseExpression).
+    LicenseExpression { expression: String, problem: String },
+    /// A value has a character that XML 1.0 does not allow, such as most
+    /// control characters.
+    IllegalCharacter { name: String, character: char },
+    /// A metadata name is neither well-known nor namespaced.
+    InvalidName(String),
+    /// A ZIP entry name is absolute or escapes the archive with `..`.
+    UnsafeEntryName(String),
+    /// A ZIP entry is encrypted, and no password was given.
+    Encrypted(String),
+    /// The password does not decrypt a ZIP entry.
+    InvalidPassword(String),
+    /// The package relationships don't point at a model part.
+    NoRootModel,
+    /// The package has no model part at all.
+    NoModelPart,
+    /// A metadata patch could not be parsed or applied.
+    Patch(String),
+    /// The config file could not be parsed.
+    Config(String),
+    /// The state file of a batch run could not be used.
+    State(String),
+    /// An API token could not be read or stored, see
+    /// [`credentials`](crate::credentials).
+    Credentials(String),
+    /// A listing could not be published with `publish`.
+    Publish(String),
+    /// No attribution can be made from the metadata, see
+    /// [`attribution`](crate::attribution).
+    Attribution(String),
+    /// The summary of a run could not be sent to a webhook or MQTT broker.
+    Notify(String),
+    /// Adding metadata would leave a name with different values in the
+    /// model parts, and `add --conflicts error` was asked for.
+    ConflictingMetadata(crate::metadata::Conflict),
+    /// Warnings were found that were turned into errors, holding how many.
+    Denied(usize),
+    /// A model part needs more memory than the limit, in bytes, allows.
+    TooLarge { part: String, limit: u64 },
+    /// An appended package did not read back as written, and was put back
+    /// as it was, see [`append`](crate::append).
+    AppendCheck(String),
+    /// An entry to copy as it is did not read back intact, however often it
+    /// was read, see [`Package::with_verified_copies`](crate::package::Package::with_verified_copies).
+    CopyCheck { entry: String, message: String },
+    /// The package has no backup of the named root model part to undo to.
+    NoBackup(String),
+    /// A thumbnail image could not be read or written.
+    Image(String),
+    /// The work was cancelled with a [`CancellationToken`](crate::cancel::CancellationToken).
+    Cancelled,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Where a document is not well-formed XML, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The line, counting from 1.
+    pub line: u64,
+    /// The column, counting characters from 1.
+    pub column: u64,
+    /// The offset of the line and column in bytes, counting from 0.
+    pub offset: u64,
+    /// The line, or the part of it around the column when it is long. Empty
+    /// when the line isn't known.
+    pub excerpt: String,
+    /// The column in the excerpt, counting characters from 1.
+    pub excerpt_column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Zip(error) => write!(f, "{}", error),
+            Error::XmlParse(error) => write!(f, "could not parse XML: {}", error),
+            Error::Syntax(error) => write!(f, "could not parse XML: {}", error),
+            Error::XmlWrite(error) => write!(f, "could not write XML: {}", error),
+            Error::MetadataFile(message) => write!(f, "{}", message),
+            Error::InvalidValue { name, value } => {
+                write!(f, "invalid value \"{}\" for {}", value, name)?;
+                match crate::well_known::explain(name) {
+                    Some(explanation) if explanation.name == name => {
+                        write!(f, ", expected {}", explanation.format.description())
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Error::LicenseExpression {
+                expression,
+                problem,
+            } => write!(
+                f,
+                "invalid SPDX license expression \"{}\": {}",
+                expression, problem
+            ),
+            Error::IllegalCharacter { name, character } => write!(
+                f,
+                "value of {} contains U+{:04X}, which XML 1.0 does not allow; remove it or encode the value, for example as base64",
+                name, *character as u32
+            ),
+            Error::InvalidName(name) => write!(
+                f,
+                "invalid metadata name \"{}\", names must be well-known or have a namespace prefix",
+                name
+            ),
+            Error::UnsafeEntryName(name) => write!(f, "unsafe entry name \"{}\"", name),
+            Error::Encrypted(name) => write!(
+                f,
+                "entry \"{}\" is encrypted, a password is needed to read it",
+                name
+            ),
+            Error::InvalidPassword(name) => {
+                write!(f, "wrong password for encrypted entry \"{}\"", name)
+            }
+            Error::NoRootModel => write!(f, "package has no root model part"),
+            Error::NoModelPart => write!(f, "package has no model part"),
+            Error::Patch(message) => write!(f, "{}", message),
+            Error::Config(message) => write!(f, "{}", message),
+            Error::State(message) => write!(f, "{}", message),
+            Error::Credentials(message) => write!(f, "{}", message),
+            Error::Publish(message) => write!(f, "{}", message),
+            Error::Attribution(message) => write!(f, "{}", message),
+            Error::Notify(message) => write!(f, "{}", message),
+            Error::ConflictingMetadata(conflict) => {
+                write!(f, "{}, see add --conflicts", conflict)
+            }
+            Error::Denied(1) => write!(f, "a denied warning was found"),
+            Error::Denied(count) => write!(f, "{} denied warnings were found", count),
+            Error::TooLarge { part, limit } => write!(
+                f,
+                "model part {} needs more than the memory limit of {} MB",
+                part,
+                limit / (1024 * 1024)
+            ),
+            Error::AppendCheck(message) => write!(
+                f,
+                "the package did not read back as appended ({}), so it was put back as it was",
+                message
+            ),
+            Error::CopyCheck { entry, message } => write!(
+                f,
+                "entry {} did not read back intact in {} attempts: {}",
+                entry,
+                crate::package::VERIFY_ATTEMPTS,
+                message
+            ),
+            Error::Image(message) => write!(f, "thumbnail image: {}", message),
+            Error::NoBackup(part) => write!(
+                f,
+                "package has no backup of model part {}, only add --append --backup keeps one",
+                part
+            ),
+            Error::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Zip(error) => Some(error),
+            Error::XmlParse(error) => Some(error),
+            Error::Syntax(error) => Some(error),
+            Error::XmlWrite(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(error: zip::result::ZipError) -> Self {
+        Error::Zip(error)
+    }
+}
+
+impl From<xmltree::ParseError> for Error {
+    fn from(error: xmltree::ParseError) -> Self {
+        Error::XmlParse(error)
+    }
+}
+
+impl From<xmltree::Error> for Error {
+    fn from(error: xmltree::Error) -> Self {
+        Error::XmlWrite(error)
+    }
+}
@@ -0,0 +1,199 @@
+//! The metadata of 3MF files in formats that other programs read.
+//!
+//! [`json_ld`] turns metadata into schema.org structured data, which web
+//! pages embed in a `<script type="application/ld+json">` element so that
+//! search engines know what the page is about:
+//!
+//! ```
+//! use metadata_3mf::{export, MetadataSet, Spdx};
+//!
+//! let metadata = MetadataSet::builder()
+//!     .title("3DBenchy")
+//!     .designer("Creative Tools")
+//!     .license(Spdx::CcBy40)
+//!     .build()
+//!     .unwrap();
+//! let data = export::json_ld(&metadata, "benchy.3mf");
+//! assert_eq!(data["@type"], "3DModel");
+//! assert_eq!(data["name"], "3DBenchy");
+//! assert_eq!(data["creator"]["name"], "Creative Tools");
+//! assert_eq!(data["license"], "https://creativecommons.org/licenses/by/4.0/");
+//! ```
+//!
+//! [`canonical`] is meant for golden files in tests instead: the same
+//! metadata always gives the same text, whatever order and formatting the
+//! file has it in.
+
+use serde_json::{json, Map, Value};
+
+use crate::license::{LicenseExpression, LicenseInfo};
+use crate::{well_known, MetadataSet, Spdx};
+
+/// The metadata as a JSON object of names and values, in order.
+pub fn json(metadata: &MetadataSet) -> Value {
+    metadata
+        .iter()
+        .map(|entry| (entry.name.clone(), Value::from(entry.value.as_str())))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+/// Like [`json`], with `LicenseTerms` as an object when it parses as an
+/// SPDX license expression: its `value`, the normalized `expression` and
+/// the `licenses` in it, with what the SPDX license list says about each.
+/// Licenses that aren't known have only their id.
+///
+/// ```
+/// use metadata_3mf::{export, MetadataSet};
+///
+/// let metadata = MetadataSet::builder()
+///     .license_terms("cc-by-4.0 OR MIT")
+///     .build()?;
+/// let data = export::json_with_spdx(&metadata);
+/// let terms = &data["LicenseTerms"];
+/// assert_eq!(terms["expression"], "CC-BY-4.0 OR MIT");
+/// assert_eq!(terms["licenses"][0]["fsf_libre"], true);
+/// assert_eq!(terms["licenses"][1]["osi_approved"], true);
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+pub fn json_with_spdx(metadata: &MetadataSet) -> Value {
+    let mut data = json(metadata);
+    let Some(terms) = metadata.license_terms() else {
+        return data;
+    };
+    let Ok(expression) = LicenseExpression::parse(terms) else {
+        return data;
+    };
+    let licenses: Vec<_> = expression
+        .license_ids()
+        .into_iter()
+        .map(|id| match LicenseInfo::lookup(id) {
+            Some(info) => json!({
+                "id": info.id,
+                "name": info.name,
+                "osi_approved": info.osi_approved,
+                "fsf_libre": info.fsf_libre,
+                "url": info.url,
+            }),
+            None => json!({ "id": id }),
+        })
+        .collect();
+    data[well_known::LICENSE_TERMS] = json!({
+        "value": terms,
+        "expression": expression.to_string(),
+        "licenses": licenses,
+    });
+    data
+}
+
+/// The metadata of model parts as text that only changes when the metadata
+/// does, for comparing with golden files. Parts are sorted by name, each
+/// under a `[part]` line, and their entries by name, one per line as
+/// `name = value`, or `name =` when empty. Attributes follow the name, sorted, as
+/// `name (key=value, ...)`. Backslashes, line breaks and tabs in values are
+/// escaped, so that every entry stays on its line.
+///
+/// ```
+/// use metadata_3mf::{export, MetadataSet};
+///
+/// let metadata = MetadataSet::builder()
+///     .title("3DBenchy")
+///     .description("A boat\r\nto test printers")
+///     .build()?;
+/// let text = export::canonical(&[("3D/3dmodel.model".to_string(), metadata)]);
+/// assert_eq!(
+///     text,
+///     "[3D/3dmodel.model]\nDescription = A boat\\nto test printers\nTitle = 3DBenchy\n"
+/// );
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+pub fn canonical(parts: &[(String, MetadataSet)]) -> String {
+    let mut parts: Vec<_> = parts.iter().collect();
+    parts.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut text = String::new();
+    for (part, metadata) in parts {
+        text.push_str(&format!("[{}]\n", part));
+        let mut entries: Vec<_> = metadata.iter().collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        for entry in entries {
+            text.push_str(&entry.name);
+            if !entry.attributes.is_empty() {
+                let mut attributes: Vec<_> = entry
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, escape(value)))
+                    .collect();
+                attributes.sort();
+                text.push_str(&format!(" ({})", attributes.join(", ")));
+            }
+            // No trailing space for editors to strip from golden files
+            match entry.value.is_empty() {
+                true => text.push_str(" =\n"),
+                false => text.push_str(&format!(" = {}\n", escape(&entry.value))),
+            }
+        }
+    }
+    text
+}
+
+/// Escapes the characters that would break a line of [`canonical`] text.
+/// Windows line breaks become plain ones first, as XML parsers read them.
+fn escape(value: &str) -> String {
+    value
+        .replace("\r\n", "\n")
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
+
+/// The metadata as a schema.org `3DModel`, which is a kind of
+/// `CreativeWork`. The name falls back to the file name when there is no
+/// `Title`, and licenses with an SPDX id are given as the URL of their
+/// text.
+pub fn json_ld(metadata: &MetadataSet, file_name: &str) -> Value {
+    let text = |value: Option<&str>| {
+        value
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let mut data = json!({
+        "@context": "https://schema.org",
+        "@type": "3DModel",
+        "name": text(metadata.title()).unwrap_or_else(|| file_name.to_string()),
+        "encodingFormat": "model/3mf",
+    });
+    let fields = data.as_object_mut().unwrap();
+    if let Some(description) = text(metadata.description()) {
+        fields.insert("description".to_string(), description.into());
+    }
+    if let Some(designer) = text(metadata.designer()) {
+        fields.insert(
+            "creator".to_string(),
+            json!({ "@type": "Person", "name": designer }),
+        );
+    }
+    if let Some(copyright) = text(metadata.copyright()) {
+        fields.insert("copyrightNotice".to_string(), copyright.into());
+    }
+    if let Some(license) = text(metadata.license_terms()) {
+        let license = match license.parse::<Spdx>() {
+            Ok(spdx) => spdx.url().to_string(),
+            Err(_) => license,
+        };
+        fields.insert("license".to_string(), license.into());
+    }
+    // Both dates are ISO 8601 already once they parse
+    for (name, key) in [
+        (well_known::CREATION_DATE, "dateCreated"),
+        (well_known::MODIFICATION_DATE, "dateModified"),
+    ] {
+        if let Some(date) = text(metadata.value(name)) {
+            if well_known::check_value(name, &date).is_ok() {
+                fields.insert(key.to_string(), date.into());
+            }
+        }
+    }
+    data
+}
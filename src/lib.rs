@@ -0,0 +1,69 @@
+//! Reading and writing metadata in 3MF files.
+//!
+//! A 3MF file is a ZIP archive containing one or more XML model parts. The
+//! document metadata lives in `<metadata>` elements directly under the
+//! `<model>` root of those parts. [`MetadataSet`] holds such a list of
+//! entries, with typed accessors for the names defined by the 3MF core
+//! specification, and [`model`] has the functions that read and update them.
+
+pub mod anonymize;
+pub mod append;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod attribution;
+pub mod cancel;
+pub mod catalog;
+pub mod config;
+pub mod credentials;
+mod deflate;
+pub mod diagnostics;
+pub mod doctor;
+mod error;
+pub mod export;
+pub mod license;
+pub mod manifest;
+pub mod metadata;
+pub mod model;
+#[cfg(feature = "net")]
+pub mod notify;
+pub mod observer;
+pub mod package;
+pub mod patch;
+pub mod producer;
+#[cfg(feature = "net")]
+pub mod publish;
+mod raw_names;
+#[cfg(feature = "net")]
+mod retry;
+pub mod schema;
+pub mod slicer;
+pub mod sources;
+pub mod state;
+pub mod thumbnail;
+pub mod warnings;
+pub mod well_known;
+mod xml;
+mod zip_headers;
+
+pub use error::{Error, Result, SyntaxError};
+pub use license::Spdx;
+pub use metadata::{MetadataDiff, MetadataEntry, MetadataSet, MetadataSetBuilder};
+pub use model::Unit;
+pub use package::{
+    add_metadata, add_metadata_to_vec, read_metadata, read_metadata_from_bytes, remove_metadata,
+};
+
+/// The optional features of the crate, and whether it was built with each.
+pub const FEATURES: [(&str, bool); 11] = [
+    ("aes", cfg!(feature = "aes")),
+    ("async", cfg!(feature = "async")),
+    ("bzip2", cfg!(feature = "bzip2")),
+    ("gitignore", cfg!(feature = "gitignore")),
+    ("keyring", cfg!(feature = "keyring")),
+    ("mmap", cfg!(feature = "mmap")),
+    ("net", cfg!(feature = "net")),
+    ("regex", cfg!(feature = "regex")),
+    ("thumbnails", cfg!(feature = "thumbnails")),
+    ("toml", cfg!(feature = "toml")),
+    ("zstd", cfg!(feature = "zstd")),
+];
@@ -0,0 +1,652 @@
+//! Licenses commonly used for 3D models, identified by SPDX id, and SPDX
+//! license expressions such as `CC-BY-4.0 OR MIT`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Error, MetadataSet, Result};
+
+/// A license from the SPDX license list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Spdx {
+    Cc010,
+    CcBy40,
+    CcBySa40,
+    CcByNd40,
+    CcByNc40,
+    CcByNcSa40,
+    CcByNcNd40,
+    Mit,
+    Apache20,
+    Gpl30OrLater,
+}
+
+impl Spdx {
+    pub const ALL: [Spdx; 10] = [
+        Spdx::Cc010,
+        Spdx::CcBy40,
+        Spdx::CcBySa40,
+        Spdx::CcByNd40,
+        Spdx::CcByNc40,
+        Spdx::CcByNcSa40,
+        Spdx::CcByNcNd40,
+        Spdx::Mit,
+        Spdx::Apache20,
+        Spdx::Gpl30OrLater,
+    ];
+
+    /// The SPDX short identifier, e.g. `CC-BY-4.0`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Spdx::Cc010 => "CC0-1.0",
+            Spdx::CcBy40 => "CC-BY-4.0",
+            Spdx::CcBySa40 => "CC-BY-SA-4.0",
+            Spdx::CcByNd40 => "CC-BY-ND-4.0",
+            Spdx::CcByNc40 => "CC-BY-NC-4.0",
+            Spdx::CcByNcSa40 => "CC-BY-NC-SA-4.0",
+            Spdx::CcByNcNd40 => "CC-BY-NC-ND-4.0",
+            Spdx::Mit => "MIT",
+            Spdx::Apache20 => "Apache-2.0",
+            Spdx::Gpl30OrLater => "GPL-3.0-or-later",
+        }
+    }
+
+    /// The full name of the license.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Spdx::Cc010 => "Creative Commons Zero v1.0 Universal",
+            Spdx::CcBy40 => "Creative Commons Attribution 4.0 International",
+            Spdx::CcBySa40 => "Creative Commons Attribution Share Alike 4.0 International",
+            Spdx::CcByNd40 => "Creative Commons Attribution No Derivatives 4.0 International",
+            Spdx::CcByNc40 => "Creative Commons Attribution Non Commercial 4.0 International",
+            Spdx::CcByNcSa40 => {
+                "Creative Commons Attribution Non Commercial Share Alike 4.0 International"
+            }
+            Spdx::CcByNcNd40 => {
+                "Creative Commons Attribution Non Commercial No Derivatives 4.0 International"
+            }
+            Spdx::Mit => "MIT License",
+            Spdx::Apache20 => "Apache License 2.0",
+            Spdx::Gpl30OrLater => "GNU General Public License v3.0 or later",
+        }
+    }
+
+    /// Where the license text can be read.
+    pub fn url(&self) -> &'static str {
+        match self {
+            Spdx::Cc010 => "https://creativecommons.org/publicdomain/zero/1.0/",
+            Spdx::CcBy40 => "https://creativecommons.org/licenses/by/4.0/",
+            Spdx::CcBySa40 => "https://creativecommons.org/licenses/by-sa/4.0/",
+            Spdx::CcByNd40 => "https://creativecommons.org/licenses/by-nd/4.0/",
+            Spdx::CcByNc40 => "https://creativecommons.org/licenses/by-nc/4.0/",
+            Spdx::CcByNcSa40 => "https://creativecommons.org/licenses/by-nc-sa/4.0/",
+            Spdx::CcByNcNd40 => "https://creativecommons.org/licenses/by-nc-nd/4.0/",
+            Spdx::Mit => "https://opensource.org/licenses/MIT",
+            Spdx::Apache20 => "https://www.apache.org/licenses/LICENSE-2.0",
+            Spdx::Gpl30OrLater => "https://www.gnu.org/licenses/gpl-3.0-standalone.html",
+        }
+    }
+
+    /// Whether the Open Source Initiative approved the license.
+    pub fn osi_approved(&self) -> bool {
+        matches!(self, Spdx::Mit | Spdx::Apache20 | Spdx::Gpl30OrLater)
+    }
+
+    /// Whether the Free Software Foundation lists the license as free.
+    pub fn fsf_libre(&self) -> bool {
+        !matches!(
+            self,
+            Spdx::CcByNd40 | Spdx::CcByNc40 | Spdx::CcByNcSa40 | Spdx::CcByNcNd40
+        )
+    }
+}
+
+impl FromStr for Spdx {
+    type Err = Error;
+
+    /// Parses an SPDX identifier. Like SPDX itself, this ignores case.
+    fn from_str(s: &str) -> Result<Self> {
+        Spdx::ALL
+            .into_iter()
+            .find(|license| license.id().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| Error::InvalidValue {
+                name: "license".to_string(),
+                value: s.to_string(),
+            })
+    }
+}
+
+impl fmt::Display for Spdx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// Licenses of the SPDX license list besides those of [`Spdx`], with their
+/// name and whether the OSI approved them and the FSF lists them as free.
+const OTHER_LICENSES: [(&str, &str, bool, bool); 19] = [
+    ("0BSD", "BSD Zero Clause License", true, false),
+    (
+        "AGPL-3.0-only",
+        "GNU Affero General Public License v3.0 only",
+        true,
+        true,
+    ),
+    (
+        "AGPL-3.0-or-later",
+        "GNU Affero General Public License v3.0 or later",
+        true,
+        true,
+    ),
+    (
+        "BSD-2-Clause",
+        "BSD 2-Clause \"Simplified\" License",
+        true,
+        true,
+    ),
+    (
+        "BSD-3-Clause",
+        "BSD 3-Clause \"New\" or \"Revised\" License",
+        true,
+        true,
+    ),
+    (
+        "CERN-OHL-P-2.0",
+        "CERN Open Hardware Licence Version 2 - Permissive",
+        true,
+        false,
+    ),
+    (
+        "CERN-OHL-S-2.0",
+        "CERN Open Hardware Licence Version 2 - Strongly Reciprocal",
+        true,
+        false,
+    ),
+    (
+        "CERN-OHL-W-2.0",
+        "CERN Open Hardware Licence Version 2 - Weakly Reciprocal",
+        true,
+        false,
+    ),
+    (
+        "GPL-2.0-only",
+        "GNU General Public License v2.0 only",
+        true,
+        true,
+    ),
+    (
+        "GPL-2.0-or-later",
+        "GNU General Public License v2.0 or later",
+        true,
+        true,
+    ),
+    (
+        "GPL-3.0-only",
+        "GNU General Public License v3.0 only",
+        true,
+        true,
+    ),
+    ("ISC", "ISC License", true, true),
+    (
+        "LGPL-2.1-only",
+        "GNU Lesser General Public License v2.1 only",
+        true,
+        true,
+    ),
+    (
+        "LGPL-2.1-or-later",
+        "GNU Lesser General Public License v2.1 or later",
+        true,
+        true,
+    ),
+    (
+        "LGPL-3.0-only",
+        "GNU Lesser General Public License v3.0 only",
+        true,
+        true,
+    ),
+    (
+        "LGPL-3.0-or-later",
+        "GNU Lesser General Public License v3.0 or later",
+        true,
+        true,
+    ),
+    ("MPL-2.0", "Mozilla Public License 2.0", true, true),
+    ("Unlicense", "The Unlicense", true, true),
+    ("Zlib", "zlib License", true, true),
+];
+
+/// What the SPDX license list says about a license.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LicenseInfo {
+    /// The SPDX id, in the case of the list.
+    pub id: &'static str,
+    pub name: &'static str,
+    pub osi_approved: bool,
+    pub fsf_libre: bool,
+    pub url: String,
+}
+
+impl LicenseInfo {
+    /// Looks up a license of the SPDX license list by id, ignoring case.
+    /// Only the licenses of [`Spdx`] and some other common ones are known.
+    pub fn lookup(id: &str) -> Option<LicenseInfo> {
+        if let Ok(license) = id.parse::<Spdx>() {
+            return Some(LicenseInfo {
+                id: license.id(),
+                name: license.name(),
+                osi_approved: license.osi_approved(),
+                fsf_libre: license.fsf_libre(),
+                url: license.url().to_string(),
+            });
+        }
+        let (id, name, osi_approved, fsf_libre) = OTHER_LICENSES
+            .into_iter()
+            .find(|(known, ..)| known.eq_ignore_ascii_case(id))?;
+        Some(LicenseInfo {
+            id,
+            name,
+            osi_approved,
+            fsf_libre,
+            url: format!("https://spdx.org/licenses/{}.html", id),
+        })
+    }
+}
+
+/// An SPDX license expression, which combines licenses with `AND` when all
+/// of them apply, and `OR` when any of them may be picked. `AND` binds
+/// tighter than `OR`, and parentheses group.
+///
+/// ```
+/// use metadata_3mf::license::LicenseExpression;
+///
+/// let expression = LicenseExpression::parse("cc-by-4.0 or (MIT and Zlib)")?;
+/// assert_eq!(expression.to_string(), "CC-BY-4.0 OR MIT AND Zlib");
+/// assert_eq!(expression.license_ids(), ["CC-BY-4.0", "MIT", "Zlib"]);
+///
+/// // Strict parsing wants the operators in upper case and known licenses
+/// assert!(LicenseExpression::parse_strict("CC-BY-4.0 or MIT").is_err());
+/// assert!(LicenseExpression::parse_strict("CC-BY-4.0 OR Foo-1.0").is_err());
+/// assert!(LicenseExpression::parse_strict("CC-BY-4.0 OR LicenseRef-Shop").is_ok());
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LicenseExpression {
+    /// A license by id, with `+` for a later version of it as well, and the
+    /// exception to it after `WITH`.
+    License {
+        id: String,
+        or_later: bool,
+        exception: Option<String>,
+    },
+    And(Vec<LicenseExpression>),
+    Or(Vec<LicenseExpression>),
+}
+
+impl LicenseExpression {
+    /// Parses an expression, with operators in any case. Known license ids
+    /// are put in the case of the SPDX license list.
+    pub fn parse(s: &str) -> Result<Self> {
+        Parser::new(s, false).parse()
+    }
+
+    /// Parses an expression as SPDX has them: with the operators in upper
+    /// case, and only licenses that [`LicenseInfo::lookup`] knows, or that
+    /// are named with `LicenseRef-`.
+    pub fn parse_strict(s: &str) -> Result<Self> {
+        let expression = Parser::new(s, true).parse()?;
+        for id in expression.license_ids() {
+            let custom = id.starts_with("LicenseRef-") || id.starts_with("DocumentRef-");
+            if !custom && LicenseInfo::lookup(id).is_none() {
+                return Err(invalid_expression(
+                    s,
+                    &format!("unknown license {}, use LicenseRef- for others", id),
+                ));
+            }
+        }
+        Ok(expression)
+    }
+
+    /// Whether a value is meant as an expression that combines licenses:
+    /// it has operators or parentheses, and its other words are licenses
+    /// that [`LicenseInfo::lookup`] knows, custom ones, or exceptions after
+    /// `WITH`. Free text that happens to have them is not.
+    ///
+    /// ```
+    /// use metadata_3mf::license::LicenseExpression;
+    ///
+    /// assert!(LicenseExpression::is_compound("CC-BY-4.0 or (MIT AND Zlib)"));
+    /// assert!(!LicenseExpression::is_compound("CC-BY-4.0"));
+    /// assert!(!LicenseExpression::is_compound(
+    ///     "Free for personal use and for schools (no resale)"
+    /// ));
+    /// ```
+    pub fn is_compound(s: &str) -> bool {
+        let tokens = Parser::new(s, false).tokens;
+        let is_operator = |token: &str| {
+            ["(", ")", "AND", "OR", "WITH"]
+                .iter()
+                .any(|operator| token.eq_ignore_ascii_case(operator))
+        };
+        tokens.iter().any(|token| is_operator(token))
+            && tokens.iter().enumerate().all(|(index, token)| {
+                let exception = index > 0 && tokens[index - 1].eq_ignore_ascii_case("WITH");
+                let id = token.strip_suffix('+').unwrap_or(token);
+                is_operator(token)
+                    || (exception && is_id(token))
+                    || id.starts_with("LicenseRef-")
+                    || id.starts_with("DocumentRef-")
+                    || LicenseInfo::lookup(id).is_some()
+            })
+    }
+
+    /// The ids of the licenses, in the order of the expression.
+    pub fn license_ids(&self) -> Vec<&str> {
+        match self {
+            LicenseExpression::License { id, .. } => vec![id.as_str()],
+            LicenseExpression::And(terms) | LicenseExpression::Or(terms) => terms
+                .iter()
+                .flat_map(LicenseExpression::license_ids)
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for LicenseExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseExpression::License {
+                id,
+                or_later,
+                exception,
+            } => {
+                f.write_str(id)?;
+                if *or_later {
+                    f.write_str("+")?;
+                }
+                match exception {
+                    Some(exception) => write!(f, " WITH {}", exception),
+                    None => Ok(()),
+                }
+            }
+            LicenseExpression::And(terms) => {
+                for (index, term) in terms.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(" AND ")?;
+                    }
+                    match term {
+                        LicenseExpression::Or(_) => write!(f, "({})", term)?,
+                        _ => write!(f, "{}", term)?,
+                    }
+                }
+                Ok(())
+            }
+            LicenseExpression::Or(terms) => {
+                for (index, term) in terms.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(" OR ")?;
+                    }
+                    write!(f, "{}", term)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for LicenseExpression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        LicenseExpression::parse(s)
+    }
+}
+
+fn invalid_expression(s: &str, problem: &str) -> Error {
+    Error::LicenseExpression {
+        expression: s.to_string(),
+        problem: problem.to_string(),
+    }
+}
+
+/// Parses expressions by recursive descent, one level per operator.
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<&'a str>,
+    position: usize,
+    strict: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, strict: bool) -> Self {
+        let mut tokens = Vec::new();
+        for word in text.split_whitespace() {
+            let mut rest = word;
+            while let Some(index) = rest.find(['(', ')']) {
+                if index > 0 {
+                    tokens.push(&rest[..index]);
+                }
+                tokens.push(&rest[index..index + 1]);
+                rest = &rest[index + 1..];
+            }
+            if !rest.is_empty() {
+                tokens.push(rest);
+            }
+        }
+        Parser {
+            text,
+            tokens,
+            position: 0,
+            strict,
+        }
+    }
+
+    fn parse(mut self) -> Result<LicenseExpression> {
+        let expression = self.or()?;
+        match self.tokens.get(self.position) {
+            None => Ok(expression),
+            Some(token) => Err(self.error(&format!("unexpected {}", token))),
+        }
+    }
+
+    fn error(&self, problem: &str) -> Error {
+        invalid_expression(self.text, problem)
+    }
+
+    /// Takes the next token if it is the operator. Strict parsing only
+    /// takes operators in upper case, and fails on others.
+    fn operator(&mut self, operator: &str) -> Result<bool> {
+        let Some(token) = self.tokens.get(self.position) else {
+            return Ok(false);
+        };
+        if *token == operator || (!self.strict && token.eq_ignore_ascii_case(operator)) {
+            self.position += 1;
+            return Ok(true);
+        }
+        if token.eq_ignore_ascii_case(operator) {
+            return Err(self.error(&format!("write the operator {} in upper case", operator)));
+        }
+        Ok(false)
+    }
+
+    fn or(&mut self) -> Result<LicenseExpression> {
+        let mut terms = vec![self.and()?];
+        while self.operator("OR")? {
+            terms.push(self.and()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.pop().unwrap(),
+            _ => LicenseExpression::Or(terms),
+        })
+    }
+
+    fn and(&mut self) -> Result<LicenseExpression> {
+        let mut terms = vec![self.term()?];
+        while self.operator("AND")? {
+            terms.push(self.term()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.pop().unwrap(),
+            _ => LicenseExpression::And(terms),
+        })
+    }
+
+    fn term(&mut self) -> Result<LicenseExpression> {
+        if self.tokens.get(self.position) == Some(&"(") {
+            self.position += 1;
+            let expression = self.or()?;
+            if self.tokens.get(self.position) != Some(&")") {
+                return Err(self.error("missing )"));
+            }
+            self.position += 1;
+            return Ok(expression);
+        }
+        let id = self.id("a license")?;
+        let (id, or_later) = match id.strip_suffix('+') {
+            Some(id) => (id, true),
+            None => (id, false),
+        };
+        if !is_id(id) {
+            return Err(self.error(&format!("invalid license id {}", id)));
+        }
+        let exception = match self.operator("WITH")? {
+            true => {
+                let exception = self.id("an exception")?;
+                if !is_id(exception) {
+                    return Err(self.error(&format!("invalid exception id {}", exception)));
+                }
+                Some(exception.to_string())
+            }
+            false => None,
+        };
+        let id = match LicenseInfo::lookup(id) {
+            Some(info) => info.id.to_string(),
+            None => id.to_string(),
+        };
+        Ok(LicenseExpression::License {
+            id,
+            or_later,
+            exception,
+        })
+    }
+
+    /// Takes the next token as an id, which must not be an operator or a
+    /// parenthesis.
+    fn id(&mut self, what: &str) -> Result<&'a str> {
+        match self.tokens.get(self.position) {
+            Some(&token)
+                if !["(", ")", "AND", "OR", "WITH"]
+                    .iter()
+                    .any(|reserved| token.eq_ignore_ascii_case(reserved)) =>
+            {
+                self.position += 1;
+                Ok(token)
+            }
+            Some(token) => Err(self.error(&format!("expected {} before {}", what, token))),
+            None => Err(self.error(&format!("expected {} at the end", what))),
+        }
+    }
+}
+
+/// Whether an id only has the letters, digits, `.` and `-` that SPDX ids
+/// are made of, and the `:` after a `DocumentRef-`.
+fn is_id(id: &str) -> bool {
+    let id = match id.strip_prefix("DocumentRef-") {
+        Some(reference) => match reference.split_once(':') {
+            Some((document, license)) if is_id(document) && license.starts_with("LicenseRef-") => {
+                license
+            }
+            _ => return false,
+        },
+        None => id,
+    };
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || matches!(character, '.' | '-'))
+}
+
+/// Ready-made metadata for common licensing situations, so that no metadata
+/// file is needed. Named by the lowercase SPDX id of the license, like
+/// `cc-by-4.0`, or `all-rights-reserved`.
+///
+/// ```
+/// use metadata_3mf::license::BuiltinPreset;
+///
+/// let preset: BuiltinPreset = "cc-by-4.0".parse()?;
+/// let metadata = preset.metadata("BASt", 2024);
+/// assert_eq!(metadata.license_terms(), Some("CC-BY-4.0"));
+/// assert_eq!(
+///     metadata.copyright(),
+///     Some("Copyright (c) 2024 BASt. Licensed under Creative Commons Attribution 4.0 International: https://creativecommons.org/licenses/by/4.0/")
+/// );
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuiltinPreset {
+    License(Spdx),
+    AllRightsReserved,
+}
+
+impl BuiltinPreset {
+    pub fn all() -> impl Iterator<Item = BuiltinPreset> {
+        Spdx::ALL
+            .into_iter()
+            .map(BuiltinPreset::License)
+            .chain([BuiltinPreset::AllRightsReserved])
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            BuiltinPreset::License(license) => license.id().to_ascii_lowercase(),
+            BuiltinPreset::AllRightsReserved => "all-rights-reserved".to_string(),
+        }
+    }
+
+    /// The `Designer`, `Copyright` and `LicenseTerms` entries for a model by
+    /// `designer`, made in `year`. The copyright notice links to the
+    /// license text.
+    pub fn metadata(&self, designer: &str, year: i32) -> MetadataSet {
+        let mut metadata = MetadataSet::new();
+        metadata.set_designer(designer);
+        match self {
+            BuiltinPreset::License(license) => {
+                metadata.set_copyright(format!(
+                    "Copyright (c) {} {}. Licensed under {}: {}",
+                    year,
+                    designer,
+                    license.name(),
+                    license.url()
+                ));
+                metadata.set_license_terms(license.id());
+            }
+            BuiltinPreset::AllRightsReserved => {
+                metadata.set_copyright(format!(
+                    "Copyright (c) {} {}. All rights reserved.",
+                    year, designer
+                ));
+                metadata.set_license_terms("All rights reserved");
+            }
+        }
+        metadata
+    }
+}
+
+impl FromStr for BuiltinPreset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        BuiltinPreset::all()
+            .find(|preset| preset.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| Error::InvalidValue {
+                name: "built-in preset".to_string(),
+                value: s.to_string(),
+            })
+    }
+}
+
+impl fmt::Display for BuiltinPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
@@ -1,21 +1,577 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::{stdout, BufReader, Seek, Write};
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdout, BufReader, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+#[cfg(feature = "gitignore")]
+use ignore::WalkBuilder;
+use metadata_3mf::cancel::CancellationToken;
+use metadata_3mf::catalog::{self, Card};
+use metadata_3mf::config::{self, Config};
+use metadata_3mf::credentials::{self, Service};
+use metadata_3mf::diagnostics::{self, Diagnostic, Severity};
+use metadata_3mf::doctor::{self, Finding};
+use metadata_3mf::license::{BuiltinPreset, LicenseExpression};
+use metadata_3mf::manifest::{self, Manifest, Row};
+use metadata_3mf::metadata::{Conditional, Conflict, Difference, MetadataFile};
+use metadata_3mf::model::{Compat, RenameConflict, Renamed};
+#[cfg(feature = "net")]
+use metadata_3mf::notify::{self, MqttTarget};
+use metadata_3mf::observer::{self, ProcessObserver, Tally};
+use metadata_3mf::package::{self, KeepEntry, Package, RawNameWriter, RawNames, UnsafeNames};
+#[cfg(feature = "net")]
+use metadata_3mf::publish;
+use metadata_3mf::state::State;
+#[cfg(feature = "thumbnails")]
+use metadata_3mf::thumbnail::Size;
+use metadata_3mf::thumbnail::{self, ImageFormat};
+use metadata_3mf::warnings::{Level, Levels, Warning};
+use metadata_3mf::{
+    anonymize, append, attribution, export, metadata, model, patch, producer, schema, slicer,
+    sources, warnings, well_known, Error, MetadataEntry, MetadataSet, MetadataSetBuilder, Result,
+    Unit,
+};
+#[cfg(feature = "regex")]
+use regex::{NoExpand, Regex, RegexBuilder};
+use tempfile::{NamedTempFile, TempPath};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 use xmltree::{Element, EmitterConfig, XMLNode};
 use zip::read::ZipFile;
-use zip::write::FileOptions;
-use zip::{ZipArchive, ZipWriter};
+use zip::{CompressionMethod, ZipWriter};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
+    #[clap(flatten)]
+    options: Options,
+
+    /// Print the version, the optional features of this build and the
+    /// formats it reads and writes, then exit
+    #[clap(long)]
+    version_info: bool,
+
     #[clap(subcommand)]
-    subcommand: Subcommands,
+    subcommand: Option<Subcommands>,
+}
+
+// Options that apply to every subcommand. A doc comment here would end up
+// as the about text of the whole program.
+#[derive(Args, Debug)]
+#[clap(next_help_heading = "GLOBAL OPTIONS")]
+struct Options {
+    /// Report how long parsing, rewriting and writing took for each file
+    #[clap(long, global = true)]
+    timing: bool,
+
+    /// Map input files into memory instead of reading them, which is faster
+    /// for large files. They must not be changed while being processed.
+    #[cfg(feature = "mmap")]
+    #[clap(long, global = true)]
+    mmap: bool,
+
+    /// Check each entry that is copied as it is against the checksum of the
+    /// input before copying it, and read it again when it doesn't match,
+    /// for files on flaky network storage
+    #[clap(long, global = true)]
+    verify_copies: bool,
+
+    /// Number of threads to compress large model parts on [default: one per core]
+    #[clap(short = 'j', long, global = true)]
+    threads: Option<usize>,
+
+    /// Keep memory use under this many megabytes: metadata changes copy the
+    /// rest of a model part as they read it, on one thread, and parts too
+    /// large to parse within the limit are refused
+    #[clap(long, value_name = "MB", global = true)]
+    max_memory: Option<u64>,
+
+    /// Rename entries with absolute or `..` names instead of copying them as they are
+    #[clap(long, global = true, conflicts_with = "strict")]
+    sanitize_paths: bool,
+
+    /// Refuse packages with entries with absolute or `..` names, and, for
+    /// show and info, packages without a model part
+    #[clap(long, global = true)]
+    strict: bool,
+
+    /// Comment of the ZIP archive of written files, instead of the comment
+    /// of the input, which is kept otherwise. Bambu Studio keeps its version
+    /// there
+    #[clap(long, global = true, value_name = "TEXT")]
+    archive_comment: Option<String>,
+
+    /// Password for encrypted packages. The output is written without
+    /// encryption.
+    #[clap(
+        long,
+        global = true,
+        env = "METADATA_3MF_PASSWORD",
+        hide_env_values = true
+    )]
+    password: Option<String>,
+
+    /// Read input files through symbolic links, and write output files
+    /// through them to their targets. This is the default
+    #[clap(long, global = true, overrides_with = "no-follow-symlinks")]
+    follow_symlinks: bool,
+
+    /// Skip input files that are symbolic links, and replace output files
+    /// that are symbolic links instead of writing to their targets
+    #[clap(long, global = true, overrides_with = "follow-symlinks")]
+    no_follow_symlinks: bool,
+
+    /// Process the 3MF files in directories given as input, and in their
+    /// subdirectories
+    #[clap(short, long, global = true)]
+    recursive: bool,
+
+    /// How many levels of subdirectories to descend into with --recursive;
+    /// 0 only processes the files directly in the directories given
+    #[clap(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// With --recursive, also process the files that .gitignore and
+    /// .m3mfignore files exclude
+    #[cfg(feature = "gitignore")]
+    #[clap(long, global = true)]
+    no_ignore: bool,
+
+    /// Skip the input files up to and including this one, to resume an
+    /// interrupted run. The files are processed in the same order every time
+    #[clap(long, global = true, value_name = "PATH")]
+    start_after: Option<PathBuf>,
+
+    /// Only process the first N input files
+    #[clap(long, global = true, value_name = "N", conflicts_with = "sample")]
+    limit: Option<usize>,
+
+    /// Only process N of the input files, picked at random, to try a change
+    /// on part of a large library first. They are processed in the usual
+    /// order
+    #[clap(long, global = true, value_name = "N")]
+    sample: Option<usize>,
+
+    /// Seed for --sample, which picks the same files with the same seed
+    /// [default: a new one for every run, which is printed]
+    #[clap(long, global = true, value_name = "SEED", requires = "sample")]
+    seed: Option<u64>,
+
+    /// Report what would be changed without writing any files
+    #[clap(short = 'n', long, global = true)]
+    dry_run: bool,
+
+    /// Write nothing unless every file succeeds: outputs are kept as
+    /// temporary files until the end of the run, and then all renamed into
+    /// place. When a file fails, the outputs written so far are removed
+    #[clap(long, global = true, conflicts_with = "state-file")]
+    transactional: bool,
+
+    /// URL to POST a JSON summary of the run to when it finishes
+    #[cfg(feature = "net")]
+    #[clap(long, global = true, value_name = "URL")]
+    notify_webhook: Option<String>,
+
+    /// MQTT broker and topic to publish the summary of the run to, as
+    /// mqtt://[user[:password]@]host[:port]/topic. Without a password in
+    /// it, the one stored with `token mqtt` is used
+    #[cfg(feature = "net")]
+    #[clap(long, global = true, value_name = "URL")]
+    notify_mqtt: Option<MqttTarget>,
+
+    /// Only send the summary of runs that failed or were stopped
+    #[cfg(feature = "net")]
+    #[clap(long, global = true)]
+    notify_failures_only: bool,
+
+    /// Never ask for anything, and fail instead, and don't act differently
+    /// when run from a terminal. For jobs in containers and CI
+    #[clap(long, global = true, env = "METADATA_3MF_NON_INTERACTIVE")]
+    non_interactive: bool,
+
+    /// Directory to resolve relative paths against, instead of the current
+    /// directory
+    #[clap(long, global = true, value_name = "DIR", env = "METADATA_3MF_WORKDIR")]
+    workdir: Option<PathBuf>,
+
+    // outputs of --transactional and the paths they are renamed to, internal only
+    #[clap(skip)]
+    staged: RefCell<Vec<(TempPath, PathBuf)>>,
+
+    // the name of the subcommand and the counts for the summary of the run,
+    // also internal only
+    #[clap(skip)]
+    subcommand: &'static str,
+    #[clap(skip)]
+    progress: Arc<Progress>,
+
+    /// Print data as tab-separated lines in a format that stays the same
+    /// between versions, for scripts. See the README for the format
+    #[clap(long, global = true)]
+    porcelain: bool,
+
+    /// Print errors as text, with the line and column of XML that is not
+    /// well-formed and the line itself, or with json, as JSON objects, one
+    /// per line
+    #[clap(
+        long,
+        global = true,
+        value_name = "FORMAT",
+        default_value = "human",
+        possible_values = ErrorFormat::NAMES
+    )]
+    error_format: ErrorFormat,
+
+    /// Command to run before processing each file, with the file and its
+    /// metadata as JSON on standard input. The file is not processed when
+    /// the command fails
+    #[clap(long, global = true, value_name = "CMD")]
+    hook_pre: Option<String>,
+
+    /// Command to run after processing each file, with the input and output
+    /// file and the metadata of the output as JSON on standard input
+    #[clap(long, global = true, value_name = "CMD")]
+    hook_post: Option<String>,
+
+    /// Wait up to this many seconds for files that another program, such as
+    /// a slicer, has locked. Without it, locked files fail right away
+    #[clap(long, global = true, value_name = "SECONDS")]
+    wait: Option<u64>,
+
+    /// File that lists the input files that are done, so that running the
+    /// same command again skips them
+    #[clap(long, global = true, value_name = "FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Forget the files the state file lists, and start over
+    #[clap(long, global = true, requires = "state-file")]
+    reset_state: bool,
+
+    /// What to do with input files that are the output of an earlier run,
+    /// because their name ends with the suffix or, with
+    /// --previous-by-metadata, they already have the metadata: skip them,
+    /// write over earlier outputs and process them anyway, or stop with an
+    /// error
+    #[clap(
+        long,
+        global = true,
+        value_name = "POLICY",
+        default_value = "skip",
+        possible_values = PreviousOutputs::NAMES
+    )]
+    previous_outputs: PreviousOutputs,
+
+    /// Also take input files of add and apply for outputs of an earlier run
+    /// when adding would not change the metadata of their root model part
+    #[clap(long, global = true)]
+    previous_by_metadata: bool,
+
+    /// Write changed models the way a slicer does, for programs that only
+    /// read what that slicer writes: strict-spec keeps the tab indentation
+    /// this program has always written
+    #[clap(
+        long,
+        global = true,
+        value_name = "PROFILE",
+        default_value = "strict-spec",
+        possible_values = Compat::NAMES
+    )]
+    compat: Compat,
+
+    /// Don't look for a warning, or for any with `all`
+    #[clap(
+        short = 'A',
+        long,
+        global = true,
+        value_name = "WARNING",
+        multiple_occurrences = true,
+        possible_values = Warning::SELECTORS
+    )]
+    allow: Vec<String>,
+
+    /// Report a warning, or all of them with `all`
+    #[clap(
+        short = 'W',
+        long,
+        global = true,
+        value_name = "WARNING",
+        multiple_occurrences = true,
+        possible_values = Warning::SELECTORS
+    )]
+    warn: Vec<String>,
+
+    /// Turn a warning into an error, or all of them with `all`. Named
+    /// warnings win over `all`, so `-D all -A missing-thumbnail` denies
+    /// every other warning
+    #[clap(
+        short = 'D',
+        long,
+        global = true,
+        value_name = "WARNING",
+        multiple_occurrences = true,
+        possible_values = Warning::SELECTORS
+    )]
+    deny: Vec<String>,
+}
+
+impl Options {
+    fn follow_symlinks(&self) -> bool {
+        !self.no_follow_symlinks
+    }
+
+    /// The level of each warning. `all` is applied first, then the named
+    /// warnings, each in the order allow, warn, deny.
+    fn warning_levels(&self) -> Levels {
+        let mut levels = Levels::default();
+        let flags = [
+            (&self.allow, Level::Allow),
+            (&self.warn, Level::Warn),
+            (&self.deny, Level::Deny),
+        ];
+        for (names, level) in flags {
+            if names.iter().any(|name| name == "all") {
+                levels.set_all(level);
+            }
+        }
+        for (names, level) in flags {
+            for name in names.iter().filter(|name| *name != "all") {
+                levels.set(name.parse().unwrap(), level);
+            }
+        }
+        levels
+    }
+
+    fn threads(&self) -> usize {
+        // Compressing in parallel holds chunks of the part in memory
+        if self.max_memory.is_some() {
+            return 1;
+        }
+        self.threads.unwrap_or_else(package::default_threads)
+    }
+
+    /// The --max-memory limit in bytes.
+    fn memory_limit(&self) -> Option<u64> {
+        self.max_memory
+            .map(|megabytes| megabytes.saturating_mul(1024 * 1024))
+    }
+
+    fn unsafe_names(&self) -> UnsafeNames {
+        if self.strict {
+            UnsafeNames::Reject
+        } else if self.sanitize_paths {
+            UnsafeNames::Sanitize
+        } else {
+            UnsafeNames::Warn
+        }
+    }
+}
+
+/// What to do with input files that are the output of an earlier run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PreviousOutputs {
+    Skip,
+    /// Output files are written over earlier outputs without `--force`, and
+    /// inputs that already have the metadata, with `--previous-by-metadata`,
+    /// are processed again. Inputs named with the suffix are still skipped,
+    /// as their output would get the suffix twice.
+    Replace,
+    Error,
+}
+
+impl PreviousOutputs {
+    const NAMES: [&'static str; 3] = ["skip", "replace", "error"];
+}
+
+impl std::str::FromStr for PreviousOutputs {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "skip" => Ok(PreviousOutputs::Skip),
+            "replace" => Ok(PreviousOutputs::Replace),
+            "error" => Ok(PreviousOutputs::Error),
+            _ => Err(format!("unknown policy {}", s)),
+        }
+    }
+}
+
+/// What `add` does when another model part has a name it sets in the root
+/// model part, with another value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConflictPolicy {
+    /// Give the other model parts the value of the root model part.
+    RootWins,
+    Error,
+    /// Add the metadata to every model part.
+    Merge,
+}
+
+impl ConflictPolicy {
+    const NAMES: [&'static str; 3] = ["root-wins", "error", "merge"];
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "root-wins" => Ok(ConflictPolicy::RootWins),
+            "error" => Ok(ConflictPolicy::Error),
+            "merge" => Ok(ConflictPolicy::Merge),
+            _ => Err(format!("unknown policy {}", s)),
+        }
+    }
+}
+
+/// How `validate` and `check` report their results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportFormat {
+    Text,
+    Sarif,
+}
+
+impl ReportFormat {
+    const NAMES: [&'static str; 2] = ["text", "sarif"];
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "sarif" => Ok(ReportFormat::Sarif),
+            _ => Err(format!("unknown format {}", s)),
+        }
+    }
+}
+
+/// What `show` prints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ShowFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl ShowFormat {
+    const NAMES: [&'static str; 3] = ["text", "json", "csv"];
+}
+
+impl std::str::FromStr for ShowFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "text" => Ok(ShowFormat::Text),
+            "json" => Ok(ShowFormat::Json),
+            "csv" => Ok(ShowFormat::Csv),
+            _ => Err(format!("unknown format {}", s)),
+        }
+    }
+}
+
+/// How errors are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ErrorFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl ErrorFormat {
+    const NAMES: [&'static str; 2] = ["human", "json"];
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown format {}", s)),
+        }
+    }
+}
+
+/// What `export` prints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExportFormat {
+    Json,
+    JsonLd,
+    Csv,
+}
+
+impl ExportFormat {
+    const NAMES: [&'static str; 3] = ["json", "jsonld", "csv"];
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "jsonld" => Ok(ExportFormat::JsonLd),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown format {}", s)),
+        }
+    }
+}
+
+// The results of `validate` and `check`. A doc comment here would end up
+// as their about text.
+#[derive(Args, Debug)]
+struct Report {
+    /// Print a line per problem, or with sarif, a SARIF log of all files
+    /// at the end, for code review and CI tools
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = ReportFormat::NAMES
+    )]
+    format: ReportFormat,
+
+    // files that failed, internal only
+    #[clap(skip)]
+    failed: RefCell<HashSet<PathBuf>>,
+
+    // what was found in all files, for the SARIF log, internal only
+    #[clap(skip)]
+    diagnostics: RefCell<Vec<Diagnostic>>,
+}
+
+impl Report {
+    fn is_sarif(&self) -> bool {
+        self.format == ReportFormat::Sarif
+    }
+
+    fn fail(&self, input_path: &Path) {
+        self.failed.borrow_mut().insert(input_path.to_path_buf());
+    }
+
+    fn failures(&self) -> usize {
+        self.failed.borrow().len()
+    }
+
+    fn add(&self, input_path: &Path, code: &'static str, severity: Severity, message: String) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            code,
+            severity,
+            file: input_path.to_string_lossy().into_owned(),
+            message,
+        });
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -25,6 +581,177 @@ enum Subcommands {
     Add(Add),
     /// Show metadata in 3MF files
     Show(Show),
+    /// Show general information about 3MF files
+    Info(Info),
+    /// Search the metadata values of 3MF files
+    #[cfg(feature = "regex")]
+    Grep(Grep),
+    /// Print the value of a metadata entry of 3MF files as it is
+    Get(Get),
+    /// Generate a static HTML page that lists 3MF files
+    Catalog(Catalog),
+    /// Print the metadata of 3MF files for other programs
+    Export(Export),
+    /// Print who made each 3MF file and its license, to paste into listings
+    /// or READMEs
+    Attribution(Attribution),
+    /// Convert the unit of 3MF files, rescaling the geometry
+    ConvertUnits(ConvertUnits),
+    /// Remove metadata from 3MF files
+    Remove(Remove),
+    /// Check that 3MF files have the metadata from a metadata file
+    Check(Check),
+    /// Edit the metadata of 3MF files with a JSON merge patch or JSON patch
+    Patch(Patch),
+    /// Rename a metadata entry in 3MF files
+    RenameKey(RenameKey),
+    /// Set the name and part number of an object in 3MF files
+    SetObject(SetObject),
+    /// Set the object, transform, part number and path of a build item in
+    /// 3MF files
+    SetItem(SetItem),
+    /// Find and replace text in metadata values of 3MF files
+    ReplaceValue(ReplaceValue),
+    /// Remove personal information from 3MF files before sharing them
+    Anonymize(Anonymize),
+    /// Add the metadata preset the config file picks for each 3MF file
+    Apply(Apply),
+    /// Check that the model parts of 3MF files are valid
+    Validate(Validate),
+    /// Run every check on 3MF files, and list what to do about each problem
+    Doctor(Doctor),
+    /// Describe the well-known metadata names of the 3MF specification
+    Explain(Explain),
+    /// Store the API tokens of services, so that they aren't given on the
+    /// command line
+    Token(Token),
+    /// Try add, show, remove and validate on a package made for the test,
+    /// to check that this program works here before blaming a file
+    SelfTest(SelfTest),
+    /// Replace 3MF files with their outputs, once those have been reviewed
+    Promote(Promote),
+    /// Copy printer and filament profiles from slicer settings into metadata
+    PromoteSlicerMetadata(PromoteSlicerMetadata),
+    /// Fix packages that broken exporters wrote
+    Repair(Repair),
+    /// Put back the root model part that add --append --backup replaced
+    Undo(Undo),
+    /// Create or update listings on Thingiverse from the metadata of 3MF
+    /// files, and add the URL of each listing to its file
+    #[cfg(feature = "net")]
+    Publish(Publish),
+}
+
+impl Subcommands {
+    /// The name of the subcommand as given on the command line.
+    fn name(&self) -> &'static str {
+        match self {
+            Subcommands::Add(_) => "add",
+            Subcommands::Show(_) => "show",
+            Subcommands::Info(_) => "info",
+            #[cfg(feature = "regex")]
+            Subcommands::Grep(_) => "grep",
+            Subcommands::Get(_) => "get",
+            Subcommands::Catalog(_) => "catalog",
+            Subcommands::Export(_) => "export",
+            Subcommands::Attribution(_) => "attribution",
+            Subcommands::ConvertUnits(_) => "convert-units",
+            Subcommands::Remove(_) => "remove",
+            Subcommands::Check(_) => "check",
+            Subcommands::Patch(_) => "patch",
+            Subcommands::RenameKey(_) => "rename-key",
+            Subcommands::SetObject(_) => "set-object",
+            Subcommands::SetItem(_) => "set-item",
+            Subcommands::ReplaceValue(_) => "replace-value",
+            Subcommands::Anonymize(_) => "anonymize",
+            Subcommands::Apply(_) => "apply",
+            Subcommands::Validate(_) => "validate",
+            Subcommands::Doctor(_) => "doctor",
+            Subcommands::Explain(_) => "explain",
+            Subcommands::Token(_) => "token",
+            Subcommands::SelfTest(_) => "self-test",
+            Subcommands::Promote(_) => "promote",
+            Subcommands::PromoteSlicerMetadata(_) => "promote-slicer-metadata",
+            Subcommands::Repair(_) => "repair",
+            Subcommands::Undo(_) => "undo",
+            #[cfg(feature = "net")]
+            Subcommands::Publish(_) => "publish",
+        }
+    }
+
+    /// Where the results go, for subcommands that report problems.
+    fn report(&self) -> Option<&Report> {
+        match self {
+            Subcommands::Validate(validate) => Some(&validate.report),
+            Subcommands::Check(check) => Some(&check.report),
+            Subcommands::Doctor(doctor) => Some(&doctor.report),
+            _ => None,
+        }
+    }
+
+    /// The file the current input is written to, if any.
+    fn output_path(&self) -> Option<&Path> {
+        match self {
+            Subcommands::Add(add) if !add.stdout_xml => add.output_path.as_deref(),
+            Subcommands::ConvertUnits(convert) => convert.output_path.as_deref(),
+            Subcommands::Remove(remove) => remove.output_path.as_deref(),
+            Subcommands::Patch(patch) => patch.output_path.as_deref(),
+            Subcommands::RenameKey(rename) => rename.output_path.as_deref(),
+            Subcommands::SetObject(set) => set.output_path.as_deref(),
+            Subcommands::SetItem(set) => set.output_path.as_deref(),
+            Subcommands::ReplaceValue(replace) => replace.output_path.as_deref(),
+            Subcommands::Anonymize(anonymize) => anonymize.output_path.as_deref(),
+            Subcommands::Apply(apply) => apply.output_path.as_deref(),
+            Subcommands::PromoteSlicerMetadata(promote) => promote.output_path.as_deref(),
+            Subcommands::Repair(repair) => repair.output_path.as_deref(),
+            Subcommands::Undo(undo) => undo.output_path.as_deref(),
+            #[cfg(feature = "net")]
+            Subcommands::Publish(publish) => publish.output_path.as_deref(),
+            Subcommands::Doctor(doctor) => doctor.output_path.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The suffix and output directory that output files are named with,
+    /// for subcommands that write files named after their input.
+    fn output_naming(&self) -> Option<(&str, Option<&Path>)> {
+        let (suffix, output_args) = match self {
+            Subcommands::Add(add) if !add.stdout_xml && !add.append => {
+                (&add.suffix, &add.output_args)
+            }
+            Subcommands::ConvertUnits(convert) => (&convert.suffix, &convert.output_args),
+            Subcommands::Remove(remove) => (&remove.suffix, &remove.output_args),
+            Subcommands::Patch(patch) => (&patch.suffix, &patch.output_args),
+            Subcommands::RenameKey(rename) => (&rename.suffix, &rename.output_args),
+            Subcommands::SetObject(set) => (&set.suffix, &set.output_args),
+            Subcommands::SetItem(set) => (&set.suffix, &set.output_args),
+            Subcommands::ReplaceValue(replace) => (&replace.suffix, &replace.output_args),
+            Subcommands::Anonymize(anonymize) => (&anonymize.suffix, &anonymize.output_args),
+            Subcommands::Apply(apply) => (&apply.suffix, &apply.output_args),
+            Subcommands::PromoteSlicerMetadata(promote) => (&promote.suffix, &promote.output_args),
+            Subcommands::Repair(repair) => (&repair.suffix, &repair.output_args),
+            Subcommands::Doctor(doctor) if doctor.fix => (&doctor.suffix, &doctor.output_args),
+            _ => return None,
+        };
+        match output_args.output {
+            Some(_) => None,
+            None => Some((suffix, output_args.output_dir.as_deref())),
+        }
+    }
+}
+
+// Where the subcommands that write files named after their input write
+// them. A doc comment here would end up as their about text.
+#[derive(Args, Debug)]
+struct OutputArgs {
+    /// Directory to write output files to, instead of next to the input
+    #[clap(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Output file, instead of one named after the input. Only for a single
+    /// input file
+    #[clap(short, long, value_name = "FILE", conflicts_with = "output-dir")]
+    output: Option<PathBuf>,
 }
 
 #[derive(Args, Debug)]
@@ -33,19 +760,127 @@ struct Add {
     #[clap(short, long, default_value = "_licensed")]
     suffix: String,
 
-    /// File containing the metadata to be added to the 3MF
-    #[clap(short, long, default_value = "metadata.xml")]
-    metadata: OsString,
+    /// File containing the metadata to be added to the 3MF [default:
+    /// metadata.xml, or none with --package-defaults]
+    #[clap(short, long)]
+    metadata: Option<OsString>,
+
+    /// Directory of metadata fragments to add instead of a metadata file:
+    /// its *.xml and *.toml files, merged in the order of their names, with
+    /// the entries of later fragments winning
+    #[clap(long, value_name = "DIR", conflicts_with = "metadata")]
+    metadata_dir: Option<OsString>,
+
+    /// Also add the metadata in the Metadata/defaults.xml part of each 3MF
+    /// file that has one. The metadata file wins when both have an entry
+    #[clap(long)]
+    package_defaults: bool,
 
     /// Whether to keep existing metadata tags when one of the same
     /// name is found in the metadata file.
     #[clap(short, long)]
     keep_existing: bool,
 
+    /// Add this preset of a v2 metadata file along with the entries outside
+    /// of presets
+    #[clap(short, long, value_name = "NAME")]
+    preset: Option<String>,
+
+    /// Add a built-in preset instead of a metadata file: the lowercase SPDX
+    /// id of a license, like cc-by-4.0, or all-rights-reserved
+    #[clap(
+        long,
+        value_name = "NAME",
+        requires = "designer",
+        conflicts_with_all = &["metadata", "metadata-dir", "preset"]
+    )]
+    builtin_preset: Option<BuiltinPreset>,
+
+    /// Designer for --builtin-preset, who is also named in the copyright
+    /// notice
+    #[clap(long, value_name = "NAME", requires = "builtin-preset")]
+    designer: Option<String>,
+
+    /// CSV file from `export --format csv` with metadata for each file, over
+    /// that of the metadata file. Files are found by their id, or by their
+    /// path in rows without one
+    #[clap(long, value_name = "CSV")]
+    manifest: Option<OsString>,
+
     /// Set Title to filename
     #[clap(short, long)]
     title: bool,
 
+    /// Only add this entry of the metadata file, can be given multiple
+    /// times
+    #[clap(long, value_name = "KEY", multiple_occurrences = true)]
+    only: Vec<String>,
+
+    /// Don't add this entry of the metadata file, can be given multiple
+    /// times
+    #[clap(long, value_name = "KEY", multiple_occurrences = true)]
+    skip: Vec<String>,
+
+    /// Set ModificationDate, and CreationDate if there is none yet, to the
+    /// current time, or to SOURCE_DATE_EPOCH if set
+    #[clap(long)]
+    stamp_dates: bool,
+
+    /// Date to stamp instead of the current time, as an RFC 3339 date-time
+    #[clap(long, value_name = "RFC3339", requires = "stamp-dates")]
+    date: Option<String>,
+
+    /// Set an entry to the value of an environment variable, as in
+    /// `Designer=DESIGNER_NAME`. Can be given multiple times
+    #[clap(long, value_name = "KEY=VAR", multiple_occurrences = true)]
+    set_from_env: Vec<String>,
+
+    /// Accept the names other programs use for the well-known names, like
+    /// creator or dc:title, in the metadata file, the manifest, the package
+    /// defaults and --set-from-env, and add them under the well-known name
+    #[clap(long)]
+    aliases: bool,
+
+    /// Also add the metadata to model parts other than the root model part,
+    /// such as the object parts of Production extension packages
+    #[clap(long)]
+    all_model_parts: bool,
+
+    /// What to do when another model part has a name that is set in the
+    /// root model part, with another value: root-wins gives that part the
+    /// value of the root model part, error fails the file, and merge adds
+    /// the metadata to every model part, as --all-model-parts does
+    #[clap(
+        long,
+        value_name = "POLICY",
+        default_value = "root-wins",
+        possible_values = ConflictPolicy::NAMES
+    )]
+    conflicts: ConflictPolicy,
+
+    /// Print the root model XML as it would be written, instead of writing
+    /// an output file
+    #[clap(long, conflicts_with_all = &["output", "output-dir"])]
+    stdout_xml: bool,
+
+    /// Update the input files themselves, by appending the changed root
+    /// model part and a new central directory instead of copying every
+    /// entry. Much faster for packages with large textures; the old part
+    /// stays in the file unused
+    #[clap(
+        long,
+        conflicts_with_all = &["output", "output-dir", "stdout-xml", "all-model-parts"]
+    )]
+    append: bool,
+
+    /// Keep the old root model part under Metadata/backup/<time>/ in the
+    /// package, for the undo subcommand to put back
+    #[clap(long, requires = "append")]
+    backup: bool,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
     /// Force overwrite of existing files
     #[clap(short, long)]
     force: bool,
@@ -64,191 +899,4820 @@ struct Add {
 
     // metadata read from file, also internal only
     #[clap(skip)]
-    metadata_xml: Option<Element>,
+    metadata_set: Option<MetadataSet>,
+
+    // entries of the metadata file for some files only, also internal only
+    #[clap(skip)]
+    conditional: Vec<Conditional>,
+
+    // the date to stamp, also internal only
+    #[clap(skip)]
+    stamp_date: Option<OffsetDateTime>,
+
+    // rows of the manifest, also internal only
+    #[clap(skip)]
+    manifest_rows: Option<Manifest>,
 }
 
 #[derive(Args, Debug)]
 struct Show {
+    /// Print the metadata as XML elements, under the name of their model
+    /// part when a file has several, or with json, an object with the
+    /// metadata of each model part of each file, or with csv, a row per
+    /// metadata entry with its file and model part
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = ShowFormat::NAMES
+    )]
+    format: ShowFormat,
+
+    /// Only print the number of metadata entries and their sorted names
+    /// for each file, without the values
+    #[clap(long, conflicts_with = "format")]
+    summary: bool,
+
+    /// Print long values in full instead of cutting them off, in the text
+    /// format
+    #[clap(long)]
+    full: bool,
+
+    /// Also print the OPC core properties, the archive comment and the
+    /// slicer settings, with every name prefixed by where it comes from:
+    /// model, core, archive or slicer
+    #[clap(long, conflicts_with = "summary")]
+    all_sources: bool,
+
+    /// Print the names other programs use for the well-known names, like
+    /// Author or dc:creator, as the well-known name
+    #[clap(long)]
+    unify_names: bool,
+
+    /// Exit with status 1 when a file has no metadata
+    #[clap(long, conflicts_with = "all-sources")]
+    fail_if_empty: bool,
+
+    /// Exit with status 1 when a file has no metadata with this name, can
+    /// be given more than once
+    #[clap(
+        long,
+        value_name = "NAME",
+        multiple_occurrences = true,
+        conflicts_with = "all-sources"
+    )]
+    fail_if_missing: Vec<String>,
+
     /// Input file(s)
     #[clap(forbid_empty_values = true, required = true)]
     input_files: Vec<OsString>,
-}
 
-fn add_metadata_to_hashmap(metadata_map: &mut HashMap<String, XMLNode>, metadata: &Element) {
-    for child in metadata.children.iter() {
-        match child {
-            XMLNode::Element(element) => {
-                metadata_map.insert(
-                    element.attributes["name"].clone(),
-                    XMLNode::Element(element.clone()),
-                );
-            }
-            _ => panic!("metadata element is not an element"),
-        }
-    }
-}
+    // the file, model part or source, and metadata for json and csv,
+    // internal only
+    #[clap(skip)]
+    parts: RefCell<Vec<(String, String, MetadataSet)>>,
 
-fn update_xml_and_copy<W>(
-    mut file: ZipFile,
-    metadata: &Element,
-    output: &mut ZipWriter<W>,
-    keep_existing: bool,
-    title: &Option<String>,
-) -> bool
-where
-    W: Write + Seek,
-{
-    // these shouldn't fail, because we use enclosed_name() to determine
-    // whether to get here. And enclosed_name returning Some means
-    // to_str() will work.
-    let file_name: String = file.enclosed_name().unwrap().to_str().unwrap().to_string();
-
-    let mut xml = Element::parse(&mut file).unwrap();
-
-    // move xml's children to temporary vec.
-    let mut children: Vec<XMLNode> = Vec::new();
-    children.append(&mut xml.children);
-
-    // add all metadata elements in xml to a hashmap, then add the metadata
-    // elements as well, overwriting any existing metadata. Or vice versa
-    // if keep_existing is true.
-    let mut metadata_map: HashMap<String, XMLNode> = HashMap::new();
-    // if we keep the existing metadata, add the new metadata to the map first.
-    if keep_existing {
-        add_metadata_to_hashmap(&mut metadata_map, metadata)
-    }
-    // put metadata children into the hashmap, add everything else to a vec
-    // to be added to the xml after the metadata.
-    let other_elements: Vec<_> = children
-        .into_iter()
-        .filter_map(|child| match child {
-            XMLNode::Element(element) if element.name == "metadata" => {
-                metadata_map.insert(
-                    element.attributes["name"].clone(),
-                    XMLNode::Element(element),
-                );
-                None
-            }
-            _ => Some(child),
-        })
-        .collect();
-    // if we don't keep the existing metadata, add the new metadata to the map last.
-    if !keep_existing {
-        add_metadata_to_hashmap(&mut metadata_map, metadata)
-    }
-    // Set title if requested
-    if let Some(title) = title {
-        eprintln!("setting title to {}", title);
-        // make a new element with the title
-        let mut title_element = Element::new("metadata");
-        title_element.attributes.insert("name".to_string(), "Title".to_string());
-        title_element.children.push(XMLNode::Text(title.clone()));
-        metadata_map.insert("Title".to_string(), XMLNode::Element(title_element));
-    }
-
-    // now add the hashmap to the xml.
-    for node in metadata_map.into_values() {
-        xml.children.push(node);
-    }
-    // and add the other elements to the xml.
-    xml.children.extend(other_elements);
-
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .compression_level(Some(9));
-    output.start_file(&file_name, options).unwrap();
-    let config = EmitterConfig::new()
-        .perform_indent(true)
-        .indent_string("\t")
-        .line_separator("\n");
-    xml.write_with_config(output, config).unwrap();
-    eprintln!("Added metadata to file {}", file_name);
-    true
+    // number of files that failed --fail-if-empty or --fail-if-missing,
+    // internal only
+    #[clap(skip)]
+    failures: Cell<usize>,
 }
 
-fn show_metadata(file: ZipFile) {
-    // Like above, should not fail
-    let file_name: String = file.enclosed_name().unwrap().to_str().unwrap().to_string();
+#[cfg(feature = "regex")]
+#[derive(Args, Debug)]
+struct Grep {
+    /// Regular expression to search the metadata values for
+    pattern: String,
 
-    let xml = Element::parse(file).unwrap();
-    let metadata = xml
-        .children
-        .into_iter()
-        .filter_map(|child| match child {
-            XMLNode::Element(mut element) => {
-                if element.name == "metadata" {
-                    element.namespace = None;
-                    element.namespaces = None;
-                    Some(element)
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        })
-        .collect::<Vec<_>>();
-    if metadata.is_empty() {
-        eprintln!("No metadata found in file {}", file_name);
-    } else {
-        eprintln!("Metadata found in file {}:", file_name);
-        let config = EmitterConfig::new()
-            .perform_indent(true)
-            .indent_string("\t")
-            .line_separator("\n")
-            .write_document_declaration(false);
-        for element in metadata {
-            element.write_with_config(stdout(), config.clone()).unwrap();
-            println!();
-        }
-    }
-}
+    /// Search for the pattern as plain text
+    #[clap(short = 'F', long)]
+    fixed_strings: bool,
 
-fn main() {
-    let mut cli = Cli::parse();
-    //eprintln!("{:?}", args);
+    /// Ignore case when matching
+    #[clap(short, long)]
+    ignore_case: bool,
 
-    if let Subcommands::Add(ref mut add) = cli.subcommand {
-        // read metadata file
-        let metadata = BufReader::new(File::open(&add.metadata).unwrap());
-        let metadata =
-            Element::parse(metadata).unwrap_or_else(|_| panic!("Could not parse metadata file"));
-        if metadata.name != "v1" {
-            eprintln!("Metadata file is not a v1 file");
-            std::process::exit(1);
-        }
-        if metadata.children.iter().any(|child| match child {
-            XMLNode::Element(element) => element.name != "metadata",
-            _ => true,
-        }) {
-            eprintln!(
-                "Metadata file contains XML elements other than v1 and its metadata children"
-            );
-            std::process::exit(1);
-        }
-        if !metadata.children.iter().any(|child| match child {
-            XMLNode::Element(element) => element.name == "metadata",
-            _ => false,
-        }) {
-            eprintln!("Metadata file has no metadata elements");
-            std::process::exit(1);
-        }
-        add.metadata_xml = Some(metadata);
-    }
+    /// Also search the names of the objects in the models
+    #[clap(long)]
+    object_names: bool,
 
-    let input_files = match cli.subcommand {
-        Subcommands::Add(ref add) => &add.input_files,
-        Subcommands::Show(ref show) => &show.input_files,
-    };
+    /// Highlight matches: auto only does when printing to a terminal,
+    /// NO_COLOR isn't set and --non-interactive isn't given
+    #[clap(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        possible_values = ["auto", "always", "never"]
+    )]
+    color: String,
 
-    #[cfg(windows)]
-    let expanded_input_files = input_files
-        .iter()
-        .flat_map(|file_name| {
-            if let Some(file_name) = file_name.to_str() {
-                glob::glob(file_name)
-                    .unwrap_or_else(|_| panic!("Could not glob {}", file_name))
-                    .map(|path| path.unwrap())
-                    .collect::<Vec<_>>()
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // the compiled pattern, internal only
+    #[clap(skip)]
+    regex: Option<Regex>,
+
+    // whether to highlight matches, internal only
+    #[clap(skip)]
+    highlight: bool,
+
+    // number of matches over all files, internal only
+    #[clap(skip)]
+    matches: Cell<usize>,
+}
+
+#[derive(Args, Debug)]
+struct Get {
+    /// Name of the metadata entry, from the root model part
+    name: String,
+
+    /// Print the value without a line break after it
+    #[clap(long)]
+    raw: bool,
+
+    /// Decode the value from base64 and print the bytes, for binary data
+    /// that programs store in metadata
+    #[clap(long, conflicts_with = "raw")]
+    decode_base64: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // number of files without a value that could be printed, internal only
+    #[clap(skip)]
+    failures: Cell<usize>,
+}
+
+#[derive(Args, Debug)]
+struct Catalog {
+    /// HTML file to write
+    #[clap(short, long, value_name = "FILE", default_value = "catalog.html")]
+    output: PathBuf,
+
+    /// Heading of the page
+    #[clap(long, default_value = "3MF catalog")]
+    title: String,
+
+    /// Overwrite the HTML file if it exists
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s) or directories to look for 3MF files in
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // directory of the HTML file, that links are relative to, internal only
+    #[clap(skip)]
+    base: PathBuf,
+
+    // cards of the files read so far, internal only
+    #[clap(skip)]
+    cards: RefCell<Vec<Card>>,
+}
+
+#[derive(Args, Debug)]
+struct Export {
+    /// Print an object with the metadata of the root model part of each
+    /// file, with jsonld, schema.org structured data to embed in web pages,
+    /// or with csv, a manifest with a row per file for `add --manifest`
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "json",
+        possible_values = ExportFormat::NAMES
+    )]
+    format: ExportFormat,
+
+    /// Print the metadata of all model parts as sorted text that only
+    /// changes when the metadata does, for golden files in tests
+    #[clap(long, conflicts_with = "format")]
+    canonical: bool,
+
+    /// Give LicenseTerms as an object in the json format, with the SPDX
+    /// license expression it parses as and the licenses in it, with their
+    /// names, URLs and whether the OSI approved them and the FSF lists them
+    /// as free
+    #[clap(long, conflicts_with = "canonical")]
+    spdx: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // what was read of each file, internal only
+    #[clap(skip)]
+    documents: RefCell<Vec<(String, serde_json::Value)>>,
+
+    // canonical text of each file, internal only
+    #[clap(skip)]
+    texts: RefCell<Vec<(String, String)>>,
+
+    // rows of the manifest for csv, internal only
+    #[clap(skip)]
+    rows: RefCell<Vec<Row>>,
+}
+
+#[derive(Args, Debug)]
+struct Attribution {
+    /// Style of the attribution: cc is the title, the author and the license,
+    /// as Creative Commons asks for, like "Title by Author is licensed under
+    /// CC BY 4.0"
+    #[clap(
+        long,
+        value_name = "STYLE",
+        default_value = "cc",
+        possible_values = ["cc"]
+    )]
+    style: String,
+
+    /// Print the attribution as text with the URLs in parentheses, or with
+    /// the title and the license linked in Markdown or HTML
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = attribution::Format::NAMES
+    )]
+    format: attribution::Format,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // number of files without an attribution, internal only
+    #[clap(skip)]
+    failures: Cell<usize>,
+}
+
+#[derive(Args, Debug)]
+struct Explain {
+    /// Metadata name to describe, instead of listing them all
+    name: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct Token {
+    /// Service to store the token of. The token is read from standard
+    /// input, so that it doesn't end up in shell history
+    #[clap(possible_values = Service::NAMES, required_unless_present = "list")]
+    service: Option<Service>,
+
+    /// Remove the stored token of the service instead
+    #[clap(long)]
+    remove: bool,
+
+    /// Store the token in the system keyring instead of the credentials file
+    #[cfg(feature = "keyring")]
+    #[clap(long, conflicts_with = "remove")]
+    keyring: bool,
+
+    /// List where the token of each service comes from
+    #[clap(long, conflicts_with_all = &["service", "remove"])]
+    list: bool,
+}
+
+#[derive(Args, Debug)]
+struct SelfTest {
+    /// Directory to make the test files in, such as the one with the 3MF
+    /// files, to check that files can be written there [default: the
+    /// temporary directory of the system]
+    #[clap(long, value_name = "DIR")]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Promote {
+    /// Suffix of the outputs that replace the input files
+    #[clap(short, long, default_value = "_licensed")]
+    suffix: String,
+
+    /// Delete the input files once their outputs have replaced them
+    #[clap(long, required_unless_present = "keep-originals")]
+    delete_originals: bool,
+
+    /// Keep the input files, renamed to end with .orig
+    #[clap(long, conflicts_with = "delete-originals")]
+    keep_originals: bool,
+
+    /// Force overwrite of existing .orig files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s), or directories to find them in
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+}
+
+#[derive(Args, Debug)]
+struct PromoteSlicerMetadata {
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_slicer")]
+    suffix: String,
+
+    /// Namespace prefix of the metadata names
+    #[clap(long, default_value = slicer::DEFAULT_PREFIX)]
+    prefix: String,
+
+    /// Whether to keep existing metadata tags when one of the same
+    /// name comes from the slicer settings.
+    #[clap(short, long)]
+    keep_existing: bool,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Repair {
+    /// Keep only one of the entries that share a name, which broken
+    /// exporters write. Readers disagree on which of them counts
+    #[clap(long)]
+    dedupe_entries: bool,
+
+    /// Which of the entries with the same name --dedupe-entries keeps: last
+    /// is the one most ZIP readers, slicers included, read
+    #[clap(
+        long,
+        value_name = "ENTRY",
+        default_value = "last",
+        possible_values = KeepEntry::NAMES
+    )]
+    keep: KeepEntry,
+
+    /// Scale PNG thumbnails to this size, for slicers that want their own,
+    /// such as 512x512 for Bambu Studio
+    #[cfg(feature = "thumbnails")]
+    #[clap(long, value_name = "WxH")]
+    thumbnail_size: Option<Size>,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_repaired")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[cfg(feature = "net")]
+#[derive(Args, Debug)]
+struct Publish {
+    /// Site to publish to. Printables has no public API for listings
+    #[clap(
+        long,
+        value_name = "SERVICE",
+        default_value = "thingiverse",
+        possible_values = ["thingiverse"]
+    )]
+    to: Service,
+
+    /// API token, instead of the one in the environment or stored with
+    /// `token`. A token given here ends up in shell history
+    #[clap(long)]
+    token: Option<String>,
+
+    /// Base URL of the API, instead of that of the site
+    #[clap(long, value_name = "URL", default_value = publish::THINGIVERSE_API)]
+    api_url: String,
+
+    /// Input file(s), which are updated in place
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+
+    // the token from wherever it was found, also internal only
+    #[clap(skip)]
+    found_token: Option<credentials::Token>,
+}
+
+#[derive(Args, Debug)]
+struct Undo {
+    /// Input file(s), which are updated in place
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Validate {
+    /// Also check the model parts against the schemas of the 3MF core and its
+    /// common extensions, reporting the line and column of each problem
+    #[clap(long)]
+    schema: bool,
+
+    /// Also want a LicenseTerms that combines SPDX licenses with AND, OR,
+    /// WITH or parentheses to be a valid SPDX license expression, with the
+    /// operators in upper case
+    #[clap(long)]
+    spdx: bool,
+
+    #[clap(flatten)]
+    report: Report,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+}
+
+#[derive(Args, Debug)]
+struct Doctor {
+    /// Apply the safe fixes, writing each file with them to an output file.
+    /// Problems that need a decision are only described
+    #[clap(long)]
+    fix: bool,
+
+    /// File with the metadata every input file must have, with the same
+    /// values, as for check
+    #[clap(long, value_name = "METADATA")]
+    against: Option<OsString>,
+
+    /// Also check the model parts against the schemas of the 3MF core and its
+    /// common extensions
+    #[clap(long)]
+    schema: bool,
+
+    /// Want a LicenseTerms that combines SPDX licenses with AND, OR,
+    /// WITH or parentheses to be a valid SPDX license expression, with the
+    /// operators in upper case
+    #[clap(long)]
+    spdx: bool,
+
+    #[clap(flatten)]
+    report: Report,
+
+    /// Suffix for output filename, with --fix
+    #[clap(short, long, default_value = "_fixed")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // metadata read from file, internal only
+    #[clap(skip)]
+    metadata_set: Option<MetadataSet>,
+
+    // entries of the metadata file for some files only, also internal only
+    #[clap(skip)]
+    conditional: Vec<Conditional>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Check {
+    /// File with the metadata every input file must have, with the same
+    /// values
+    #[clap(long, value_name = "METADATA")]
+    against: OsString,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    #[clap(flatten)]
+    report: Report,
+
+    // metadata read from file, internal only
+    #[clap(skip)]
+    metadata_set: Option<MetadataSet>,
+
+    // entries of the metadata file for some files only, also internal only
+    #[clap(skip)]
+    conditional: Vec<Conditional>,
+}
+
+#[derive(Args, Debug)]
+struct Info {
+    /// Also print the objects of each model part as a tree, with the
+    /// components each is assembled from, their transforms, and the names
+    /// and part numbers of all of them
+    #[clap(long)]
+    objects: bool,
+
+    /// Also print the items of the build of each model part, with the
+    /// object each places, its transform, part number and the model part
+    /// the object is in
+    #[clap(long)]
+    items: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+}
+
+#[derive(Args, Debug)]
+struct ConvertUnits {
+    /// Unit to convert to
+    #[clap(long, possible_values = Unit::NAMES)]
+    to: Unit,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_converted")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Remove {
+    /// Name of a metadata entry to remove, can be given multiple times
+    #[clap(short, long = "key", required = true, multiple_occurrences = true)]
+    keys: Vec<String>,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_stripped")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct Patch {
+    /// The patch: an object as in RFC 7386, with `null` for entries to
+    /// remove, or an array of RFC 6902 operations on paths like `/Title`
+    #[clap(
+        short,
+        long,
+        value_name = "JSON",
+        required_unless_present = "patch-file",
+        conflicts_with = "patch-file"
+    )]
+    patch: Option<String>,
+
+    /// File to read the patch from
+    #[clap(long, value_name = "FILE")]
+    patch_file: Option<PathBuf>,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_patched")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+
+    // the parsed patch, also internal only
+    #[clap(skip)]
+    parsed: Option<patch::Patch>,
+}
+
+#[derive(Args, Debug)]
+struct RenameKey {
+    /// Name of the metadata entry to rename
+    #[clap(long, value_name = "KEY")]
+    from: String,
+
+    /// New name of the metadata entry
+    #[clap(long, value_name = "KEY")]
+    to: String,
+
+    /// What to do when a file already has an entry with the new name: leave
+    /// both, replace it with the renamed entry, or keep it and drop the old
+    /// one
+    #[clap(long, default_value = "skip", possible_values = RenameConflict::NAMES)]
+    on_conflict: RenameConflict,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_renamed")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct SetObject {
+    /// Id of the object to change
+    #[clap(long, value_name = "ID")]
+    id: String,
+
+    /// Model part the object is in [default: the root model part]
+    #[clap(long, value_name = "PART")]
+    part: Option<String>,
+
+    /// New name of the object, or an empty one to remove it
+    #[clap(long)]
+    name: Option<String>,
+
+    /// New part number of the object, which BOM and ERP systems read, or an
+    /// empty one to remove it
+    #[clap(long, value_name = "PARTNUMBER")]
+    partnumber: Option<String>,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_objects")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct SetItem {
+    /// Number of the build item to change, counting from 1 as `info --items`
+    /// does
+    #[clap(long, value_name = "N")]
+    item: usize,
+
+    /// Model part the build is in [default: the root model part]
+    #[clap(long, value_name = "PART")]
+    part: Option<String>,
+
+    /// Id of the object the item places
+    #[clap(long, value_name = "ID")]
+    objectid: Option<String>,
+
+    /// New transform of the item, 12 numbers separated by spaces, or an empty
+    /// one to remove it
+    #[clap(long, value_name = "MATRIX")]
+    transform: Option<String>,
+
+    /// New part number of the item, or an empty one to remove it
+    #[clap(long, value_name = "PARTNUMBER")]
+    partnumber: Option<String>,
+
+    /// Model part the object is in, as the `p:path` of the production
+    /// extension, or an empty one to remove it
+    #[clap(long, value_name = "PART")]
+    path: Option<String>,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_items")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ReplaceValue {
+    /// Name of a metadata entry to replace text in, can be given multiple
+    /// times
+    #[clap(short, long = "key", required = true, multiple_occurrences = true)]
+    keys: Vec<String>,
+
+    /// Text to find in the values
+    #[clap(long, value_name = "TEXT")]
+    find: String,
+
+    /// Text to replace every occurrence with. With --regex, `$1` or `${name}`
+    /// insert what a group matched
+    #[clap(long, value_name = "TEXT")]
+    replace: String,
+
+    /// Take --find as a regular expression
+    #[cfg(feature = "regex")]
+    #[clap(long)]
+    regex: bool,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_replaced")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+
+    // the compiled --find pattern, also internal only
+    #[cfg(feature = "regex")]
+    #[clap(skip)]
+    pattern: Option<Regex>,
+
+    // number of files with changed values, internal only
+    #[clap(skip)]
+    changed_files: Cell<usize>,
+}
+
+impl ReplaceValue {
+    #[cfg(feature = "regex")]
+    fn replace_in(&self, value: &str) -> String {
+        let pattern = self.pattern.as_ref().unwrap();
+        if self.regex {
+            pattern.replace_all(value, self.replace.as_str())
+        } else {
+            pattern.replace_all(value, NoExpand(&self.replace))
+        }
+        .into_owned()
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn replace_in(&self, value: &str) -> String {
+        value.replace(&self.find, &self.replace)
+    }
+}
+
+#[derive(Args, Debug)]
+struct Anonymize {
+    /// Also remove this metadata entry, can be given multiple times
+    #[clap(long, value_name = "KEY", multiple_occurrences = true)]
+    also: Vec<String>,
+
+    /// Keep this metadata entry after all, can be given multiple times
+    #[clap(long, value_name = "KEY", multiple_occurrences = true)]
+    except: Vec<String>,
+
+    /// Keep the slicer settings parts, which name printers, profiles and
+    /// print hosts
+    #[clap(long)]
+    keep_slicer_settings: bool,
+
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_anonymized")]
+    suffix: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+
+    // the default keys and --also, also internal only
+    #[clap(skip)]
+    keys: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct Apply {
+    /// Suffix for output filename
+    #[clap(short, long, default_value = "_licensed")]
+    suffix: String,
+
+    /// Config file with the presets and the rules that pick them, instead of
+    /// ~/.config/metadata_3mf/config (%APPDATA%\metadata_3mf\config on
+    /// Windows)
+    #[clap(long, value_name = "FILE", env = "METADATA_3MF_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Whether to keep existing metadata tags when one of the same
+    /// name is in the preset.
+    #[clap(short, long)]
+    keep_existing: bool,
+
+    /// Also add the preset to model parts other than the root model part,
+    /// such as the object parts of Production extension packages
+    #[clap(long)]
+    all_model_parts: bool,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Force overwrite of existing files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Input file(s)
+    #[clap(forbid_empty_values = true, required = true)]
+    input_files: Vec<OsString>,
+
+    // output file is just used internally
+    #[clap(skip)]
+    output_path: Option<PathBuf>,
+
+    // the parsed config file, also internal only
+    #[clap(skip)]
+    settings: Config,
+
+    // presets read so far, by name, with their conditional entries, also
+    // internal only
+    #[clap(skip)]
+    presets: Vec<(String, MetadataSet, Vec<Conditional>)>,
+
+    // the preset for the current file, also internal only
+    #[clap(skip)]
+    preset: Option<String>,
+}
+
+impl Add {
+    fn metadata_path(&self) -> &OsStr {
+        self.metadata
+            .as_deref()
+            .or(self.metadata_dir.as_deref())
+            .unwrap_or_else(|| OsStr::new("metadata.xml"))
+    }
+
+    /// The metadata to add to `package`: that of the package defaults part,
+    /// if asked for, with the metadata given on the command line over it,
+    /// and the row of the manifest over that.
+    fn metadata_for<R: Read + Seek>(
+        &self,
+        package: &mut Package<R>,
+        input_path: &Path,
+    ) -> Result<MetadataSet> {
+        let mut metadata = match self.package_defaults {
+            true => package::read_package_defaults(package)?.unwrap_or_default(),
+            false => MetadataSet::new(),
+        };
+        if self.aliases {
+            metadata.unalias();
+        }
+        let included = |entry: &MetadataEntry| {
+            (self.only.is_empty() || self.only.contains(&entry.name))
+                && !self.skip.contains(&entry.name)
+        };
+        metadata.retain(included);
+        let existing = match self.conditional.is_empty() {
+            true => MetadataSet::new(),
+            false => package::read_root_metadata(package)?,
+        };
+        metadata.extend(with_conditional(
+            self.metadata_set.as_ref().unwrap(),
+            &self.conditional,
+            &existing,
+            input_path,
+        ));
+        if let Some(manifest) = &self.manifest_rows {
+            let id = manifest::file_id(package)?;
+            match manifest.find_file(&id, input_path) {
+                Some(row) => metadata.extend(row.metadata.iter().filter(|e| included(e)).cloned()),
+                None => eprintln!(
+                    "Warning: the manifest has no row for {}",
+                    input_path.display()
+                ),
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+impl Apply {
+    /// The config file given, or else the default one.
+    fn config_path(&self) -> Option<PathBuf> {
+        if let Some(config) = &self.config {
+            return Some(config.clone());
+        }
+        Some(config::config_dir()?.join("config"))
+    }
+
+    /// Picks the preset for `input_path`, reading it the first time it is
+    /// used. Returns false when no rule matches.
+    fn select_preset(&mut self, input_path: &Path, error_format: ErrorFormat) -> bool {
+        let input_path = fs::canonicalize(input_path).unwrap_or_else(|_| input_path.to_path_buf());
+        let Some(name) = self.settings.preset_for(&input_path) else {
+            return false;
+        };
+        if !self.presets.iter().any(|(preset, ..)| preset == name) {
+            let (metadata, conditional) = read_metadata_file(
+                self.settings.preset(name).unwrap().as_os_str(),
+                None,
+                error_format,
+            );
+            self.presets.push((name.to_string(), metadata, conditional));
+        }
+        self.preset = Some(name.to_string());
+        true
+    }
+
+    /// The metadata of the preset for `input_path`, with the conditional
+    /// entries that apply to it.
+    fn metadata_for<R: Read + Seek>(
+        &self,
+        package: &mut Package<R>,
+        input_path: &Path,
+    ) -> Result<MetadataSet> {
+        let (_, metadata, conditional) = self.current_preset();
+        let existing = match conditional.is_empty() {
+            true => MetadataSet::new(),
+            false => package::read_root_metadata(package)?,
+        };
+        Ok(with_conditional(
+            metadata,
+            conditional,
+            &existing,
+            input_path,
+        ))
+    }
+
+    fn current_preset(&self) -> &(String, MetadataSet, Vec<Conditional>) {
+        let preset = self.preset.as_ref().unwrap();
+        self.presets
+            .iter()
+            .find(|(name, ..)| name == preset)
+            .unwrap()
+    }
+}
+
+/// Why an input file was not processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Skip {
+    Symlink,
+    SameFile,
+    Done,
+    NoRule,
+    OutputName,
+    HasMetadata,
+    NoOutput,
+}
+
+impl Skip {
+    /// The reason in porcelain lines and the JSON summary of the run.
+    fn code(self) -> &'static str {
+        match self {
+            Skip::Symlink => "symlink",
+            Skip::SameFile => "same-file",
+            Skip::Done => "done",
+            Skip::NoRule => "no-rule",
+            Skip::OutputName => "output-name",
+            Skip::HasMetadata => "has-metadata",
+            Skip::NoOutput => "no-output",
+        }
+    }
+
+    /// What the files skipped for the reason are, after their number.
+    fn description(self) -> &'static str {
+        match self {
+            Skip::Symlink => "symbolic links",
+            Skip::SameFile => "the same file as an earlier input",
+            Skip::Done => "listed as done in the state file",
+            Skip::NoRule => "matched by no rule in the config file",
+            Skip::OutputName => "outputs of an earlier run, going by their name",
+            Skip::HasMetadata => "outputs of an earlier run, as they have the metadata",
+            Skip::NoOutput => "without an output to replace them with",
+        }
+    }
+}
+
+/// Reports on stderr which file is being processed, and how that went, and
+/// keeps count for the summary of the run.
+#[derive(Debug, Default)]
+struct Progress {
+    tally: Tally,
+    total: AtomicUsize,
+    // the files that failed, with their error
+    failures: Mutex<Vec<(String, String)>>,
+    // the files that were skipped, with why
+    skipped: Mutex<Vec<(String, Skip)>>,
+    error_format: ErrorFormat,
+}
+
+impl Progress {
+    /// Reports that the file is skipped, `because` of what, and keeps it for
+    /// the summary of the run.
+    fn skip(&self, path: &Path, reason: Skip, because: impl std::fmt::Display, porcelain: bool) {
+        eprintln!("Skipping {}, because {}", path.display(), because);
+        if porcelain {
+            porcelain_line(path, "skipped", &[reason.code()]);
+        }
+        self.skipped
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push((path.to_string_lossy().into_owned(), reason));
+    }
+
+    /// Prints how many files were skipped for each reason.
+    fn report_skipped(&self) {
+        let skipped = self
+            .skipped
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if skipped.is_empty() {
+            return;
+        }
+        let mut counts = BTreeMap::new();
+        for (_, reason) in skipped.iter() {
+            *counts.entry(*reason).or_insert(0) += 1;
+        }
+        eprintln!("Skipped {} files:", skipped.len());
+        for (reason, count) in counts {
+            eprintln!("  {} {}", count, reason.description());
+        }
+    }
+}
+
+impl ProcessObserver for Progress {
+    fn started(&self, path: &Path, index: usize, total: usize) {
+        ProcessObserver::started(&self.tally, path, index, total);
+        self.total.store(total, Ordering::Relaxed);
+        eprintln!("Processing {}", path.display());
+    }
+
+    fn warning(&self, path: Option<&Path>, message: &str) {
+        self.tally.warning(path, message);
+        eprintln!("Warning: {}", message);
+    }
+
+    fn finished(&self, path: &Path, outcome: std::result::Result<(), &Error>) {
+        self.tally.finished(path, outcome);
+        if let Err(error) = outcome {
+            self.failures
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .push((path.to_string_lossy().into_owned(), error.to_string()));
+        }
+        match outcome {
+            Ok(()) => (),
+            Err(Error::Cancelled) => eprintln!(
+                "Stopped processing {}, nothing was written for it",
+                path.display()
+            ),
+            Err(error) => print_error(
+                self.error_format,
+                &format!("Error processing {}", path.display()),
+                path,
+                error,
+            ),
+        }
+    }
+}
+
+/// Prints an error about `file` in the format of --error-format, after
+/// `heading` for people.
+fn print_error(format: ErrorFormat, heading: &str, file: &Path, error: &Error) {
+    match format {
+        ErrorFormat::Human => {
+            eprintln!("{}: {}", heading, error);
+            if let Error::Syntax(syntax) = error {
+                if !syntax.excerpt.is_empty() {
+                    // A tab would move the marker away from the column
+                    eprintln!("  {}", syntax.excerpt.replace('\t', " "));
+                    eprintln!("  {:>1$}", "^", syntax.excerpt_column);
+                }
+            }
+        }
+        ErrorFormat::Json => {
+            let mut json = serde_json::json!({
+                "file": file.to_string_lossy(),
+                "error": error.to_string(),
+            });
+            if let Error::Syntax(syntax) = error {
+                json["line"] = syntax.line.into();
+                json["column"] = syntax.column.into();
+                json["offset"] = syntax.offset.into();
+                json["excerpt"] = syntax.excerpt.clone().into();
+                json["message"] = syntax.message.clone().into();
+            }
+            eprintln!("{}", json);
+        }
+    }
+}
+
+/// Exit code after Ctrl-C, the one shells give a process that SIGINT ended.
+const INTERRUPTED: i32 = 130;
+
+/// Exit code after SIGTERM, the one shells give a process that it ended.
+const TERMINATED: i32 = 143;
+
+/// The exit code of a cancelled run, that of the signal that cancelled it.
+static CANCELLED: AtomicI32 = AtomicI32::new(INTERRUPTED);
+
+/// Cancels `token` on the first Ctrl-C, which rolls back the file being
+/// processed and stops the run after it. A second Ctrl-C ends the program
+/// right away. SIGTERM, which container runtimes stop programs with, does
+/// the same, with its own exit code.
+#[cfg(unix)]
+fn cancel_on_interrupt(token: CancellationToken) {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    for (signal, code) in [(SIGINT, INTERRUPTED), (SIGTERM, TERMINATED)] {
+        let token = token.clone();
+        let handler = move || {
+            if token.is_cancelled() {
+                signal_hook::low_level::exit(code);
+            }
+            CANCELLED.store(code, Ordering::SeqCst);
+            token.cancel();
+        };
+        // The handler only stores atomics or exits, which is safe in a
+        // signal handler
+        let registered = unsafe { signal_hook::low_level::register(signal, handler) };
+        if let Err(error) = registered {
+            eprintln!("Warning: Ctrl-C will not stop cleanly: {}", error);
+        }
+    }
+}
+
+#[cfg(windows)]
+fn cancel_on_interrupt(token: CancellationToken) {
+    use std::sync::OnceLock;
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+    unsafe extern "system" fn handler(_ctrl_type: u32) -> windows_sys::core::BOOL {
+        match TOKEN.get() {
+            Some(token) if !token.is_cancelled() => {
+                token.cancel();
+                1
+            }
+            // The default handler ends the program
+            _ => 0,
+        }
+    }
+
+    if TOKEN.set(token).is_ok() && unsafe { SetConsoleCtrlHandler(Some(handler), 1) } == 0 {
+        eprintln!("Warning: Ctrl-C will not stop cleanly");
+    }
+}
+
+/// Time spent in the phases of processing one file, reported by `--timing`.
+#[derive(Debug, Default)]
+struct Timings {
+    /// Decompressing and parsing model parts
+    parse: Duration,
+    /// Changing the parsed models
+    rewrite: Duration,
+    /// Serializing and compressing the changed models
+    write: Duration,
+}
+
+impl Timings {
+    fn measure<T>(phase: &mut Duration, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        *phase += start.elapsed();
+        result
+    }
+}
+
+/// How changed model parts are written to the output.
+#[derive(Clone, Copy, Debug)]
+struct PartWriter {
+    threads: usize,
+    compat: Compat,
+    memory_limit: Option<u64>,
+}
+
+impl PartWriter {
+    /// Reads a model part for a metadata change. With a memory limit, only
+    /// the start with the metadata is read, and the rest is left in `file`
+    /// for [`PartWriter::write_edit`] to copy.
+    fn read_part(&self, file: &mut ZipFile) -> Result<Vec<u8>> {
+        match self.memory_limit {
+            Some(limit) => package::read_model_prefix(file, limit),
+            None => package::read_model_part(file),
+        }
+    }
+
+    /// Prepares the change of the metadata of a part read by
+    /// [`PartWriter::read_part`]. The start of a part can't be parsed as a
+    /// whole, so a part laid out in a way that needs that is refused with a
+    /// memory limit.
+    fn edit<'a>(&self, file_name: &str, bytes: &'a [u8]) -> Result<model::MetadataEdit<'a>> {
+        match self.memory_limit {
+            Some(limit) => match model::MetadataPatch::new(bytes)? {
+                Some(patch) => Ok(model::MetadataEdit::Patch(patch)),
+                None => Err(Error::TooLarge {
+                    part: file_name.to_string(),
+                    limit,
+                }),
+            },
+            None => model::MetadataEdit::new(bytes),
+        }
+    }
+
+    /// Writes the changed part, followed by what [`PartWriter::read_part`]
+    /// left of it in `file`, with the compression method of `file`.
+    fn write_edit<W: Write + Seek>(
+        &self,
+        output: &mut ZipWriter<W>,
+        file_name: &str,
+        edit: &mut model::MetadataEdit,
+        file: &mut ZipFile,
+    ) -> Result<()> {
+        let method = file.compression();
+        package::write_model_part_as(output, file_name, method, self.threads, |writer| {
+            edit.write_with(&mut *writer, self.compat)?;
+            io::copy(file, writer)?;
+            Ok(())
+        })
+    }
+
+    /// Writes the model, with the compression method `method` of the part it
+    /// was read from.
+    fn write_model<W: Write + Seek>(
+        &self,
+        output: &mut ZipWriter<W>,
+        file_name: &str,
+        method: CompressionMethod,
+        xml: &mut Element,
+    ) -> Result<()> {
+        package::write_model_part_as(output, file_name, method, self.threads, |writer| {
+            model::write_model_with(xml, writer, self.compat)
+        })
+    }
+}
+
+/// Merges `metadata` into a model, and sets the title and dates if `add`
+/// asks for them.
+fn merge_add(xml: &mut Element, metadata: &MetadataSet, add: &Add) -> Result<()> {
+    model::merge_metadata(xml, metadata, add.keep_existing);
+    // Set title if requested
+    if let Some(title) = &add.title_value {
+        let title = MetadataSet::builder().title(title.clone()).build()?;
+        model::merge_metadata(xml, &title, false);
+    }
+    if let Some(date) = add.stamp_date {
+        let mut metadata = MetadataSet::from_model(xml);
+        if !metadata.contains(well_known::CREATION_DATE) {
+            metadata.set_creation_date(date)?;
+        }
+        metadata.set_modification_date(date)?;
+        model::set_metadata(xml, &metadata);
+    }
+    Ok(())
+}
+
+/// The names that adding `metadata` changes in the root model part, which
+/// another model part has with another value. The root model part comes
+/// first in the values of each, with the value it gets.
+fn add_conflicts<R: Read + Seek>(
+    package: &mut Package<R>,
+    root: &str,
+    metadata: &MetadataSet,
+    add: &Add,
+) -> Result<Vec<Conflict>> {
+    if package.model_part_names()?.len() < 2 {
+        return Ok(Vec::new());
+    }
+    let mut parts = package::read_model_parts_metadata(package)?;
+    let Some(index) = parts.iter().position(|(name, _)| name == root) else {
+        return Ok(Vec::new());
+    };
+    let (_, before) = parts.remove(index);
+    // Only the metadata of the model is needed to see what add makes of it
+    let mut xml = Element::new("model");
+    model::set_metadata(&mut xml, &before);
+    merge_add(&mut xml, metadata, add)?;
+    let after = MetadataSet::from_model(&xml);
+    let diff = before.diff(&after);
+    let changed: Vec<_> = diff
+        .added
+        .iter()
+        .map(|entry| &entry.name)
+        .chain(diff.changed.iter().map(|change| &change.new.name))
+        .collect();
+    parts.insert(0, (root.to_string(), after));
+    Ok(metadata::conflicts(&parts)
+        .into_iter()
+        .filter(|conflict| changed.contains(&&conflict.name))
+        .collect())
+}
+
+/// The entries each other model part gets from the root model part with
+/// `add --conflicts root-wins`, from [`add_conflicts`].
+fn root_wins_updates(conflicts: &[Conflict]) -> Vec<(String, MetadataSet)> {
+    let mut updates: Vec<(String, MetadataSet)> = Vec::new();
+    for conflict in conflicts {
+        let (_, root_value) = &conflict.values[0];
+        for (part, value) in &conflict.values[1..] {
+            if value == root_value {
+                continue;
+            }
+            let entry = MetadataEntry::new(conflict.name.as_str(), root_value.as_str());
+            match updates.iter_mut().find(|(name, _)| name == part) {
+                Some((_, update)) => {
+                    update.insert(entry);
+                }
+                None => updates.push((part.clone(), [entry].into_iter().collect())),
+            }
+        }
+    }
+    updates
+}
+
+/// The root model part, when metadata should only be added to that one.
+/// Packages without a model relationship have all their model parts
+/// updated, as there is no telling which is the root.
+fn metadata_root<R: Read + Seek>(
+    package: &mut Package<R>,
+    all_model_parts: bool,
+) -> Result<Option<String>> {
+    if all_model_parts {
+        return Ok(None);
+    }
+    match package.root_model_name() {
+        Ok(name) => Ok(Some(name)),
+        Err(Error::NoRootModel) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Whether adding would leave the metadata of the root model part of the
+/// package as it is, with everything add or apply resolves for it. Packages
+/// without a root model part have no metadata to compare.
+fn has_metadata(input_path: &Path, options: &Options, subcommand: &Subcommands) -> Result<bool> {
+    let mut package = Package::open(input_path)?.with_memory_limit(options.memory_limit());
+    if let Some(password) = &options.password {
+        package = package.with_password(password.as_bytes())?;
+    }
+    if package.root_model_name().is_err() {
+        return Ok(false);
+    }
+    let before = package::read_root_metadata(&mut package)?;
+    // Only the metadata of the model is needed to see what adding makes of it
+    let mut xml = Element::new("model");
+    model::set_metadata(&mut xml, &before);
+    match subcommand {
+        Subcommands::Add(add) => {
+            let metadata = add.metadata_for(&mut package, input_path)?;
+            if metadata.is_empty() && add.title_value.is_none() {
+                return Ok(false);
+            }
+            merge_add(&mut xml, &metadata, add)?;
+        }
+        Subcommands::Apply(apply) => {
+            let metadata = apply.metadata_for(&mut package, input_path)?;
+            if metadata.is_empty() {
+                return Ok(false);
+            }
+            model::merge_metadata(&mut xml, &metadata, apply.keep_existing);
+        }
+        _ => return Ok(false),
+    }
+    Ok(before.diff(&MetadataSet::from_model(&xml)).is_empty())
+}
+
+/// Whether to copy a model part as it is, because it isn't the root model
+/// part. Non-root model parts hold objects for the root model, and must not
+/// carry document metadata.
+fn skips_part(file: &ZipFile, root: Option<&str>) -> bool {
+    match root {
+        Some(root) if file.name() != root => {
+            eprintln!(
+                "Warning: not adding metadata to {}, because it is not the root model part",
+                file.name()
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+fn update_xml_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    metadata: &MetadataSet,
+    add: &Add,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    if let Some(title) = &add.title_value {
+        eprintln!("setting title to {}", title);
+    }
+    Timings::measure(&mut timings.rewrite, || {
+        merge_add(edit.xml_mut(), metadata, add)
+    })?;
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    eprintln!("Added metadata to file {}", file_name);
+    Ok(true)
+}
+
+fn apply_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    apply: &Apply,
+    metadata: &MetadataSet,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    Timings::measure(&mut timings.rewrite, || {
+        model::merge_metadata(edit.xml_mut(), metadata, apply.keep_existing)
+    });
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    eprintln!(
+        "Added preset {} to file {}",
+        apply.preset.as_ref().unwrap(),
+        file_name
+    );
+    Ok(true)
+}
+
+fn merge_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    metadata: &MetadataSet,
+    keep_existing: bool,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    Timings::measure(&mut timings.rewrite, || {
+        model::merge_metadata(edit.xml_mut(), metadata, keep_existing)
+    });
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    for entry in metadata.iter() {
+        eprintln!("{}: {} = {}", file_name, entry.name, entry.value);
+    }
+    Ok(true)
+}
+
+fn convert_units_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    to: Unit,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let mut xml = Timings::measure(&mut timings.parse, || {
+        package::read_model_within(&mut file, parts.memory_limit, false)
+    })?;
+    let from = match Timings::measure(&mut timings.rewrite, || model::convert_units(&mut xml, to)) {
+        Ok(from) => from,
+        Err(error) => {
+            eprintln!("Not converting file {}: {}", file_name, error);
+            return Ok(false);
+        }
+    };
+    if from == to {
+        eprintln!("File {} already uses unit {}", file_name, to);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_model(output, output_name, file.compression(), &mut xml)
+    })?;
+    eprintln!("Converted file {} from {} to {}", file_name, from, to);
+    Ok(true)
+}
+
+fn remove_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    keys: &[String],
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    let removed = Timings::measure(&mut timings.rewrite, || {
+        model::remove_metadata(edit.xml_mut(), keys)
+    });
+    if removed.is_empty() {
+        eprintln!("No matching metadata in file {}", file_name);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    for entry in removed {
+        eprintln!("Removed {} from file {}", entry.name, file_name);
+    }
+    Ok(true)
+}
+
+fn patch_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    patch: &patch::Patch,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    let changed = Timings::measure(&mut timings.rewrite, || -> Result<bool> {
+        let xml = edit.xml_mut();
+        let mut metadata = MetadataSet::from_model(xml);
+        let changed = patch.apply(&mut metadata)?;
+        if changed {
+            model::set_metadata(xml, &metadata);
+        }
+        Ok(changed)
+    })?;
+    if !changed {
+        eprintln!("Patch changes nothing in file {}", file_name);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    eprintln!("Patched file {}", file_name);
+    Ok(true)
+}
+
+fn rename_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    rename: &RenameKey,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    let renamed = Timings::measure(&mut timings.rewrite, || {
+        model::rename_metadata(edit.xml_mut(), &rename.from, &rename.to, rename.on_conflict)
+    });
+    match &renamed {
+        Renamed::NotFound => {
+            eprintln!("No {} in file {}", rename.from, file_name);
+            return Ok(false);
+        }
+        Renamed::Conflict => {
+            eprintln!(
+                "Warning: not renaming {} in file {}, because it already has {}",
+                rename.from, file_name, rename.to
+            );
+            return Ok(false);
+        }
+        Renamed::Renamed(_) | Renamed::Dropped(_) => (),
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    match renamed {
+        Renamed::Renamed(Some(replaced)) => eprintln!(
+            "Renamed {} to {} in file {}, replacing \"{}\"",
+            rename.from, rename.to, file_name, replaced.value
+        ),
+        Renamed::Dropped(dropped) => eprintln!(
+            "Removed {} (\"{}\") from file {}, keeping its {}",
+            rename.from, dropped.value, file_name, rename.to
+        ),
+        _ => eprintln!(
+            "Renamed {} to {} in file {}",
+            rename.from, rename.to, file_name
+        ),
+    }
+    Ok(true)
+}
+
+fn set_object_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    set: &SetObject,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let mut xml = Timings::measure(&mut timings.parse, || {
+        package::read_model_within(&mut file, parts.memory_limit, false)
+    })?;
+    let found = Timings::measure(&mut timings.rewrite, || {
+        model::set_object_attributes(
+            &mut xml,
+            &set.id,
+            set.name.as_deref(),
+            set.partnumber.as_deref(),
+        )
+    });
+    if !found {
+        eprintln!("No object {} in file {}", set.id, file_name);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_model(output, output_name, file.compression(), &mut xml)
+    })?;
+    eprintln!("Set object {} in file {}", set.id, file_name);
+    Ok(true)
+}
+
+fn set_item_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    set: &SetItem,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let mut xml = Timings::measure(&mut timings.parse, || {
+        package::read_model_within(&mut file, parts.memory_limit, false)
+    })?;
+    let edit = model::BuildItemEdit {
+        object_id: set.objectid.clone(),
+        transform: set.transform.clone(),
+        part_number: set.partnumber.clone(),
+        // Paths in 3MF files are absolute
+        path: set.path.as_ref().map(|path| match path.is_empty() {
+            true => String::new(),
+            false => format!("/{}", path.trim_start_matches('/')),
+        }),
+    };
+    let found = Timings::measure(&mut timings.rewrite, || {
+        model::edit_build_item(&mut xml, set.item - 1, &edit)
+    });
+    if !found {
+        eprintln!("No build item {} in file {}", set.item, file_name);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_model(output, output_name, file.compression(), &mut xml)
+    })?;
+    eprintln!("Set build item {} in file {}", set.item, file_name);
+    Ok(true)
+}
+
+fn replace_values_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    replace: &ReplaceValue,
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    let changes = Timings::measure(&mut timings.rewrite, || {
+        let xml = edit.xml_mut();
+        let mut metadata = MetadataSet::from_model(xml);
+        let mut changes = Vec::new();
+        for key in &replace.keys {
+            let Some(value) = metadata.value(key).map(str::to_string) else {
+                continue;
+            };
+            let replaced = replace.replace_in(&value);
+            if replaced != value {
+                metadata.set(key, replaced.clone());
+                changes.push((key, value, replaced));
+            }
+        }
+        if !changes.is_empty() {
+            model::set_metadata(xml, &metadata);
+        }
+        changes
+    });
+    if changes.is_empty() {
+        eprintln!("No matching values in file {}", file_name);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    for (key, value, replaced) in changes {
+        eprintln!(
+            "Replaced {} \"{}\" with \"{}\" in file {}",
+            key, value, replaced, file_name
+        );
+    }
+    Ok(true)
+}
+
+fn anonymize_and_copy<W>(
+    mut file: ZipFile,
+    output_name: &str,
+    anonymize: &Anonymize,
+    removed_parts: &[String],
+    output: &mut ZipWriter<W>,
+    parts: PartWriter,
+    timings: &mut Timings,
+) -> Result<bool>
+where
+    W: Write + Seek,
+{
+    let file_name = file.name().to_string();
+
+    if removed_parts
+        .iter()
+        .any(|part| part.eq_ignore_ascii_case(&file_name))
+    {
+        eprintln!("Removed part {}", file_name);
+        return Ok(true);
+    }
+    if file_name.ends_with(".rels") || file_name == package::CONTENT_TYPES_PART {
+        let mut xml = Element::parse(&mut file)?;
+        if !anonymize::remove_part_references(&mut xml, &file_name, removed_parts) {
+            return Ok(false);
+        }
+        package::write_xml_part_as(output, output_name, file.compression(), &xml)?;
+        return Ok(true);
+    }
+    if !file.enclosed_name().is_some_and(package::is_model_part) {
+        return Ok(false);
+    }
+
+    let bytes = Timings::measure(&mut timings.parse, || parts.read_part(&mut file))?;
+    let mut edit = Timings::measure(&mut timings.parse, || parts.edit(&file_name, &bytes))?;
+    let removed = Timings::measure(&mut timings.rewrite, || {
+        anonymize::remove_metadata(edit.xml_mut(), &anonymize.keys, &anonymize.except)
+    });
+    if removed.is_empty() {
+        eprintln!("No personal metadata in file {}", file_name);
+        return Ok(false);
+    }
+
+    Timings::measure(&mut timings.write, || {
+        parts.write_edit(output, output_name, &mut edit, &mut file)
+    })?;
+    for entry in removed {
+        eprintln!("Removed {} from file {}", entry.name, file_name);
+    }
+    Ok(true)
+}
+
+/// Prints a line of `--porcelain` output: tab-separated fields, the first of
+/// which is the input file and the second the kind of line. Backslashes,
+/// tabs and line breaks in fields are escaped as `\\`, `\t`, `\n` and `\r`.
+fn porcelain_line(input_path: &Path, kind: &str, fields: &[&str]) {
+    let escape = |field: &str| {
+        field
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    };
+    let mut line = escape(&input_path.to_string_lossy());
+    line.push('\t');
+    line.push_str(kind);
+    for field in fields {
+        line.push('\t');
+        line.push_str(&escape(field));
+    }
+    println!("{}", line);
+}
+
+/// Prints the metadata of a model part, or keeps it for the json and csv
+/// output at the end. `grouped` is set when the package has several model
+/// parts, to tell their metadata apart. With `--summary` nothing is
+/// printed, the metadata is only returned.
+fn show_metadata(
+    mut file: ZipFile,
+    show: &Show,
+    grouped: bool,
+    input_path: &Path,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<MetadataSet> {
+    let file_name = file.name().to_string();
+
+    let xml = Timings::measure(&mut timings.parse, || {
+        package::read_model_within(&mut file, options.memory_limit(), true)
+    })?;
+    let mut metadata = MetadataSet::from_model(&xml);
+    if show.unify_names {
+        metadata.unalias();
+    }
+    if show.summary {
+        return Ok(metadata);
+    }
+    if show.format != ShowFormat::Text {
+        show.parts.borrow_mut().push((
+            input_path.to_string_lossy().into_owned(),
+            file_name,
+            metadata.clone(),
+        ));
+    } else if metadata.is_empty() {
+        eprintln!("No metadata found in file {}", file_name);
+    } else if options.porcelain {
+        for entry in &metadata {
+            porcelain_line(
+                input_path,
+                "metadata",
+                &[&file_name, &entry.name, &entry.value],
+            );
+        }
+    } else {
+        eprintln!("Metadata found in file {}:", file_name);
+        let config = EmitterConfig::new()
+            .perform_indent(true)
+            .indent_string("\t")
+            .line_separator("\n")
+            .write_document_declaration(false);
+        if grouped {
+            println!("{}:", file_name);
+        }
+        for entry in &metadata {
+            if grouped {
+                print!("\t");
+            }
+            let mut element = entry.to_element();
+            if !show.full {
+                if let Some(shortened) = shorten(&entry.value) {
+                    element.children = vec![XMLNode::Text(shortened)];
+                }
+            }
+            element.write_with_config(stdout(), config.clone())?;
+            println!();
+        }
+    }
+    Ok(metadata)
+}
+
+/// Counts the file as a failure when it has no metadata with
+/// `--fail-if-empty`, or lacks a name of `--fail-if-missing`.
+fn check_show_gates(show: &Show, names: &[String], input_path: &Path) {
+    let mut failed = false;
+    if show.fail_if_empty && names.is_empty() {
+        eprintln!("No metadata in {}", input_path.display());
+        failed = true;
+    }
+    for name in &show.fail_if_missing {
+        if !names.contains(name) {
+            eprintln!("No {} metadata in {}", name, input_path.display());
+            failed = true;
+        }
+    }
+    if failed {
+        show.failures.set(show.failures.get() + 1);
+    }
+}
+
+/// Prints the metadata of every source of the package, as one listing of
+/// names prefixed with their source.
+fn show_all_sources<R: Read + Seek>(
+    package: &mut Package<R>,
+    show: &Show,
+    input_path: &Path,
+    options: &Options,
+) -> Result<()> {
+    let mut sources = sources::read_all_sources(package)?;
+    if show.unify_names {
+        for (_, metadata) in &mut sources {
+            metadata.unalias();
+        }
+    }
+    if show.format != ShowFormat::Text {
+        let path = input_path.to_string_lossy();
+        show.parts.borrow_mut().extend(
+            sources
+                .into_iter()
+                .map(|(source, metadata)| (path.to_string(), source.name().to_string(), metadata)),
+        );
+        return Ok(());
+    }
+    if sources.is_empty() {
+        eprintln!("No metadata found in file {}", input_path.display());
+    }
+    for (source, metadata) in &sources {
+        for entry in metadata {
+            if options.porcelain {
+                porcelain_line(
+                    input_path,
+                    "source",
+                    &[source.name(), &entry.name, &entry.value],
+                );
+                continue;
+            }
+            let value = match show.full {
+                true => None,
+                false => shorten(&entry.value),
+            };
+            println!(
+                "{}:{} = {}",
+                source.name(),
+                entry.name,
+                value.as_deref().unwrap_or(&entry.value)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// How many characters of a value `show` prints without `--full`.
+const SHOWN_LENGTH: usize = 200;
+
+/// The start of a value that is too long to show in full, with an ellipsis
+/// and the length of the whole value.
+fn shorten(value: &str) -> Option<String> {
+    let length = value.chars().count();
+    if length <= SHOWN_LENGTH {
+        return None;
+    }
+    // Nobody reads the start of a blob
+    if is_opaque(value) {
+        return Some(format!(
+            "({} characters of base64, see get --decode-base64)",
+            length
+        ));
+    }
+    let start: String = value.chars().take(SHOWN_LENGTH).collect();
+    Some(format!("{}… ({} characters)", start, length))
+}
+
+/// Whether a value looks like binary data in base64 rather than text: a
+/// single word of base64 characters.
+fn is_opaque(value: &str) -> bool {
+    let value = value.trim();
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '\r' | '\n'))
+}
+
+/// Decodes base64, with or without padding and line breaks. `None` when
+/// the text isn't base64.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut padding = false;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding = true;
+                continue;
+            }
+            _ => return None,
+        };
+        if padding {
+            return None;
+        }
+        bits = bits << 6 | value as u32;
+        count += 1;
+        if count == 4 {
+            bytes.extend_from_slice(&bits.to_be_bytes()[1..]);
+            bits = 0;
+            count = 0;
+        }
+    }
+    match count {
+        0 => (),
+        2 => bytes.push((bits >> 4) as u8),
+        3 => bytes.extend_from_slice(&((bits >> 2) as u16).to_be_bytes()),
+        _ => return None,
+    }
+    Some(bytes)
+}
+
+/// Prints the number of metadata entries in the model parts of a file, and
+/// their names in order, once each.
+fn show_summary(names: &mut Vec<String>, input_path: &Path, options: &Options) {
+    let count = names.len();
+    names.sort();
+    names.dedup();
+    if options.porcelain {
+        porcelain_line(
+            input_path,
+            "summary",
+            &[&count.to_string(), &names.join(" ")],
+        );
+    } else {
+        println!("{}: {} metadata entries", input_path.display(), count);
+        for name in names {
+            println!("\t{}", name);
+        }
+    }
+}
+
+/// A line of CSV, with the cells quoted where needed.
+fn csv_line(cells: &[&str]) -> String {
+    cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Prints the metadata values, and object names if asked for, of a model
+/// part that match the pattern of `grep`.
+#[cfg(feature = "regex")]
+fn grep_model(
+    mut file: ZipFile,
+    grep: &Grep,
+    input_path: &Path,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<()> {
+    let file_name = file.name().to_string();
+    let regex = grep.regex.as_ref().unwrap();
+
+    let xml = Timings::measure(&mut timings.parse, || {
+        package::read_model_within(&mut file, options.memory_limit(), !grep.object_names)
+    })?;
+    let mut candidates: Vec<(&str, String, String)> = MetadataSet::from_model(&xml)
+        .iter()
+        .map(|entry| ("metadata", entry.name.clone(), entry.value.clone()))
+        .collect();
+    if grep.object_names {
+        candidates.extend(
+            model::object_names(&xml)
+                .into_iter()
+                .map(|(id, name)| ("object", id.to_string(), name.to_string())),
+        );
+    }
+    for (kind, key, value) in candidates {
+        if !regex.is_match(&value) {
+            continue;
+        }
+        grep.matches.set(grep.matches.get() + 1);
+        if options.porcelain {
+            porcelain_line(input_path, "match", &[&file_name, kind, &key, &value]);
+            continue;
+        }
+        let value = if grep.highlight {
+            regex
+                .replace_all(&value, "\x1b[1;31m$0\x1b[0m")
+                .into_owned()
+        } else {
+            value
+        };
+        let key = match kind {
+            "object" => format!("object {}", key),
+            _ => key,
+        };
+        println!(
+            "{}: {}: {} = {}",
+            input_path.display(),
+            file_name,
+            key,
+            value
+        );
+    }
+    Ok(())
+}
+
+fn show_info(
+    mut file: ZipFile,
+    info: &Info,
+    input_path: &Path,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<()> {
+    let file_name = file.name().to_string();
+
+    let xml = Timings::measure(&mut timings.parse, || {
+        package::read_model_within(&mut file, options.memory_limit(), false)
+    })?;
+    if options.porcelain {
+        show_info_porcelain(&xml, &file_name, input_path)?;
+        if info.objects {
+            show_object_tree(&xml, &file_name, input_path, options);
+        }
+        if info.items {
+            show_build_items(&xml, &file_name, input_path, options);
+        }
+        return Ok(());
+    }
+    eprintln!("Info for file {}:", file_name);
+    match model::unit(&xml) {
+        Ok((unit, true)) => println!("unit: {} (default)", unit),
+        Ok((unit, false)) => println!("unit: {}", unit),
+        Err(error) => println!("unit: {}", error),
+    }
+    let groups = model::build_items_by_object(&xml);
+    println!(
+        "build items: {}",
+        groups
+            .iter()
+            .map(|group| group.transforms.len())
+            .sum::<usize>()
+    );
+    for group in groups {
+        let count = group.transforms.len();
+        if count < 2 {
+            continue;
+        }
+        let different = group.different_transforms();
+        // Objects in other model parts are named there
+        let name = match &group.path {
+            Some(path) => format!(" in {}", path),
+            None => model::object_name(&xml, &group.object_id)
+                .map(|name| format!(" ({})", name))
+                .unwrap_or_default(),
+        };
+        if different == 1 {
+            println!(
+                "object {}{} is placed {} times with the same transform",
+                group.object_id, name, count
+            );
+        } else {
+            println!(
+                "object {}{} is placed {} times with {} different transforms",
+                group.object_id, name, count, different
+            );
+        }
+    }
+    if info.objects {
+        show_object_tree(&xml, &file_name, input_path, options);
+    }
+    if info.items {
+        show_build_items(&xml, &file_name, input_path, options);
+    }
+    Ok(())
+}
+
+/// Prints the items of the build of a model part, numbered from 1 as
+/// `set-item --item` takes them.
+fn show_build_items(xml: &Element, file_name: &str, input_path: &Path, options: &Options) {
+    if !options.porcelain {
+        println!("build:");
+    }
+    for (number, item) in model::build_items(xml).iter().enumerate() {
+        let number = (number + 1).to_string();
+        if options.porcelain {
+            porcelain_line(
+                input_path,
+                "item",
+                &[
+                    file_name,
+                    &number,
+                    &item.object_id,
+                    item.path.as_deref().unwrap_or_default(),
+                    item.part_number.as_deref().unwrap_or_default(),
+                    item.transform.as_deref().unwrap_or_default(),
+                ],
+            );
+            continue;
+        }
+        let mut line = format!("  item {}: object {}", number, item.object_id);
+        match &item.path {
+            Some(path) => line += &format!(" in {}", path),
+            None => {
+                if let Some(name) = model::object_name(xml, &item.object_id) {
+                    line += &format!(" ({})", name);
+                }
+            }
+        }
+        if let Some(part_number) = &item.part_number {
+            line += &format!(", part number {}", part_number);
+        }
+        if let Some(transform) = &item.transform {
+            line += &format!(", transform {}", transform);
+        }
+        println!("{}", line);
+    }
+}
+
+/// Prints the objects of a model part as a tree: the objects that no
+/// component uses at the top, and below each object the objects of its
+/// components.
+fn show_object_tree(xml: &Element, file_name: &str, input_path: &Path, options: &Options) {
+    let objects = model::objects(xml);
+    let used: HashSet<&str> = objects
+        .iter()
+        .flat_map(|object| &object.components)
+        .filter(|component| component.path.is_none())
+        .map(|component| component.object_id.as_str())
+        .collect();
+    if !options.porcelain {
+        println!("objects:");
+    }
+    let tree = ObjectTree {
+        objects: &objects,
+        file_name,
+        input_path,
+        options,
+    };
+    for object in objects
+        .iter()
+        .filter(|object| !used.contains(object.id.as_str()))
+    {
+        tree.show(&object.id, None, &mut Vec::new());
+    }
+}
+
+/// What [`show_object_tree`] prints the objects of.
+struct ObjectTree<'a> {
+    objects: &'a [model::Object],
+    file_name: &'a str,
+    input_path: &'a Path,
+    options: &'a Options,
+}
+
+impl<'a> ObjectTree<'a> {
+    /// Prints the object with the id, placed by `component` if it is one,
+    /// and then its components. `ancestors` holds the ids of the objects
+    /// above it; a component that refers back to one of those is printed,
+    /// but not followed.
+    fn show(
+        &self,
+        id: &'a str,
+        component: Option<&model::Component>,
+        ancestors: &mut Vec<&'a str>,
+    ) {
+        let path = component.and_then(|component| component.path.as_deref());
+        let object = match path {
+            Some(_) => None,
+            None => self.objects.iter().find(|object| object.id == id),
+        };
+        let name = object.and_then(|object| object.name.as_deref());
+        let part_number = object.and_then(|object| object.part_number.as_deref());
+        let transform = component.and_then(|component| component.transform.as_deref());
+        let cycle = ancestors.contains(&id);
+        if self.options.porcelain {
+            porcelain_line(
+                self.input_path,
+                "object",
+                &[
+                    self.file_name,
+                    ancestors.last().copied().unwrap_or_default(),
+                    id,
+                    path.unwrap_or_default(),
+                    name.unwrap_or_default(),
+                    part_number.unwrap_or_default(),
+                    transform.unwrap_or_default(),
+                ],
+            );
+        } else {
+            let mut line = format!("{:1$}object {2}", "", ancestors.len() * 2 + 2, id);
+            if let Some(name) = name {
+                line += &format!(" ({})", name);
+            }
+            match (path, object) {
+                (Some(path), _) => line += &format!(" in {}", path),
+                (None, None) => line += ", which is missing",
+                (None, Some(object)) if object.kind != "model" => {
+                    line += &format!(", type {}", object.kind)
+                }
+                _ => (),
+            }
+            if let Some(part_number) = part_number {
+                line += &format!(", part number {}", part_number);
+            }
+            if let Some(transform) = transform {
+                line += &format!(", transform {}", transform);
+            }
+            if cycle {
+                line += ", which contains itself";
+            }
+            println!("{}", line);
+        }
+        let Some(object) = object.filter(|_| !cycle) else {
+            return;
+        };
+        ancestors.push(id);
+        for component in &object.components {
+            self.show(&component.object_id, Some(component), ancestors);
+        }
+        ancestors.pop();
+    }
+}
+
+/// Prints the application that produced the package, as far as it can be
+/// told.
+fn show_producer<R: Read + Seek>(
+    package: &mut Package<R>,
+    input_path: &Path,
+    options: &Options,
+) -> Result<()> {
+    let producer = producer::identify(package, options.memory_limit())?;
+    match (producer, options.porcelain) {
+        (Some(producer), true) => porcelain_line(
+            input_path,
+            "producer",
+            &[
+                &producer.name,
+                producer.version.as_deref().unwrap_or_default(),
+                producer.evidence.name(),
+            ],
+        ),
+        (None, true) => porcelain_line(input_path, "producer", &["", "", ""]),
+        (Some(producer), false) => match producer.version {
+            Some(version) => println!(
+                "producer: {} {} (from {})",
+                producer.name, version, producer.evidence
+            ),
+            None => println!("producer: {} (from {})", producer.name, producer.evidence),
+        },
+        (None, false) => println!("producer: unknown"),
+    }
+    Ok(())
+}
+
+/// Prints the comment of the ZIP archive, which some slicers keep their
+/// name and version in.
+fn show_archive_comment<R: Read + Seek>(
+    package: &mut Package<R>,
+    input_path: &Path,
+    options: &Options,
+) {
+    let comment = String::from_utf8_lossy(package.archive().comment()).into_owned();
+    match (comment.is_empty(), options.porcelain) {
+        (_, true) => porcelain_line(input_path, "archive-comment", &[&comment]),
+        (true, false) => println!("archive comment: none"),
+        (false, false) => println!("archive comment: {}", comment),
+    }
+}
+
+/// Describes the thumbnails of the package, and what is wrong with them.
+fn show_thumbnails<R: Read + Seek>(
+    package: &mut Package<R>,
+    input_path: &Path,
+    options: &Options,
+) -> Result<()> {
+    for thumbnail in thumbnail::read_thumbnails(package)? {
+        let format = thumbnail.format.map_or("unknown", ImageFormat::name);
+        let size = thumbnail
+            .size
+            .map(|size| size.to_string())
+            .unwrap_or_default();
+        if options.porcelain {
+            let content_type = thumbnail.content_type.as_deref().unwrap_or_default();
+            porcelain_line(
+                input_path,
+                "thumbnail",
+                &[&thumbnail.name, content_type, format, &size],
+            );
+        } else if size.is_empty() {
+            println!("thumbnail: {}, {}", thumbnail.name, format);
+        } else {
+            println!("thumbnail: {}, {} {}", thumbnail.name, format, size);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the backups that `add --append --backup` kept, oldest first.
+fn show_backups<R: Read + Seek>(package: &mut Package<R>, input_path: &Path, options: &Options) {
+    let archive = package.archive();
+    let backups = (0..archive.len())
+        .filter_map(|index| Some(archive.by_index_raw(index).ok()?.name().to_string()))
+        .filter(|name| name.starts_with(append::BACKUP_DIRECTORY))
+        .collect::<Vec<_>>();
+    for backup in &backups {
+        match options.porcelain {
+            true => porcelain_line(input_path, "backup", &[backup]),
+            false => println!("backup: {}", backup),
+        }
+    }
+}
+
+fn show_info_porcelain(xml: &Element, file_name: &str, input_path: &Path) -> Result<()> {
+    match model::unit(xml) {
+        Ok((unit, default)) => porcelain_line(
+            input_path,
+            "unit",
+            &[
+                file_name,
+                unit.name(),
+                if default { "default" } else { "explicit" },
+            ],
+        ),
+        Err(error) => eprintln!("Warning: {} in file {}", error, file_name),
+    }
+    let groups = model::build_items_by_object(xml);
+    let count: usize = groups.iter().map(|group| group.transforms.len()).sum();
+    porcelain_line(input_path, "build-items", &[file_name, &count.to_string()]);
+    for group in groups {
+        let count = group.transforms.len();
+        if count < 2 {
+            continue;
+        }
+        let name = match group.path {
+            Some(_) => None,
+            None => model::object_name(xml, &group.object_id),
+        };
+        porcelain_line(
+            input_path,
+            "repeated-object",
+            &[
+                file_name,
+                &group.object_id,
+                name.unwrap_or_default(),
+                &count.to_string(),
+                &group.different_transforms().to_string(),
+                group.path.as_deref().unwrap_or_default(),
+            ],
+        );
+    }
+    Ok(())
+}
+
+/// Creates the output as a temporary file in the directory of
+/// `output_path`, so that no half-written file is ever left under the final
+/// name. See [`finish_output`]. For a dry run, which never gets to the final
+/// name, the system's temporary directory is used instead.
+fn create_output(
+    output_path: &Path,
+    options: &Options,
+) -> Result<ZipWriter<RawNameWriter<NamedTempFile>>> {
+    let temp_dir = std::env::temp_dir();
+    let dir = match output_path.parent() {
+        _ if options.dry_run => &temp_dir,
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".metadata_3mf").suffix(".tmp");
+    // The same permissions File::create would give the file
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    Ok(ZipWriter::new(RawNameWriter::new(
+        builder.tempfile_in(dir)?,
+    )?))
+}
+
+/// Opens `input_path` to update it in place. With `--transactional`, a
+/// copy next to it is updated instead, which [`stage_output`] then keeps
+/// for the end of the run.
+fn open_in_place(input_path: &Path, options: &Options) -> Result<(File, Option<TempPath>)> {
+    if !options.transactional {
+        let file = OpenOptions::new().read(true).write(true).open(input_path)?;
+        return Ok((file, None));
+    }
+    let dir = match input_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let copy = tempfile::Builder::new()
+        .prefix(".metadata_3mf")
+        .suffix(".tmp")
+        .tempfile_in(dir)?
+        .into_temp_path();
+    fs::copy(input_path, &copy)?;
+    let file = OpenOptions::new().read(true).write(true).open(&copy)?;
+    Ok((file, Some(copy)))
+}
+
+/// Keeps a finished output of `--transactional` until the end of the run,
+/// when [`commit_staged`] renames it to `output_path`.
+fn stage_output(temp: TempPath, output_path: &Path, options: &Options) {
+    options
+        .staged
+        .borrow_mut()
+        .push((temp, output_path.to_path_buf()));
+}
+
+/// Where the output for `output_path` is until the end of the run: its
+/// temporary file with `--transactional`, or else the output itself.
+fn staged_path(output_path: &Path, options: &Options) -> PathBuf {
+    let staged = options.staged.borrow();
+    match staged.iter().rev().find(|(_, path)| path == output_path) {
+        Some((temp, _)) => temp.to_path_buf(),
+        None => output_path.to_path_buf(),
+    }
+}
+
+/// Renames the staged outputs of `--transactional` into place, all or
+/// nothing, and exits when that fails.
+fn commit_staged(options: &Options) {
+    let staged = options.staged.take();
+    if staged.is_empty() {
+        return;
+    }
+    let renames = staged
+        .iter()
+        .map(|(temp, path)| (temp.to_path_buf(), path.clone()))
+        .collect::<Vec<_>>();
+    if let Err(error) = rename_all(&renames) {
+        eprintln!("Could not replace {}, no files were changed", error);
+        // The outputs are back under their temporary names
+        drop(staged);
+        end_run(options, 1);
+    }
+    for (temp, _) in staged {
+        // Renamed away already, there is nothing left to remove
+        let _ = temp.keep();
+    }
+    eprintln!("Wrote {} files", renames.len());
+}
+
+/// Ends the run with `code`: removes the staged outputs of
+/// `--transactional` that weren't committed, and sends the notifications.
+/// Exiting skips destructors, which would remove staged outputs otherwise.
+fn end_run(options: &Options, code: i32) -> ! {
+    let staged = options.staged.take();
+    if !staged.is_empty() {
+        eprintln!(
+            "Removed the outputs of {} files, no files were changed",
+            staged.len()
+        );
+    }
+    drop(staged);
+    #[cfg(feature = "net")]
+    send_notifications(options, code);
+    std::process::exit(code);
+}
+
+/// The summary of the run that notifications send.
+#[cfg(feature = "net")]
+fn run_summary(options: &Options, code: i32) -> serde_json::Value {
+    let progress = &options.progress;
+    let failures = progress
+        .failures
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .iter()
+        .map(|(file, error)| serde_json::json!({ "file": file, "error": error }))
+        .collect::<Vec<_>>();
+    let skipped = progress
+        .skipped
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .iter()
+        .map(|(file, reason)| serde_json::json!({ "file": file, "reason": reason.code() }))
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "subcommand": options.subcommand,
+        "status": match code {
+            0 => "succeeded",
+            INTERRUPTED | TERMINATED => "cancelled",
+            _ => "failed",
+        },
+        "exit_code": code,
+        "dry_run": options.dry_run,
+        "inputs": progress.total.load(Ordering::Relaxed),
+        "processed": progress.tally.started(),
+        "succeeded": progress.tally.succeeded(),
+        "failed": progress.tally.failed(),
+        "warnings": progress.tally.warnings(),
+        "failures": failures,
+        "skipped": skipped,
+    })
+}
+
+/// Sends the summary of the run to the webhook and MQTT topic, if given.
+/// A notification that can't be sent is only a warning.
+#[cfg(feature = "net")]
+fn send_notifications(options: &Options, code: i32) {
+    if code == 0 && options.notify_failures_only
+        || options.notify_webhook.is_none() && options.notify_mqtt.is_none()
+    {
+        return;
+    }
+    let summary = run_summary(options, code);
+    if let Some(url) = &options.notify_webhook {
+        if let Err(error) = notify::post_webhook(url, &summary) {
+            eprintln!("Warning: {}", error);
+        }
+    }
+    if let Some(target) = &options.notify_mqtt {
+        let published = target
+            .with_stored_password()
+            .and_then(|target| notify::publish_mqtt(&target, &summary));
+        if let Err(error) = published {
+            eprintln!("Warning: {}", error);
+        }
+    }
+}
+
+/// Renames each file to its target, in order, all or nothing. Existing
+/// targets are moved aside first, and removed once every rename is done.
+/// When a rename fails, the renames done so far are undone and the targets
+/// put back, and the error names the target that failed.
+fn rename_all(renames: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    let mut done = Vec::new();
+    for (from, to) in renames {
+        match rename_aside(from, to) {
+            Ok(aside) => done.push((from, to, aside)),
+            Err(error) => {
+                // Best effort, the error that started it is what gets reported
+                for (from, to, aside) in done.into_iter().rev() {
+                    let _ = fs::rename(to, from);
+                    if let Some(aside) = aside {
+                        let _ = fs::rename(&aside, to);
+                    }
+                }
+                return Err(io::Error::new(
+                    error.kind(),
+                    format!("{}: {}", to.display(), error),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renames `from` to `to`, moving an existing `to` aside to a temporary
+/// file next to it, which is removed when the returned path is dropped.
+fn rename_aside(from: &Path, to: &Path) -> io::Result<Option<TempPath>> {
+    let aside = match fs::symlink_metadata(to) {
+        Ok(_) => {
+            let dir = match to.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let aside = tempfile::Builder::new()
+                .prefix(".metadata_3mf")
+                .suffix(".orig")
+                .tempfile_in(dir)?
+                .into_temp_path();
+            fs::rename(to, &aside)?;
+            Some(aside)
+        }
+        Err(_) => None,
+    };
+    if let Err(error) = fs::rename(from, to) {
+        if let Some(aside) = &aside {
+            let _ = fs::rename(aside, to);
+        }
+        return Err(error);
+    }
+    Ok(aside)
+}
+
+/// Finishes the output, putting back the stored entry names of
+/// `raw_names`, and renames it to `output_path`. The temporary file is in
+/// the same directory, so the rename stays on one filesystem and replaces
+/// any existing file atomically, wherever the input is. A dry run only
+/// reports what it would have written, and with `--transactional`, the
+/// output is staged for the end of the run instead.
+fn finish_output(
+    mut output: ZipWriter<RawNameWriter<NamedTempFile>>,
+    raw_names: RawNames,
+    output_path: &Path,
+    options: &Options,
+) -> Result<()> {
+    if options.dry_run {
+        eprintln!("Dry run, not writing {}", output_path.display());
+        return Ok(());
+    }
+    if let Some(comment) = &options.archive_comment {
+        output.set_comment(comment.clone());
+    }
+    let output = output.finish()?.restore(&raw_names)?;
+    if options.transactional {
+        stage_output(output.into_temp_path(), output_path, options);
+        return Ok(());
+    }
+    output.persist(output_path).map_err(|error| error.error)?;
+    Ok(())
+}
+
+/// The most symbolic links followed in a row before giving up, the same
+/// limit Linux uses.
+const MAX_SYMLINKS: usize = 40;
+
+/// Follows `path` through any symbolic links to the path they end up at,
+/// which need not exist yet. Fails on a cycle of links.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    let mut seen = HashSet::new();
+    while fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        if !seen.insert(path.clone()) || seen.len() > MAX_SYMLINKS {
+            return Err(io::Error::other(format!(
+                "cycle of symbolic links at {}",
+                path.display()
+            )));
+        }
+        let target = fs::read_link(&path)?;
+        path = match path.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+    }
+    Ok(path)
+}
+
+/// The name of ignore files of this program, which exclude files from
+/// --recursive like `.gitignore` files do.
+#[cfg(feature = "gitignore")]
+const IGNORE_FILE_NAME: &str = ".m3mfignore";
+
+/// Adds the 3MF files in `dir` and its subdirectories to `files`, sorted by
+/// name, descending at most `--max-depth` levels. Files and directories that
+/// a `.gitignore` or `.m3mfignore` file excludes are left out, unless
+/// `--no-ignore` is given. Symbolic links to files are added, and dealt
+/// with like any other input; symbolic links to directories are not
+/// followed.
+#[cfg(feature = "gitignore")]
+fn find_3mf_files(dir: &Path, options: &Options, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut walk = WalkBuilder::new(dir);
+    // The depth of the walk counts the directory itself
+    walk.max_depth(options.max_depth.map(|depth| depth + 1))
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .hidden(false)
+        .ignore(false)
+        .require_git(false);
+    match options.no_ignore {
+        true => walk.standard_filters(false),
+        false => walk.add_custom_ignore_filename(IGNORE_FILE_NAME),
+    };
+    for entry in walk.build() {
+        let entry = entry.map_err(io::Error::other)?;
+        let path = entry.path();
+        if entry.file_type().is_some_and(|kind| !kind.is_dir())
+            && path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("3mf"))
+        {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Adds the 3MF files in `dir` and its subdirectories to `files` like the
+/// above, without ignore files, which need the `gitignore` feature.
+#[cfg(not(feature = "gitignore"))]
+fn find_3mf_files(dir: &Path, options: &Options, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    fn walk(dir: &Path, depth: Option<usize>, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            // Symbolic links are not followed into directories, as above
+            if entry.file_type()?.is_dir() {
+                if depth != Some(0) {
+                    walk(&path, depth.map(|depth| depth - 1), files)?;
+                }
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("3mf"))
+            {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+    walk(dir, options.max_depth, files)
+}
+
+/// Picks `count` of the files at random, keeping their order. The same seed
+/// picks the same files from the same list.
+fn sample_files(files: Vec<PathBuf>, count: usize, seed: u64) -> Vec<PathBuf> {
+    // SplitMix64, which is plenty random for picking files
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    // Each file is picked with the chance of the number still needed out of
+    // the number left, which picks exactly `count`
+    let mut needed = count.min(files.len()) as u64;
+    let mut left = files.len() as u64;
+    files
+        .into_iter()
+        .filter(|_| {
+            let picked = next() % left < needed;
+            left -= 1;
+            needed -= picked as u64;
+            picked
+        })
+        .collect()
+}
+
+/// Builds the output path for `input_path` by adding `suffix` to its file
+/// stem, in `output_dir` if given. Returns `None` when the stem already ends
+/// with the suffix.
+fn output_path_with_suffix(
+    input_path: &Path,
+    suffix: &str,
+    output_dir: Option<&Path>,
+) -> Option<PathBuf> {
+    if let (Some(stem), extension) = (input_path.file_stem(), input_path.extension()) {
+        if stem.as_encoded_bytes().ends_with(suffix.as_bytes()) {
+            return None;
+        }
+        let mut name = stem.to_os_string();
+        name.push(OsStr::new(suffix));
+        if let Some(extension) = extension {
+            name.push(OsString::from("."));
+            name.push(extension);
+        }
+        match output_dir {
+            Some(dir) => Some(dir.join(name)),
+            None => Some(input_path.with_file_name(name)),
+        }
+    } else {
+        panic!("Could not get file stem from {}", input_path.display());
+    }
+}
+
+/// Whether opening a file failed because another program has it open
+/// without sharing it, which only Windows enforces.
+fn is_locked(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33))
+}
+
+/// Checks that `path` can be opened for reading, or for writing when
+/// `write` is set, waiting up to `wait` seconds while another program has
+/// it locked. Returns false when it is still locked. Other errors are left
+/// to be reported when the file is used.
+fn wait_until_unlocked(path: &Path, write: bool, wait: Option<u64>) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(wait.unwrap_or(0));
+    let mut waiting = false;
+    loop {
+        // Opening for writing without truncating leaves the file as it is
+        let result = fs::OpenOptions::new().read(!write).write(write).open(path);
+        match result {
+            Err(error) if is_locked(&error) => {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                if !waiting {
+                    eprintln!("Waiting for {} to be unlocked", path.display());
+                    waiting = true;
+                }
+                std::thread::sleep(Duration::from_millis(250));
+            }
+            _ => return true,
+        }
+    }
+}
+
+/// Reads every entry of the package at `path`, which checks their CRC-32
+/// checksums.
+fn verify_package(path: &Path) -> Result<()> {
+    let mut package = Package::open(path)?;
+    for index in 0..package.len() {
+        io::copy(&mut package.by_index(index)?, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+/// Replaces each input file with its output, after checking that the output
+/// is intact. Outputs among the inputs are left for their input files.
+fn promote_files(promote: &Promote, input_files: &[PathBuf], options: &Options) {
+    let mut files = Vec::new();
+    for path in input_files {
+        if path.is_dir() {
+            if let Err(error) = find_3mf_files(path, options, &mut files) {
+                eprintln!("Could not read directory {}: {}", path.display(), error);
+                end_run(options, 1);
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    let mut failures = 0;
+    let mut promoted = 0;
+    let mut staged = Vec::new();
+    for input_path in &files {
+        let Some(output_path) = output_path_with_suffix(input_path, &promote.suffix, None) else {
+            continue;
+        };
+        if !output_path.exists() {
+            options.progress.skip(
+                input_path,
+                Skip::NoOutput,
+                format_args!("there is no {}", output_path.display()),
+                options.porcelain,
+            );
+            continue;
+        }
+        if let Err(error) = verify_package(&output_path) {
+            eprintln!(
+                "Not replacing {}, because {} is damaged: {}",
+                input_path.display(),
+                output_path.display(),
+                error
+            );
+            failures += 1;
+            continue;
+        }
+        let mut backup = None;
+        if promote.keep_originals {
+            let mut name = input_path.as_os_str().to_os_string();
+            name.push(".orig");
+            let path = PathBuf::from(name);
+            if path.exists() && !promote.force {
+                eprintln!(
+                    "Not replacing {}, because {} already exists, use -f or --force to ignore",
+                    input_path.display(),
+                    path.display()
+                );
+                failures += 1;
+                continue;
+            }
+            backup = Some(path);
+        }
+        if !wait_until_unlocked(input_path, true, options.wait) {
+            eprintln!(
+                "Not replacing {}, because it is locked by another program",
+                input_path.display()
+            );
+            failures += 1;
+            continue;
+        }
+        if options.dry_run {
+            eprintln!(
+                "Would replace {} with {}",
+                input_path.display(),
+                output_path.display()
+            );
+            if options.porcelain {
+                porcelain_line(input_path, "dry-run", &[&output_path.to_string_lossy()]);
+            }
+            promoted += 1;
+            continue;
+        }
+        if options.transactional {
+            staged.push((input_path.clone(), output_path, backup));
+            continue;
+        }
+        let result = match &backup {
+            Some(backup) => fs::rename(input_path, backup).and_then(|()| {
+                fs::rename(&output_path, input_path).map_err(|error| {
+                    // Put the original back, or else say where it is
+                    match fs::rename(backup, input_path) {
+                        Ok(()) => error,
+                        Err(_) => io::Error::new(
+                            error.kind(),
+                            format!("{}, the original is at {}", error, backup.display()),
+                        ),
+                    }
+                })
+            }),
+            None => fs::rename(&output_path, input_path),
+        };
+        if let Err(error) = result {
+            eprintln!(
+                "Could not replace {} with {}: {}",
+                input_path.display(),
+                output_path.display(),
+                error
+            );
+            failures += 1;
+            continue;
+        }
+        report_promoted(input_path, &output_path, backup.as_deref(), options);
+        promoted += 1;
+    }
+    // With --transactional, nothing is replaced until every file can be
+    if !staged.is_empty() {
+        if failures > 0 {
+            eprintln!(
+                "Replaced no files, because {} files could not be replaced",
+                failures
+            );
+            end_run(options, 1);
+        }
+        let mut renames = Vec::new();
+        for (input_path, output_path, backup) in &staged {
+            if let Some(backup) = backup {
+                renames.push((input_path.clone(), backup.clone()));
+            }
+            renames.push((output_path.clone(), input_path.clone()));
+        }
+        if let Err(error) = rename_all(&renames) {
+            eprintln!("Could not replace {}, no files were changed", error);
+            end_run(options, 1);
+        }
+        for (input_path, output_path, backup) in &staged {
+            report_promoted(input_path, output_path, backup.as_deref(), options);
+        }
+        promoted = staged.len();
+    }
+    options.progress.report_skipped();
+    if options.dry_run {
+        eprintln!("Would replace {} files", promoted);
+    } else {
+        eprintln!("Replaced {} files", promoted);
+    }
+    if failures > 0 {
+        eprintln!("{} files could not be replaced", failures);
+        end_run(options, 1);
+    }
+}
+
+/// Reports that `output_path` replaced `input_path`.
+fn report_promoted(
+    input_path: &Path,
+    output_path: &Path,
+    backup: Option<&Path>,
+    options: &Options,
+) {
+    eprintln!(
+        "Replaced {} with {}",
+        input_path.display(),
+        output_path.display()
+    );
+    if options.porcelain {
+        let backup = backup.unwrap_or(Path::new(""));
+        porcelain_line(
+            input_path,
+            "promoted",
+            &[&output_path.to_string_lossy(), &backup.to_string_lossy()],
+        );
+    }
+}
+
+/// Output paths that more than one input file would be written to, with
+/// those input files. Paths that only differ in case collide too, as they
+/// are the same file on Windows and macOS.
+fn output_collisions<'a>(
+    input_files: &'a [PathBuf],
+    suffix: &str,
+    output_dir: Option<&Path>,
+) -> Vec<(PathBuf, Vec<&'a Path>)> {
+    let mut seen_inputs = HashSet::new();
+    let mut outputs: Vec<(PathBuf, Vec<&Path>)> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+    for input_path in input_files {
+        // The same file under two names is only processed once anyway
+        let canonical = fs::canonicalize(input_path).unwrap_or_else(|_| input_path.clone());
+        if !seen_inputs.insert(canonical) {
+            continue;
+        }
+        let Some(output_path) = output_path_with_suffix(input_path, suffix, output_dir) else {
+            continue;
+        };
+        let key = output_path.to_string_lossy().to_lowercase();
+        match indices.get(&key) {
+            Some(&index) => outputs[index].1.push(input_path),
+            None => {
+                indices.insert(key, outputs.len());
+                outputs.push((output_path, vec![input_path]));
+            }
+        }
+    }
+    outputs.retain(|(_, inputs)| inputs.len() > 1);
+    outputs
+}
+
+/// Something wrong with a package that `validate` found. Problems that have
+/// no position, such as a model part that isn't well-formed, are at line 0.
+struct Problem {
+    code: &'static str,
+    part: String,
+    line: u64,
+    column: u64,
+    message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.part, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.part, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+/// Checks that the package has a root model part, and that every model
+/// part is well-formed, or with `schema`, valid against the schemas.
+/// The values of well-known metadata must be in the format of their name,
+/// and with `spdx`, a `LicenseTerms` that combines licenses a valid SPDX
+/// license expression.
+fn validate_package<R: Read + Seek>(
+    package: &mut Package<R>,
+    schema: bool,
+    spdx: bool,
+    memory_limit: Option<u64>,
+    timings: &mut Timings,
+) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let problem = |code, part: &str, message: String| Problem {
+        code,
+        part: part.to_string(),
+        line: 0,
+        column: 0,
+        message,
+    };
+    match package.root_model_name() {
+        Ok(name) if package.index_of(&name).is_none() => problems.push(problem(
+            diagnostics::MISSING_ROOT_MODEL,
+            "_rels/.rels",
+            format!("root model part {} is missing", name),
+        )),
+        Ok(_) => (),
+        Err(error @ Error::NoRootModel) => problems.push(problem(
+            diagnostics::MISSING_ROOT_MODEL,
+            "_rels/.rels",
+            error.to_string(),
+        )),
+        Err(error @ Error::XmlParse(_)) => problems.push(problem(
+            diagnostics::MALFORMED_XML,
+            "_rels/.rels",
+            error.to_string(),
+        )),
+        Err(error) => return Err(error),
+    }
+    let mut entries = package.entries();
+    while let Some(entry) = entries.next_entry() {
+        let entry = entry?;
+        if entry.path().is_none() || !entry.is_model_part() {
+            continue;
+        }
+        let part = entry.name().to_string();
+        let mut file = entry.into_zip_file();
+        // The part is held in memory next to its parsed form
+        if let Some(limit) = memory_limit {
+            if file.size().saturating_mul(package::PARSE_OVERHEAD + 1) > limit {
+                return Err(Error::TooLarge { part, limit });
+            }
+        }
+        let bytes = package::read_model_part(&mut file)?;
+        let result = Timings::measure(&mut timings.parse, || {
+            let xml = model::read_model(bytes.as_slice())?;
+            let errors = if schema {
+                schema::validate_model(bytes.as_slice())?
+            } else {
+                Vec::new()
+            };
+            Ok((xml, errors))
+        });
+        match result {
+            Ok((xml, errors)) => {
+                problems.extend(errors.into_iter().map(|error| Problem {
+                    code: diagnostics::SCHEMA,
+                    part: part.clone(),
+                    line: error.line,
+                    column: error.column,
+                    message: error.message,
+                }));
+                let mut counts: Vec<(&str, usize)> = Vec::new();
+                let names = xml
+                    .children
+                    .iter()
+                    .filter_map(|child| child.as_element())
+                    .filter(|element| element.name == "metadata")
+                    .filter_map(|element| element.attributes.get("name"));
+                for name in names {
+                    match counts.iter_mut().find(|(counted, _)| counted == name) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((name, 1)),
+                    }
+                }
+                for (name, count) in counts.into_iter().filter(|(_, count)| *count > 1) {
+                    problems.push(problem(
+                        diagnostics::DUPLICATE_METADATA,
+                        &part,
+                        format!("metadata name {} appears {} times", name, count),
+                    ));
+                }
+                for entry in &MetadataSet::from_model(&xml) {
+                    if let Err(error) = well_known::check_value(&entry.name, &entry.value) {
+                        problems.push(problem(
+                            diagnostics::INVALID_VALUE,
+                            &part,
+                            error.to_string(),
+                        ));
+                    }
+                    if spdx
+                        && entry.name == well_known::LICENSE_TERMS
+                        && LicenseExpression::is_compound(&entry.value)
+                    {
+                        if let Err(error) = LicenseExpression::parse_strict(&entry.value) {
+                            problems.push(problem(
+                                diagnostics::INVALID_VALUE,
+                                &part,
+                                error.to_string(),
+                            ));
+                        }
+                    }
+                }
+            }
+            Err(error @ Error::XmlParse(_)) => problems.push(problem(
+                diagnostics::MALFORMED_XML,
+                &part,
+                error.to_string(),
+            )),
+            Err(Error::Syntax(syntax)) => problems.push(Problem {
+                code: diagnostics::MALFORMED_XML,
+                part: part.clone(),
+                line: syntax.line,
+                column: syntax.column,
+                message: syntax.message,
+            }),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(problems)
+}
+
+/// Runs every check of `doctor` on the package: the checksums of the
+/// entries, `validate`, the content types, the warnings and, with
+/// `--against`, `check`.
+fn diagnose<R: Read + Seek>(
+    package: &mut Package<R>,
+    input_path: &Path,
+    doctor: &Doctor,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<Vec<Finding>> {
+    let mut findings = doctor::verify_entries(package)?;
+    let problems = validate_package(
+        package,
+        doctor.schema,
+        doctor.spdx,
+        options.memory_limit(),
+        timings,
+    )?;
+    findings.extend(problems.into_iter().map(|problem| Finding {
+        code: problem.code,
+        severity: Severity::Error,
+        message: problem.to_string(),
+        fix: None,
+    }));
+    findings.extend(doctor::check_content_types(package)?);
+    let root_metadata = match package::read_root_metadata(package) {
+        Ok(metadata) => Some(metadata),
+        // validate_package has reported the root model part already
+        Err(Error::XmlParse(_) | Error::Syntax(_)) => None,
+        Err(error) => return Err(error),
+    };
+    if let (Some(expected), Some(metadata)) = (&doctor.metadata_set, &root_metadata) {
+        let expected = with_conditional(expected, &doctor.conditional, metadata, input_path);
+        findings.extend(
+            metadata
+                .differences(&expected)
+                .into_iter()
+                .map(|difference| Finding {
+                    code: difference_code(&difference),
+                    severity: Severity::Error,
+                    message: difference.to_string(),
+                    fix: None,
+                }),
+        );
+    }
+    // Without the relationships or the root model part, the warnings that
+    // read them can't be looked for
+    let mut levels = options.warning_levels();
+    if root_metadata.is_none() {
+        levels.set(Warning::NonConformantName, Level::Allow);
+        levels.set(Warning::DeprecatedKey, Level::Allow);
+        levels.set(Warning::MissingThumbnail, Level::Allow);
+        levels.set(Warning::ThumbnailFormat, Level::Allow);
+    }
+    findings.extend(
+        warnings::check(package, &levels)?
+            .into_iter()
+            .map(|found| Finding {
+                code: found.warning.code(),
+                severity: match found.level {
+                    Level::Deny => Severity::Error,
+                    _ => Severity::Warning,
+                },
+                message: found.message,
+                fix: found.fix,
+            }),
+    );
+    Ok(findings)
+}
+
+/// The code `check` reports a difference with.
+fn difference_code(difference: &Difference) -> &'static str {
+    match difference {
+        Difference::Missing(_) => diagnostics::MISSING_METADATA,
+        Difference::Mismatch { .. } => diagnostics::MISMATCHED_METADATA,
+    }
+}
+
+fn process_file(
+    subcommand: &Subcommands,
+    input_path: &Path,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<()> {
+    // open input file
+    #[cfg(feature = "mmap")]
+    if options.mmap {
+        return process_package(
+            subcommand,
+            input_path,
+            Package::open_mmap(input_path)?,
+            options,
+            timings,
+        );
+    }
+    process_package(
+        subcommand,
+        input_path,
+        Package::open(input_path)?,
+        options,
+        timings,
+    )
+}
+
+fn process_package<R: Read + Seek>(
+    subcommand: &Subcommands,
+    input_path: &Path,
+    mut package: Package<R>,
+    options: &Options,
+    timings: &mut Timings,
+) -> Result<()> {
+    let parts = PartWriter {
+        threads: options.threads(),
+        compat: options.compat,
+        memory_limit: options.memory_limit(),
+    };
+    let unsafe_names = options.unsafe_names();
+    package = package.with_memory_limit(options.memory_limit());
+    if options.verify_copies {
+        package = package.with_verified_copies();
+    }
+    match &options.password {
+        Some(password) => package = package.with_password(password.as_bytes())?,
+        None => {
+            if let Some(name) = package.encrypted_entry_names()?.into_iter().next() {
+                return Err(Error::Encrypted(name));
+            }
+        }
+    }
+    // Refuse the package before any output is written
+    if unsafe_names == UnsafeNames::Reject {
+        if let Some(name) = package.unsafe_entry_names()?.into_iter().next() {
+            return Err(Error::UnsafeEntryName(name));
+        }
+    }
+    // Doctor lists the warnings with its other findings
+    let found = match subcommand {
+        Subcommands::Doctor(_) => Vec::new(),
+        _ => warnings::check(&mut package, &options.warning_levels())?,
+    };
+    let report = subcommand.report().filter(|report| report.is_sarif());
+    let mut denied = 0;
+    for found in found {
+        let (label, severity) = match found.level {
+            Level::Deny => ("Error", Severity::Error),
+            _ => ("Warning", Severity::Warning),
+        };
+        eprintln!(
+            "{}: {} in {} [{} {}]",
+            label,
+            found.message,
+            input_path.display(),
+            found.warning.code(),
+            found.warning
+        );
+        if let Some(report) = report {
+            // The log is only printed at the end, so a denied warning fails
+            // the file instead of stopping the run
+            if severity == Severity::Error {
+                report.fail(input_path);
+            }
+            let code = found.warning.code();
+            report.add(input_path, code, severity, found.message);
+        } else if severity == Severity::Error {
+            denied += 1;
+        }
+    }
+    if denied > 0 {
+        return Err(Error::Denied(denied));
+    }
+    match subcommand {
+        Subcommands::Add(add) if add.stdout_xml => {
+            let metadata = add.metadata_for(&mut package, input_path)?;
+            let name = package.root_model_name()?;
+            let index = package.index_of(&name).ok_or(Error::NoRootModel)?;
+            let bytes = package::read_model_part(&mut package.by_index(index)?)?;
+            let mut edit = model::MetadataEdit::new(&bytes)?;
+            if let Some(title) = &add.title_value {
+                eprintln!("setting title to {}", title);
+            }
+            merge_add(edit.xml_mut(), &metadata, add)?;
+            let mut xml = Vec::new();
+            edit.write_with(&mut xml, options.compat)?;
+            if !xml.ends_with(b"\n") {
+                xml.push(b'\n');
+            }
+            stdout().write_all(&xml)?;
+        }
+        Subcommands::Add(add) if add.append => {
+            let metadata = add.metadata_for(&mut package, input_path)?;
+            let root = package.root_model_name()?;
+            for conflict in add_conflicts(&mut package, &root, &metadata, add)? {
+                match add.conflicts {
+                    ConflictPolicy::Error => return Err(Error::ConflictingMetadata(conflict)),
+                    _ => eprintln!(
+                        "Warning: {}, and --append only changes the root model part",
+                        conflict
+                    ),
+                }
+            }
+            // The input is written to, so it must not be open or mapped
+            drop(package);
+            if options.dry_run {
+                eprintln!("Dry run, not appending to {}", input_path.display());
+                return Ok(());
+            }
+            let (mut file, copy) = open_in_place(input_path, options)?;
+            let comment = options.archive_comment.as_deref();
+            let appended = Timings::measure(&mut timings.write, || {
+                append::append_root_model(&mut file, comment, add.backup, |bytes| {
+                    let mut edit = model::MetadataEdit::new(bytes)?;
+                    if let Some(title) = &add.title_value {
+                        eprintln!("setting title to {}", title);
+                    }
+                    merge_add(edit.xml_mut(), &metadata, add)?;
+                    let mut xml = Vec::new();
+                    edit.write_with(&mut xml, options.compat)?;
+                    Ok(xml)
+                })
+            })?;
+            eprintln!(
+                "Appended model part {} to file {}, the old one leaves {} bytes unused",
+                appended.part,
+                input_path.display(),
+                appended.superseded
+            );
+            if let Some(backup) = appended.backup {
+                eprintln!("Kept the old one as {}", backup);
+            }
+            if let Some(copy) = copy {
+                stage_output(copy, input_path, options);
+            }
+        }
+        Subcommands::Add(add) => {
+            let output_path = add.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let metadata = add.metadata_for(&mut package, input_path)?;
+            let all_model_parts = add.all_model_parts || add.conflicts == ConflictPolicy::Merge;
+            let root = metadata_root(&mut package, all_model_parts)?;
+            let conflicts = match &root {
+                Some(root) => add_conflicts(&mut package, root, &metadata, add)?,
+                None => Vec::new(),
+            };
+            if add.conflicts == ConflictPolicy::Error {
+                if let Some(conflict) = conflicts.first() {
+                    return Err(Error::ConflictingMetadata(conflict.clone()));
+                }
+            }
+            let updates = root_wins_updates(&conflicts);
+            // copy all files from input to output
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    if let Some((_, update)) = updates.iter().find(|(part, _)| part == file.name())
+                    {
+                        return merge_and_copy(file, name, update, false, output, parts, timings);
+                    }
+                    if skips_part(&file, root.as_deref()) {
+                        return Ok(false);
+                    }
+                    update_xml_and_copy(file, name, &metadata, add, output, parts, timings)
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::PromoteSlicerMetadata(promote) => {
+            let output_path = promote.output_path.as_ref().unwrap();
+            let metadata = slicer::read_slicer_metadata(&mut package, &promote.prefix)?;
+            if metadata.is_empty() {
+                eprintln!(
+                    "Warning: no slicer settings found in {}",
+                    input_path.display()
+                );
+            }
+            let mut output = create_output(output_path, options)?;
+            let root = metadata_root(&mut package, false)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    if skips_part(&file, root.as_deref()) {
+                        return Ok(false);
+                    }
+                    merge_and_copy(
+                        file,
+                        name,
+                        &metadata,
+                        promote.keep_existing,
+                        output,
+                        parts,
+                        timings,
+                    )
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::Apply(apply) => {
+            let output_path = apply.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let metadata = apply.metadata_for(&mut package, input_path)?;
+            let root = metadata_root(&mut package, apply.all_model_parts)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    if skips_part(&file, root.as_deref()) {
+                        return Ok(false);
+                    }
+                    apply_and_copy(file, name, apply, &metadata, output, parts, timings)
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::ConvertUnits(convert) => {
+            let output_path = convert.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    convert_units_and_copy(file, name, convert.to, output, parts, timings)
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::Patch(patch) => {
+            let output_path = patch.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    let parsed = patch.parsed.as_ref().unwrap();
+                    patch_and_copy(file, name, parsed, output, parts, timings)
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::RenameKey(rename) => {
+            let output_path = rename.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| rename_and_copy(file, name, rename, output, parts, timings),
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::SetObject(set) => {
+            let part = match &set.part {
+                Some(part) => part.trim_start_matches('/').to_string(),
+                None => package.root_model_name()?,
+            };
+            let output_path = set.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| match file.name() == part {
+                    true => set_object_and_copy(file, name, set, output, parts, timings),
+                    false => Ok(false),
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::SetItem(set) => {
+            let part = match &set.part {
+                Some(part) => part.trim_start_matches('/').to_string(),
+                None => package.root_model_name()?,
+            };
+            let output_path = set.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| match file.name() == part {
+                    true => set_item_and_copy(file, name, set, output, parts, timings),
+                    false => Ok(false),
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::ReplaceValue(replace) => {
+            let output_path = replace.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let mut changed = false;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    let updated =
+                        replace_values_and_copy(file, name, replace, output, parts, timings)?;
+                    changed |= updated;
+                    Ok(updated)
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+            if changed {
+                replace.changed_files.set(replace.changed_files.get() + 1);
+            }
+        }
+        Subcommands::Anonymize(anonymize) => {
+            let mut removed_parts =
+                package.relationship_targets(package::CORE_PROPERTIES_RELATIONSHIP_TYPE)?;
+            let mut entries = package.entries();
+            while let Some(entry) = entries.next_entry() {
+                let entry = entry?;
+                if entry.content_type() == Some(package::CORE_PROPERTIES_CONTENT_TYPE)
+                    || (!anonymize.keep_slicer_settings && anonymize::is_slicer_part(entry.name()))
+                {
+                    removed_parts.push(entry.name().to_string());
+                }
+            }
+            let output_path = anonymize.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_entries(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    anonymize_and_copy(
+                        file,
+                        name,
+                        anonymize,
+                        &removed_parts,
+                        output,
+                        parts,
+                        timings,
+                    )
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::Repair(repair) => {
+            #[cfg(feature = "thumbnails")]
+            let thumbnails = match repair.thumbnail_size {
+                Some(_) => package.relationship_targets(package::THUMBNAIL_RELATIONSHIP_TYPE)?,
+                None => Vec::new(),
+            };
+            let output_path = repair.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            #[cfg(feature = "thumbnails")]
+            let resize = |file: ZipFile, name: &str, output: &mut _| match repair.thumbnail_size {
+                Some(size) if thumbnails.iter().any(|thumbnail| thumbnail == file.name()) => {
+                    thumbnail::copy_resized(file, name, size, output)
+                }
+                _ => Ok(false),
+            };
+            #[cfg(not(feature = "thumbnails"))]
+            let resize = |_: ZipFile, _: &str, _: &mut _| Ok(false);
+            let raw_names = match repair.dedupe_entries {
+                true => package::dedupe_entries_with(
+                    &mut package,
+                    &mut output,
+                    unsafe_names,
+                    repair.keep,
+                    resize,
+                )?,
+                false => package::copy_entries(&mut package, &mut output, unsafe_names, resize)?,
+            };
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::Remove(remove) => {
+            let output_path = remove.output_path.as_ref().unwrap();
+            let mut output = create_output(output_path, options)?;
+            let raw_names = package::copy_archive(
+                &mut package,
+                &mut output,
+                unsafe_names,
+                |file, name, output| {
+                    remove_and_copy(file, name, &remove.keys, output, parts, timings)
+                },
+            )?;
+            finish_output(output, raw_names, output_path, options)?;
+        }
+        Subcommands::Check(check) => {
+            let name = package.root_model_name()?;
+            let index = package.index_of(&name).ok_or(Error::NoRootModel)?;
+            let xml = Timings::measure(&mut timings.parse, || {
+                package::read_model_within(
+                    &mut package.by_index(index)?,
+                    options.memory_limit(),
+                    true,
+                )
+            })?;
+            let existing = MetadataSet::from_model(&xml);
+            let expected = with_conditional(
+                check.metadata_set.as_ref().unwrap(),
+                &check.conditional,
+                &existing,
+                input_path,
+            );
+            let differences = existing.differences(&expected);
+            let report = &check.report;
+            if !differences.is_empty() {
+                report.fail(input_path);
+            }
+            if report.is_sarif() {
+                for difference in differences {
+                    let code = difference_code(&difference);
+                    report.add(input_path, code, Severity::Error, difference.to_string());
+                }
+            } else if !options.porcelain {
+                if differences.is_empty() {
+                    println!("{}: ok", input_path.display());
+                }
+                for difference in differences {
+                    let code = difference_code(&difference);
+                    println!("{}: {} [{}]", input_path.display(), difference, code);
+                }
+            } else if differences.is_empty() {
+                porcelain_line(input_path, "ok", &[]);
+            } else {
+                for difference in differences {
+                    match difference {
+                        Difference::Missing(name) => {
+                            porcelain_line(input_path, "missing", &[&name])
+                        }
+                        Difference::Mismatch {
+                            name,
+                            expected,
+                            actual,
+                        } => porcelain_line(input_path, "mismatch", &[&name, &expected, &actual]),
+                    }
+                }
+            }
+        }
+        Subcommands::Validate(validate) => {
+            let problems = validate_package(
+                &mut package,
+                validate.schema,
+                validate.spdx,
+                options.memory_limit(),
+                timings,
+            )?;
+            let report = &validate.report;
+            if !problems.is_empty() {
+                report.fail(input_path);
+            }
+            if report.is_sarif() {
+                for problem in problems {
+                    report.add(
+                        input_path,
+                        problem.code,
+                        Severity::Error,
+                        problem.to_string(),
+                    );
+                }
+            } else if !options.porcelain {
+                if problems.is_empty() {
+                    println!("{}: ok", input_path.display());
+                }
+                for problem in problems {
+                    println!("{}: {} [{}]", input_path.display(), problem, problem.code);
+                }
+            } else if problems.is_empty() {
+                porcelain_line(input_path, "ok", &[]);
+            } else {
+                for problem in problems {
+                    porcelain_line(
+                        input_path,
+                        "invalid",
+                        &[
+                            &problem.part,
+                            &problem.line.to_string(),
+                            &problem.column.to_string(),
+                            &problem.message,
+                        ],
+                    );
+                }
+            }
+        }
+        Subcommands::Doctor(doctor) => {
+            let findings = diagnose(&mut package, input_path, doctor, options, timings)?;
+            let mut fixes = Vec::new();
+            if doctor.fix {
+                for fix in findings.iter().filter_map(|finding| finding.fix.clone()) {
+                    if !fixes.contains(&fix) {
+                        fixes.push(fix);
+                    }
+                }
+                let output_path = doctor.output_path.as_ref().unwrap();
+                let mut output = create_output(output_path, options)?;
+                let raw_names =
+                    doctor::apply_fixes(&mut package, &mut output, unsafe_names, &fixes)?;
+                finish_output(output, raw_names, output_path, options)?;
+            }
+            let report = &doctor.report;
+            for finding in &findings {
+                let fixed = finding.fix.is_some() && doctor.fix;
+                if finding.severity == Severity::Error && !fixed {
+                    report.fail(input_path);
+                }
+                if report.is_sarif() {
+                    let message = finding.message.clone();
+                    report.add(input_path, finding.code, finding.severity, message);
+                    continue;
+                }
+                let remedy = match (&finding.fix, fixed) {
+                    (Some(_), true) => "fixed",
+                    (Some(_), false) => "safe",
+                    (None, _) => "manual",
+                };
+                if options.porcelain {
+                    porcelain_line(
+                        input_path,
+                        "finding",
+                        &[finding.code, &finding.message, remedy, &finding.remedy()],
+                    );
+                    continue;
+                }
+                let label = match finding.severity {
+                    Severity::Error => "",
+                    Severity::Warning => "warning: ",
+                };
+                println!("{}: {}{}", input_path.display(), label, finding);
+                match remedy {
+                    "fixed" => println!("  fixed: {}", finding.remedy()),
+                    "safe" => println!("  fix: {} (--fix does this)", finding.remedy()),
+                    _ => println!("  fix: {}", finding.remedy()),
+                }
+            }
+            if findings.is_empty() && !report.is_sarif() {
+                match options.porcelain {
+                    true => porcelain_line(input_path, "ok", &[]),
+                    false => println!("{}: ok", input_path.display()),
+                }
+            }
+        }
+        Subcommands::Show(show) if show.all_sources => {
+            show_all_sources(&mut package, show, input_path, options)?;
+        }
+        Subcommands::Show(_) | Subcommands::Info(_) => {
+            if let Subcommands::Info(_) = subcommand {
+                show_producer(&mut package, input_path, options)?;
+                show_archive_comment(&mut package, input_path, options);
+                show_backups(&mut package, input_path, options);
+                show_thumbnails(&mut package, input_path, options)?;
+            }
+            let grouped = package
+                .archive()
+                .file_names()
+                .filter(|name| package::is_model_part(Path::new(name)))
+                .count()
+                > 1;
+            let mut names = Vec::new();
+            let mut model_parts = 0;
+            let mut entries = package.entries();
+            while let Some(entry) = entries.next_entry() {
+                let entry = entry?;
+                if entry.path().is_none() {
+                    eprintln!("Skipping entry with unsafe name \"{}\"", entry.name());
+                } else if entry.is_model_part() {
+                    model_parts += 1;
+                    match subcommand {
+                        Subcommands::Show(show) => {
+                            let metadata = show_metadata(
+                                entry.into_zip_file(),
+                                show,
+                                grouped,
+                                input_path,
+                                options,
+                                timings,
+                            )?;
+                            names.extend(metadata.into_iter().map(|entry| entry.name));
+                        }
+                        Subcommands::Info(info) => {
+                            show_info(entry.into_zip_file(), info, input_path, options, timings)?
+                        }
+                        _ => unreachable!("only show and info get here"),
+                    }
+                }
+            }
+            if let Subcommands::Show(show) = subcommand {
+                check_show_gates(show, &names, input_path);
+            }
+            // A ZIP file without a model part is not a model without metadata
+            if model_parts == 0 {
+                if options.strict {
+                    return Err(Error::NoModelPart);
+                }
+                match options.porcelain {
+                    true => porcelain_line(input_path, "no-model", &[]),
+                    false => eprintln!("No model part found in {}", input_path.display()),
+                }
+                return Ok(());
+            }
+            if let Subcommands::Show(show) = subcommand {
+                if show.summary {
+                    show_summary(&mut names, input_path, options);
+                }
+            }
+        }
+        #[cfg(feature = "regex")]
+        Subcommands::Grep(grep) => {
+            let mut entries = package.entries();
+            while let Some(entry) = entries.next_entry() {
+                let entry = entry?;
+                if entry.path().is_none() {
+                    eprintln!("Skipping entry with unsafe name \"{}\"", entry.name());
+                } else if entry.is_model_part() {
+                    grep_model(entry.into_zip_file(), grep, input_path, options, timings)?
+                }
+            }
+        }
+        Subcommands::Get(get) => {
+            let metadata = package::read_root_metadata(&mut package)?;
+            let value = match metadata.value(&get.name) {
+                Some(value) => value,
+                None => {
+                    eprintln!("No {} metadata in {}", get.name, input_path.display());
+                    get.failures.set(get.failures.get() + 1);
+                    return Ok(());
+                }
+            };
+            let mut stdout = stdout().lock();
+            if get.decode_base64 {
+                match decode_base64(value) {
+                    Some(bytes) => stdout.write_all(&bytes)?,
+                    None => {
+                        eprintln!(
+                            "The {} metadata of {} is not base64",
+                            get.name,
+                            input_path.display()
+                        );
+                        get.failures.set(get.failures.get() + 1);
+                    }
+                }
+            } else if get.raw {
+                stdout.write_all(value.as_bytes())?;
+            } else {
+                writeln!(stdout, "{}", value)?;
+            }
+        }
+        Subcommands::Catalog(catalog) => {
+            let mut card = Card::read(&mut package)?;
+            card.file_name = input_path
+                .file_name()
+                .unwrap_or(input_path.as_os_str())
+                .to_string_lossy()
+                .into_owned();
+            // Files outside the directory of the page are linked to by their
+            // absolute path
+            let path = fs::canonicalize(input_path)?;
+            card.path = path
+                .strip_prefix(&catalog.base)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned();
+            catalog.cards.borrow_mut().push(card);
+        }
+        Subcommands::Export(export) if export.canonical => {
+            let metadata = package::read_package_metadata(&mut package)?;
+            let path = input_path.to_string_lossy().into_owned();
+            export
+                .texts
+                .borrow_mut()
+                .push((path, export::canonical(&metadata)));
+        }
+        Subcommands::Export(export) if export.format == ExportFormat::Csv => {
+            let row = Row {
+                id: manifest::file_id(&mut package)?,
+                file: input_path.to_string_lossy().into_owned(),
+                metadata: package::read_root_metadata(&mut package)?,
+            };
+            export.rows.borrow_mut().push(row);
+        }
+        Subcommands::Export(export) => {
+            let metadata = package::read_root_metadata(&mut package)?;
+            let document = match export.format {
+                ExportFormat::Csv => unreachable!("csv is written as a manifest"),
+                ExportFormat::Json if export.spdx => export::json_with_spdx(&metadata),
+                ExportFormat::Json => export::json(&metadata),
+                ExportFormat::JsonLd => {
+                    let file_name = input_path
+                        .file_name()
+                        .unwrap_or(input_path.as_os_str())
+                        .to_string_lossy();
+                    export::json_ld(&metadata, &file_name)
+                }
+            };
+            let path = input_path.to_string_lossy().into_owned();
+            export.documents.borrow_mut().push((path, document));
+        }
+        Subcommands::Attribution(attribution) => {
+            let metadata = package::read_root_metadata(&mut package)?;
+            let file_name = input_path
+                .file_name()
+                .unwrap_or(input_path.as_os_str())
+                .to_string_lossy();
+            match attribution::Attribution::new(&metadata, &file_name) {
+                Ok(found) => println!("{}", found.render(attribution.format)),
+                Err(Error::Attribution(message)) => {
+                    eprintln!("No attribution for {}: {}", input_path.display(), message);
+                    attribution.failures.set(attribution.failures.get() + 1);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Subcommands::Explain(_) => unreachable!("explain doesn't read packages"),
+        Subcommands::Token(_) => unreachable!("token doesn't read packages"),
+        Subcommands::SelfTest(_) => unreachable!("self-test doesn't read packages"),
+        Subcommands::Promote(_) => unreachable!("promote doesn't read packages one by one"),
+        Subcommands::Undo(_) => {
+            // The input is written to, so it must not be open or mapped
+            drop(package);
+            if options.dry_run {
+                eprintln!("Dry run, not undoing {}", input_path.display());
+                return Ok(());
+            }
+            let (mut file, copy) = open_in_place(input_path, options)?;
+            let undone = Timings::measure(&mut timings.write, || {
+                append::undo(&mut file, options.archive_comment.as_deref())
+            })?;
+            eprintln!(
+                "Put back {} as model part {} of file {}",
+                undone.backup.unwrap_or_default(),
+                undone.part,
+                input_path.display()
+            );
+            if let Some(copy) = copy {
+                stage_output(copy, input_path, options);
+            }
+        }
+        #[cfg(feature = "net")]
+        Subcommands::Publish(publish) => {
+            let output_path = publish.output_path.as_ref().unwrap();
+            let metadata = package::read_root_metadata(&mut package)?;
+            let listing = publish::Listing::new(&metadata)?;
+            let name = publish::provenance_name(publish.to);
+            let existing = metadata.value(&name).and_then(publish::thing_id);
+            if options.dry_run {
+                match existing {
+                    Some(id) => eprintln!("Dry run, not updating thing:{}", id),
+                    None => eprintln!("Dry run, not creating a listing"),
+                }
+                return Ok(());
+            }
+            // What can fail before there is a listing does
+            let root = metadata_root(&mut package, false)?;
+            let mut output = create_output(output_path, options)?;
+            let token = publish.found_token.as_ref().unwrap();
+            let client = publish::Thingiverse::new(&publish.api_url, token.value());
+            let url = client.publish(&listing, existing)?;
+            match existing {
+                Some(_) => eprintln!("Updated {} from {}", url, input_path.display()),
+                None => eprintln!("Created {} from {}", url, input_path.display()),
+            }
+            let provenance = MetadataSet::builder().custom(&name, url.clone()).build()?;
+            let write = || {
+                let raw_names = package::copy_archive(
+                    &mut package,
+                    &mut output,
+                    unsafe_names,
+                    |file, name, output| {
+                        if skips_part(&file, root.as_deref()) {
+                            return Ok(false);
+                        }
+                        merge_and_copy(file, name, &provenance, false, output, parts, timings)
+                    },
+                )?;
+                finish_output(output, raw_names, output_path, options)
+            };
+            // Without the URL in the file, publishing it again would create
+            // another listing
+            write().map_err(|error| {
+                Error::Publish(format!(
+                    "{} is listed at {}, but the URL could not be written to {}: {}. Add it as {} before publishing the file again, or another listing is created",
+                    input_path.display(),
+                    url,
+                    output_path.display(),
+                    error,
+                    name
+                ))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The metadata of every model part of a package as a JSON object, for
+/// hooks. `null` when the package can't be read.
+fn metadata_json(path: &Path, options: &Options) -> serde_json::Value {
+    let read = || -> Result<serde_json::Value> {
+        let mut package = Package::open(path)?.with_memory_limit(options.memory_limit());
+        if let Some(password) = &options.password {
+            package = package.with_password(password.as_bytes())?;
+        }
+        let memory_limit = package.memory_limit();
+        let mut parts = serde_json::Map::new();
+        let mut entries = package.entries();
+        while let Some(entry) = entries.next_entry() {
+            let entry = entry?;
+            if entry.path().is_some() && entry.is_model_part() {
+                let name = entry.name().to_string();
+                let xml =
+                    package::read_model_within(&mut entry.into_zip_file(), memory_limit, true)?;
+                let metadata = MetadataSet::from_model(&xml)
+                    .into_iter()
+                    .map(|entry| (entry.name, entry.value.into()))
+                    .collect();
+                parts.insert(name, serde_json::Value::Object(metadata));
+            }
+        }
+        Ok(parts.into())
+    };
+    read().unwrap_or_else(|error| {
+        eprintln!(
+            "Warning: could not read metadata of {} for hook: {}",
+            path.display(),
+            error
+        );
+        serde_json::Value::Null
+    })
+}
+
+/// Runs a hook command through the shell, with `input` as JSON on its
+/// standard input. Its standard output goes to standard error, to keep it
+/// apart from the data this program prints.
+fn run_hook(command: &str, input: &serde_json::Value) -> io::Result<ExitStatus> {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(format!("{}\n", input).as_bytes()) {
+        // A hook doesn't have to read its input
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => (),
+        result => result?,
+    }
+    drop(stdin);
+    child.wait()
+}
+
+/// Runs the `--hook-pre` or `--hook-post` command for an input file, if
+/// given, and exits when it fails.
+fn hook(subcommand: &Subcommands, options: &Options, kind: &str, input_path: &Path) {
+    let command = match kind {
+        "pre" => &options.hook_pre,
+        _ => &options.hook_post,
+    };
+    let Some(command) = command else {
+        return;
+    };
+    let output_path = subcommand
+        .output_path()
+        .filter(|_| kind == "post" && !options.dry_run);
+    // Staged outputs of --transactional are only renamed at the end
+    let metadata = match output_path {
+        Some(output_path) => metadata_json(&staged_path(output_path, options), options),
+        None => metadata_json(input_path, options),
+    };
+    let input = serde_json::json!({
+        "hook": kind,
+        "subcommand": subcommand.name(),
+        "input": input_path.to_string_lossy(),
+        "output": output_path.map(|path| path.to_string_lossy()),
+        "dry_run": options.dry_run,
+        "metadata": metadata,
+    });
+    match run_hook(command, &input) {
+        Ok(status) if status.success() => (),
+        Ok(status) => {
+            eprintln!(
+                "{} hook for {} failed: {}",
+                kind,
+                input_path.display(),
+                status
+            );
+            end_run(options, 1);
+        }
+        Err(error) => {
+            eprintln!("Could not run {} hook {}: {}", kind, command, error);
+            end_run(options, 1);
+        }
+    }
+}
+
+/// Parses a metadata file, in TOML if it is named `*.toml`, and exits if
+/// that fails.
+fn parse_metadata_file(path: &Path, error_format: ErrorFormat) -> MetadataFile {
+    let toml = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+    let result = match File::open(path) {
+        #[cfg(feature = "toml")]
+        Ok(file) if toml => io::read_to_string(file)
+            .map_err(Error::from)
+            .and_then(|text| MetadataFile::read_toml(&text)),
+        #[cfg(not(feature = "toml"))]
+        Ok(_) if toml => Err(Error::MetadataFile(
+            "Metadata files in TOML need the toml feature".to_string(),
+        )),
+        Ok(file) => MetadataFile::read(BufReader::new(file)),
+        Err(error) => {
+            eprintln!("Could not open metadata file {}: {}", path.display(), error);
+            std::process::exit(1);
+        }
+    };
+    match result {
+        Ok(file) => file,
+        Err(error) => {
+            match (&error, error_format) {
+                (Error::Syntax(_), _) | (_, ErrorFormat::Json) => print_error(
+                    error_format,
+                    &format!("Could not parse metadata file {}", path.display()),
+                    path,
+                    &error,
+                ),
+                // Fragments of a directory need saying which one it is
+                (Error::MetadataFile(_), _) => {
+                    eprintln!("{} (in {})", error, path.display())
+                }
+                _ => eprintln!("{}", error),
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads a metadata file, or a directory of fragments of one merged in
+/// order, or a preset from it, expanding environment variables in its
+/// values, and exits if that fails. Returns the entries along with the
+/// conditional ones.
+fn read_metadata_file(
+    path: &OsStr,
+    preset: Option<&str>,
+    error_format: ErrorFormat,
+) -> (MetadataSet, Vec<Conditional>) {
+    let path = Path::new(path);
+    let (file, what) = match path.is_dir() {
+        true => {
+            let fragments = match metadata::fragments(path) {
+                Ok(fragments) if fragments.is_empty() => {
+                    eprintln!(
+                        "Metadata directory {} has no *.xml or *.toml fragments",
+                        path.display()
+                    );
+                    std::process::exit(1);
+                }
+                Ok(fragments) => fragments,
+                Err(error) => {
+                    eprintln!(
+                        "Could not read metadata directory {}: {}",
+                        path.display(),
+                        error
+                    );
+                    std::process::exit(1);
+                }
+            };
+            let mut file = MetadataFile::default();
+            for fragment in fragments {
+                file.merge(parse_metadata_file(&fragment, error_format));
+            }
+            (file, "Metadata directory")
+        }
+        false => (parse_metadata_file(path, error_format), "Metadata file"),
+    };
+    let metadata = match preset {
+        None => Ok(file.entries),
+        Some(name) => file.preset(name).ok_or_else(|| {
+            let presets: Vec<_> = file.preset_names().collect();
+            Error::MetadataFile(format!(
+                "{} {} has no preset {}, it has: {}",
+                what,
+                path.display(),
+                name,
+                if presets.is_empty() {
+                    "none".to_string()
+                } else {
+                    presets.join(", ")
+                }
+            ))
+        }),
+    };
+    // Values can refer to environment variables as ${NAME}. What they
+    // expand to is only known here, so well-known values are checked here,
+    // saying which variables they came from
+    let expand = |metadata: MetadataSet| {
+        metadata
+            .into_iter()
+            .map(|mut entry| {
+                let expanded =
+                    metadata::expand_variables(&entry.value, |name| std::env::var(name).ok())?;
+                if expanded != entry.value {
+                    well_known::check_value(&entry.name, &expanded).map_err(|error| {
+                        Error::MetadataFile(format!(
+                            "{}, which \"{}\" expanded to",
+                            error, entry.value
+                        ))
+                    })?;
+                }
+                entry.value = expanded;
+                Ok(entry)
+            })
+            .collect::<Result<MetadataSet>>()
+    };
+    let result = metadata.and_then(|metadata| {
+        let conditional = file
+            .conditional
+            .into_iter()
+            .map(|conditional| {
+                Ok(Conditional {
+                    entries: expand(conditional.entries)?,
+                    ..conditional
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((expand(metadata)?, conditional))
+    });
+    match result {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            match error_format {
+                ErrorFormat::Json => print_error(
+                    error_format,
+                    &format!("Could not read metadata from {}", path.display()),
+                    path,
+                    &error,
+                ),
+                ErrorFormat::Human => eprintln!("{}", error),
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `metadata` with the entries of the conditionals that apply to the file
+/// at `input_path` over it, where `existing` is the metadata of its root
+/// model part.
+fn with_conditional(
+    metadata: &MetadataSet,
+    conditional: &[Conditional],
+    existing: &MetadataSet,
+    input_path: &Path,
+) -> MetadataSet {
+    let mut metadata = metadata.clone();
+    if conditional.is_empty() {
+        return metadata;
+    }
+    let path = fs::canonicalize(input_path).unwrap_or_else(|_| input_path.to_path_buf());
+    for conditional in conditional {
+        if conditional.applies(existing, &path) {
+            metadata.extend(conditional.entries.iter().cloned());
+        }
+    }
+    metadata
+}
+
+/// Prints what a well-known metadata name means, or lists them all.
+fn explain(explain: &Explain) {
+    let Some(name) = &explain.name else {
+        for explanation in &well_known::EXPLANATIONS {
+            println!("{:<18}{}", explanation.name, explanation.meaning);
+        }
+        return;
+    };
+    let Some(explanation) = well_known::explain(name) else {
+        if name.contains(':') {
+            eprintln!(
+                "{} has a namespace prefix, so its meaning is up to whoever defined the namespace",
+                name
+            );
+        } else {
+            eprintln!(
+                "{} is not a well-known metadata name, those are: {}",
+                name,
+                well_known::ALL.join(", ")
+            );
+        }
+        std::process::exit(1);
+    };
+    println!("{}", explanation.name);
+    println!("{}", explanation.meaning);
+    println!("Format: {}", explanation.format.description());
+    println!("Examples: {}", explanation.examples.join(", "));
+}
+
+/// The metadata entry that self-test adds and removes again.
+const SELF_TEST_ENTRY: (&str, &str) = ("Copyright", "Copyright (c) metadata_3mf self-test");
+
+/// A step of self-test, with what it does, which fails with a message.
+type SelfTestStep<'a> = (&'a str, &'a dyn Fn() -> std::result::Result<(), String>);
+
+/// A package with a single triangle and a title, for self-test.
+fn self_test_package() -> Result<Vec<u8>> {
+    let content_types = format!(
+        r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="{}"/><Default Extension="model" ContentType="{}"/></Types>"#,
+        package::RELATIONSHIPS_CONTENT_TYPE,
+        package::MODEL_CONTENT_TYPE
+    );
+    let relationships = format!(
+        r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Target="/3D/3dmodel.model" Id="rel0" Type="{}"/></Relationships>"#,
+        package::MODEL_RELATIONSHIP_TYPE
+    );
+    let model = format!(
+        r#"<model unit="millimeter" xml:lang="en-US" xmlns="{}"><metadata name="Title">Self-test</metadata><resources><object id="1" type="model"><mesh><vertices><vertex x="0" y="0" z="0"/><vertex x="10" y="0" z="0"/><vertex x="0" y="10" z="0"/></vertices><triangles><triangle v1="0" v2="1" v3="2"/></triangles></mesh></object></resources><build><item objectid="1"/></build></model>"#,
+        schema::CORE_NAMESPACE
+    );
+    let mut output = ZipWriter::new(io::Cursor::new(Vec::new()));
+    for (name, contents) in [
+        (package::CONTENT_TYPES_PART, content_types),
+        (package::RELATIONSHIPS_PART, relationships),
+        ("3D/3dmodel.model", model),
+    ] {
+        output.start_file(name, zip::write::FileOptions::default())?;
+        output.write_all(contents.as_bytes())?;
+    }
+    Ok(output.finish()?.into_inner())
+}
+
+/// Makes a package in a temporary directory and runs add, show, remove and
+/// validate on it with this program, printing how each went. Stops at the
+/// first step that fails, as the later ones need its output.
+fn self_test(self_test: &SelfTest) {
+    let dir = match &self_test.dir {
+        Some(dir) => tempfile::tempdir_in(dir),
+        None => tempfile::tempdir(),
+    };
+    let dir = match dir {
+        Ok(dir) => dir,
+        Err(error) => {
+            eprintln!("Could not make a temporary directory: {}", error);
+            std::process::exit(1);
+        }
+    };
+    println!("Testing in {}", dir.path().display());
+    let input = dir.path().join("self-test.3mf");
+    let licensed = dir.path().join("self-test_licensed.3mf");
+    let stripped = dir.path().join("self-test_licensed_stripped.3mf");
+    let metadata = dir.path().join("metadata.xml");
+    let (name, value) = SELF_TEST_ENTRY;
+
+    let run = |args: &[&OsStr]| -> std::result::Result<String, String> {
+        let program = std::env::current_exe().map_err(|error| error.to_string())?;
+        let output = Command::new(program)
+            .arg("--non-interactive")
+            .args(args)
+            .output()
+            .map_err(|error| error.to_string())?;
+        match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        }
+    };
+    let show = |path: &Path| run(&["show".as_ref(), path.as_ref()]);
+    let steps: [SelfTestStep; 5] = [
+        ("create", &|| {
+            let package = self_test_package().map_err(|error| error.to_string())?;
+            fs::write(&input, package).map_err(|error| error.to_string())?;
+            let entry = format!("<v1><metadata name=\"{}\">{}</metadata></v1>", name, value);
+            fs::write(&metadata, entry).map_err(|error| error.to_string())
+        }),
+        ("add", &|| {
+            run(&[
+                "add".as_ref(),
+                "-m".as_ref(),
+                metadata.as_ref(),
+                input.as_ref(),
+            ])?;
+            match licensed.exists() {
+                true => Ok(()),
+                false => Err(format!("{} was not written", licensed.display())),
+            }
+        }),
+        ("show", &|| match show(&licensed)?.contains(value) {
+            true => Ok(()),
+            false => Err(format!("{} is not in the added metadata", name)),
+        }),
+        ("remove", &|| {
+            run(&[
+                "remove".as_ref(),
+                "-k".as_ref(),
+                name.as_ref(),
+                licensed.as_ref(),
+            ])?;
+            match show(&stripped)?.contains(value) {
+                true => Err(format!("{} is still there after removing it", name)),
+                false => Ok(()),
+            }
+        }),
+        ("validate", &|| {
+            run(&["validate".as_ref(), stripped.as_ref()]).map(|_| ())
+        }),
+    ];
+    for (step, test) in steps {
+        match test() {
+            Ok(()) => println!("{}: ok", step),
+            Err(error) => {
+                println!("{}: failed", step);
+                eprintln!("{}", error);
+                println!("Self-test failed");
+                std::process::exit(1);
+            }
+        }
+    }
+    println!("Self-test passed");
+}
+
+/// Stores, removes or lists the API tokens of services.
+fn token(token: &Token, options: &Options) {
+    if token.list {
+        for service in Service::ALL {
+            match credentials::find(service) {
+                Ok(Some(found)) => println!("{}: {}", service, found.source),
+                Ok(None) => println!("{}: no token", service),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+    let service = token.service.unwrap();
+    if token.remove {
+        match credentials::remove(service) {
+            Ok(true) => eprintln!("Removed the token of {}", service),
+            Ok(false) => eprintln!("There was no stored token of {}", service),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if io::stdin().is_terminal() {
+        if options.non_interactive {
+            eprintln!(
+                "No token for {} on standard input, and --non-interactive doesn't ask for one",
+                service
+            );
+            std::process::exit(1);
+        }
+        eprint!("Token for {}: ", service);
+    }
+    let mut line = String::new();
+    if let Err(error) = io::stdin().read_line(&mut line) {
+        eprintln!("Could not read the token: {}", error);
+        std::process::exit(1);
+    }
+    #[cfg(feature = "keyring")]
+    let stored = match token.keyring {
+        true => credentials::store_in_keyring(service, line.trim()),
+        false => credentials::store(service, line.trim()),
+    };
+    #[cfg(not(feature = "keyring"))]
+    let stored = credentials::store(service, line.trim());
+    match stored {
+        Ok(source) => eprintln!("Stored the token of {} in {}", service, source),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+    // The environment comes first, so the stored token isn't the one used
+    if std::env::var_os(service.env_var()).is_some() {
+        eprintln!("Warning: {} is set, and is used instead", service.env_var());
+    }
+}
+
+/// Prints what --version-info reports, one `name: values` line each so
+/// that scripts can look for what they need.
+fn version_info() {
+    let list = |names: Vec<&str>| match names.is_empty() {
+        true => "none".to_string(),
+        false => names.join(", "),
+    };
+    let features = |built: bool| {
+        metadata_3mf::FEATURES
+            .iter()
+            .filter(|feature| feature.1 == built)
+            .map(|feature| feature.0)
+            .collect()
+    };
+    println!("metadata_3mf {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "platform: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    println!("features: {}", list(features(true)));
+    println!("without features: {}", list(features(false)));
+    let built = |formats: &[(&'static str, bool)]| {
+        formats
+            .iter()
+            .filter(|format| format.1)
+            .map(|format| format.0)
+            .collect()
+    };
+    println!(
+        "read compression: {}",
+        list(built(&[
+            ("stored", true),
+            ("deflated", true),
+            ("bzip2", cfg!(feature = "bzip2")),
+            ("zstd", cfg!(feature = "zstd")),
+        ]))
+    );
+    println!("write compression: stored, deflated");
+    println!(
+        "read encryption: {}",
+        list(built(&[
+            ("zipcrypto", true),
+            ("aes", cfg!(feature = "aes"))
+        ]))
+    );
+    println!(
+        "metadata files: {}",
+        list(built(&[("xml", true), ("toml", cfg!(feature = "toml"))]))
+    );
+    println!("thumbnails: png, jpeg");
+    println!("show formats: {}", ShowFormat::NAMES.join(", "));
+    println!("export formats: {}", ExportFormat::NAMES.join(", "));
+    println!("report formats: {}", ReportFormat::NAMES.join(", "));
+    println!("error formats: {}", ErrorFormat::NAMES.join(", "));
+}
+
+/// The input files of the command line, with globs expanded on Windows,
+/// directories searched with --recursive, and --start-after, --limit and
+/// --sample applied.
+fn expand_input_files(input_files: &[OsString], options: &Options) -> Vec<PathBuf> {
+    #[cfg(windows)]
+    let expanded_input_files = input_files
+        .iter()
+        .flat_map(|file_name| {
+            if let Some(file_name) = file_name.to_str() {
+                glob::glob(file_name)
+                    .unwrap_or_else(|_| panic!("Could not glob {}", file_name))
+                    .map(|path| path.unwrap())
+                    .collect::<Vec<_>>()
             } else {
                 vec![PathBuf::from(file_name)]
             }
@@ -256,123 +5720,1081 @@ fn main() {
         .collect::<Vec<_>>();
 
     #[cfg(not(windows))]
-    let expanded_input_files = input_files
-        .iter()
-        .map(|file_name| PathBuf::from(file_name))
-        .collect::<Vec<_>>();
+    let expanded_input_files = input_files.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    let expanded_input_files = if options.recursive {
+        let mut files = Vec::new();
+        for path in expanded_input_files {
+            if path.is_dir() {
+                if let Err(error) = find_3mf_files(&path, options, &mut files) {
+                    eprintln!("Could not read directory {}: {}", path.display(), error);
+                    std::process::exit(1);
+                }
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    } else {
+        expanded_input_files
+    };
+
+    let expanded_input_files = match &options.start_after {
+        Some(start_after) => {
+            // The same file may be named differently than on the earlier run
+            let canonical = fs::canonicalize(start_after).ok();
+            let same = |path: &PathBuf| {
+                path == start_after
+                    || canonical.is_some() && fs::canonicalize(path).ok() == canonical
+            };
+            let Some(position) = expanded_input_files.iter().position(same) else {
+                eprintln!(
+                    "--start-after {} is not one of the input files",
+                    start_after.display()
+                );
+                std::process::exit(1);
+            };
+            expanded_input_files[position + 1..].to_vec()
+        }
+        None => expanded_input_files,
+    };
+
+    match (options.limit, options.sample) {
+        (Some(limit), _) => expanded_input_files.into_iter().take(limit).collect(),
+        (_, Some(count)) => {
+            let seed = options.seed.unwrap_or_else(|| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                now.as_nanos() as u64
+            });
+            eprintln!(
+                "Sampling {} of {} files with --seed {}",
+                count.min(expanded_input_files.len()),
+                expanded_input_files.len(),
+                seed
+            );
+            sample_files(expanded_input_files, count, seed)
+        }
+        _ => expanded_input_files,
+    }
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+    // --version-info works without a subcommand, like --version
+    if cli.version_info {
+        version_info();
+        return;
+    }
+    // Anything else needs one, and gets the help without, as clap does
+    let Some(mut subcommand) = cli.subcommand.take() else {
+        let _ = Cli::command().write_help(&mut io::stderr());
+        std::process::exit(2);
+    };
+    cli.options.subcommand = subcommand.name();
+    cli.options.progress = Arc::new(Progress {
+        error_format: cli.options.error_format,
+        ..Default::default()
+    });
+    if let Some(dir) = &cli.options.workdir {
+        if let Err(error) = std::env::set_current_dir(dir) {
+            eprintln!("Could not change to {}: {}", dir.display(), error);
+            std::process::exit(1);
+        }
+    }
+    if let Subcommands::Explain(ref subcommand) = subcommand {
+        explain(subcommand);
+        return;
+    }
+    if let Subcommands::Token(ref subcommand) = subcommand {
+        token(subcommand, &cli.options);
+        return;
+    }
+    if let Subcommands::SelfTest(ref subcommand) = subcommand {
+        self_test(subcommand);
+        return;
+    }
+    if let Subcommands::Promote(ref promote) = subcommand {
+        let input_files = expand_input_files(&promote.input_files, &cli.options);
+        promote_files(promote, &input_files, &cli.options);
+        end_run(&cli.options, 0);
+    }
+    //eprintln!("{:?}", args);
+
+    if let Some(comment) = &cli.options.archive_comment {
+        if comment.len() > package::MAX_COMMENT_LENGTH {
+            eprintln!(
+                "--archive-comment is {} bytes long, ZIP archives allow at most {}",
+                comment.len(),
+                package::MAX_COMMENT_LENGTH
+            );
+            std::process::exit(1);
+        }
+    }
+
+    match subcommand {
+        Subcommands::Add(ref mut add) => {
+            let mut metadata = match (add.builtin_preset, &add.designer) {
+                (Some(preset), Some(designer)) => {
+                    // The copyright year is reproducible like the stamped dates
+                    let now = match package::source_date_epoch() {
+                        Ok(epoch) => epoch.unwrap_or_else(OffsetDateTime::now_utc),
+                        Err(error) => {
+                            eprintln!("{}", error);
+                            std::process::exit(1);
+                        }
+                    };
+                    preset.metadata(designer, now.year())
+                }
+                _ if add.metadata.is_none()
+                    && add.metadata_dir.is_none()
+                    && (add.package_defaults || add.manifest.is_some())
+                    && add.preset.is_none() =>
+                {
+                    MetadataSet::new()
+                }
+                _ => {
+                    let (metadata, conditional) = read_metadata_file(
+                        add.metadata_path(),
+                        add.preset.as_deref(),
+                        cli.options.error_format,
+                    );
+                    add.conditional = conditional;
+                    metadata
+                }
+            };
+            if add.aliases {
+                metadata.unalias();
+                for conditional in &mut add.conditional {
+                    conditional.entries.unalias();
+                }
+            }
+            for key in add.only.iter().chain(&add.skip) {
+                if !metadata.contains(key) {
+                    match add.builtin_preset {
+                        Some(preset) => {
+                            eprintln!("Warning: built-in preset {} has no entry {}", preset, key)
+                        }
+                        None if add.package_defaults || add.manifest.is_some() => (),
+                        None => eprintln!(
+                            "Warning: metadata file {} has no entry {}",
+                            add.metadata_path().to_string_lossy(),
+                            key
+                        ),
+                    }
+                }
+            }
+            let included = |entry: &MetadataEntry| {
+                (add.only.is_empty() || add.only.contains(&entry.name))
+                    && !add.skip.contains(&entry.name)
+            };
+            metadata.retain(included);
+            for conditional in &mut add.conditional {
+                conditional.entries.retain(included);
+            }
+            if metadata.is_empty()
+                && add.conditional.iter().all(|c| c.entries.is_empty())
+                && !add.package_defaults
+                && add.manifest.is_none()
+            {
+                eprintln!("Warning: no entries of the metadata file are left to add");
+            }
+            for assignment in &add.set_from_env {
+                let Some((mut key, variable)) = assignment.split_once('=') else {
+                    eprintln!("--set-from-env needs KEY=VAR, got {}", assignment);
+                    std::process::exit(1);
+                };
+                if let Some(known) = well_known::unalias(key).filter(|_| add.aliases) {
+                    key = known;
+                }
+                if let Err(error) = well_known::validate_name(key) {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+                let value = std::env::var(variable)
+                    .map_err(|error| error.to_string())
+                    .and_then(|value| {
+                        well_known::check_value(key, &value)
+                            .map(|()| value)
+                            .map_err(|error| error.to_string())
+                    });
+                match value {
+                    Ok(value) => metadata.set(key, value),
+                    Err(error) => {
+                        eprintln!("Environment variable {}: {}", variable, error);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            // The entries get the checks of the builder, as in the library.
+            // Names are copied as they are, --set-from-env checked its own
+            let build = |metadata: MetadataSet| {
+                metadata
+                    .into_iter()
+                    .fold(
+                        MetadataSet::builder(),
+                        MetadataSetBuilder::entry_with_any_name,
+                    )
+                    .build()
+            };
+            let built = build(metadata).and_then(|metadata| {
+                for conditional in &mut add.conditional {
+                    conditional.entries = build(std::mem::take(&mut conditional.entries))?;
+                }
+                Ok(metadata)
+            });
+            let metadata = match built {
+                Ok(metadata) => metadata,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            };
+            add.metadata_set = Some(metadata);
+            if let Some(path) = &add.manifest {
+                match File::open(path)
+                    .map_err(Error::from)
+                    .and_then(|file| match add.aliases {
+                        true => Manifest::read_with_aliases(file),
+                        false => Manifest::read(file),
+                    }) {
+                    Ok(manifest) => add.manifest_rows = Some(manifest),
+                    Err(error) => {
+                        eprintln!(
+                            "Could not read manifest {}: {}",
+                            path.to_string_lossy(),
+                            error
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if add.stamp_dates {
+                let date = match &add.date {
+                    Some(date) => OffsetDateTime::parse(date, &Rfc3339)
+                        .map_err(|error| format!("Invalid --date {}: {}", date, error)),
+                    None => package::source_date_epoch()
+                        .map(|epoch| epoch.unwrap_or_else(OffsetDateTime::now_utc))
+                        .map_err(|error| error.to_string()),
+                };
+                match date {
+                    // Sub-second precision would only be noise in metadata
+                    Ok(date) => add.stamp_date = date.replace_nanosecond(0).ok(),
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Subcommands::Check(ref mut check) => {
+            let (metadata, conditional) =
+                read_metadata_file(&check.against, None, cli.options.error_format);
+            check.metadata_set = Some(metadata);
+            check.conditional = conditional;
+        }
+        Subcommands::Doctor(ref mut doctor) => {
+            let output_args = &doctor.output_args;
+            if !doctor.fix && (output_args.output.is_some() || output_args.output_dir.is_some()) {
+                eprintln!(
+                    "doctor only writes files with --fix, so --output and --output-dir need it"
+                );
+                std::process::exit(1);
+            }
+            if let Some(against) = &doctor.against {
+                let (metadata, conditional) =
+                    read_metadata_file(against, None, cli.options.error_format);
+                doctor.metadata_set = Some(metadata);
+                doctor.conditional = conditional;
+            }
+        }
+        Subcommands::Patch(ref mut patch) => {
+            let json = match (&patch.patch, &patch.patch_file) {
+                (Some(json), _) => json.clone(),
+                (None, Some(path)) => match fs::read_to_string(path) {
+                    Ok(json) => json,
+                    Err(error) => {
+                        eprintln!("Could not read patch file {}: {}", path.display(), error);
+                        std::process::exit(1);
+                    }
+                },
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            match patch::Patch::parse(&json) {
+                Ok(parsed) => patch.parsed = Some(parsed),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        #[cfg(feature = "thumbnails")]
+        Subcommands::Repair(ref repair)
+            if !repair.dedupe_entries && repair.thumbnail_size.is_none() =>
+        {
+            eprintln!("Nothing to repair, use --dedupe-entries or --thumbnail-size");
+            std::process::exit(1);
+        }
+        #[cfg(not(feature = "thumbnails"))]
+        Subcommands::Repair(ref repair) if !repair.dedupe_entries => {
+            eprintln!("Nothing to repair, use --dedupe-entries");
+            std::process::exit(1);
+        }
+        Subcommands::SetObject(ref set) if set.name.is_none() && set.partnumber.is_none() => {
+            eprintln!("Nothing to set, use --name or --partnumber");
+            std::process::exit(1);
+        }
+        Subcommands::SetItem(ref set) => {
+            if set.item == 0 {
+                eprintln!("Build items are numbered from 1, see info --items");
+                std::process::exit(1);
+            }
+            if set.objectid.is_none()
+                && set.transform.is_none()
+                && set.partnumber.is_none()
+                && set.path.is_none()
+            {
+                eprintln!("Nothing to set, use --objectid, --transform, --partnumber or --path");
+                std::process::exit(1);
+            }
+            if let Some(transform) = set.transform.as_deref() {
+                if !transform.is_empty() && !model::is_transform(transform) {
+                    eprintln!(
+                        "Invalid transform \"{}\", expected 12 numbers separated by spaces",
+                        transform
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        Subcommands::RenameKey(ref rename) => {
+            if let Err(error) = well_known::validate_name(&rename.to) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+            if rename.from == rename.to {
+                eprintln!("--from and --to are both {}", rename.from);
+                std::process::exit(1);
+            }
+        }
+        Subcommands::Apply(ref mut apply) => {
+            let Some(path) = apply.config_path() else {
+                eprintln!("No config file, use --config");
+                std::process::exit(1);
+            };
+            match Config::read(&path) {
+                Ok(config) => apply.settings = config,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Subcommands::Anonymize(ref mut anonymize) => {
+            anonymize.keys = anonymize::DEFAULT_KEYS
+                .iter()
+                .map(|key| key.to_string())
+                .chain(anonymize.also.iter().cloned())
+                .collect();
+        }
+        #[cfg(feature = "regex")]
+        Subcommands::Grep(ref mut grep) => {
+            let pattern = if grep.fixed_strings {
+                regex::escape(&grep.pattern)
+            } else {
+                grep.pattern.clone()
+            };
+            match RegexBuilder::new(&pattern)
+                .case_insensitive(grep.ignore_case)
+                .build()
+            {
+                Ok(regex) => grep.regex = Some(regex),
+                Err(error) => {
+                    eprintln!("Invalid regular expression: {}", error);
+                    std::process::exit(1);
+                }
+            }
+            grep.highlight = match grep.color.as_str() {
+                "always" => true,
+                "never" => false,
+                _ => {
+                    !cli.options.non_interactive
+                        && stdout().is_terminal()
+                        && std::env::var_os("NO_COLOR").is_none()
+                }
+            };
+        }
+        Subcommands::Catalog(ref mut catalog) => {
+            if catalog.output.exists() && !catalog.force {
+                eprintln!(
+                    "{} already exists, use -f or --force to ignore",
+                    catalog.output.display()
+                );
+                std::process::exit(1);
+            }
+            let parent = match catalog.output.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            match fs::canonicalize(parent) {
+                Ok(base) => catalog.base = base,
+                Err(error) => {
+                    eprintln!("Could not find {}: {}", parent.display(), error);
+                    std::process::exit(1);
+                }
+            }
+            // A catalog is of a directory, so always look inside them
+            cli.options.recursive = true;
+        }
+        #[cfg(feature = "net")]
+        Subcommands::Publish(ref mut publish) => {
+            let found = match publish.token.take() {
+                Some(token) => Ok(Some(credentials::Token::new(
+                    token,
+                    credentials::Source::CommandLine,
+                ))),
+                None => credentials::find(publish.to),
+            };
+            match found {
+                Ok(Some(token)) => publish.found_token = Some(token),
+                // A dry run doesn't talk to the site
+                Ok(None) if cli.options.dry_run => (),
+                Ok(None) => {
+                    eprintln!(
+                        "No API token for {}, store one with `metadata_3mf token {}` or set {}",
+                        publish.to,
+                        publish.to,
+                        publish.to.env_var()
+                    );
+                    std::process::exit(1);
+                }
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Subcommands::ReplaceValue(ref replace) if replace.find.is_empty() => {
+            eprintln!("--find must not be empty");
+            std::process::exit(1);
+        }
+        #[cfg(feature = "regex")]
+        Subcommands::ReplaceValue(ref mut replace) => {
+            let pattern = if replace.regex {
+                Regex::new(&replace.find)
+            } else {
+                Regex::new(&regex::escape(&replace.find))
+            };
+            match pattern {
+                Ok(pattern) => replace.pattern = Some(pattern),
+                Err(error) => {
+                    eprintln!("Invalid regular expression: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => (),
+    }
+
+    let input_files = match subcommand {
+        Subcommands::Add(ref add) => &add.input_files,
+        Subcommands::Show(ref show) => &show.input_files,
+        Subcommands::Info(ref info) => &info.input_files,
+        #[cfg(feature = "regex")]
+        Subcommands::Grep(ref grep) => &grep.input_files,
+        Subcommands::Get(ref get) => &get.input_files,
+        Subcommands::Catalog(ref catalog) => &catalog.input_files,
+        Subcommands::Export(ref export) => &export.input_files,
+        Subcommands::Attribution(ref attribution) => &attribution.input_files,
+        Subcommands::ConvertUnits(ref convert) => &convert.input_files,
+        Subcommands::Remove(ref remove) => &remove.input_files,
+        Subcommands::Patch(ref patch) => &patch.input_files,
+        Subcommands::RenameKey(ref rename) => &rename.input_files,
+        Subcommands::SetObject(ref set) => &set.input_files,
+        Subcommands::SetItem(ref set) => &set.input_files,
+        Subcommands::ReplaceValue(ref replace) => &replace.input_files,
+        Subcommands::Anonymize(ref anonymize) => &anonymize.input_files,
+        Subcommands::Apply(ref apply) => &apply.input_files,
+        Subcommands::PromoteSlicerMetadata(ref promote) => &promote.input_files,
+        Subcommands::Repair(ref repair) => &repair.input_files,
+        Subcommands::Undo(ref undo) => &undo.input_files,
+        #[cfg(feature = "net")]
+        Subcommands::Publish(ref publish) => &publish.input_files,
+        Subcommands::Check(ref check) => &check.input_files,
+        Subcommands::Validate(ref validate) => &validate.input_files,
+        Subcommands::Doctor(ref doctor) => &doctor.input_files,
+        Subcommands::Explain(_) => unreachable!("explain has returned already"),
+        Subcommands::Token(_) => unreachable!("token has returned already"),
+        Subcommands::SelfTest(_) => unreachable!("self-test has returned already"),
+        Subcommands::Promote(_) => unreachable!("promote has returned already"),
+    };
+
+    let expanded_input_files = expand_input_files(input_files, &cli.options);
+
+    let output = match subcommand {
+        Subcommands::Add(ref add) => &add.output_args.output,
+        Subcommands::ConvertUnits(ref convert) => &convert.output_args.output,
+        Subcommands::Remove(ref remove) => &remove.output_args.output,
+        Subcommands::Patch(ref patch) => &patch.output_args.output,
+        Subcommands::RenameKey(ref rename) => &rename.output_args.output,
+        Subcommands::SetObject(ref set) => &set.output_args.output,
+        Subcommands::SetItem(ref set) => &set.output_args.output,
+        Subcommands::ReplaceValue(ref replace) => &replace.output_args.output,
+        Subcommands::Anonymize(ref anonymize) => &anonymize.output_args.output,
+        Subcommands::Apply(ref apply) => &apply.output_args.output,
+        Subcommands::PromoteSlicerMetadata(ref promote) => &promote.output_args.output,
+        Subcommands::Repair(ref repair) => &repair.output_args.output,
+        Subcommands::Doctor(ref doctor) => &doctor.output_args.output,
+        _ => &None,
+    };
+    if output.is_some() && expanded_input_files.len() != 1 {
+        eprintln!(
+            "-o/--output needs exactly one input file, got {}",
+            expanded_input_files.len()
+        );
+        std::process::exit(1);
+    }
+
+    if let Some((suffix, output_dir)) = subcommand.output_naming() {
+        let collisions = output_collisions(&expanded_input_files, suffix, output_dir);
+        for (output_path, inputs) in &collisions {
+            eprintln!(
+                "{} would be written for each of {}",
+                output_path.display(),
+                inputs
+                    .iter()
+                    .map(|input| input.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if !collisions.is_empty() {
+            eprintln!(
+                "{} output files would be written more than once, nothing was written",
+                collisions.len()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let mut state = cli.options.state_file.as_ref().map(|path| {
+        State::open(path, subcommand.name(), cli.options.reset_state).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })
+    });
 
     eprintln!("Number of input files: {}", expanded_input_files.len());
     // loop over input files, exit with an error if any input
     // file starts with our prefix, or don't exist.
-    for input_path in &expanded_input_files {
-        eprintln!("Processing {}", input_path.to_string_lossy());
+    let mut seen_inputs = HashSet::new();
+    observer::set_observer(cli.options.progress.clone());
+    let token = CancellationToken::new();
+    cancel_on_interrupt(token.clone());
+    let mut done = 0;
+    for (index, input_path) in expanded_input_files.iter().enumerate() {
+        if token.is_cancelled() {
+            break;
+        }
+        if input_path.is_symlink() {
+            if !cli.options.follow_symlinks() {
+                cli.options.progress.skip(
+                    input_path,
+                    Skip::Symlink,
+                    "it is a symbolic link",
+                    cli.options.porcelain,
+                );
+                continue;
+            }
+            if let Err(error) = resolve_symlinks(input_path) {
+                eprintln!("{}", error);
+                end_run(&cli.options, 1);
+            }
+        }
         if !input_path.exists() {
             eprintln!("{} does not exist", input_path.to_string_lossy());
-            std::process::exit(1);
+            end_run(&cli.options, 1);
+        }
+        if input_path.is_dir() {
+            eprintln!(
+                "{} is a directory, use -r or --recursive to process the files in it",
+                input_path.to_string_lossy()
+            );
+            end_run(&cli.options, 1);
         }
         if !input_path.is_file() {
             eprintln!("{} is not a file", input_path.to_string_lossy());
-            std::process::exit(1);
+            end_run(&cli.options, 1);
+        }
+        if !wait_until_unlocked(input_path, false, cli.options.wait) {
+            eprintln!(
+                "{} is locked by another program, close it there or use --wait",
+                input_path.display()
+            );
+            end_run(&cli.options, 1);
+        }
+        // A link and its target, or the same file under two names, are only
+        // processed once
+        if let Ok(canonical) = fs::canonicalize(input_path) {
+            if !seen_inputs.insert(canonical) {
+                cli.options.progress.skip(
+                    input_path,
+                    Skip::SameFile,
+                    "it is the same file as an earlier input",
+                    cli.options.porcelain,
+                );
+                continue;
+            }
+        }
+        if state
+            .as_ref()
+            .is_some_and(|state| state.is_done(input_path))
+        {
+            cli.options.progress.skip(
+                input_path,
+                Skip::Done,
+                "the state file lists it as done",
+                cli.options.porcelain,
+            );
+            continue;
+        }
+        if let Subcommands::Apply(ref mut apply) = subcommand {
+            if !apply.select_preset(input_path, cli.options.error_format) {
+                cli.options.progress.skip(
+                    input_path,
+                    Skip::NoRule,
+                    "no rule in the config file matches it",
+                    cli.options.porcelain,
+                );
+                continue;
+            }
+        }
+        // Previewing doesn't write anything, but the title still comes from
+        // the output file name
+        let writes_output = !cli.options.dry_run
+            && !matches!(subcommand, Subcommands::Add(ref add) if add.stdout_xml);
+        // Appends, undos and publishing update the input in place
+        match subcommand {
+            Subcommands::Add(ref mut add) if add.append => {
+                add.output_path = Some(input_path.clone());
+            }
+            Subcommands::Undo(ref mut undo) => undo.output_path = Some(input_path.clone()),
+            #[cfg(feature = "net")]
+            Subcommands::Publish(ref mut publish) => publish.output_path = Some(input_path.clone()),
+            _ => (),
         }
-        if let Subcommands::Add(ref mut add) = cli.subcommand {
-            let output_file_name;
-            if let (Some(stem), extension) = (input_path.file_stem(), input_path.extension()) {
-                if stem.to_string_lossy().ends_with(&add.suffix) {
+        let output = match subcommand {
+            Subcommands::Add(ref mut add) if !add.append => Some((
+                &add.suffix,
+                add.force,
+                &add.output_args.output_dir,
+                &add.output_args.output,
+                &mut add.output_path,
+            )),
+            Subcommands::ConvertUnits(ref mut convert) => Some((
+                &convert.suffix,
+                convert.force,
+                &convert.output_args.output_dir,
+                &convert.output_args.output,
+                &mut convert.output_path,
+            )),
+            Subcommands::Remove(ref mut remove) => Some((
+                &remove.suffix,
+                remove.force,
+                &remove.output_args.output_dir,
+                &remove.output_args.output,
+                &mut remove.output_path,
+            )),
+            Subcommands::Patch(ref mut patch) => Some((
+                &patch.suffix,
+                patch.force,
+                &patch.output_args.output_dir,
+                &patch.output_args.output,
+                &mut patch.output_path,
+            )),
+            Subcommands::RenameKey(ref mut rename) => Some((
+                &rename.suffix,
+                rename.force,
+                &rename.output_args.output_dir,
+                &rename.output_args.output,
+                &mut rename.output_path,
+            )),
+            Subcommands::SetObject(ref mut set) => Some((
+                &set.suffix,
+                set.force,
+                &set.output_args.output_dir,
+                &set.output_args.output,
+                &mut set.output_path,
+            )),
+            Subcommands::SetItem(ref mut set) => Some((
+                &set.suffix,
+                set.force,
+                &set.output_args.output_dir,
+                &set.output_args.output,
+                &mut set.output_path,
+            )),
+            Subcommands::ReplaceValue(ref mut replace) => Some((
+                &replace.suffix,
+                replace.force,
+                &replace.output_args.output_dir,
+                &replace.output_args.output,
+                &mut replace.output_path,
+            )),
+            Subcommands::Anonymize(ref mut anonymize) => Some((
+                &anonymize.suffix,
+                anonymize.force,
+                &anonymize.output_args.output_dir,
+                &anonymize.output_args.output,
+                &mut anonymize.output_path,
+            )),
+            Subcommands::Apply(ref mut apply) => Some((
+                &apply.suffix,
+                apply.force,
+                &apply.output_args.output_dir,
+                &apply.output_args.output,
+                &mut apply.output_path,
+            )),
+            Subcommands::PromoteSlicerMetadata(ref mut promote) => Some((
+                &promote.suffix,
+                promote.force,
+                &promote.output_args.output_dir,
+                &promote.output_args.output,
+                &mut promote.output_path,
+            )),
+            Subcommands::Repair(ref mut repair) => Some((
+                &repair.suffix,
+                repair.force,
+                &repair.output_args.output_dir,
+                &repair.output_args.output,
+                &mut repair.output_path,
+            )),
+            Subcommands::Doctor(ref mut doctor) if doctor.fix => Some((
+                &doctor.suffix,
+                doctor.force,
+                &doctor.output_args.output_dir,
+                &doctor.output_args.output,
+                &mut doctor.output_path,
+            )),
+            _ => None,
+        };
+        if let Some((suffix, force, output_dir, output, output_path)) = output {
+            if let (Some(output_dir), true) = (output_dir, writes_output) {
+                if let Err(error) = fs::create_dir_all(output_dir) {
                     eprintln!(
-                        "Skipping {}, because it already ends with suffix, exiting",
-                        input_path.display()
+                        "Could not create output directory {}: {}",
+                        output_dir.display(),
+                        error
                     );
-                    continue;
-                }
-                let mut name = stem.to_os_string();
-                name.push(OsStr::new(&add.suffix));
-                if add.title {
-                    add.title_value = Some(name.to_string_lossy().to_string());
+                    end_run(&cli.options, 1);
                 }
-                if let Some(extension) = extension {
-                    name.push(OsString::from("."));
-                    name.push(extension);
+            }
+            let new_output_path = match output {
+                Some(output) => output.clone(),
+                None => match output_path_with_suffix(input_path, suffix, output_dir.as_deref()) {
+                    Some(path) => path,
+                    None if cli.options.previous_outputs == PreviousOutputs::Error => {
+                        eprintln!(
+                            "{} is the output of an earlier run, because its name ends with {}",
+                            input_path.display(),
+                            suffix
+                        );
+                        end_run(&cli.options, 1);
+                    }
+                    None => {
+                        cli.options.progress.skip(
+                            input_path,
+                            Skip::OutputName,
+                            format_args!(
+                                "its name ends with {}, so it is the output of an earlier run",
+                                suffix
+                            ),
+                            cli.options.porcelain,
+                        );
+                        continue;
+                    }
+                },
+            };
+            let new_output_path = if cli.options.follow_symlinks() {
+                match resolve_symlinks(&new_output_path) {
+                    Ok(path) => path,
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        end_run(&cli.options, 1);
+                    }
                 }
-                output_file_name = Some(name);
             } else {
-                panic!("Could not get file stem from {}", input_path.display());
-            }
-            // Shouldn't fail because of the panic above
-            let output_file_name = output_file_name.unwrap();
-            let output_path = input_path.with_file_name(output_file_name);
-
-            if output_path.exists() && !add.force {
+                new_output_path
+            };
+            // A dangling link counts as an existing file too when it is the
+            // link itself that will be replaced
+            // Earlier outputs are only written over without --force when
+            // they are named after the input
+            let replace =
+                cli.options.previous_outputs == PreviousOutputs::Replace && output.is_none();
+            if writes_output && fs::symlink_metadata(&new_output_path).is_ok() && !force && !replace
+            {
                 eprintln!(
                     "{} already exists, use -f or --force to ignore",
-                    output_path.to_string_lossy()
+                    new_output_path.to_string_lossy()
                 );
-                std::process::exit(1);
+                end_run(&cli.options, 1);
+            }
+            if writes_output
+                && new_output_path.is_file()
+                && !wait_until_unlocked(&new_output_path, true, cli.options.wait)
+            {
+                eprintln!(
+                    "{} is locked by another program, close it there or use --wait",
+                    new_output_path.display()
+                );
+                end_run(&cli.options, 1);
             }
-            add.output_path = Some(output_path);
+            *output_path = Some(new_output_path);
         }
-        // open input file
-        let input = File::open(input_path).unwrap_or_else(|_| {
-            panic!("Failed to open input file {}", input_path.to_string_lossy())
-        });
-        let input = BufReader::new(input);
-        let mut input = ZipArchive::new(input).unwrap();
-
-        match cli.subcommand {
-            Subcommands::Add(ref add) => {
-                // open output file
+        if let Subcommands::Add(ref mut add) = subcommand {
+            if add.title {
+                // Title is the output file name, without the extension
                 let output_path = add.output_path.as_ref().unwrap();
-                let output = File::create(output_path).unwrap_or_else(|_| {
-                    panic!(
-                        "Failed to open output file {}",
-                        output_path.to_string_lossy()
-                    )
-                });
-                let mut output = ZipWriter::new(output);
-                // copy all files from input to output
-                for file_number in 0..input.len() {
-                    let file = input
-                        .by_index(file_number)
-                        .expect("failure reading from ZIP archive");
-                    let mut updated = false;
-                    match file.enclosed_name() {
-                        Some(path) if path.extension() == Some(OsStr::new("model")) => {
-                            updated = update_xml_and_copy(
-                                file,
-                                add.metadata_xml.as_ref().unwrap(),
-                                &mut output,
-                                add.keep_existing,
-                                &add.title_value,
-                            )
-                        }
-                        _ => {
-                            drop(file);
-                        }
-                    }
-
-                    if !updated {
-                        let file = input
-                            .by_index_raw(file_number)
-                            .expect("failure reading from ZIP archive");
-                        output.raw_copy_file(file).expect("writing raw copy failed");
+                add.title_value = Some(
+                    output_path
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                );
+            }
+        }
+        let adds = matches!(subcommand, Subcommands::Add(_) | Subcommands::Apply(_));
+        if adds
+            && cli.options.previous_by_metadata
+            && cli.options.previous_outputs != PreviousOutputs::Replace
+            // A package that can't be read fails when it is processed
+            && has_metadata(input_path, &cli.options, &subcommand).unwrap_or(false)
+        {
+            if cli.options.previous_outputs == PreviousOutputs::Error {
+                eprintln!(
+                    "{} is the output of an earlier run, because it already has the metadata",
+                    input_path.display()
+                );
+                end_run(&cli.options, 1);
+            }
+            cli.options.progress.skip(
+                input_path,
+                Skip::HasMetadata,
+                "it already has the metadata, so it is the output of an earlier run",
+                cli.options.porcelain,
+            );
+            continue;
+        }
+        hook(&subcommand, &cli.options, "pre", input_path);
+        let mut timings = Timings::default();
+        let start = Instant::now();
+        let total = expanded_input_files.len();
+        let processed = observer::observe(input_path, index, total, || {
+            token.run(|| process_file(&subcommand, input_path, &cli.options, &mut timings))
+        });
+        match processed {
+            Err(Error::Cancelled) => break,
+            Err(_) => end_run(&cli.options, 1),
+            Ok(()) => done += 1,
+        }
+        if let (true, Some(output_path)) = (cli.options.porcelain, subcommand.output_path()) {
+            let kind = if cli.options.dry_run {
+                "dry-run"
+            } else {
+                "written"
+            };
+            porcelain_line(input_path, kind, &[&output_path.to_string_lossy()]);
+        }
+        hook(&subcommand, &cli.options, "post", input_path);
+        if let Some(state) = &mut state {
+            if let Err(error) = state.mark_done(input_path) {
+                eprintln!("{}", error);
+                end_run(&cli.options, 1);
+            }
+        }
+        if cli.options.timing {
+            eprintln!(
+                "Timing for {}: parse {:.1?}, rewrite {:.1?}, write {:.1?}, total {:.1?}",
+                input_path.display(),
+                timings.parse,
+                timings.rewrite,
+                timings.write,
+                start.elapsed()
+            );
+        }
+    }
+    if let Subcommands::ReplaceValue(ref replace) = subcommand {
+        eprintln!(
+            "{} values in {} of {} files",
+            if cli.options.dry_run {
+                "Would change"
+            } else {
+                "Changed"
+            },
+            replace.changed_files.get(),
+            expanded_input_files.len()
+        );
+    }
+    cli.options.progress.report_skipped();
+    if token.is_cancelled() {
+        eprintln!(
+            "Cancelled after {} of {} files",
+            done,
+            expanded_input_files.len()
+        );
+        if let Some(path) = &cli.options.state_file {
+            eprintln!(
+                "{} lists the files that are done, run the same command again to continue",
+                path.display()
+            );
+        }
+        end_run(&cli.options, CANCELLED.load(Ordering::SeqCst));
+    }
+    commit_staged(&cli.options);
+    if let Subcommands::Catalog(ref catalog) = subcommand {
+        let cards = catalog.cards.borrow();
+        let html = catalog::render(&catalog.title, &cards);
+        if let Err(error) = fs::write(&catalog.output, html) {
+            eprintln!("Could not write {}: {}", catalog.output.display(), error);
+            end_run(&cli.options, 1);
+        }
+        eprintln!(
+            "Wrote {} with {} files",
+            catalog.output.display(),
+            cards.len()
+        );
+    }
+    if let Subcommands::Show(ref show) = subcommand {
+        let parts = show.parts.take();
+        match show.format {
+            ShowFormat::Text => (),
+            ShowFormat::Json => {
+                let mut files = serde_json::Map::new();
+                for (path, part, metadata) in parts {
+                    let file = files
+                        .entry(path)
+                        .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+                    file.as_object_mut()
+                        .unwrap()
+                        .insert(part, export::json(&metadata));
+                }
+                println!("{}", serde_json::to_string_pretty(&files).unwrap());
+            }
+            ShowFormat::Csv => {
+                println!("{}", csv_line(&["file", "part", "name", "value"]));
+                for (path, part, metadata) in &parts {
+                    for entry in metadata {
+                        println!("{}", csv_line(&[path, part, &entry.name, &entry.value]));
                     }
                 }
-                output
-                    .finish()
-                    .expect("failed to finish writing ZIP archive");
-            }
-            Subcommands::Show(_) => {
-                for file_number in 0..input.len() {
-                    let file = input
-                        .by_index(file_number)
-                        .expect("failure reading from ZIP archive");
-                    match file.enclosed_name() {
-                        Some(path) if path.extension() == Some(OsStr::new("model")) => {
-                            show_metadata(file)
-                        }
-                        _ => (),
-                    };
+            }
+        }
+    }
+    if let Subcommands::Export(ref export) = subcommand {
+        if export.canonical {
+            let texts = export.texts.take();
+            // A single file gets no header, so that its text doesn't depend
+            // on where it is
+            for (path, text) in &texts {
+                if texts.len() > 1 {
+                    println!("# {}", path);
                 }
+                print!("{}", text);
+            }
+            end_run(&cli.options, 0);
+        }
+        if export.format == ExportFormat::Csv {
+            if let Err(error) = manifest::write_csv(stdout().lock(), &export.rows.borrow()) {
+                eprintln!("{}", error);
+                end_run(&cli.options, 1);
             }
+            end_run(&cli.options, 0);
+        }
+        let documents = export.documents.take();
+        // One JSON-LD document can describe many things in a graph
+        let output = match export.format {
+            ExportFormat::Json | ExportFormat::Csv => documents.into_iter().collect(),
+            ExportFormat::JsonLd if documents.len() == 1 => documents.into_iter().next().unwrap().1,
+            ExportFormat::JsonLd => {
+                let graph = documents
+                    .into_iter()
+                    .map(|(_, mut document)| {
+                        document.as_object_mut().unwrap().shift_remove("@context");
+                        document
+                    })
+                    .collect::<Vec<_>>();
+                serde_json::json!({ "@context": "https://schema.org", "@graph": graph })
+            }
+        };
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    }
+    // Like grep, finding nothing is a failure
+    #[cfg(feature = "regex")]
+    if let Subcommands::Grep(ref grep) = subcommand {
+        if grep.matches.get() == 0 {
+            end_run(&cli.options, 1);
+        }
+    }
+    if let Subcommands::Get(ref get) = subcommand {
+        if get.failures.get() > 0 {
+            end_run(&cli.options, 1);
+        }
+    }
+    if let Subcommands::Attribution(ref attribution) = subcommand {
+        if attribution.failures.get() > 0 {
+            end_run(&cli.options, 1);
+        }
+    }
+    if let Subcommands::Show(ref show) = subcommand {
+        if show.failures.get() > 0 {
+            eprintln!(
+                "{} of {} files lack the expected metadata",
+                show.failures.get(),
+                expanded_input_files.len()
+            );
+            end_run(&cli.options, 1);
+        }
+    }
+    if let Some(report) = subcommand.report().filter(|report| report.is_sarif()) {
+        let log = diagnostics::sarif(&report.diagnostics.borrow());
+        println!("{}", serde_json::to_string_pretty(&log).unwrap());
+    }
+    if let Subcommands::Validate(ref validate) = subcommand {
+        if validate.report.failures() > 0 {
+            eprintln!(
+                "{} of {} files are not valid",
+                validate.report.failures(),
+                expanded_input_files.len()
+            );
+            end_run(&cli.options, 1);
+        }
+    }
+    if let Subcommands::Doctor(ref doctor) = subcommand {
+        if doctor.report.failures() > 0 {
+            let state = match doctor.fix {
+                true => "still have",
+                false => "have",
+            };
+            eprintln!(
+                "{} of {} files {} problems",
+                doctor.report.failures(),
+                expanded_input_files.len(),
+                state
+            );
+            end_run(&cli.options, 1);
+        }
+    }
+    if let Subcommands::Check(ref check) = subcommand {
+        if check.report.failures() > 0 {
+            eprintln!(
+                "{} of {} files lack the expected metadata",
+                check.report.failures(),
+                expanded_input_files.len()
+            );
+            end_run(&cli.options, 1);
         }
     }
+    end_run(&cli.options, 0);
 }
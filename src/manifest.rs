@@ -0,0 +1,263 @@
+//! Manifests: the metadata of many 3MF files as a CSV table, with a row per
+//! file, to edit in a spreadsheet.
+//!
+//! The first two columns are `id` and `file`, and every further column is
+//! a metadata name:
+//!
+//! ```text
+//! id,file,Title,Designer
+//! 3f1c0a9e52b7d481,library/benchy.3mf,3DBenchy,Creative Tools
+//! ```
+//!
+//! The id is a fingerprint of the objects and build of the root model
+//! part, which stays the same when the file is renamed or its metadata
+//! changes, so a row finds its file again after either. Copies of a model
+//! share an id. Rows with an empty id are found by their file instead.
+//!
+//! ```
+//! use metadata_3mf::manifest::{Manifest, Row};
+//! use metadata_3mf::MetadataSet;
+//!
+//! let row = Row {
+//!     id: "3f1c0a9e52b7d481".to_string(),
+//!     file: "benchy.3mf".to_string(),
+//!     metadata: MetadataSet::builder().title("Boat, small").build().unwrap(),
+//! };
+//! let mut csv = Vec::new();
+//! metadata_3mf::manifest::write_csv(&mut csv, &[row.clone()]).unwrap();
+//! assert_eq!(
+//!     String::from_utf8(csv.clone()).unwrap(),
+//!     "id,file,Title\r\n3f1c0a9e52b7d481,benchy.3mf,\"Boat, small\"\r\n"
+//! );
+//! let manifest = Manifest::read(&csv[..]).unwrap();
+//! assert_eq!(manifest.find("3f1c0a9e52b7d481", "renamed.3mf"), Some(&row));
+//! ```
+
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+use xmltree::{Element, XMLNode};
+
+use crate::package::{self, Package};
+use crate::{model, well_known, Error, MetadataEntry, MetadataSet, Result};
+
+/// The metadata of one file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Row {
+    pub id: String,
+    /// The path of the file, as it was given when the manifest was written.
+    pub file: String,
+    pub metadata: MetadataSet,
+}
+
+/// The rows of a manifest that was read.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    rows: Vec<Row>,
+}
+
+impl Manifest {
+    /// Reads a manifest. Empty cells are left out of the metadata of their
+    /// row, so a spreadsheet can leave out what it doesn't change.
+    pub fn read<R: Read>(reader: R) -> Result<Self> {
+        Manifest::read_with(reader, false)
+    }
+
+    /// Like [`Manifest::read`], but columns may also be named with aliases
+    /// of well-known names, see [`well_known::unalias`].
+    pub fn read_with_aliases<R: Read>(reader: R) -> Result<Self> {
+        Manifest::read_with(reader, true)
+    }
+
+    fn read_with<R: Read>(mut reader: R, aliases: bool) -> Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut records = parse_csv(&text)?.into_iter();
+        let mut header = records.next().unwrap_or_default();
+        if header.len() < 2 || header[0] != "id" || header[1] != "file" {
+            return Err(Error::MetadataFile(
+                "a manifest must start with the columns id and file".to_string(),
+            ));
+        }
+        for name in header.iter_mut().skip(2) {
+            if let Some(known) = well_known::unalias(name).filter(|_| aliases) {
+                *name = known.to_string();
+            }
+            well_known::validate_name(name)?;
+        }
+        let rows = records
+            .filter(|record| record.iter().any(|cell| !cell.is_empty()))
+            .map(|record| {
+                let cell = |index: usize| record.get(index).cloned().unwrap_or_default();
+                Row {
+                    id: cell(0),
+                    file: cell(1),
+                    metadata: header[2..]
+                        .iter()
+                        .enumerate()
+                        .map(|(index, name)| MetadataEntry::new(name.clone(), cell(index + 2)))
+                        .filter(|entry| !entry.value.is_empty())
+                        .collect(),
+                }
+            })
+            .collect();
+        Ok(Manifest { rows })
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Finds the row of a file by its id, or else by its path.
+    pub fn find(&self, id: &str, path: &str) -> Option<&Row> {
+        self.rows.iter().find(|row| row.id == id).or_else(|| {
+            self.rows
+                .iter()
+                .find(|row| row.id.is_empty() && row.file == path)
+        })
+    }
+
+    /// Like [`Manifest::find`], but also finds rows whose file is the same
+    /// file as `path` under another name.
+    pub fn find_file(&self, id: &str, path: &Path) -> Option<&Row> {
+        self.find(id, &path.to_string_lossy()).or_else(|| {
+            let path = path.canonicalize().ok()?;
+            self.rows.iter().find(|row| {
+                row.id.is_empty() && Path::new(&row.file).canonicalize().ok() == Some(path.clone())
+            })
+        })
+    }
+}
+
+/// Writes rows as CSV, with a column for every metadata name in the order
+/// they are first found.
+pub fn write_csv<W: Write>(mut writer: W, rows: &[Row]) -> Result<()> {
+    let mut names: Vec<&str> = Vec::new();
+    for row in rows {
+        for entry in &row.metadata {
+            if !names.contains(&entry.name.as_str()) {
+                names.push(&entry.name);
+            }
+        }
+    }
+    let header: Vec<&str> = ["id", "file"].into_iter().chain(names.clone()).collect();
+    write_record(&mut writer, &header)?;
+    for row in rows {
+        let record: Vec<&str> = [row.id.as_str(), row.file.as_str()]
+            .into_iter()
+            .chain(
+                names
+                    .iter()
+                    .map(|name| row.metadata.value(name).unwrap_or("")),
+            )
+            .collect();
+        write_record(&mut writer, &record)?;
+    }
+    Ok(())
+}
+
+fn write_record<W: Write>(writer: &mut W, cells: &[&str]) -> Result<()> {
+    let line = cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    // RFC 4180 ends lines with CRLF, which spreadsheets expect
+    write!(writer, "{}\r\n", line)?;
+    Ok(())
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut cell)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => (),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut cell));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::MetadataFile(
+            "a quoted cell of the manifest is not closed".to_string(),
+        ));
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The id of a package in manifests: a fingerprint of the elements and
+/// attributes of the resources and build of the root model part, in
+/// hexadecimal. Text and formatting are left out, so rewriting the model
+/// doesn't change it.
+pub fn file_id<R: Read + Seek>(package: &mut Package<R>) -> Result<String> {
+    let name = package.root_model_name()?;
+    let index = package.index_of(&name).ok_or(Error::NoRootModel)?;
+    let bytes = package::read_model_part(&mut package.by_index(index)?)?;
+    let xml = model::read_model(&bytes[..])?;
+    let mut hash = Fnv::default();
+    for element in xml.children.iter().filter_map(XMLNode::as_element) {
+        if element.name == "resources" || element.name == "build" {
+            hash_element(&mut hash, element);
+        }
+    }
+    Ok(format!("{:016x}", hash.0))
+}
+
+fn hash_element(hash: &mut Fnv, element: &Element) {
+    hash.write(b"<");
+    hash.write(element.name.as_bytes());
+    for (name, value) in &element.attributes {
+        hash.write(b" ");
+        hash.write(name.as_bytes());
+        hash.write(b"=");
+        hash.write(value.as_bytes());
+    }
+    hash.write(b">");
+    for child in element.children.iter().filter_map(XMLNode::as_element) {
+        hash_element(hash, child);
+    }
+    hash.write(b"/");
+}
+
+/// 64-bit FNV-1a, which unlike the hasher of the standard library gives
+/// the same result on every version and platform.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
@@ -0,0 +1,1236 @@
+//! Metadata entries as found in the `<metadata>` elements of a 3MF model,
+//! and the metadata files the `add` command reads them from.
+
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use time::OffsetDateTime;
+use xmltree::{Element, XMLNode};
+
+use crate::well_known::{self, validate_characters, validate_name};
+use crate::{Error, Result, Spdx};
+
+/// A single `<metadata>` element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataEntry {
+    pub name: String,
+    pub value: String,
+    /// Attributes other than `name`, in document order.
+    pub attributes: Vec<(String, String)>,
+}
+
+impl MetadataEntry {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        MetadataEntry {
+            name: name.into(),
+            value: value.into(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Converts a `<metadata>` element. Returns `None` if the element has
+    /// no `name` attribute.
+    pub fn from_element(element: &Element) -> Option<Self> {
+        let name = element.attributes.get("name")?.clone();
+        let attributes = element
+            .attributes
+            .iter()
+            .filter(|(key, _)| *key != "name")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let value = element
+            .get_text()
+            .map(|text| text.into_owned())
+            .unwrap_or_default();
+        Some(MetadataEntry {
+            name,
+            value,
+            attributes,
+        })
+    }
+
+    /// Whether the entry has the same value and attributes as `other`, in
+    /// any order of the attributes.
+    fn same_as(&self, other: &MetadataEntry) -> bool {
+        let sorted = |entry: &MetadataEntry| {
+            let mut attributes = entry.attributes.clone();
+            attributes.sort();
+            attributes
+        };
+        self.value == other.value && sorted(self) == sorted(other)
+    }
+
+    /// Checks that the value and the attribute values only have characters
+    /// XML 1.0 allows, see [`validate_characters`].
+    pub fn validate_characters(&self) -> Result<()> {
+        validate_characters(&self.name, &self.value)?;
+        for (key, value) in &self.attributes {
+            validate_characters(&format!("{} attribute {}", self.name, key), value)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_element(&self) -> Element {
+        let mut element = Element::new("metadata");
+        element
+            .attributes
+            .insert("name".to_string(), self.name.clone());
+        for (key, value) in &self.attributes {
+            element.attributes.insert(key.clone(), value.clone());
+        }
+        if !self.value.is_empty() {
+            element.children.push(XMLNode::Text(self.value.clone()));
+        }
+        element
+    }
+}
+
+/// An ordered list of metadata entries with unique names.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataSet {
+    entries: Vec<MetadataEntry>,
+}
+
+impl MetadataSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts building a set, see [`MetadataSetBuilder`].
+    pub fn builder() -> MetadataSetBuilder {
+        MetadataSetBuilder::default()
+    }
+
+    /// Collects the `<metadata>` children of a `<model>` element. Later
+    /// entries win when a name occurs more than once.
+    pub fn from_model(model: &Element) -> Self {
+        model
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|element| element.name == "metadata")
+            .filter_map(MetadataEntry::from_element)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, MetadataEntry> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MetadataEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Returns the text value of the entry with the given name.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.get(name).map(|entry| entry.value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds an entry, replacing an existing entry of the same name in place.
+    /// Returns the replaced entry, if any.
+    pub fn insert(&mut self, entry: MetadataEntry) -> Option<MetadataEntry> {
+        match self
+            .entries
+            .iter_mut()
+            .find(|existing| existing.name == entry.name)
+        {
+            Some(existing) => Some(std::mem::replace(existing, entry)),
+            None => {
+                self.entries.push(entry);
+                None
+            }
+        }
+    }
+
+    /// Sets the value of an entry, keeping the attributes of an existing
+    /// entry of the same name.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        match self.entries.iter_mut().find(|entry| entry.name == name) {
+            Some(entry) => entry.value = value.into(),
+            None => self.entries.push(MetadataEntry::new(name, value)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<MetadataEntry> {
+        let index = self.entries.iter().position(|entry| entry.name == name)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Keeps only the entries for which `keep` returns true.
+    pub fn retain(&mut self, keep: impl FnMut(&MetadataEntry) -> bool) {
+        self.entries.retain(keep);
+    }
+
+    /// Renames the entry `from` to `to` in place, keeping its value and
+    /// attributes. An existing entry named `to` is replaced and returned.
+    /// Does nothing when there is no entry `from`.
+    pub fn rename(&mut self, from: &str, to: &str) -> Option<MetadataEntry> {
+        if from == to || !self.contains(from) {
+            return None;
+        }
+        let replaced = self.remove(to);
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.name == from)
+            .expect("checked above");
+        entry.name = to.to_string();
+        replaced
+    }
+
+    /// Renames the entries whose names are aliases of well-known names, see
+    /// [`well_known::unalias`], keeping their place. An entry that has the
+    /// well-known name already wins over its aliases, which are dropped.
+    pub fn unalias(&mut self) {
+        let names: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        for name in names {
+            let Some(known) = well_known::unalias(&name) else {
+                continue;
+            };
+            if self.contains(known) {
+                self.remove(&name);
+            } else {
+                self.rename(&name, known);
+            }
+        }
+    }
+
+    /// Compares this set with a `new` one, such as the metadata of a file
+    /// before and after an edit. Entries are matched by name; added and
+    /// changed entries are in the order of `new`, removed ones in the
+    /// order of this set.
+    ///
+    /// ```
+    /// use metadata_3mf::MetadataSet;
+    ///
+    /// let old = MetadataSet::builder().title("Boat").designer("Jane").build()?;
+    /// let new = MetadataSet::builder().title("Benchy").rating(5).build()?;
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.added[0].name, "Rating");
+    /// assert_eq!(diff.removed[0].name, "Designer");
+    /// assert_eq!(diff.changed[0].old.value, "Boat");
+    /// assert_eq!(diff.changed[0].new.value, "Benchy");
+    /// # Ok::<(), metadata_3mf::Error>(())
+    /// ```
+    pub fn diff(&self, new: &MetadataSet) -> MetadataDiff {
+        let mut diff = MetadataDiff::default();
+        for entry in new {
+            match self.get(&entry.name) {
+                None => diff.added.push(entry.clone()),
+                Some(old) if !old.same_as(entry) => diff.changed.push(MetadataChange {
+                    old: old.clone(),
+                    new: entry.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+        diff.removed = self
+            .iter()
+            .filter(|entry| !new.contains(&entry.name))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    /// Returns the entries of `expected` that are missing from this set or
+    /// have another value here. Entries only this set has don't count, and
+    /// neither do attributes.
+    pub fn differences(&self, expected: &MetadataSet) -> Vec<Difference> {
+        let diff = self.diff(expected);
+        let missing = diff
+            .added
+            .into_iter()
+            .map(|entry| Difference::Missing(entry.name));
+        let mismatched = diff
+            .changed
+            .into_iter()
+            .filter(|change| change.old.value != change.new.value)
+            .map(|change| Difference::Mismatch {
+                name: change.new.name,
+                expected: change.new.value,
+                actual: change.old.value,
+            });
+        // In the order of the expected entries
+        let mut differences: Vec<_> = missing.chain(mismatched).collect();
+        differences.sort_by_key(|difference| {
+            let name = match difference {
+                Difference::Missing(name) | Difference::Mismatch { name, .. } => name,
+            };
+            expected.iter().position(|entry| entry.name == *name)
+        });
+        differences
+    }
+}
+
+/// How one [`MetadataSet`] turns into another, see [`MetadataSet::diff`].
+/// Meant for previews of a change, so that they can be shown in any way.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataDiff {
+    /// Entries that only the new set has.
+    pub added: Vec<MetadataEntry>,
+    /// Entries that only the old set has.
+    pub removed: Vec<MetadataEntry>,
+    /// Entries with the same name in both sets, but another value or other
+    /// attributes.
+    pub changed: Vec<MetadataChange>,
+}
+
+impl MetadataDiff {
+    /// Whether the sets are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// One line per entry, with `+` for added, `-` for removed and `~` for
+/// changed entries.
+impl fmt::Display for MetadataDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.added {
+            writeln!(f, "+ {} = {}", entry.name, entry.value)?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {} = {}", entry.name, entry.value)?;
+        }
+        for change in &self.changed {
+            writeln!(
+                f,
+                "~ {} = {} -> {}",
+                change.new.name, change.old.value, change.new.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An entry that [`MetadataDiff`] found changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataChange {
+    pub old: MetadataEntry,
+    pub new: MetadataEntry,
+}
+
+/// An expected entry that a [`MetadataSet`] lacks, see
+/// [`MetadataSet::differences`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    /// There is no entry with this name.
+    Missing(String),
+    /// The entry has another value.
+    Mismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Missing(name) => write!(f, "missing {}", name),
+            Difference::Mismatch {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{} is \"{}\", expected \"{}\"", name, actual, expected),
+        }
+    }
+}
+
+/// A metadata name with different values in the model parts of one
+/// package, see [`conflicts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub name: String,
+    /// Each model part that has the name, with its value there.
+    pub values: Vec<(String, String)>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "metadata name {} differs between model parts:",
+            self.name
+        )?;
+        for (index, (part, value)) in self.values.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(f, "{} {} has \"{}\"", separator, part, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The names that the model parts don't agree on, in the order they are
+/// first found in. Names that only one model part has are no conflict.
+pub fn conflicts(parts: &[(String, MetadataSet)]) -> Vec<Conflict> {
+    let mut conflicts: Vec<Conflict> = Vec::new();
+    for (_, metadata) in parts {
+        for entry in metadata {
+            if conflicts.iter().any(|conflict| conflict.name == entry.name) {
+                continue;
+            }
+            let values: Vec<_> = parts
+                .iter()
+                .filter_map(|(part, metadata)| {
+                    let value = metadata.value(&entry.name)?;
+                    Some((part.clone(), value.to_string()))
+                })
+                .collect();
+            if values.iter().any(|(_, value)| *value != entry.value) {
+                conflicts.push(Conflict {
+                    name: entry.name.clone(),
+                    values,
+                });
+            }
+        }
+    }
+    conflicts
+}
+
+impl FromIterator<MetadataEntry> for MetadataSet {
+    fn from_iter<I: IntoIterator<Item = MetadataEntry>>(iter: I) -> Self {
+        let mut set = MetadataSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<MetadataEntry> for MetadataSet {
+    fn extend<I: IntoIterator<Item = MetadataEntry>>(&mut self, iter: I) {
+        for entry in iter {
+            self.insert(entry);
+        }
+    }
+}
+
+impl IntoIterator for MetadataSet {
+    type Item = MetadataEntry;
+    type IntoIter = std::vec::IntoIter<MetadataEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MetadataSet {
+    type Item = &'a MetadataEntry;
+    type IntoIter = std::slice::Iter<'a, MetadataEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+/// Builds a [`MetadataSet`], validating every entry as it is added:
+///
+/// ```
+/// use metadata_3mf::{MetadataSet, Spdx};
+///
+/// let metadata = MetadataSet::builder()
+///     .title("Benchy")
+///     .license(Spdx::CcBy40)
+///     .custom("m:Foo", "bar")
+///     .build()?;
+/// assert_eq!(metadata.license_terms(), Some("CC-BY-4.0"));
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+///
+/// The first invalid entry is reported by [`MetadataSetBuilder::build`].
+#[derive(Debug, Default)]
+pub struct MetadataSetBuilder {
+    set: MetadataSet,
+    error: Option<Error>,
+}
+
+impl MetadataSetBuilder {
+    pub fn title(self, title: impl Into<String>) -> Self {
+        self.custom(well_known::TITLE, title)
+    }
+
+    pub fn designer(self, designer: impl Into<String>) -> Self {
+        self.custom(well_known::DESIGNER, designer)
+    }
+
+    pub fn description(self, description: impl Into<String>) -> Self {
+        self.custom(well_known::DESCRIPTION, description)
+    }
+
+    pub fn copyright(self, copyright: impl Into<String>) -> Self {
+        self.custom(well_known::COPYRIGHT, copyright)
+    }
+
+    pub fn license_terms(self, license_terms: impl Into<String>) -> Self {
+        self.custom(well_known::LICENSE_TERMS, license_terms)
+    }
+
+    /// Sets `LicenseTerms` to the SPDX identifier of the license.
+    pub fn license(self, license: Spdx) -> Self {
+        self.license_terms(license.id())
+    }
+
+    pub fn application(self, application: impl Into<String>) -> Self {
+        self.custom(well_known::APPLICATION, application)
+    }
+
+    pub fn rating(self, rating: u32) -> Self {
+        self.custom(well_known::RATING, rating.to_string())
+    }
+
+    pub fn creation_date(mut self, date: OffsetDateTime) -> Self {
+        if let Err(error) = self.set.set_creation_date(date) {
+            self.error.get_or_insert(error);
+        }
+        self
+    }
+
+    pub fn modification_date(mut self, date: OffsetDateTime) -> Self {
+        if let Err(error) = self.set.set_modification_date(date) {
+            self.error.get_or_insert(error);
+        }
+        self
+    }
+
+    /// Adds an entry with any name, which has to be either well-known or
+    /// namespaced.
+    pub fn custom(self, name: &str, value: impl Into<String>) -> Self {
+        self.entry(MetadataEntry::new(name, value))
+    }
+
+    /// Adds a complete entry, including its extra attributes. The value of
+    /// a well-known name has to be in its format, see
+    /// [`well_known::check_value`].
+    pub fn entry(self, entry: MetadataEntry) -> Self {
+        match validate_name(&entry.name) {
+            Ok(()) => self.entry_with_any_name(entry),
+            Err(error) => self.fail(error),
+        }
+    }
+
+    /// Adds an entry like [`entry`](Self::entry), but with any name, for
+    /// entries taken as they are from files that other programs wrote.
+    pub fn entry_with_any_name(mut self, entry: MetadataEntry) -> Self {
+        let valid = entry
+            .validate_characters()
+            .and_then(|()| well_known::check_value(&entry.name, &entry.value));
+        match valid {
+            Ok(()) => {
+                self.set.insert(entry);
+                self
+            }
+            Err(error) => self.fail(error),
+        }
+    }
+
+    fn fail(mut self, error: Error) -> Self {
+        self.error.get_or_insert(error);
+        self
+    }
+
+    pub fn build(self) -> Result<MetadataSet> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.set),
+        }
+    }
+}
+
+/// A metadata file: the entries to add, and with a `v2` file, named presets
+/// of further entries.
+///
+/// Version 1 files are one of:
+///
+/// - a `<v1>` or `<metadata-list>` root element with only `<metadata>`
+///   children,
+/// - just a list of `<metadata>` elements,
+/// - a `<model>` element, such as a whole model part, from whose
+///   `<metadata>` children the entries are taken.
+///
+/// Each `<metadata>` element must have a `name` attribute. Comments,
+/// processing instructions and whitespace between the elements are
+/// skipped, as are comments within values.
+///
+/// A `<v2>` file checks the attributes of its entries, which may be
+/// `preserve`, `type`, `xml:lang` and `prefix`, which puts the entry in a
+/// namespace as `prefix:name`. It can also group entries into presets:
+///
+/// ```
+/// use metadata_3mf::metadata::MetadataFile;
+///
+/// let file = MetadataFile::read(r#"<v2>
+///     <metadata name="Designer" preserve="1">BASt</metadata>
+///     <metadata name="Sku" prefix="shop">A-1</metadata>
+///     <preset name="commercial">
+///         <metadata name="LicenseTerms" xml:lang="en-US">All rights reserved</metadata>
+///     </preset>
+/// </v2>"#.as_bytes())?;
+/// assert_eq!(file.entries.value("shop:Sku"), Some("A-1"));
+/// let commercial = file.preset("commercial").unwrap();
+/// assert_eq!(commercial.license_terms(), Some("All rights reserved"));
+/// assert_eq!(commercial.designer(), Some("BASt"));
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+///
+/// Entries in a `<when>` element are only for the files that it matches,
+/// see [`Conditional`].
+///
+/// The same can be written in TOML, see [`MetadataFile::read_toml`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataFile {
+    /// The entries outside of any preset.
+    pub entries: MetadataSet,
+    presets: Vec<(String, MetadataSet)>,
+    /// The entries that are only for some files, in file order.
+    pub conditional: Vec<Conditional>,
+}
+
+/// Entries of a `v2` metadata file that are only for the files whose
+/// conditions all hold, from a `<when>` element:
+///
+/// - `name="Designer"` holds when the file has a `Designer` entry, and
+///   with `equals="Our Studio"` or `not-equals="Our Studio"`, when it has
+///   or hasn't got that value,
+/// - `path="prototypes/**"` holds for files whose path matches the glob,
+///   where `*` stays within a directory and `**` matches any number of
+///   them. Relative patterns match at any depth.
+///
+/// The metadata looked at is that of the root model part of the file,
+/// before anything is added.
+///
+/// ```
+/// use std::path::Path;
+/// use metadata_3mf::metadata::MetadataFile;
+/// use metadata_3mf::MetadataSet;
+///
+/// let file = MetadataFile::read(r#"<v2>
+///     <when name="Designer" equals="Our Studio">
+///         <metadata name="Copyright">Copyright (c) 2024, Our Studio</metadata>
+///     </when>
+///     <when path="prototypes/**">
+///         <metadata name="LicenseTerms">CC-BY-NC-4.0</metadata>
+///     </when>
+/// </v2>"#.as_bytes())?;
+/// let ours = MetadataSet::builder().designer("Our Studio").build()?;
+/// let path = Path::new("/library/prototypes/clip.3mf");
+/// assert!(file.conditional[0].applies(&ours, path));
+/// assert!(!file.conditional[0].applies(&MetadataSet::new(), path));
+/// assert!(file.conditional[1].applies(&ours, path));
+/// assert!(!file.conditional[1].applies(&ours, Path::new("/library/clip.3mf")));
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conditional {
+    pub conditions: Vec<Condition>,
+    pub entries: MetadataSet,
+}
+
+/// A condition of a [`Conditional`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The file has an entry of the name.
+    Has(String),
+    /// The file has an entry of the name with the value.
+    Equals(String, String),
+    /// The file has no entry of the name with the value.
+    NotEquals(String, String),
+    /// The path of the file matches the glob.
+    Path(Pattern),
+}
+
+impl Condition {
+    /// Whether the condition holds for a file at `path` with the metadata.
+    pub fn holds(&self, metadata: &MetadataSet, path: &Path) -> bool {
+        match self {
+            Condition::Has(name) => metadata.contains(name),
+            Condition::Equals(name, value) => metadata.value(name) == Some(value.as_str()),
+            Condition::NotEquals(name, value) => metadata.value(name) != Some(value.as_str()),
+            Condition::Path(pattern) => {
+                let options = MatchOptions {
+                    require_literal_separator: true,
+                    ..MatchOptions::new()
+                };
+                pattern.matches_path_with(path, options)
+            }
+        }
+    }
+}
+
+impl Conditional {
+    /// Whether all conditions hold for a file at `path` with the metadata.
+    pub fn applies(&self, metadata: &MetadataSet, path: &Path) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.holds(metadata, path))
+    }
+
+    /// Reads the conditions of a `<when>` element, or of a `when` table of a
+    /// TOML file.
+    fn conditions<'a>(
+        attributes: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Vec<Condition>> {
+        let mut name = None;
+        let mut value = None;
+        let mut conditions = Vec::new();
+        for (key, text) in attributes {
+            match key {
+                "name" => name = Some(text.to_string()),
+                "equals" | "not-equals" => value = Some((key, text.to_string())),
+                "path" => {
+                    // Relative patterns match anywhere
+                    let glob = match text.starts_with("**") || Path::new(text).has_root() {
+                        true => text.to_string(),
+                        false => format!("**/{}", text),
+                    };
+                    let pattern = Pattern::new(&glob).map_err(|error| {
+                        Error::MetadataFile(format!(
+                            "Condition path=\"{}\" is not a valid pattern: {}",
+                            text, error
+                        ))
+                    })?;
+                    conditions.push(Condition::Path(pattern));
+                }
+                _ => {
+                    return Err(Error::MetadataFile(format!(
+                    "Condition has unknown attribute {}, expected name, equals, not-equals or path",
+                    key
+                )))
+                }
+            }
+        }
+        match (name, value) {
+            (Some(name), None) => conditions.push(Condition::Has(name)),
+            (Some(name), Some(("equals", value))) => {
+                conditions.push(Condition::Equals(name, value))
+            }
+            (Some(name), Some((_, value))) => conditions.push(Condition::NotEquals(name, value)),
+            (None, Some((key, _))) => {
+                return Err(Error::MetadataFile(format!(
+                    "Condition has {} but no name to compare",
+                    key
+                )))
+            }
+            (None, None) => (),
+        }
+        if conditions.is_empty() {
+            return Err(Error::MetadataFile(
+                "Condition has neither a name nor a path".to_string(),
+            ));
+        }
+        Ok(conditions)
+    }
+}
+
+impl MetadataFile {
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let parse = |bytes: &[u8]| {
+            crate::xml::parse(bytes).map_err(|error| match error {
+                Error::Syntax(_) => error,
+                _ => Error::MetadataFile("Could not parse metadata file".to_string()),
+            })
+        };
+        let mut metadata = parse(&bytes)?;
+        if metadata.name == "metadata" {
+            // A list of elements has no root to hold them, so give it one
+            let (wrapped, root_at) = wrap_in_root(&bytes);
+            metadata = parse(&wrapped).map_err(|error| match error {
+                // Syntax errors are reported where they are in the file
+                Error::Syntax(syntax) => {
+                    // The wrapped document has no byte order mark
+                    let bom = match bytes.starts_with(BOM) {
+                        true => BOM.len(),
+                        false => 0,
+                    };
+                    let offset = syntax.offset as usize + bom;
+                    let offset = match offset.checked_sub(root_at) {
+                        Some(after) if after >= LIST_START.len() => offset - LIST_START.len(),
+                        Some(_) => root_at,
+                        None => offset,
+                    };
+                    Error::Syntax(crate::xml::syntax_error_at(&bytes, offset, syntax.message))
+                }
+                error => error,
+            })?;
+        }
+        match metadata.name.as_str() {
+            "model" => {
+                let entries = metadata
+                    .children
+                    .iter()
+                    .filter_map(XMLNode::as_element)
+                    .filter(|element| element.name == "metadata")
+                    .map(metadata_file_entry)
+                    .collect::<Result<MetadataSet>>()?;
+                if entries.is_empty() {
+                    return Err(Error::MetadataFile(
+                        "Model in metadata file has no metadata elements".to_string(),
+                    ));
+                }
+                Ok(MetadataFile {
+                    entries,
+                    ..MetadataFile::default()
+                })
+            }
+            "v1" | "metadata-list" => {
+                let entries = metadata_children(&metadata, metadata_file_entry)?;
+                if entries.is_empty() {
+                    return Err(Error::MetadataFile(
+                        "Metadata file has no metadata elements".to_string(),
+                    ));
+                }
+                Ok(MetadataFile {
+                    entries,
+                    ..MetadataFile::default()
+                })
+            }
+            "v2" => read_v2(&metadata),
+            name => Err(Error::MetadataFile(format!(
+                "Metadata file has a <{}> root element, expected <v1>, <v2>, <metadata-list>, <model> or a list of <metadata> elements",
+                name
+            ))),
+        }
+    }
+
+    /// The names of the presets, in file order.
+    pub fn preset_names(&self) -> impl Iterator<Item = &str> {
+        self.presets.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// The entries outside of presets together with those of the named
+    /// preset, which win when both have a name.
+    pub fn preset(&self, name: &str) -> Option<MetadataSet> {
+        let (_, preset) = self.presets.iter().find(|(preset, _)| preset == name)?;
+        let mut entries = self.entries.clone();
+        entries.extend(preset.iter().cloned());
+        Some(entries)
+    }
+
+    /// Reads a metadata file in TOML. Keys are the names of entries, and
+    /// inline tables give an entry the attributes of a `v2` file along with
+    /// its `value`. Values may also be numbers, booleans and dates. The
+    /// tables under `preset` are presets, and each `[[when]]` table is a
+    /// [`Conditional`], with its conditions as keys and its entries in a
+    /// `metadata` table:
+    ///
+    /// ```
+    /// use metadata_3mf::metadata::MetadataFile;
+    ///
+    /// let file = MetadataFile::read_toml(r#"
+    ///     Designer = { value = "BASt", preserve = true }
+    ///     "shop:Sku" = "A-1"
+    ///     Rating = 5
+    ///
+    ///     [preset.commercial]
+    ///     LicenseTerms = { value = "All rights reserved", "xml:lang" = "en-US" }
+    ///
+    ///     [[when]]
+    ///     path = "prototypes/**"
+    ///     metadata = { LicenseTerms = "CC-BY-NC-4.0" }
+    /// "#)?;
+    /// assert_eq!(file.entries.value("Rating"), Some("5"));
+    /// let commercial = file.preset("commercial").unwrap();
+    /// assert_eq!(commercial.license_terms(), Some("All rights reserved"));
+    /// assert_eq!(commercial.value("shop:Sku"), Some("A-1"));
+    /// assert_eq!(file.conditional[0].entries.license_terms(), Some("CC-BY-NC-4.0"));
+    /// # Ok::<(), metadata_3mf::Error>(())
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn read_toml(text: &str) -> Result<Self> {
+        let table: toml::Table = text.parse().map_err(|error: toml::de::Error| {
+            let start = error.span().map_or(0, |span| span.start);
+            let before = &text[..start];
+            let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+            Error::MetadataFile(format!(
+                "Could not parse metadata file: line {}, column {}: {}",
+                before.matches('\n').count() + 1,
+                before[line_start..].chars().count() + 1,
+                error.message().trim()
+            ))
+        })?;
+        let mut file = MetadataFile::default();
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("preset", toml::Value::Table(presets)) => {
+                    for (name, entries) in presets {
+                        let toml::Value::Table(entries) = entries else {
+                            return Err(Error::MetadataFile(format!(
+                                "Preset {} is not a table of metadata entries",
+                                name
+                            )));
+                        };
+                        let entries = entries
+                            .into_iter()
+                            .map(|(name, value)| toml_entry(&name, value))
+                            .collect::<Result<MetadataSet>>()?;
+                        file.presets.push((name, entries));
+                    }
+                }
+                ("when", toml::Value::Array(conditionals)) => {
+                    for conditional in conditionals {
+                        file.conditional.push(toml_conditional(conditional)?);
+                    }
+                }
+                (_, value) => {
+                    file.entries.insert(toml_entry(&key, value)?);
+                }
+            }
+        }
+        if file.entries.is_empty() && file.presets.is_empty() && file.conditional.is_empty() {
+            return Err(Error::MetadataFile(
+                "Metadata file has no metadata entries".to_string(),
+            ));
+        }
+        Ok(file)
+    }
+
+    /// Adds the entries, presets and conditional entries of `other`, whose
+    /// entries win over those of the same name, within presets of the same
+    /// name too.
+    pub fn merge(&mut self, other: MetadataFile) {
+        self.entries.extend(other.entries);
+        self.conditional.extend(other.conditional);
+        for (name, entries) in other.presets {
+            match self.presets.iter_mut().find(|(preset, _)| *preset == name) {
+                Some((_, preset)) => preset.extend(entries),
+                None => self.presets.push((name, entries)),
+            }
+        }
+    }
+}
+
+/// The metadata files in a directory of fragments: the `*.xml` and
+/// `*.toml` files, by file name, which is the order to
+/// [merge](MetadataFile::merge) them in. Hidden files are left out, such as
+/// those editors leave behind.
+pub fn fragments(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut fragments = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let fragment = Path::new(name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("xml") || extension.eq_ignore_ascii_case("toml")
+            });
+        if fragment && !name.starts_with('.') && path.is_file() {
+            fragments.push(path);
+        }
+    }
+    fragments.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(fragments)
+}
+
+/// Reads a metadata file, see [`MetadataFile`], and returns the entries
+/// outside of presets.
+pub fn read_metadata_file<R: Read>(reader: R) -> Result<MetadataSet> {
+    Ok(MetadataFile::read(reader)?.entries)
+}
+
+/// The `<metadata>` children of `parent`, converted with `entry`. Anything
+/// but those, whitespace and comments is an error.
+fn metadata_children(
+    parent: &Element,
+    entry: fn(&Element) -> Result<MetadataEntry>,
+) -> Result<MetadataSet> {
+    let mut entries = MetadataSet::new();
+    for child in &parent.children {
+        match child {
+            XMLNode::Element(element) if element.name == "metadata" => {
+                entries.insert(entry(element)?);
+            }
+            XMLNode::Element(element) => {
+                return Err(Error::MetadataFile(format!(
+                "Metadata file contains a <{}> element, only <metadata> elements may be in <{}>",
+                element.name, parent.name
+            )))
+            }
+            XMLNode::Text(text) | XMLNode::CData(text) if !text.trim().is_empty() => {
+                return Err(Error::MetadataFile(format!(
+                    "Metadata file contains text \"{}\" outside of <metadata> elements",
+                    text.trim()
+                )))
+            }
+            _ => (),
+        }
+    }
+    Ok(entries)
+}
+
+fn read_v2(root: &Element) -> Result<MetadataFile> {
+    let mut file = MetadataFile::default();
+    // Presets are taken out first, so the rest must be metadata
+    let mut rest = root.clone();
+    rest.children.clear();
+    for child in &root.children {
+        match child {
+            XMLNode::Element(element) if element.name == "preset" => {
+                let name = element.attributes.get("name").ok_or_else(|| {
+                    Error::MetadataFile("Preset element has no name attribute".to_string())
+                })?;
+                if file.preset_names().any(|preset| preset == name) {
+                    return Err(Error::MetadataFile(format!(
+                        "Preset {} is defined twice",
+                        name
+                    )));
+                }
+                let entries = metadata_children(element, metadata_file_entry_v2)?;
+                file.presets.push((name.clone(), entries));
+            }
+            XMLNode::Element(element) if element.name == "when" => {
+                let conditions = Conditional::conditions(
+                    element
+                        .attributes
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                )?;
+                let entries = metadata_children(element, metadata_file_entry_v2)?;
+                file.conditional.push(Conditional {
+                    conditions,
+                    entries,
+                });
+            }
+            _ => rest.children.push(child.clone()),
+        }
+    }
+    file.entries = metadata_children(&rest, metadata_file_entry_v2)?;
+    if file.entries.is_empty() && file.presets.is_empty() && file.conditional.is_empty() {
+        return Err(Error::MetadataFile(
+            "Metadata file has no metadata elements".to_string(),
+        ));
+    }
+    Ok(file)
+}
+
+/// [`metadata_file_entry`] for `v2` files, which checks the attributes and
+/// moves a `prefix` into the name.
+fn metadata_file_entry_v2(element: &Element) -> Result<MetadataEntry> {
+    let mut entry = metadata_file_entry(element)?;
+    let mut prefix = None;
+    let mut attributes = Vec::new();
+    for (key, value) in std::mem::take(&mut entry.attributes) {
+        match key.as_str() {
+            "prefix" => prefix = Some(value),
+            "preserve" if !matches!(value.as_str(), "0" | "1" | "true" | "false") => {
+                return Err(Error::MetadataFile(format!(
+                    "Metadata element {} has preserve=\"{}\", expected 0, 1, true or false",
+                    entry.name, value
+                )))
+            }
+            "preserve" | "type" | "xml:lang" => attributes.push((key, value)),
+            _ => {
+                return Err(Error::MetadataFile(format!(
+                    "Metadata element {} has unknown attribute {}, expected preserve, type, xml:lang or prefix",
+                    entry.name, key
+                )))
+            }
+        }
+    }
+    if let Some(prefix) = prefix {
+        if entry.name.contains(':') {
+            return Err(Error::MetadataFile(format!(
+                "Metadata element {} has a prefix attribute and a prefix in its name",
+                entry.name
+            )));
+        }
+        entry.name = format!("{}:{}", prefix, entry.name);
+    }
+    entry.attributes = attributes;
+    Ok(entry)
+}
+
+/// Converts an entry of a TOML metadata file, checking it like those of
+/// `v2` files.
+#[cfg(feature = "toml")]
+fn toml_entry(name: &str, value: toml::Value) -> Result<MetadataEntry> {
+    let mut element = Element::new("metadata");
+    element
+        .attributes
+        .insert("name".to_string(), name.to_string());
+    let value = match value {
+        toml::Value::Table(table) => {
+            let mut value = None;
+            for (key, attribute) in table {
+                let attribute = toml_text(name, attribute)?;
+                match key.as_str() {
+                    "value" => value = Some(attribute),
+                    _ => {
+                        element.attributes.insert(key, attribute);
+                    }
+                }
+            }
+            value.ok_or_else(|| {
+                Error::MetadataFile(format!("Metadata entry {} has no value", name))
+            })?
+        }
+        value => toml_text(name, value)?,
+    };
+    element.children.push(XMLNode::Text(value));
+    metadata_file_entry_v2(&element)
+}
+
+/// Converts a `when` table of a TOML metadata file, whose `metadata` table
+/// has the entries and whose other keys are the conditions.
+#[cfg(feature = "toml")]
+fn toml_conditional(value: toml::Value) -> Result<Conditional> {
+    let toml::Value::Table(mut table) = value else {
+        return Err(Error::MetadataFile(
+            "Condition is not a table with conditions and metadata".to_string(),
+        ));
+    };
+    let entries = match table.remove("metadata") {
+        Some(toml::Value::Table(entries)) => entries
+            .into_iter()
+            .map(|(name, value)| toml_entry(&name, value))
+            .collect::<Result<MetadataSet>>()?,
+        _ => {
+            return Err(Error::MetadataFile(
+                "Condition has no metadata table".to_string(),
+            ))
+        }
+    };
+    let attributes = table
+        .into_iter()
+        .map(|(key, value)| Ok((key.clone(), toml_text(&key, value)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let conditions = Conditional::conditions(
+        attributes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    )?;
+    Ok(Conditional {
+        conditions,
+        entries,
+    })
+}
+
+/// The text of a TOML value, which may not be an array or a table.
+#[cfg(feature = "toml")]
+fn toml_text(name: &str, value: toml::Value) -> Result<String> {
+    let not_text = |kind: &str| {
+        Error::MetadataFile(format!(
+            "Metadata entry {} has {}, values must be text, numbers, booleans or dates",
+            name, kind
+        ))
+    };
+    match value {
+        toml::Value::String(text) => Ok(text),
+        toml::Value::Array(_) => Err(not_text("an array")),
+        toml::Value::Table(_) => Err(not_text("a table")),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// The byte order mark of UTF-8.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The start tag of the root element of metadata files without one.
+const LIST_START: &[u8] = b"<metadata-list>";
+
+/// Puts a document in a `<metadata-list>` root element, after its XML
+/// declaration if it has one. Returns the document and where the start tag
+/// went in `bytes`.
+fn wrap_in_root(bytes: &[u8]) -> (Vec<u8>, usize) {
+    let text = bytes.strip_prefix(BOM).unwrap_or(bytes);
+    let start = text.iter().position(|byte| !byte.is_ascii_whitespace());
+    let body_start = match start {
+        Some(start) if text[start..].starts_with(b"<?xml") => text[start..]
+            .windows(2)
+            .position(|window| window == b"?>")
+            .map_or(text.len(), |end| start + end + 2),
+        _ => 0,
+    };
+    let mut wrapped = text[..body_start].to_vec();
+    wrapped.extend_from_slice(LIST_START);
+    wrapped.extend_from_slice(&text[body_start..]);
+    wrapped.extend_from_slice(b"</metadata-list>");
+    (wrapped, bytes.len() - text.len() + body_start)
+}
+
+/// Converts a `<metadata>` element of a metadata file, whose value may
+/// only be text.
+fn metadata_file_entry(element: &Element) -> Result<MetadataEntry> {
+    let mut entry = MetadataEntry::from_element(element)
+        .ok_or_else(|| Error::MetadataFile("Metadata element has no name attribute".to_string()))?;
+    entry.value.clear();
+    for child in &element.children {
+        match child {
+            XMLNode::Text(text) | XMLNode::CData(text) => entry.value.push_str(text),
+            XMLNode::Element(child) => {
+                return Err(Error::MetadataFile(format!(
+                    "Metadata element {} contains a <{}> element, values must be text",
+                    entry.name, child.name
+                )))
+            }
+            XMLNode::Comment(_) | XMLNode::ProcessingInstruction(..) => (),
+        }
+    }
+    Ok(entry)
+}
+
+/// Replaces `${NAME}` in a metadata value with what `lookup` returns for
+/// `NAME`, usually an environment variable. `$${` stands for a literal `${`.
+/// Fails when `lookup` returns `None` or a reference is not closed.
+///
+/// ```
+/// use metadata_3mf::metadata::expand_variables;
+///
+/// let lookup = |name: &str| (name == "BUILD").then(|| "42".to_string());
+/// assert_eq!(expand_variables("Build ${BUILD}", lookup)?, "Build 42");
+/// assert_eq!(expand_variables("$${BUILD}", lookup)?, "${BUILD}");
+/// assert!(expand_variables("${OPERATOR}", lookup).is_err());
+/// # Ok::<(), metadata_3mf::Error>(())
+/// ```
+pub fn expand_variables(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(Error::MetadataFile(format!(
+                "Unclosed variable reference in \"{}\"",
+                value
+            )));
+        };
+        let name = &rest[start + 2..start + end];
+        match lookup(name) {
+            Some(replacement) => expanded.push_str(&replacement),
+            None => {
+                return Err(Error::MetadataFile(format!(
+                    "Variable {} in \"{}\" is not set",
+                    name, value
+                )))
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
@@ -0,0 +1,946 @@
+//! Operations on the XML of a 3MF model part.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use xmltree::{Element, EmitterConfig, XMLNode};
+
+use crate::schema::PRODUCTION_NAMESPACE;
+use crate::{observer, Error, MetadataEntry, MetadataSet, Result};
+
+/// Units allowed in the `unit` attribute of a 3MF model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Micron,
+    Millimeter,
+    Centimeter,
+    Inch,
+    Foot,
+    Meter,
+}
+
+impl Unit {
+    /// The unit used when a model has no `unit` attribute.
+    pub const DEFAULT: Unit = Unit::Millimeter;
+
+    /// The names of all units, as used in the `unit` attribute.
+    pub const NAMES: [&'static str; 6] = [
+        "micron",
+        "millimeter",
+        "centimeter",
+        "inch",
+        "foot",
+        "meter",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Unit::Micron => "micron",
+            Unit::Millimeter => "millimeter",
+            Unit::Centimeter => "centimeter",
+            Unit::Inch => "inch",
+            Unit::Foot => "foot",
+            Unit::Meter => "meter",
+        }
+    }
+
+    pub fn in_millimeters(&self) -> f64 {
+        match self {
+            Unit::Micron => 0.001,
+            Unit::Millimeter => 1.0,
+            Unit::Centimeter => 10.0,
+            Unit::Inch => 25.4,
+            Unit::Foot => 304.8,
+            Unit::Meter => 1000.0,
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "micron" => Ok(Unit::Micron),
+            "millimeter" => Ok(Unit::Millimeter),
+            "centimeter" => Ok(Unit::Centimeter),
+            "inch" => Ok(Unit::Inch),
+            "foot" => Ok(Unit::Foot),
+            "meter" => Ok(Unit::Meter),
+            _ => Err(Error::InvalidValue {
+                name: "unit".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub fn read_model<R: Read>(reader: R) -> Result<Element> {
+    crate::xml::parse(reader)
+}
+
+/// Writes a model the way this crate always has: tab indented, with
+/// newlines between elements.
+pub fn write_model<W: Write>(xml: &Element, writer: W) -> Result<()> {
+    let config = Compat::StrictSpec.emitter_config().perform_indent(true);
+    Ok(xml.write_with_config(writer, config)?)
+}
+
+/// Writes a model with the serialization quirks of `compat`. Empty
+/// metadata of the model may get an empty text to be written as a start
+/// and an end tag.
+pub fn write_model_with<W: Write>(xml: &mut Element, mut writer: W, compat: Compat) -> Result<()> {
+    if compat == Compat::StrictSpec {
+        return write_model(xml, writer);
+    }
+    compat.open_empty_metadata(xml);
+    // The slicers spell the encoding in upper case, and some readers
+    // compare the declaration as a string
+    writer.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+    let config = compat
+        .emitter_config()
+        .perform_indent(true)
+        .write_document_declaration(false);
+    Ok(xml.write_with_config(writer, config)?)
+}
+
+/// How written models are laid out, for consumers that only read what a
+/// particular slicer writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compat {
+    /// Tab indented, as this crate always has written models.
+    StrictSpec,
+    /// Indented with a space, with empty elements closed as `<a />`, like
+    /// Cura writes. Older Cura versions fail on tab indentation.
+    Cura,
+    /// Indented with a space, with empty elements closed as `<a/>` and
+    /// empty metadata written as `<metadata name="a"></metadata>`, like
+    /// PrusaSlicer writes.
+    Prusa,
+    /// The same layout as PrusaSlicer, which Bambu Studio inherited.
+    Bambu,
+}
+
+impl Compat {
+    /// The names of all profiles, as given on the command line.
+    pub const NAMES: [&'static str; 4] = ["strict-spec", "cura", "prusa", "bambu"];
+
+    /// The emitter settings of the profile, without indentation turned on,
+    /// so that they also fit writing single elements.
+    fn emitter_config(&self) -> EmitterConfig {
+        let config = EmitterConfig::new().line_separator("\n");
+        match self {
+            Compat::StrictSpec => config.indent_string("\t"),
+            Compat::Cura => config.indent_string(" "),
+            Compat::Prusa | Compat::Bambu => config.indent_string(" ").pad_self_closing(false),
+        }
+    }
+
+    /// Gives the empty metadata of the model an empty text, for profiles
+    /// that write it as a start and an end tag. Other empty elements stay
+    /// self-closing.
+    fn open_empty_metadata(&self, xml: &mut Element) {
+        if !matches!(self, Compat::Prusa | Compat::Bambu) {
+            return;
+        }
+        for child in &mut xml.children {
+            if let XMLNode::Element(element) = child {
+                if element.name == "metadata" && element.children.is_empty() {
+                    element.children.push(XMLNode::Text(String::new()));
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Compat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strict-spec" => Ok(Compat::StrictSpec),
+            "cura" => Ok(Compat::Cura),
+            "prusa" => Ok(Compat::Prusa),
+            "bambu" => Ok(Compat::Bambu),
+            _ => Err(Error::InvalidValue {
+                name: "compatibility profile".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Replaces all `<metadata>` children of the model with the given set. The
+/// metadata goes first, followed by the other children in their original
+/// order.
+pub fn set_metadata(xml: &mut Element, metadata: &MetadataSet) {
+    let other_children =
+        std::mem::take(&mut xml.children)
+            .into_iter()
+            .filter(|child| match child {
+                XMLNode::Element(element) => element.name != "metadata",
+                _ => true,
+            });
+    xml.children = metadata
+        .iter()
+        .map(|entry| XMLNode::Element(entry.to_element()))
+        .chain(other_children)
+        .collect();
+}
+
+/// Merges `metadata` into the metadata of the model. When a name exists in
+/// both, the new entry wins, unless `keep_existing` is set.
+pub fn merge_metadata(xml: &mut Element, metadata: &MetadataSet, keep_existing: bool) {
+    let mut merged = MetadataSet::from_model(xml);
+    for entry in metadata {
+        if !(keep_existing && merged.contains(&entry.name)) {
+            merged.insert(entry.clone());
+        }
+    }
+    set_metadata(xml, &merged);
+}
+
+/// Removes the metadata entries with the given names from the model, and
+/// returns the entries that were actually there.
+pub fn remove_metadata(xml: &mut Element, names: &[String]) -> Vec<MetadataEntry> {
+    let mut metadata = MetadataSet::from_model(xml);
+    let removed: Vec<_> = names
+        .iter()
+        .filter_map(|name| metadata.remove(name))
+        .collect();
+    if !removed.is_empty() {
+        set_metadata(xml, &metadata);
+    }
+    removed
+}
+
+/// What [`rename_metadata`] does when the model already has an entry with
+/// the new name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenameConflict {
+    /// Leave both entries as they are.
+    Skip,
+    /// Replace the existing entry with the renamed one.
+    Overwrite,
+    /// Keep the existing entry and drop the one that would be renamed.
+    KeepTarget,
+}
+
+impl RenameConflict {
+    /// The names of all policies, as given on the command line.
+    pub const NAMES: [&'static str; 3] = ["skip", "overwrite", "keep-target"];
+}
+
+impl FromStr for RenameConflict {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(RenameConflict::Skip),
+            "overwrite" => Ok(RenameConflict::Overwrite),
+            "keep-target" => Ok(RenameConflict::KeepTarget),
+            _ => Err(Error::InvalidValue {
+                name: "conflict policy".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// The outcome of [`rename_metadata`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Renamed {
+    /// The model has no entry with the old name.
+    NotFound,
+    /// The entry was renamed. Holds the entry it replaced, if any.
+    Renamed(Option<MetadataEntry>),
+    /// Both names exist and the model was left as it was.
+    Conflict,
+    /// Both names exist and the entry with the old name was removed.
+    Dropped(MetadataEntry),
+}
+
+/// Renames the metadata entry `from` of the model to `to`, keeping its
+/// place, value and attributes. `on_conflict` decides what happens when
+/// there already is an entry `to`.
+pub fn rename_metadata(
+    xml: &mut Element,
+    from: &str,
+    to: &str,
+    on_conflict: RenameConflict,
+) -> Renamed {
+    let mut metadata = MetadataSet::from_model(xml);
+    if !metadata.contains(from) {
+        return Renamed::NotFound;
+    }
+    let renamed = match (metadata.contains(to), on_conflict) {
+        (true, RenameConflict::Skip) => return Renamed::Conflict,
+        (true, RenameConflict::KeepTarget) => {
+            Renamed::Dropped(metadata.remove(from).expect("checked above"))
+        }
+        _ => Renamed::Renamed(metadata.rename(from, to)),
+    };
+    set_metadata(xml, &metadata);
+    renamed
+}
+
+/// The `<metadata>` elements at the top of a model, parsed on their own so
+/// that a metadata change can copy the rest of the model byte for byte
+/// instead of parsing and writing all of it.
+///
+/// The 3MF schema puts metadata before the resources and build, so only the
+/// metadata directly after the `<model>` start tag is considered. The rest of
+/// the model is not checked for well-formedness.
+#[derive(Debug)]
+pub struct MetadataPatch<'a> {
+    /// Everything up to and including the `<model>` start tag.
+    head: &'a [u8],
+    /// The whitespace before the first child of the model, repeated before
+    /// every metadata element that gets written.
+    indent: &'a [u8],
+    /// Everything after the last metadata element.
+    tail: &'a [u8],
+    /// The model element, with only its metadata as children.
+    pub xml: Element,
+}
+
+impl<'a> MetadataPatch<'a> {
+    /// Finds and parses the metadata at the top of a model part. Returns
+    /// `None` when the part is laid out in a way that needs a full parse,
+    /// for example when it isn't UTF-8 or the model element has a namespace
+    /// prefix.
+    pub fn new(model: &'a [u8]) -> Result<Option<Self>> {
+        let head_end = match model_start_tag_end(model) {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let indent_end = skip_whitespace(model, head_end);
+        let mut end = head_end;
+        loop {
+            let start = skip_whitespace(model, end);
+            if !is_tag(model, start, b"metadata") {
+                break;
+            }
+            match metadata_element_end(model, start) {
+                Some(element_end) => end = element_end,
+                None => return Ok(None),
+            }
+        }
+
+        // Parse the metadata together with the model start tag, so that
+        // namespace declarations and the document prolog still apply.
+        let mut document = Vec::with_capacity(end + "</model>".len());
+        document.extend_from_slice(&model[..end]);
+        document.extend_from_slice(b"</model>");
+        let xml = read_model(document.as_slice())?;
+        Ok(Some(MetadataPatch {
+            head: &model[..head_end],
+            indent: &model[head_end..indent_end],
+            tail: &model[end..],
+            xml,
+        }))
+    }
+
+    /// Writes the model with the metadata of `xml` in place of the original.
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        self.write_with(writer, Compat::StrictSpec)
+    }
+
+    /// Like [`MetadataPatch::write`], with empty metadata written the way
+    /// `compat` asks for. The rest of the model is copied as it was.
+    pub fn write_with<W: Write>(&self, mut writer: W, compat: Compat) -> Result<()> {
+        let config = compat.emitter_config().write_document_declaration(false);
+        // Only the metadata was parsed, so this copy is cheap
+        let mut xml = self.xml.clone();
+        compat.open_empty_metadata(&mut xml);
+        writer.write_all(self.head)?;
+        for child in &xml.children {
+            if let XMLNode::Element(element) = child {
+                if element.name == "metadata" {
+                    writer.write_all(self.indent)?;
+                    element.write_with_config(&mut writer, config.clone())?;
+                }
+            }
+        }
+        writer.write_all(self.tail)?;
+        Ok(())
+    }
+}
+
+/// A model part read for a metadata change. The metadata is patched in
+/// place when possible, otherwise the whole model is parsed and rewritten.
+#[derive(Debug)]
+pub enum MetadataEdit<'a> {
+    Patch(MetadataPatch<'a>),
+    Full(Element),
+}
+
+impl<'a> MetadataEdit<'a> {
+    pub fn new(model: &'a [u8]) -> Result<Self> {
+        Ok(match MetadataPatch::new(model)? {
+            Some(patch) => MetadataEdit::Patch(patch),
+            None => MetadataEdit::Full(read_model(model)?),
+        })
+    }
+
+    /// The model, which with a patch only has its metadata as children.
+    pub fn xml(&self) -> &Element {
+        match self {
+            MetadataEdit::Patch(patch) => &patch.xml,
+            MetadataEdit::Full(xml) => xml,
+        }
+    }
+
+    /// The model to change the metadata of. With a patch, the model only
+    /// has its metadata as children, so nothing else should be changed.
+    pub fn xml_mut(&mut self) -> &mut Element {
+        match self {
+            MetadataEdit::Patch(patch) => &mut patch.xml,
+            MetadataEdit::Full(xml) => xml,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        match self {
+            MetadataEdit::Patch(patch) => patch.write(writer),
+            MetadataEdit::Full(xml) => write_model(xml, writer),
+        }
+    }
+
+    /// Writes the model with the serialization quirks of `compat`, see
+    /// [`write_model_with`].
+    pub fn write_with<W: Write>(&mut self, writer: W, compat: Compat) -> Result<()> {
+        match self {
+            MetadataEdit::Patch(patch) => patch.write_with(writer, compat),
+            MetadataEdit::Full(xml) => write_model_with(xml, writer, compat),
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut position: usize) -> usize {
+    while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+        position += 1;
+    }
+    position
+}
+
+/// Checks for an unprefixed start tag with the given name at `position`.
+fn is_tag(bytes: &[u8], position: usize, name: &[u8]) -> bool {
+    let rest = &bytes[position.min(bytes.len())..];
+    rest.first() == Some(&b'<')
+        && rest[1..].starts_with(name)
+        && rest
+            .get(1 + name.len())
+            .is_some_and(|&byte| byte.is_ascii_whitespace() || byte == b'>' || byte == b'/')
+}
+
+/// Finds the end of the tag starting at `position`, skipping over quoted
+/// attribute values. Returns the position after the `>`, and whether the
+/// tag was self-closing.
+fn tag_end(bytes: &[u8], position: usize) -> Option<(usize, bool)> {
+    let mut quote = None;
+    for (offset, &byte) in bytes[position..].iter().enumerate() {
+        match (quote, byte) {
+            (Some(open), _) if byte == open => quote = None,
+            (Some(_), _) => (),
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (None, b'>') => {
+                let end = position + offset;
+                return Some((end + 1, bytes[end - 1] == b'/'));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Skips the XML declaration, comments and processing instructions before
+/// the root element, and returns the position after the `<model>` start
+/// tag.
+fn model_start_tag_end(bytes: &[u8]) -> Option<usize> {
+    let mut position = 0;
+    loop {
+        position = skip_whitespace(bytes, position);
+        let rest = &bytes[position..];
+        if rest.starts_with(b"<?") {
+            position += find(rest, b"?>")? + 2;
+        } else if rest.starts_with(b"<!--") {
+            position += find(rest, b"-->")? + 3;
+        } else if is_tag(bytes, position, b"model") {
+            return match tag_end(bytes, position)? {
+                (end, false) => Some(end),
+                (_, true) => None,
+            };
+        } else {
+            // A byte order mark, a DOCTYPE or a prefixed root element
+            return None;
+        }
+    }
+}
+
+/// Returns the position after the metadata element starting at `position`.
+/// Only text content is accepted, anything else needs a full parse.
+fn metadata_element_end(bytes: &[u8], position: usize) -> Option<usize> {
+    let (start_tag_end, self_closing) = tag_end(bytes, position)?;
+    if self_closing {
+        return Some(start_tag_end);
+    }
+    let content_end = start_tag_end
+        + bytes[start_tag_end..]
+            .iter()
+            .position(|&byte| byte == b'<')?;
+    let rest = &bytes[content_end..];
+    if !rest.starts_with(b"</metadata") {
+        return None;
+    }
+    let close = skip_whitespace(bytes, content_end + "</metadata".len());
+    (bytes.get(close) == Some(&b'>')).then(|| close + 1)
+}
+
+/// Finds the `<resources>` start tag that follows the metadata of a model,
+/// looking from `from` on.
+pub(crate) fn resources_start(bytes: &[u8], from: usize) -> Option<usize> {
+    let mut position = from;
+    while let Some(offset) = find(&bytes[position..], b"<resources") {
+        position += offset;
+        if is_tag(bytes, position, b"resources") {
+            return Some(position);
+        }
+        position += 1;
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Reads the `unit` attribute of a model. The boolean is true when the
+/// attribute is absent and the default unit is implied.
+pub fn unit(xml: &Element) -> Result<(Unit, bool)> {
+    match xml.attributes.get("unit") {
+        Some(unit) => Ok((unit.parse()?, false)),
+        None => Ok((Unit::DEFAULT, true)),
+    }
+}
+
+/// Changes the unit of the model, rescaling the geometry so that the
+/// physical size stays the same. Returns the previous unit; when that is
+/// equal to `to`, the model is left untouched.
+pub fn convert_units(xml: &mut Element, to: Unit) -> Result<Unit> {
+    let (from, _) = unit(xml)?;
+    if from != to {
+        scale_element(xml, from.in_millimeters() / to.in_millimeters());
+        xml.attributes
+            .insert("unit".to_string(), to.name().to_string());
+    }
+    Ok(from)
+}
+
+/// Formats a coordinate without float noise or trailing zeros. Twelve
+/// significant digits keep well clear of the noise in the last digits of an
+/// f64, and are still more than any printer resolves, so converting back to
+/// the original unit gives the original number.
+fn format_number(value: f64) -> String {
+    let magnitude = if value == 0.0 {
+        0
+    } else {
+        value.abs().log10().floor() as i32
+    };
+    let decimals = (11 - magnitude).max(0) as usize;
+    let formatted = format!("{:.*}", decimals, value);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    if trimmed == "-0" {
+        "0".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn scale_attributes(element: &mut Element, names: &[&str], factor: f64) {
+    for name in names {
+        if let Some(value) = element.attributes.get_mut(*name) {
+            if let Ok(number) = value.trim().parse::<f64>() {
+                *value = format_number(number * factor);
+            }
+        }
+    }
+}
+
+/// Scales the translation part of a 3MF transform matrix. The other nine
+/// values stay as they are, because the vertices themselves get scaled.
+fn scale_transform(element: &mut Element, factor: f64) {
+    if let Some(transform) = element.attributes.get_mut("transform") {
+        let mut values = transform
+            .split_whitespace()
+            .map(|value| value.parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap_or_default();
+        if values.len() != 12 {
+            observer::warning(format_args!(
+                "ignoring malformed transform \"{}\"",
+                transform
+            ));
+            return;
+        }
+        for value in &mut values[9..] {
+            *value *= factor;
+        }
+        *transform = values
+            .into_iter()
+            .map(format_number)
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+}
+
+/// Scales every length in the model: mesh vertices, build item and
+/// component transforms, and beam lattice and slice stack dimensions.
+fn scale_element(xml: &mut Element, factor: f64) {
+    // Walk the tree with an explicit stack, so that hostile nesting depths
+    // cannot overflow the call stack.
+    let mut stack = vec![xml];
+    while let Some(element) = stack.pop() {
+        match element.name.as_str() {
+            "vertex" => scale_attributes(element, &["x", "y", "z"], factor),
+            "item" | "component" => scale_transform(element, factor),
+            "beamlattice" => scale_attributes(element, &["radius", "minlength"], factor),
+            "beam" => scale_attributes(element, &["r1", "r2"], factor),
+            "slicestack" => scale_attributes(element, &["zbottom"], factor),
+            "slice" => scale_attributes(element, &["ztop"], factor),
+            _ => (),
+        }
+        stack.extend(
+            element
+                .children
+                .iter_mut()
+                .filter_map(XMLNode::as_mut_element),
+        );
+    }
+}
+
+/// The build items of a model that place the same object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placements {
+    pub object_id: String,
+    /// The model part the object is in, from the `p:path` of the production
+    /// extension, when it is not in this one.
+    pub path: Option<String>,
+    /// The transform of each item as its 12 numbers, the identity for items
+    /// without one. Malformed transforms are empty.
+    pub transforms: Vec<Vec<f64>>,
+}
+
+impl Placements {
+    /// How many of the transforms differ. They are compared as numbers, so
+    /// `1.0` and `1` are the same.
+    pub fn different_transforms(&self) -> usize {
+        let mut different: Vec<&Vec<f64>> = Vec::new();
+        for transform in &self.transforms {
+            if !different.contains(&transform) {
+                different.push(transform);
+            }
+        }
+        different.len()
+    }
+}
+
+/// Groups the build items of a model by the object they reference, in order
+/// of first appearance. Objects with the same id in different model parts
+/// are different objects.
+pub fn build_items_by_object(xml: &Element) -> Vec<Placements> {
+    let mut groups: Vec<Placements> = Vec::new();
+    for item in build_items(xml) {
+        let transform = match item.transform.as_deref() {
+            Some(transform) if is_transform(transform) => transform
+                .split_whitespace()
+                .map(|value| value.parse().unwrap())
+                .collect(),
+            Some(_) => Vec::new(),
+            None => vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+        };
+        match groups
+            .iter_mut()
+            .find(|group| group.object_id == item.object_id && group.path == item.path)
+        {
+            Some(group) => group.transforms.push(transform),
+            None => groups.push(Placements {
+                object_id: item.object_id,
+                path: item.path,
+                transforms: vec![transform],
+            }),
+        }
+    }
+    groups
+}
+
+/// The ids and names of the objects of a model that have a name.
+pub fn object_names(xml: &Element) -> Vec<(&str, &str)> {
+    xml.get_child("resources")
+        .map(|resources| resources.children.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|child| child.as_element())
+        .filter(|element| element.name == "object")
+        .filter_map(|object| {
+            let id = object.attributes.get("id")?;
+            let name = object.attributes.get("name")?;
+            Some((id.as_str(), name.as_str()))
+        })
+        .collect()
+}
+
+/// Looks up the `name` attribute of the object with the given id.
+pub fn object_name<'a>(xml: &'a Element, object_id: &str) -> Option<&'a str> {
+    xml.get_child("resources")?
+        .children
+        .iter()
+        .filter_map(|child| child.as_element())
+        .find(|element| {
+            element.name == "object"
+                && element.attributes.get("id").map(String::as_str) == Some(object_id)
+        })?
+        .attributes
+        .get("name")
+        .map(String::as_str)
+}
+
+/// An object of a model, with the components it is assembled from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Object {
+    pub id: String,
+    pub name: Option<String>,
+    pub part_number: Option<String>,
+    /// The `type` attribute, `model` when it has none.
+    pub kind: String,
+    pub components: Vec<Component>,
+}
+
+/// A component of an object: another object, placed with a transform.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Component {
+    pub object_id: String,
+    /// The transform with whitespace normalized, if the component has one.
+    pub transform: Option<String>,
+    /// The model part the object is in, from the `p:path` of the
+    /// production extension, when it is not in this one.
+    pub path: Option<String>,
+}
+
+/// The child elements of `element` with the given name.
+fn children_named<'a>(element: &'a Element, name: &'a str) -> impl Iterator<Item = &'a Element> {
+    element
+        .children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .filter(move |child| child.name == name)
+}
+
+/// The value of the attribute `name` in `namespace` of an element, with
+/// whatever prefix the document gives the namespace.
+fn namespaced_attribute<'a>(element: &'a Element, namespace: &str, name: &str) -> Option<&'a str> {
+    let namespaces = element.namespaces.as_ref()?;
+    element.attributes.iter().find_map(|(key, value)| {
+        let (prefix, local) = key.split_once(':')?;
+        (local == name && namespaces.get(prefix) == Some(namespace)).then_some(value.as_str())
+    })
+}
+
+/// The objects of a model, in the order of the model.
+pub fn objects(xml: &Element) -> Vec<Object> {
+    let attribute = |element: &Element, name: &str| element.attributes.get(name).cloned();
+    let Some(resources) = xml.get_child("resources") else {
+        return Vec::new();
+    };
+    children_named(resources, "object")
+        .map(|object| {
+            let components = object
+                .get_child("components")
+                .into_iter()
+                .flat_map(|components| children_named(components, "component"))
+                .map(|component| Component {
+                    object_id: attribute(component, "objectid").unwrap_or_default(),
+                    transform: attribute(component, "transform").map(|transform| {
+                        transform.split_whitespace().collect::<Vec<_>>().join(" ")
+                    }),
+                    path: namespaced_attribute(component, PRODUCTION_NAMESPACE, "path")
+                        .map(str::to_string),
+                })
+                .collect();
+            Object {
+                id: attribute(object, "id").unwrap_or_default(),
+                name: attribute(object, "name"),
+                part_number: attribute(object, "partnumber"),
+                kind: attribute(object, "type").unwrap_or_else(|| "model".to_string()),
+                components,
+            }
+        })
+        .collect()
+}
+
+/// Sets the `name` and `partnumber` attributes of the object with the id.
+/// `None` leaves an attribute as it is, and an empty value removes it.
+/// Returns false when the model has no such object.
+pub fn set_object_attributes(
+    xml: &mut Element,
+    id: &str,
+    name: Option<&str>,
+    part_number: Option<&str>,
+) -> bool {
+    let object = xml.get_mut_child("resources").and_then(|resources| {
+        resources
+            .children
+            .iter_mut()
+            .filter_map(XMLNode::as_mut_element)
+            .find(|element| {
+                element.name == "object"
+                    && element.attributes.get("id").map(String::as_str) == Some(id)
+            })
+    });
+    let Some(object) = object else {
+        return false;
+    };
+    for (attribute, value) in [("name", name), ("partnumber", part_number)] {
+        match value {
+            Some("") => {
+                object.attributes.remove(attribute);
+            }
+            Some(value) => {
+                object
+                    .attributes
+                    .insert(attribute.to_string(), value.to_string());
+            }
+            None => (),
+        }
+    }
+    true
+}
+
+/// An item of the build of a model, which places an object on the plate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildItem {
+    pub object_id: String,
+    /// The transform with whitespace normalized, if the item has one.
+    pub transform: Option<String>,
+    pub part_number: Option<String>,
+    /// The model part the object is in, from the `p:path` of the production
+    /// extension, when it is not in this one.
+    pub path: Option<String>,
+}
+
+/// The items of the build of a model, in order.
+pub fn build_items(xml: &Element) -> Vec<BuildItem> {
+    let attribute = |element: &Element, name: &str| element.attributes.get(name).cloned();
+    let Some(build) = xml.get_child("build") else {
+        return Vec::new();
+    };
+    children_named(build, "item")
+        .map(|item| BuildItem {
+            object_id: attribute(item, "objectid").unwrap_or_default(),
+            transform: attribute(item, "transform")
+                .map(|transform| transform.split_whitespace().collect::<Vec<_>>().join(" ")),
+            part_number: attribute(item, "partnumber"),
+            path: namespaced_attribute(item, PRODUCTION_NAMESPACE, "path").map(str::to_string),
+        })
+        .collect()
+}
+
+/// Changes to the attributes of a build item. Each attribute that is `Some`
+/// is set, or removed when it is empty; the object id can't be removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildItemEdit {
+    pub object_id: Option<String>,
+    pub transform: Option<String>,
+    pub part_number: Option<String>,
+    pub path: Option<String>,
+}
+
+/// Whether a transform is the 12 numbers of a 3MF transform matrix.
+pub fn is_transform(transform: &str) -> bool {
+    let values: Vec<_> = transform.split_whitespace().collect();
+    values.len() == 12 && values.iter().all(|value| value.parse::<f64>().is_ok())
+}
+
+/// Changes the build item at `index`, counting from 0. Setting a path
+/// declares the namespace of the production extension on the model when it
+/// has no prefix for it yet. Returns false when the build has no such item.
+pub fn edit_build_item(xml: &mut Element, index: usize, edit: &BuildItemEdit) -> bool {
+    let prefix = match &edit.path {
+        Some(path) if !path.is_empty() => Some(production_prefix(xml)),
+        _ => None,
+    };
+    let item = xml.get_mut_child("build").and_then(|build| {
+        build
+            .children
+            .iter_mut()
+            .filter_map(XMLNode::as_mut_element)
+            .filter(|element| element.name == "item")
+            .nth(index)
+    });
+    let Some(item) = item else {
+        return false;
+    };
+    if let Some(object_id) = edit.object_id.as_deref().filter(|id| !id.is_empty()) {
+        item.attributes
+            .insert("objectid".to_string(), object_id.to_string());
+    }
+    for (attribute, value) in [
+        ("transform", &edit.transform),
+        ("partnumber", &edit.part_number),
+    ] {
+        match value.as_deref() {
+            Some("") => {
+                item.attributes.remove(attribute);
+            }
+            Some(value) => {
+                item.attributes
+                    .insert(attribute.to_string(), value.to_string());
+            }
+            None => (),
+        }
+    }
+    if edit.path.is_some() {
+        let namespaces = item.namespaces.clone();
+        item.attributes.retain(|key, _| {
+            let Some((prefix, "path")) = key.split_once(':') else {
+                return true;
+            };
+            let in_scope = namespaces
+                .as_ref()
+                .and_then(|namespaces| namespaces.get(prefix));
+            in_scope != Some(PRODUCTION_NAMESPACE)
+        });
+    }
+    if let (Some(prefix), Some(path)) = (prefix, &edit.path) {
+        item.attributes
+            .insert(format!("{}:path", prefix), path.clone());
+    }
+    true
+}
+
+/// The prefix of the production extension in a model, which is declared on
+/// the model as `p`, or `p` and a number when that is taken, if it has none.
+fn production_prefix(xml: &mut Element) -> String {
+    let namespaces = xml.namespaces.get_or_insert_with(xmltree::Namespace::empty);
+    if let Some((prefix, _)) = namespaces
+        .into_iter()
+        .find(|(_, namespace)| *namespace == PRODUCTION_NAMESPACE)
+    {
+        return prefix.to_string();
+    }
+    let prefix = std::iter::once("p".to_string())
+        .chain((1..).map(|number| format!("p{}", number)))
+        .find(|prefix| namespaces.get(prefix).is_none())
+        .unwrap();
+    namespaces.put(prefix.as_str(), PRODUCTION_NAMESPACE);
+    prefix
+}
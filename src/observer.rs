@@ -0,0 +1,153 @@
+//! Events about the files being processed, for progress bars, logs and the
+//! windows of programs that embed this crate.
+//!
+//! A [`ProcessObserver`] is told when a file is started and finished, how
+//! far copying its parts has come, and about the warnings found on the way.
+//! Without one, warnings are printed to standard error as they always
+//! were. Observers are shared between threads, so files can be processed
+//! in parallel, each in a call to [`observe`] on its own thread, which
+//! also ties the events of the library functions to the file they are for.
+//!
+//! ```
+//! use std::path::Path;
+//! use std::sync::Arc;
+//!
+//! use metadata_3mf::observer::{self, Tally};
+//!
+//! let tally = Arc::new(Tally::default());
+//! observer::set_observer(tally.clone());
+//! let result: metadata_3mf::Result<()> =
+//!     observer::observe(Path::new("missing.3mf"), 0, 1, || {
+//!         metadata_3mf::read_metadata(std::fs::File::open("missing.3mf")?)?;
+//!         Ok(())
+//!     });
+//! assert!(result.is_err());
+//! assert_eq!(tally.failed(), 1);
+//! ```
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::{Error, Result};
+
+/// Gets told about the files being processed. All methods do nothing
+/// unless implemented.
+pub trait ProcessObserver: Send + Sync {
+    /// A file is about to be processed, as number `index` of `total`.
+    fn started(&self, _path: &Path, _index: usize, _total: usize) {}
+
+    /// Part `done` of the `total` parts of a package was copied to the
+    /// output. `path` is the file being processed on this thread, if any.
+    fn progress(&self, _path: Option<&Path>, _part: &str, _done: usize, _total: usize) {}
+
+    /// Something was found that doesn't stop processing.
+    fn warning(&self, _path: Option<&Path>, _message: &str) {}
+
+    /// A file is done, or failed with the error.
+    fn finished(&self, _path: &Path, _outcome: std::result::Result<(), &Error>) {}
+}
+
+/// Prints warnings to standard error. This is the observer until another
+/// one is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stderr;
+
+impl ProcessObserver for Stderr {
+    fn warning(&self, _path: Option<&Path>, message: &str) {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+/// Counts files and warnings, for a summary at the end of a run.
+#[derive(Debug, Default)]
+pub struct Tally {
+    started: AtomicUsize,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    warnings: AtomicUsize,
+}
+
+impl Tally {
+    pub fn started(&self) -> usize {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.succeeded.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+}
+
+impl ProcessObserver for Tally {
+    fn started(&self, _path: &Path, _index: usize, _total: usize) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn warning(&self, _path: Option<&Path>, _message: &str) {
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finished(&self, _path: &Path, outcome: std::result::Result<(), &Error>) {
+        match outcome {
+            Ok(()) => self.succeeded.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+static OBSERVER: RwLock<Option<Arc<dyn ProcessObserver>>> = RwLock::new(None);
+
+thread_local! {
+    // The file that `observe` is processing on this thread
+    static CURRENT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Sends the events of all threads to `observer` from now on.
+pub fn set_observer(observer: Arc<dyn ProcessObserver>) {
+    *OBSERVER.write().unwrap_or_else(|error| error.into_inner()) = Some(observer);
+}
+
+fn current() -> Arc<dyn ProcessObserver> {
+    OBSERVER
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(Stderr))
+}
+
+/// Processes a file with `work`, telling the observer when it starts and
+/// how it finished. Warnings and progress from library functions that
+/// `work` calls are reported for `path`.
+pub fn observe<T>(
+    path: &Path,
+    index: usize,
+    total: usize,
+    work: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let observer = current();
+    observer.started(path, index, total);
+    let previous = CURRENT.with(|current| current.replace(Some(path.to_path_buf())));
+    let result = work();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    observer.finished(path, result.as_ref().map(|_| ()));
+    result
+}
+
+pub(crate) fn warning(message: impl Display) {
+    let message = message.to_string();
+    CURRENT.with(|path| current().warning(path.borrow().as_deref(), &message));
+}
+
+pub(crate) fn progress(part: &str, done: usize, total: usize) {
+    CURRENT.with(|path| current().progress(path.borrow().as_deref(), part, done, total));
+}
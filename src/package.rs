@@ -0,0 +1,1228 @@
+//! Reading the parts of a 3MF package, and copying them from one ZIP
+//! archive to another.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use time::OffsetDateTime;
+use xmltree::{Element, XMLNode};
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::deflate::ParallelDeflate;
+pub use crate::raw_names::{RawNameWriter, RawNames};
+use crate::{cancel, model, observer, Error, MetadataSet, Result};
+
+/// The name of the part that maps part names to content types.
+pub const CONTENT_TYPES_PART: &str = "[Content_Types].xml";
+
+/// The name of the part with the relationships of the package itself.
+pub const RELATIONSHIPS_PART: &str = "_rels/.rels";
+
+/// The relationship type that points at the root model part.
+pub const MODEL_RELATIONSHIP_TYPE: &str =
+    "http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel";
+
+/// The relationship type that points at the thumbnail of the package.
+pub const THUMBNAIL_RELATIONSHIP_TYPE: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail";
+
+/// The relationship type that points at the OPC core properties part.
+pub const CORE_PROPERTIES_RELATIONSHIP_TYPE: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties";
+
+/// The content type of the OPC core properties part.
+pub const CORE_PROPERTIES_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-package.core-properties+xml";
+
+/// The content type of relationships parts, such as `_rels/.rels`.
+pub const RELATIONSHIPS_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-package.relationships+xml";
+
+/// The content type of 3MF model parts.
+pub const MODEL_CONTENT_TYPE: &str = "application/vnd.ms-package.3dmanufacturing-3dmodel+xml";
+
+/// Where appends keep the model parts they replace, see
+/// [`append`](crate::append). Nothing refers to the entries in it.
+pub const BACKUP_DIRECTORY: &str = "Metadata/backup/";
+
+/// The `Default` and `Override` rules of a `[Content_Types].xml` part.
+#[derive(Clone, Debug, Default)]
+pub struct ContentTypes {
+    /// Lowercase extension and content type.
+    defaults: Vec<(String, String)>,
+    /// Lowercase part name, with leading slash, and content type.
+    overrides: Vec<(String, String)>,
+}
+
+impl ContentTypes {
+    pub fn parse<R: Read>(reader: R) -> Result<Self> {
+        let xml = Element::parse(reader)?;
+        let mut content_types = ContentTypes::default();
+        for element in xml.children.iter().filter_map(XMLNode::as_element) {
+            let content_type = match element.attributes.get("ContentType") {
+                Some(content_type) => content_type.clone(),
+                None => continue,
+            };
+            match (element.name.as_str(), &element.attributes) {
+                ("Default", attributes) if attributes.contains_key("Extension") => content_types
+                    .defaults
+                    .push((attributes["Extension"].to_lowercase(), content_type)),
+                ("Override", attributes) if attributes.contains_key("PartName") => content_types
+                    .overrides
+                    .push((attributes["PartName"].to_lowercase(), content_type)),
+                _ => (),
+            }
+        }
+        Ok(content_types)
+    }
+
+    /// Looks up the content type of a part by its ZIP entry name. Overrides
+    /// take precedence over defaults; both are matched case-insensitively.
+    pub fn get(&self, entry_name: &str) -> Option<&str> {
+        let part_name = format!("/{}", entry_name.trim_start_matches('/')).to_lowercase();
+        if let Some((_, content_type)) = self.overrides.iter().find(|(name, _)| *name == part_name)
+        {
+            return Some(content_type);
+        }
+        let extension = part_extension(&part_name)?;
+        self.defaults
+            .iter()
+            .find(|(default, _)| default == extension)
+            .map(|(_, content_type)| content_type.as_str())
+    }
+}
+
+/// The extension of a part name as OPC sees it: everything after the last
+/// dot of the last segment, so that `_rels/.rels` has the extension `rels`.
+pub fn part_extension(name: &str) -> Option<&str> {
+    let segment = name.rsplit('/').next()?;
+    segment.rsplit_once('.').map(|(_, extension)| extension)
+}
+
+/// How often an entry is read before giving up on it, with
+/// [`Package::with_verified_copies`].
+pub const VERIFY_ATTEMPTS: usize = 3;
+
+/// A 3MF package opened for reading.
+pub struct Package<R> {
+    archive: ZipArchive<R>,
+    content_types: ContentTypes,
+    password: Option<Vec<u8>>,
+    verify_copies: bool,
+    /// How many bytes reading the metadata of a model part may take, see
+    /// [`Package::with_memory_limit`].
+    memory_limit: Option<u64>,
+    /// The length of the archive, to tell entries whose data runs past its
+    /// end.
+    archive_len: u64,
+}
+
+impl Package<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Package::new(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Package<Cursor<Mmap>> {
+    /// Opens a package by mapping the file into memory, which saves the
+    /// read and seek calls of going through a `BufReader`. Mostly useful for
+    /// large files.
+    ///
+    /// The file must not be changed by anyone while the package is open:
+    /// the map would change underneath the reader, or raise a bus error when
+    /// the file is truncated.
+    pub fn open_mmap(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: see above, the caller guarantees that the file stays as it
+        // is. Nothing in this crate writes to its inputs.
+        let map = unsafe { Mmap::map(&file)? };
+        Package::new(Cursor::new(map))
+    }
+}
+
+impl<'a> Package<Cursor<&'a [u8]>> {
+    /// Opens a package that is already in memory.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        Package::new(Cursor::new(bytes))
+    }
+}
+
+impl<R: Read + Seek> Package<R> {
+    /// Opens the archive and reads its content types. A missing or
+    /// encrypted `[Content_Types].xml` is not an error; no part has a
+    /// content type then.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        let mut package = Package {
+            archive: ZipArchive::new(reader)?,
+            content_types: ContentTypes::default(),
+            password: None,
+            verify_copies: false,
+            memory_limit: None,
+            archive_len,
+        };
+        package.read_content_types()?;
+        Ok(package)
+    }
+
+    /// Sets the password to decrypt encrypted entries with, and reads the
+    /// content types again in case they were encrypted.
+    pub fn with_password(mut self, password: impl Into<Vec<u8>>) -> Result<Self> {
+        self.password = Some(password.into());
+        self.read_content_types()?;
+        Ok(self)
+    }
+
+    /// Checks each entry that is copied as it is against the CRC-32 of the
+    /// central directory before copying it, and reads it again when it
+    /// doesn't match, up to [`VERIFY_ATTEMPTS`] times. Guards against
+    /// silent corruption when reading from flaky network storage, at the
+    /// cost of decompressing every entry once more.
+    pub fn with_verified_copies(mut self) -> Self {
+        self.verify_copies = true;
+        self
+    }
+
+    /// Reads the metadata of model parts within `memory_limit` bytes, as
+    /// [`read_model_within`] does, in [`read_root_metadata`],
+    /// [`read_package_metadata`] and [`read_model_parts_metadata`]. Without
+    /// a limit, model parts are read whole.
+    pub fn with_memory_limit(mut self, memory_limit: Option<u64>) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// The limit set with [`Package::with_memory_limit`].
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.memory_limit
+    }
+
+    fn read_content_types(&mut self) -> Result<()> {
+        if let Some(index) = self.index_of(CONTENT_TYPES_PART) {
+            match open_file(&mut self.archive, self.password.as_deref(), index) {
+                Ok(file) => self.content_types = ContentTypes::parse(file)?,
+                Err(Error::Encrypted(_)) => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// The index of the entry with the given name.
+    pub fn index_of(&mut self, name: &str) -> Option<usize> {
+        (0..self.archive.len()).find(|&index| {
+            self.archive
+                .by_index_raw(index)
+                .is_ok_and(|file| file.name() == name)
+        })
+    }
+
+    /// The entry name of the root model part, the target of the 3D model
+    /// relationship in `_rels/.rels`. Fails with [`Error::NoRootModel`]
+    /// when there is none.
+    pub fn root_model_name(&mut self) -> Result<String> {
+        self.relationship_targets(MODEL_RELATIONSHIP_TYPE)?
+            .into_iter()
+            .next()
+            .ok_or(Error::NoRootModel)
+    }
+
+    /// The entry names of the targets of the package relationships of the
+    /// given type in `_rels/.rels`.
+    pub fn relationship_targets(&mut self, relationship_type: &str) -> Result<Vec<String>> {
+        let Some(index) = self.index_of(RELATIONSHIPS_PART) else {
+            return Ok(Vec::new());
+        };
+        let xml = Element::parse(self.by_index(index)?)?;
+        Ok(xml
+            .children
+            .iter()
+            .filter_map(XMLNode::as_element)
+            .filter(|element| element.name == "Relationship")
+            .filter(|element| {
+                element.attributes.get("Type").map(String::as_str) == Some(relationship_type)
+            })
+            .filter_map(|element| element.attributes.get("Target"))
+            .map(|target| target.trim_start_matches('/').to_string())
+            .collect())
+    }
+
+    /// Whether the entry is encrypted, with either ZipCrypto or AES.
+    pub fn is_encrypted(&mut self, index: usize) -> Result<bool> {
+        is_encrypted(&mut self.archive, index)
+    }
+
+    /// Opens an entry for reading, decrypting it if needed. Fails with
+    /// [`Error::Encrypted`] when there is no password to decrypt it with,
+    /// and [`Error::InvalidPassword`] when the password is wrong.
+    pub fn by_index(&mut self, index: usize) -> Result<ZipFile<'_>> {
+        open_file(&mut self.archive, self.password.as_deref(), index)
+    }
+
+    pub fn content_types(&self) -> &ContentTypes {
+        &self.content_types
+    }
+
+    /// The number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.archive.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archive.is_empty()
+    }
+
+    /// Walks over the entries of the archive one at a time, without reading
+    /// their contents up front.
+    pub fn entries(&mut self) -> Entries<'_, R> {
+        Entries {
+            package: self,
+            index: 0,
+        }
+    }
+
+    /// The names of the entries that are absolute or contain `..`, see
+    /// [`UnsafeNames`].
+    pub fn unsafe_entry_names(&mut self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for index in 0..self.archive.len() {
+            let file = self.archive.by_index_raw(index)?;
+            if file.enclosed_name().is_none() {
+                names.push(file.name().to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    /// The names that more than one entry of the archive has, in the order
+    /// they first appear. Readers disagree on which of those entries counts,
+    /// see [`dedupe_entries`].
+    pub fn duplicate_entry_names(&mut self) -> Result<Vec<String>> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for index in 0..self.archive.len() {
+            let file = self.archive.by_index_raw(index)?;
+            match counts.iter_mut().find(|(name, _)| name == file.name()) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((file.name().to_string(), 1)),
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    /// The names of the model parts, in the order of the archive.
+    pub fn model_part_names(&mut self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for index in 0..self.archive.len() {
+            let file = self.archive.by_index_raw(index)?;
+            if is_model_part(Path::new(file.name())) {
+                names.push(file.name().to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    /// The names of the entries that are encrypted.
+    pub fn encrypted_entry_names(&mut self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for index in 0..self.archive.len() {
+            if self.is_encrypted(index)? {
+                names.push(self.archive.by_index_raw(index)?.name().to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    pub fn archive(&mut self) -> &mut ZipArchive<R> {
+        &mut self.archive
+    }
+
+    pub fn into_archive(self) -> ZipArchive<R> {
+        self.archive
+    }
+}
+
+fn is_encrypted<R: Read + Seek>(archive: &mut ZipArchive<R>, index: usize) -> Result<bool> {
+    // The zip crate panics on AES entries without the encryption flag, so
+    // look for the AES extra field first.
+    if has_aes_extra_field(archive.by_index_raw(index)?.extra_data()) {
+        return Ok(true);
+    }
+    match archive.by_index(index) {
+        Ok(_) => Ok(false),
+        Err(ZipError::UnsupportedArchive(message)) if message == ZipError::PASSWORD_REQUIRED => {
+            Ok(true)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn has_aes_extra_field(mut extra: &[u8]) -> bool {
+    while extra.len() >= 4 {
+        let kind = u16::from_le_bytes([extra[0], extra[1]]);
+        let length = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if kind == 0x9901 {
+            return true;
+        }
+        extra = extra.get(4 + length..).unwrap_or_default();
+    }
+    false
+}
+
+fn open_file<'a, R: Read + Seek>(
+    archive: &'a mut ZipArchive<R>,
+    password: Option<&[u8]>,
+    index: usize,
+) -> Result<ZipFile<'a>> {
+    if !is_encrypted(archive, index)? {
+        return Ok(archive.by_index(index)?);
+    }
+    let name = archive.by_index_raw(index)?.name().to_string();
+    match password {
+        None => Err(Error::Encrypted(name)),
+        Some(password) => match archive.by_index_decrypt(index, password)? {
+            Ok(file) => Ok(file),
+            Err(_) => Err(Error::InvalidPassword(name)),
+        },
+    }
+}
+
+/// A streaming walk over the entries of a [`Package`]. Every entry borrows
+/// the package, so this is not an [`Iterator`]; use it like:
+///
+/// ```no_run
+/// # fn main() -> metadata_3mf::Result<()> {
+/// let mut package = metadata_3mf::package::Package::open("model.3mf")?;
+/// let mut entries = package.entries();
+/// while let Some(entry) = entries.next_entry() {
+///     let entry = entry?;
+///     println!("{} {:?}", entry.name(), entry.content_type());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Entries<'a, R> {
+    package: &'a mut Package<R>,
+    index: usize,
+}
+
+impl<'a, R: Read + Seek> Entries<'a, R> {
+    pub fn next_entry(&mut self) -> Option<Result<Entry<'_>>> {
+        if self.index >= self.package.archive.len() {
+            return None;
+        }
+        let index = self.index;
+        self.index += 1;
+        let package = &mut *self.package;
+        let file = match open_file(&mut package.archive, package.password.as_deref(), index) {
+            Ok(file) => file,
+            Err(error) => return Some(Err(error)),
+        };
+        Some(Ok(Entry {
+            content_type: package.content_types.get(file.name()).map(str::to_string),
+            index,
+            file,
+        }))
+    }
+}
+
+/// A single entry of a [`Package`], readable as a stream of its
+/// decompressed contents.
+pub struct Entry<'a> {
+    file: ZipFile<'a>,
+    content_type: Option<String>,
+    index: usize,
+}
+
+impl<'a> Entry<'a> {
+    /// The name of the entry as stored in the archive.
+    pub fn name(&self) -> &str {
+        self.file.name()
+    }
+
+    /// The name as a relative path, or `None` if the name would escape the
+    /// directory it is extracted to.
+    pub fn path(&self) -> Option<&Path> {
+        self.file.enclosed_name()
+    }
+
+    /// The content type according to `[Content_Types].xml`.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// The index of the entry in the archive.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The uncompressed size.
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+
+    pub fn is_model_part(&self) -> bool {
+        self.path().map(is_model_part).unwrap_or(false)
+    }
+
+    pub fn into_zip_file(self) -> ZipFile<'a> {
+        self.file
+    }
+}
+
+impl<'a> Read for Entry<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+/// Whether the path is a model part, going by its extension. Backups under
+/// [`BACKUP_DIRECTORY`] are not.
+pub fn is_model_part(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("model")) && !path.starts_with(BACKUP_DIRECTORY)
+}
+
+/// What to do with entries whose names are absolute or contain `..`, and
+/// would end up outside the target directory when extracted. Such names are
+/// a common sign of a malicious archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnsafeNames {
+    /// Copy the entries as they are, with a warning.
+    #[default]
+    Warn,
+    /// Copy the entries under a sanitized name, with a warning.
+    Sanitize,
+    /// Fail with [`Error::UnsafeEntryName`](crate::Error::UnsafeEntryName).
+    Reject,
+}
+
+/// Turns an unsafe entry name into a relative one, by dropping drive
+/// letters, empty, `.` and `..` components, and NUL bytes. Backslashes are
+/// taken as separators. Returns `None` when nothing is left.
+pub fn sanitize_name(name: &str) -> Option<String> {
+    let components: Vec<_> = name
+        .split(['/', '\\'])
+        .map(|component| component.replace('\0', ""))
+        .filter(|component| !matches!(component.as_str(), "" | "." | ".."))
+        .filter(|component| !component.ends_with(':'))
+        .collect();
+    if components.is_empty() {
+        None
+    } else {
+        Some(components.join("/"))
+    }
+}
+
+/// The longest comment a ZIP archive can have, in bytes. The zip crate
+/// writes longer comments without complaint, and a broken archive with them.
+pub const MAX_COMMENT_LENGTH: usize = u16::MAX as usize;
+
+/// Copies every entry of `input` to `output`. Model parts are handed to
+/// `update` along with the name to write them under, and `update` returns
+/// whether it wrote the part itself; all other entries, and model parts
+/// that `update` left alone, are raw copied.
+/// Entries with unsafe names are never handed to `update`, and are dealt
+/// with according to `unsafe_names`.
+///
+/// The zip crate cannot write encrypted entries, so encrypted entries are
+/// decrypted with the password of `input` and written without encryption.
+///
+/// The comment of the archive is carried over, see
+/// [`MAX_COMMENT_LENGTH`].
+///
+/// Entries whose names the zip crate would not write back as stored are
+/// written under a placeholder, which are returned. Write to a [`RawNameWriter`] to put the
+/// stored names back once the archive is finished.
+pub fn copy_archive<R, W, F>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    unsafe_names: UnsafeNames,
+    update: F,
+) -> Result<RawNames>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+    F: FnMut(ZipFile, &str, &mut ZipWriter<W>) -> Result<bool>,
+{
+    copy_entries_with(input, output, unsafe_names, false, update)
+}
+
+/// Like [`copy_archive`], but hands every entry with a safe name to
+/// `update`, not just the model parts. An entry that `update` claims to
+/// have written without writing anything is left out of the output.
+pub fn copy_entries<R, W, F>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    unsafe_names: UnsafeNames,
+    update: F,
+) -> Result<RawNames>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+    F: FnMut(ZipFile, &str, &mut ZipWriter<W>) -> Result<bool>,
+{
+    copy_entries_with(input, output, unsafe_names, true, update)
+}
+
+fn copy_entries_with<R, W, F>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    unsafe_names: UnsafeNames,
+    all_entries: bool,
+    mut update: F,
+) -> Result<RawNames>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+    F: FnMut(ZipFile, &str, &mut ZipWriter<W>) -> Result<bool>,
+{
+    let mut raw_names = RawNames::default();
+    output.set_raw_comment(input.archive.comment().to_vec());
+    for file_number in 0..input.len() {
+        cancel::check()?;
+        let encrypted = input.is_encrypted(file_number)?;
+        let file = input.by_index(file_number)?;
+        let mut updated = false;
+        let mut name = file.name().to_string();
+        match file.enclosed_name() {
+            Some(path) if all_entries || is_model_part(path) => {
+                name = raw_names.name_for(&file);
+                updated = update(file, &name, output)?;
+            }
+            Some(_) => {
+                name = raw_names.name_for(&file);
+                drop(file);
+            }
+            None => {
+                drop(file);
+                if let Some(sanitized) = unsafe_name(&input.archive, &name, unsafe_names)? {
+                    name = sanitized;
+                }
+            }
+        }
+
+        observer::progress(&name, file_number + 1, input.len());
+        if updated {
+            continue;
+        }
+        if encrypted {
+            observer::warning(format_args!(
+                "writing encrypted entry {} without encryption",
+                name
+            ));
+            let mut file = input.by_index(file_number)?;
+            output.start_file(name, rewrite_options(file.compression()))?;
+            io::copy(&mut file, output)?;
+        } else {
+            if input.verify_copies {
+                verify_entry(&mut input.archive, file_number, &name)?;
+            }
+            copy_entry(input, output, file_number, name)?;
+        }
+    }
+    Ok(raw_names)
+}
+
+/// Copies the entry as it is when its data is where the central directory
+/// says it is. Otherwise, as with some quirky archives where decompressing
+/// still works, decompresses the entry and compresses it again, with a
+/// warning, instead of failing the whole package. Stored entries stay
+/// stored.
+fn copy_entry<R, W>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    index: usize,
+    name: String,
+) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let problem = match input.archive.by_index_raw(index) {
+        Ok(file)
+            if file.data_start().saturating_add(file.compressed_size()) > input.archive_len =>
+        {
+            "its data runs past the end of the archive".to_string()
+        }
+        Ok(file) => {
+            output.raw_copy_file_rename(file, name)?;
+            return Ok(());
+        }
+        Err(error) => error.to_string(),
+    };
+    observer::warning(format_args!(
+        "entry {} can't be copied as it is, {}, compressing it again",
+        name, problem
+    ));
+    let mut file = input.archive.by_index(index)?;
+    let mut options = rewrite_options(file.compression())
+        .last_modified_time(file.last_modified())
+        .large_file(file.size() > u32::MAX as u64);
+    if let Some(mode) = file.unix_mode() {
+        options = options.unix_permissions(mode);
+    }
+    output.start_file(name, options)?;
+    io::copy(&mut file, output)?;
+    Ok(())
+}
+
+/// Reads the entry until its data matches its CRC-32, which the zip crate
+/// checks at the end of the data, for [`Package::with_verified_copies`].
+fn verify_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    name: &str,
+) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let read = archive
+            .by_index(index)
+            .map_err(Error::from)
+            .and_then(|mut file| Ok(io::copy(&mut file, &mut io::sink())?));
+        match read {
+            Ok(_) => return Ok(()),
+            Err(error) if attempt < VERIFY_ATTEMPTS => observer::warning(format_args!(
+                "entry {} did not read back intact ({}), reading it again",
+                name, error
+            )),
+            Err(error) => {
+                return Err(Error::CopyCheck {
+                    entry: name.to_string(),
+                    message: error.to_string(),
+                })
+            }
+        }
+        attempt += 1;
+    }
+}
+
+/// Which of the entries with the same name [`dedupe_entries`] keeps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeepEntry {
+    /// The first one in the archive, which [`Package::index_of`] finds.
+    First,
+    /// The last one in the archive, which most ZIP readers, slicers
+    /// included, end up reading.
+    #[default]
+    Last,
+}
+
+impl KeepEntry {
+    /// The names of the policies, as given on the command line.
+    pub const NAMES: [&'static str; 2] = ["first", "last"];
+}
+
+impl FromStr for KeepEntry {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(KeepEntry::First),
+            "last" => Ok(KeepEntry::Last),
+            _ => Err(Error::InvalidValue {
+                name: "entry to keep".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Like [`copy_entries`], but copies only one of the entries that share a
+/// name, as picked by `keep`, and leaves the others out with a warning.
+/// Entries with unsafe names are copied as `unsafe_names` says, duplicate
+/// or not.
+pub fn dedupe_entries<R, W>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    unsafe_names: UnsafeNames,
+    keep: KeepEntry,
+) -> Result<RawNames>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    dedupe_entries_with(input, output, unsafe_names, keep, |_, _, _| Ok(false))
+}
+
+/// Like [`dedupe_entries`], but hands the entries that are kept to
+/// `update`, as [`copy_entries`] does.
+pub fn dedupe_entries_with<R, W, F>(
+    input: &mut Package<R>,
+    output: &mut ZipWriter<W>,
+    unsafe_names: UnsafeNames,
+    keep: KeepEntry,
+    mut update: F,
+) -> Result<RawNames>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+    F: FnMut(ZipFile, &str, &mut ZipWriter<W>) -> Result<bool>,
+{
+    // How many entries of each name are still to come
+    let mut remaining: HashMap<String, usize> = HashMap::new();
+    for index in 0..input.len() {
+        let name = input.archive.by_index_raw(index)?.name().to_string();
+        *remaining.entry(name).or_default() += 1;
+    }
+    let mut seen = HashSet::new();
+    copy_entries(input, output, unsafe_names, |file, name, output| {
+        let count = remaining.get_mut(file.name()).expect("counted above");
+        *count -= 1;
+        let first = seen.insert(file.name().to_string());
+        let kept = match keep {
+            KeepEntry::First => first,
+            KeepEntry::Last => *count == 0,
+        };
+        if kept {
+            return update(file, name, output);
+        }
+        observer::warning(format_args!("leaving out duplicate entry {}", file.name()));
+        // An entry claimed as written without writing it is left out
+        Ok(true)
+    })
+}
+
+/// Applies `unsafe_names` to an entry, and returns the name to copy it
+/// under if that changes.
+fn unsafe_name<R>(
+    input: &ZipArchive<R>,
+    name: &str,
+    unsafe_names: UnsafeNames,
+) -> Result<Option<String>>
+where
+    R: Read + Seek,
+{
+    match unsafe_names {
+        UnsafeNames::Warn => {
+            observer::warning(format_args!("copying entry with unsafe name \"{}\"", name));
+            Ok(None)
+        }
+        UnsafeNames::Sanitize => match sanitize_name(name) {
+            // Don't let the renamed entry shadow one that is already there
+            Some(sanitized) if !input.file_names().any(|other| other == sanitized) => {
+                observer::warning(format_args!(
+                    "renaming entry with unsafe name \"{}\" to \"{}\"",
+                    name, sanitized
+                ));
+                Ok(Some(sanitized))
+            }
+            _ => Err(Error::UnsafeEntryName(name.to_string())),
+        },
+        UnsafeNames::Reject => Err(Error::UnsafeEntryName(name.to_string())),
+    }
+}
+
+/// The deflate level for rewritten model parts. Level 9 takes several times
+/// as long on large models, for output that is only a few percent smaller.
+pub const COMPRESSION_LEVEL: i32 = 6;
+
+/// The options to write an entry again with that was compressed with
+/// `method`. Stored entries stay stored, as some tools need them that way,
+/// such as thumbnails for fast previews. Everything else is deflated.
+fn rewrite_options(method: CompressionMethod) -> FileOptions {
+    match method {
+        CompressionMethod::Stored => {
+            FileOptions::default().compression_method(CompressionMethod::Stored)
+        }
+        _ => FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(COMPRESSION_LEVEL)),
+    }
+}
+
+/// Starts a model part in the archive, to be written to next, with the
+/// method of [`rewrite_options`].
+fn start_model_part<W>(
+    output: &mut ZipWriter<W>,
+    file_name: &str,
+    method: CompressionMethod,
+) -> Result<()>
+where
+    W: Write + Seek,
+{
+    let options = rewrite_options(method).last_modified_time(entry_time()?);
+    output.start_file(file_name, options)?;
+    Ok(())
+}
+
+/// The time given by the `SOURCE_DATE_EPOCH` environment variable, in
+/// seconds since 1970, which reproducible builds use instead of the current
+/// time. Fails when the variable is set to something else than a number.
+pub fn source_date_epoch() -> Result<Option<OffsetDateTime>> {
+    let Some(value) = std::env::var_os("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    let invalid = || Error::InvalidValue {
+        name: "SOURCE_DATE_EPOCH".to_string(),
+        value: value.to_string_lossy().to_string(),
+    };
+    let seconds = value
+        .to_str()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(invalid)?;
+    OffsetDateTime::from_unix_timestamp(seconds)
+        .map(Some)
+        .map_err(|_| invalid())
+}
+
+/// The modification time of the entries this crate writes: the time of
+/// [`source_date_epoch`] if set, so that the output is reproducible, and
+/// the current time otherwise. ZIP can't go back further than 1980.
+pub(crate) fn entry_time() -> Result<zip::DateTime> {
+    let time = source_date_epoch()?.unwrap_or_else(OffsetDateTime::now_utc);
+    Ok(zip::DateTime::from_date_and_time(
+        time.year().try_into().unwrap_or_default(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+    )
+    .unwrap_or_default())
+}
+
+/// The number of threads to compress large model parts on by default: one
+/// per core.
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Writes a deflated model part to the archive with `write`. Parts larger
+/// than a megabyte are compressed in chunks on up to `threads` threads.
+pub fn write_model_part_with<W, F>(
+    output: &mut ZipWriter<W>,
+    file_name: &str,
+    threads: usize,
+    write: F,
+) -> Result<()>
+where
+    W: Write + Seek,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    write_model_part_as(
+        output,
+        file_name,
+        CompressionMethod::Deflated,
+        threads,
+        write,
+    )
+}
+
+/// [`write_model_part_with`] for a part that was compressed with `method`
+/// in the input, which stays stored if it was stored.
+pub fn write_model_part_as<W, F>(
+    output: &mut ZipWriter<W>,
+    file_name: &str,
+    method: CompressionMethod,
+    threads: usize,
+    write: F,
+) -> Result<()>
+where
+    W: Write + Seek,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    if threads <= 1 || method == CompressionMethod::Stored {
+        start_model_part(output, file_name, method)?;
+        return write(output);
+    }
+    let mut deflate = ParallelDeflate::new(COMPRESSION_LEVEL as u32, threads);
+    write(&mut deflate)?;
+    deflate.finish(output, file_name)
+}
+
+/// Writes a model part to the archive.
+pub fn write_model_part<W>(output: &mut ZipWriter<W>, file_name: &str, xml: &Element) -> Result<()>
+where
+    W: Write + Seek,
+{
+    write_model_part_with(output, file_name, default_threads(), |writer| {
+        model::write_model(xml, writer)
+    })
+}
+
+/// Writes an XML part other than a model, such as relationships or content
+/// types, to the archive.
+pub fn write_xml_part<W>(output: &mut ZipWriter<W>, file_name: &str, xml: &Element) -> Result<()>
+where
+    W: Write + Seek,
+{
+    write_xml_part_as(output, file_name, CompressionMethod::Deflated, xml)
+}
+
+/// [`write_xml_part`] for a part that was compressed with `method` in the
+/// input, which stays stored if it was stored.
+pub fn write_xml_part_as<W>(
+    output: &mut ZipWriter<W>,
+    file_name: &str,
+    method: CompressionMethod,
+    xml: &Element,
+) -> Result<()>
+where
+    W: Write + Seek,
+{
+    start_model_part(output, file_name, method)?;
+    model::write_model(xml, output)
+}
+
+/// Reads a model part for a metadata change, see [`model::MetadataEdit`].
+pub fn read_model_part(file: &mut ZipFile) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads the start of a model part, up to where its resources begin, so
+/// that a metadata change can copy the rest straight from `file` instead of
+/// holding all of it in memory. Fails when that start is over `limit`
+/// bytes.
+pub fn read_model_prefix(file: &mut ZipFile, limit: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(bytes);
+        }
+        // The tag may have been cut in two by the previous read
+        let from = bytes.len().saturating_sub("<resources".len());
+        bytes.extend_from_slice(&chunk[..read]);
+        if model::resources_start(&bytes, from).is_some() {
+            return Ok(bytes);
+        }
+        if bytes.len() as u64 > limit {
+            return Err(Error::TooLarge {
+                part: file.name().to_string(),
+                limit,
+            });
+        }
+    }
+}
+
+/// How many times its size parsing a model part takes in memory, roughly.
+pub const PARSE_OVERHEAD: u64 = 10;
+
+/// Parses a model part, refusing parts too large to parse within
+/// `memory_limit` bytes, the `--max-memory` of the command line. With a
+/// limit and `metadata_only`, only the metadata at the start of the part is
+/// read, which fits for any size of part.
+pub fn read_model_within(
+    file: &mut ZipFile,
+    memory_limit: Option<u64>,
+    metadata_only: bool,
+) -> Result<Element> {
+    let Some(limit) = memory_limit else {
+        return model::read_model(file);
+    };
+    let part = file.name().to_string();
+    let too_large = || Error::TooLarge { part, limit };
+    if metadata_only {
+        let bytes = read_model_prefix(file, limit)?;
+        if let Some(patch) = model::MetadataPatch::new(&bytes)? {
+            return Ok(patch.xml);
+        }
+        // A part without resources is read whole, and parsed when it fits
+        let whole = bytes.len() as u64 == file.size();
+        return match whole && (bytes.len() as u64).saturating_mul(PARSE_OVERHEAD) <= limit {
+            true => model::read_model(bytes.as_slice()),
+            false => Err(too_large()),
+        };
+    }
+    if file.size().saturating_mul(PARSE_OVERHEAD) > limit {
+        return Err(too_large());
+    }
+    model::read_model(file)
+}
+
+/// The part a package can carry metadata defaults in, in any layout that
+/// [`crate::metadata::MetadataFile`] reads, for `add --package-defaults`.
+pub const DEFAULTS_PART: &str = "Metadata/defaults.xml";
+
+/// Reads the [`DEFAULTS_PART`] of the package, if it has one.
+pub fn read_package_defaults<R: Read + Seek>(
+    package: &mut Package<R>,
+) -> Result<Option<MetadataSet>> {
+    let Some(index) = package.index_of(DEFAULTS_PART) else {
+        return Ok(None);
+    };
+    let file = package.by_index(index)?;
+    match crate::metadata::read_metadata_file(file) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(Error::MetadataFile(message)) => Err(Error::MetadataFile(format!(
+            "{} in package: {}",
+            DEFAULTS_PART, message
+        ))),
+        Err(error) => Err(error),
+    }
+}
+
+/// Reads the metadata of the root model part, without parsing the rest of
+/// it when it can be helped. Packages without a root model part have none.
+pub fn read_root_metadata<R: Read + Seek>(package: &mut Package<R>) -> Result<MetadataSet> {
+    let name = match package.root_model_name() {
+        Ok(name) => name,
+        Err(Error::NoRootModel) => return Ok(MetadataSet::new()),
+        Err(error) => return Err(error),
+    };
+    let Some(index) = package.index_of(&name) else {
+        return Ok(MetadataSet::new());
+    };
+    let memory_limit = package.memory_limit;
+    read_part_metadata(&mut package.by_index(index)?, memory_limit)
+}
+
+/// Reads the metadata of a model part, within the memory limit of the
+/// package when it has one.
+fn read_part_metadata(file: &mut ZipFile, memory_limit: Option<u64>) -> Result<MetadataSet> {
+    if memory_limit.is_some() {
+        let xml = read_model_within(file, memory_limit, true)?;
+        return Ok(MetadataSet::from_model(&xml));
+    }
+    let bytes = read_model_part(file)?;
+    Ok(MetadataSet::from_model(
+        model::MetadataEdit::new(&bytes)?.xml(),
+    ))
+}
+
+/// Reads the metadata of every model part in the package, paired with the
+/// name of the part.
+pub fn read_metadata<R: Read + Seek>(reader: R) -> Result<Vec<(String, MetadataSet)>> {
+    read_package_metadata(&mut Package::new(reader)?)
+}
+
+/// Like [`read_metadata`], for a package that is open already.
+pub fn read_package_metadata<R: Read + Seek>(
+    package: &mut Package<R>,
+) -> Result<Vec<(String, MetadataSet)>> {
+    let memory_limit = package.memory_limit;
+    let mut entries = package.entries();
+    let mut metadata = Vec::new();
+    while let Some(entry) = entries.next_entry() {
+        let entry = entry?;
+        if entry.is_model_part() {
+            let name = entry.name().to_string();
+            let xml = read_model_within(&mut entry.into_zip_file(), memory_limit, true)?;
+            metadata.push((name, MetadataSet::from_model(&xml)));
+        }
+    }
+    Ok(metadata)
+}
+
+/// Like [`read_package_metadata`], without parsing the rest of each model
+/// part when it can be helped, as [`read_root_metadata`] does.
+pub fn read_model_parts_metadata<R: Read + Seek>(
+    package: &mut Package<R>,
+) -> Result<Vec<(String, MetadataSet)>> {
+    let memory_limit = package.memory_limit;
+    let mut entries = package.entries();
+    let mut metadata = Vec::new();
+    while let Some(entry) = entries.next_entry() {
+        let entry = entry?;
+        if entry.is_model_part() {
+            let name = entry.name().to_string();
+            let part_metadata = read_part_metadata(&mut entry.into_zip_file(), memory_limit)?;
+            metadata.push((name, part_metadata));
+        }
+    }
+    Ok(metadata)
+}
+
+/// Copies the package from `input` to `output`, merging `metadata` into
+/// every model part as [`model::merge_metadata`] does. Returns `output`
+/// once the archive is complete. Entries with characters XML 1.0 doesn't
+/// allow are refused before anything is written.
+pub fn add_metadata<R, W>(
+    input: R,
+    output: W,
+    metadata: &MetadataSet,
+    keep_existing: bool,
+) -> Result<W>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    for entry in metadata {
+        entry.validate_characters()?;
+    }
+    let mut input = Package::new(input)?;
+    let mut output = ZipWriter::new(RawNameWriter::new(output)?);
+    let raw_names = copy_archive(
+        &mut input,
+        &mut output,
+        UnsafeNames::Warn,
+        |mut file, name, output| {
+            let bytes = read_model_part(&mut file)?;
+            let mut edit = model::MetadataEdit::new(&bytes)?;
+            model::merge_metadata(edit.xml_mut(), metadata, keep_existing);
+            let method = file.compression();
+            write_model_part_as(output, name, method, default_threads(), |writer| {
+                edit.write(writer)
+            })?;
+            Ok(true)
+        },
+    )?;
+    output.finish()?.restore(&raw_names)
+}
+
+/// [`read_metadata`] for a package that is already in memory.
+pub fn read_metadata_from_bytes(bytes: &[u8]) -> Result<Vec<(String, MetadataSet)>> {
+    read_metadata(Cursor::new(bytes))
+}
+
+/// [`add_metadata`] entirely in memory, returning the rewritten package.
+pub fn add_metadata_to_vec(
+    input: &[u8],
+    metadata: &MetadataSet,
+    keep_existing: bool,
+) -> Result<Vec<u8>> {
+    let output = add_metadata(
+        Cursor::new(input),
+        Cursor::new(Vec::new()),
+        metadata,
+        keep_existing,
+    )?;
+    Ok(output.into_inner())
+}
+
+/// Copies the package from `input` to `output`, removing the metadata
+/// entries with the given names from every model part. Model parts without
+/// any of those entries are copied unchanged.
+pub fn remove_metadata<R, W>(input: R, output: W, names: &[String]) -> Result<W>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut input = Package::new(input)?;
+    let mut output = ZipWriter::new(RawNameWriter::new(output)?);
+    let raw_names = copy_archive(
+        &mut input,
+        &mut output,
+        UnsafeNames::Warn,
+        |mut file, name, output| {
+            let bytes = read_model_part(&mut file)?;
+            let mut edit = model::MetadataEdit::new(&bytes)?;
+            if model::remove_metadata(edit.xml_mut(), names).is_empty() {
+                return Ok(false);
+            }
+            let method = file.compression();
+            write_model_part_as(output, name, method, default_threads(), |writer| {
+                edit.write(writer)
+            })?;
+            Ok(true)
+        },
+    )?;
+    output.finish()?.restore(&raw_names)
+}
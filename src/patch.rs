@@ -0,0 +1,235 @@
+//! Scripted metadata edits in JSON, treating the metadata as a flat object
+//! of names to values.
+//!
+//! Both JSON merge patches ([RFC 7386]) and JSON patch documents
+//! ([RFC 6902]) are understood:
+//!
+//! ```
+//! use metadata_3mf::patch::Patch;
+//! use metadata_3mf::MetadataSet;
+//!
+//! let mut metadata = MetadataSet::builder().rating(3).build()?;
+//! let patch = Patch::parse(r#"{"LicenseTerms": "CC-BY-4.0", "Rating": null}"#)?;
+//! assert!(patch.apply(&mut metadata)?);
+//! assert_eq!(metadata.license_terms(), Some("CC-BY-4.0"));
+//! assert!(!metadata.contains("Rating"));
+//!
+//! let patch = Patch::parse(r#"[{"op": "move", "from": "/LicenseTerms", "path": "/Copyright"}]"#)?;
+//! patch.apply(&mut metadata)?;
+//! assert_eq!(metadata.copyright(), Some("CC-BY-4.0"));
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+//!
+//! [RFC 7386]: https://www.rfc-editor.org/rfc/rfc7386
+//! [RFC 6902]: https://www.rfc-editor.org/rfc/rfc6902
+
+use serde_json::{Map, Value};
+
+use crate::well_known::{validate_characters, validate_name};
+use crate::{Error, MetadataEntry, MetadataSet, Result};
+
+/// A parsed patch, see the [module documentation](self).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Patch {
+    operations: Vec<Operation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Operation {
+    /// Sets an entry, adding it if needed.
+    Add {
+        name: String,
+        value: String,
+    },
+    /// Removes an entry. A merge patch may remove entries that aren't
+    /// there, a JSON patch may not.
+    Remove {
+        name: String,
+        required: bool,
+    },
+    /// Sets an entry that must already be there.
+    Replace {
+        name: String,
+        value: String,
+    },
+    Move {
+        from: String,
+        name: String,
+    },
+    Copy {
+        from: String,
+        name: String,
+    },
+    /// Fails the patch unless the entry has the value.
+    Test {
+        name: String,
+        value: String,
+    },
+}
+
+impl Patch {
+    /// Parses a merge patch, which is an object, or a JSON patch document,
+    /// which is an array of operations.
+    pub fn parse(json: &str) -> Result<Self> {
+        let json: Value = serde_json::from_str(json)
+            .map_err(|error| Error::Patch(format!("could not parse patch: {}", error)))?;
+        let operations = match json {
+            Value::Object(members) => merge_patch(members)?,
+            Value::Array(operations) => operations.iter().map(operation).collect::<Result<_>>()?,
+            _ => {
+                return Err(Error::Patch(
+                    "a patch must be an object or an array of operations".to_string(),
+                ))
+            }
+        };
+        Ok(Patch { operations })
+    }
+
+    /// Applies the patch to `metadata`, all or nothing: when an operation
+    /// fails, `metadata` is left as it was. Returns whether anything
+    /// changed.
+    pub fn apply(&self, metadata: &mut MetadataSet) -> Result<bool> {
+        let mut patched = metadata.clone();
+        for operation in &self.operations {
+            match operation {
+                Operation::Add { name, value } => patched.set(name, value.clone()),
+                Operation::Remove { name, required } => {
+                    if patched.remove(name).is_none() && *required {
+                        return Err(missing(name));
+                    }
+                }
+                Operation::Replace { name, value } => {
+                    if !patched.contains(name) {
+                        return Err(missing(name));
+                    }
+                    patched.set(name, value.clone());
+                }
+                Operation::Move { from, name } => {
+                    let entry = patched.remove(from).ok_or_else(|| missing(from))?;
+                    patched.insert(MetadataEntry {
+                        name: name.clone(),
+                        ..entry
+                    });
+                }
+                Operation::Copy { from, name } => {
+                    let entry = patched.get(from).ok_or_else(|| missing(from))?;
+                    let entry = MetadataEntry {
+                        name: name.clone(),
+                        ..entry.clone()
+                    };
+                    patched.insert(entry);
+                }
+                Operation::Test { name, value } => match patched.value(name) {
+                    Some(actual) if actual == value => (),
+                    actual => {
+                        return Err(Error::Patch(format!(
+                            "test failed: {} is {}, expected \"{}\"",
+                            name,
+                            actual
+                                .map_or("missing".to_string(), |actual| format!("\"{}\"", actual)),
+                            value
+                        )))
+                    }
+                },
+            }
+        }
+        let changed = patched != *metadata;
+        *metadata = patched;
+        Ok(changed)
+    }
+}
+
+fn missing(name: &str) -> Error {
+    Error::Patch(format!("no metadata entry {}", name))
+}
+
+fn merge_patch(members: Map<String, Value>) -> Result<Vec<Operation>> {
+    members
+        .into_iter()
+        .map(|(name, value)| {
+            if value.is_null() {
+                Ok(Operation::Remove {
+                    name,
+                    required: false,
+                })
+            } else {
+                validate_name(&name)?;
+                let value = string_value(&name, &value)?;
+                Ok(Operation::Add { name, value })
+            }
+        })
+        .collect()
+}
+
+fn operation(operation: &Value) -> Result<Operation> {
+    let member = |key: &str| {
+        operation
+            .get(key)
+            .ok_or_else(|| Error::Patch(format!("operation {} has no \"{}\"", operation, key)))
+    };
+    let op = member("op")?.as_str().unwrap_or_default();
+    let name = pointer(member("path")?)?;
+    let from = || pointer(member("from")?);
+    let value = || string_value(&name, member("value")?);
+    Ok(match op {
+        "add" => Operation::Add {
+            value: value()?,
+            name: validated(name)?,
+        },
+        "remove" => Operation::Remove {
+            name,
+            required: true,
+        },
+        "replace" => Operation::Replace {
+            value: value()?,
+            name: validated(name)?,
+        },
+        "move" => Operation::Move {
+            from: from()?,
+            name: validated(name)?,
+        },
+        "copy" => Operation::Copy {
+            from: from()?,
+            name: validated(name)?,
+        },
+        "test" => Operation::Test {
+            value: value()?,
+            name,
+        },
+        _ => return Err(Error::Patch(format!("unknown operation {}", member("op")?))),
+    })
+}
+
+fn validated(name: String) -> Result<String> {
+    validate_name(&name)?;
+    Ok(name)
+}
+
+/// The metadata name a JSON pointer refers to. Only pointers to members of
+/// the top level object, like `/Title`, make sense for metadata.
+fn pointer(pointer: &Value) -> Result<String> {
+    let invalid = || Error::Patch(format!("invalid metadata path {}", pointer));
+    let name = pointer
+        .as_str()
+        .and_then(|pointer| pointer.strip_prefix('/'))
+        .ok_or_else(invalid)?;
+    if name.contains('/') {
+        return Err(invalid());
+    }
+    Ok(name.replace("~1", "/").replace("~0", "~"))
+}
+
+/// Metadata values are text; numbers and booleans are taken as written.
+fn string_value(name: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => {
+            validate_characters(name, value)?;
+            Ok(value.clone())
+        }
+        Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+        _ => Err(Error::Patch(format!(
+            "value of {} must be a string, number or boolean",
+            name
+        ))),
+    }
+}
@@ -0,0 +1,180 @@
+//! Guessing which application produced a package, as files from different
+//! producers have different quirks.
+//!
+//! The clues are tried from the most to the least telling: the
+//! `Application` metadata of the root model, the private parts that slicers
+//! keep their settings in, the namespaces declared on the root model, and
+//! the comment of the ZIP archive.
+
+use std::fmt;
+use std::io::{Read, Seek};
+
+use zip::result::ZipError;
+
+use crate::package::{self, Package};
+use crate::{well_known, Error, MetadataSet, Result};
+
+/// The application that produced a package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Producer {
+    /// The name of the application, like `PrusaSlicer`.
+    pub name: String,
+    pub version: Option<String>,
+    /// The clue the producer was recognized by.
+    pub evidence: Evidence,
+}
+
+/// What gave the producer away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Evidence {
+    /// The `Application` metadata of the root model.
+    Application,
+    /// A part that only this application writes.
+    PrivatePart,
+    /// A namespace that only this application declares.
+    Namespace,
+    /// The comment of the ZIP archive.
+    ZipComment,
+}
+
+impl Evidence {
+    /// The name of the clue, as printed by `info`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Evidence::Application => "application-metadata",
+            Evidence::PrivatePart => "private-part",
+            Evidence::Namespace => "namespace",
+            Evidence::ZipComment => "zip-comment",
+        }
+    }
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parts that only one application writes, with its name.
+const PRIVATE_PARTS: [(&str, &str); 3] = [
+    ("Metadata/Slic3r_PE.config", "PrusaSlicer"),
+    ("Metadata/project_settings.config", "BambuStudio"),
+    ("Cura/", "Cura"),
+];
+
+/// Namespaces that only one application declares, with its name.
+const NAMESPACES: [(&str, &str); 3] = [
+    ("http://schemas.slic3r.org/3mf/2017/06", "PrusaSlicer"),
+    ("http://schemas.bambulab.com/package/2021", "BambuStudio"),
+    ("http://software.ultimaker.com/xml/cura/3mf/2015/10", "Cura"),
+];
+
+/// Guesses the producer of the package. Returns `None` when there are no
+/// clues. Parts that don't fit in `memory_limit` bytes, if given, are
+/// refused with [`Error::TooLarge`](crate::Error::TooLarge), see
+/// [`package::read_model_within`].
+pub fn identify<R: Read + Seek>(
+    package: &mut Package<R>,
+    memory_limit: Option<u64>,
+) -> Result<Option<Producer>> {
+    let root = match package.root_model_name() {
+        Ok(name) => name,
+        Err(crate::Error::NoRootModel) => String::new(),
+        Err(error) => return Err(error),
+    };
+    let xml = match package.index_of(&root) {
+        Some(index) => Some(package::read_model_within(
+            &mut package.by_index(index)?,
+            memory_limit,
+            true,
+        )?),
+        None => None,
+    };
+    let producer = |name: &str, version: Option<&str>, evidence| {
+        Ok(Some(Producer {
+            name: name.to_string(),
+            version: version.map(str::to_string),
+            evidence,
+        }))
+    };
+
+    if let Some(xml) = &xml {
+        let metadata = MetadataSet::from_model(xml);
+        if let Some(application) = metadata.value(well_known::APPLICATION) {
+            let (name, version) = split_version(application.trim());
+            return producer(name, version, Evidence::Application);
+        }
+    }
+    let names: Vec<String> = package.archive().file_names().map(str::to_string).collect();
+    for (part, name) in PRIVATE_PARTS {
+        if let Some(part) = names.iter().find(|entry| entry.starts_with(part)) {
+            let version = private_part_version(package, part, memory_limit)?;
+            return producer(name, version.as_deref(), Evidence::PrivatePart);
+        }
+    }
+    if let Some(namespaces) = xml.as_ref().and_then(|xml| xml.namespaces.as_ref()) {
+        for (namespace, name) in NAMESPACES {
+            if namespaces.0.values().any(|uri| uri == namespace) {
+                return producer(name, None, Evidence::Namespace);
+            }
+        }
+    }
+    let comment = String::from_utf8_lossy(package.archive().comment())
+        .trim()
+        .to_string();
+    if !comment.is_empty() {
+        let (name, version) = split_version(&comment);
+        return producer(name, version, Evidence::ZipComment);
+    }
+    Ok(None)
+}
+
+/// Splits `PrusaSlicer-2.6.0+win64` or `UltiMaker Cura 5.4.0` into the name
+/// and the version, which starts with a digit after a dash or space.
+fn split_version(text: &str) -> (&str, Option<&str>) {
+    let position = text.char_indices().find(|&(index, c)| {
+        (c == '-' || c == ' ')
+            && text[index + 1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_digit())
+    });
+    match position {
+        Some((index, _)) => (&text[..index], Some(&text[index + 1..])),
+        None => (text, None),
+    }
+}
+
+/// The version the private part states, if it does.
+fn private_part_version<R: Read + Seek>(
+    package: &mut Package<R>,
+    part: &str,
+    memory_limit: Option<u64>,
+) -> Result<Option<String>> {
+    let index = package
+        .index_of(part)
+        .ok_or(Error::Zip(ZipError::FileNotFound))?;
+    let mut file = package.by_index(index)?;
+    if let Some(limit) = memory_limit.filter(|&limit| file.size() > limit) {
+        return Err(Error::TooLarge {
+            part: part.to_string(),
+            limit,
+        });
+    }
+    let mut text = String::new();
+    // Parts that aren't text have no version to find
+    if file.read_to_string(&mut text).is_err() {
+        return Ok(None);
+    }
+    if let Some(line) = text.lines().next() {
+        // PrusaSlicer: `; generated by PrusaSlicer 2.6.0+win64 on ...`
+        if let Some(generator) = line.strip_prefix("; generated by ") {
+            let generator = generator.split(" on ").next().unwrap_or(generator);
+            return Ok(split_version(generator).1.map(str::to_string));
+        }
+    }
+    // Bambu Studio: a JSON object with a `version`
+    Ok(serde_json::from_str::<serde_json::Value>(&text)
+        .ok()
+        .and_then(|json| json.get("version")?.as_str().map(str::to_string)))
+}
@@ -0,0 +1,195 @@
+//! Listings on model sharing sites made from the metadata of 3MF files,
+//! for `publish`.
+//!
+//! Thingiverse is the only site with a public API for creating listings.
+//! The listing gets the `Title`, `Description` and `LicenseTerms` of the
+//! root model part as its name, description and license, which has to be
+//! one of the SPDX ids Thingiverse has a license for. A new listing is
+//! created as a work in progress without files, so that nothing is public
+//! before the files are uploaded and it is published on the site.
+//!
+//! The URL of the listing is written back into the file as the metadata
+//! entry [`provenance_name`], and a file that has one gets its listing
+//! updated instead of a new one:
+//!
+//! ```
+//! use metadata_3mf::credentials::Service;
+//! use metadata_3mf::publish::{self, Listing};
+//! use metadata_3mf::{MetadataSet, Spdx};
+//!
+//! let metadata = MetadataSet::builder()
+//!     .title("Cable clip")
+//!     .description("Holds two cables")
+//!     .license(Spdx::CcBy40)
+//!     .build()?;
+//! let listing = Listing::new(&metadata)?;
+//! assert_eq!(listing.license, "cc");
+//! assert_eq!(publish::provenance_name(Service::Thingiverse), "publish:Thingiverse");
+//! assert_eq!(publish::thing_id("https://www.thingiverse.com/thing:4242"), Some(4242));
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::credentials::Service;
+use crate::retry;
+use crate::{Error, MetadataSet, Result, Spdx};
+
+/// The namespace prefix of the entries with the URLs of listings.
+pub const PREFIX: &str = "publish";
+
+/// Where the Thingiverse API is.
+pub const THINGIVERSE_API: &str = "https://api.thingiverse.com";
+
+/// How long to wait for an answer before giving up.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The metadata name of the URL of the listing of the file on `service`.
+pub fn provenance_name(service: Service) -> String {
+    let name = match service {
+        Service::Thingiverse => "Thingiverse",
+        Service::Printables => "Printables",
+        Service::Mqtt => "MQTT",
+    };
+    format!("{}:{}", PREFIX, name)
+}
+
+/// The license code Thingiverse uses for the license, if it has one.
+pub fn thingiverse_license(license: Spdx) -> Option<&'static str> {
+    match license {
+        Spdx::Cc010 => Some("pd0"),
+        Spdx::CcBy40 => Some("cc"),
+        Spdx::CcBySa40 => Some("cc-sa"),
+        Spdx::CcByNd40 => Some("cc-nd"),
+        Spdx::CcByNc40 => Some("cc-nc"),
+        Spdx::CcByNcSa40 => Some("cc-nc-sa"),
+        Spdx::CcByNcNd40 => Some("cc-nc-nd"),
+        Spdx::Gpl30OrLater => Some("gpl"),
+        Spdx::Mit | Spdx::Apache20 => None,
+    }
+}
+
+/// The id of a Thingiverse listing, from its URL, such as 4242 for
+/// `https://www.thingiverse.com/thing:4242`.
+pub fn thing_id(url: &str) -> Option<u64> {
+    let (_, id) = url.trim().trim_end_matches('/').rsplit_once("thing:")?;
+    id.parse().ok()
+}
+
+/// What a listing says about a model.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub name: String,
+    pub description: String,
+    /// The license code of the site.
+    pub license: &'static str,
+}
+
+impl Listing {
+    /// The listing for a model with the metadata, which needs a `Title` and
+    /// a `LicenseTerms` that is an SPDX id Thingiverse has a license for.
+    pub fn new(metadata: &MetadataSet) -> Result<Listing> {
+        let text = |value: Option<&str>| value.map(str::trim).unwrap_or_default().to_string();
+        let name = text(metadata.title());
+        if name.is_empty() {
+            return Err(Error::Publish(
+                "no Title to name the listing with".to_string(),
+            ));
+        }
+        let terms = text(metadata.license_terms());
+        let license = terms
+            .parse::<Spdx>()
+            .ok()
+            .and_then(thingiverse_license)
+            .ok_or_else(|| {
+                let supported = Spdx::ALL
+                    .iter()
+                    .filter(|spdx| thingiverse_license(**spdx).is_some())
+                    .map(Spdx::id)
+                    .collect::<Vec<_>>();
+                Error::Publish(format!(
+                    "LicenseTerms \"{}\" is not a license Thingiverse has, use one of {}",
+                    terms,
+                    supported.join(", ")
+                ))
+            })?;
+        Ok(Listing {
+            name,
+            description: text(metadata.description()),
+            license,
+        })
+    }
+}
+
+/// A client of the Thingiverse API.
+pub struct Thingiverse {
+    agent: ureq::Agent,
+    api_url: String,
+    token: String,
+}
+
+impl Thingiverse {
+    /// A client of the API at `api_url`, usually [`THINGIVERSE_API`],
+    /// authenticated with `token`.
+    pub fn new(api_url: &str, token: &str) -> Self {
+        Thingiverse {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Creates a listing, or updates the one with the id `existing`, and
+    /// returns its URL.
+    pub fn publish(&self, listing: &Listing, existing: Option<u64>) -> Result<String> {
+        let mut body = json!({
+            "name": listing.name,
+            "description": listing.description,
+            "license": listing.license,
+        });
+        let request = match existing {
+            Some(id) => self
+                .agent
+                .request("PATCH", &format!("{}/things/{}", self.api_url, id)),
+            None => {
+                body["is_wip"] = true.into();
+                self.agent.post(&format!("{}/things/", self.api_url))
+            }
+        };
+        let request = request.set("Authorization", &format!("Bearer {}", self.token));
+        // Creating twice would make two listings, updating twice is harmless
+        let transient = match existing {
+            Some(_) => retry::transient,
+            None => retry::transient_unsent,
+        };
+        let response = retry::retry(
+            || request.clone().send_json(&body).map_err(Box::new),
+            |error| transient(error),
+        )
+        .map_err(|error| match *error {
+            ureq::Error::Status(status, response) => {
+                let text = response.into_string().unwrap_or_default();
+                Error::Publish(format!(
+                    "Thingiverse refused the listing with status {}: {}",
+                    status,
+                    text.trim()
+                ))
+            }
+            ureq::Error::Transport(error) => {
+                Error::Publish(format!("could not reach Thingiverse: {}", error))
+            }
+        })?;
+        let thing: Value = response.into_json().map_err(|error| {
+            Error::Publish(format!("unexpected answer from Thingiverse: {}", error))
+        })?;
+        match (thing["public_url"].as_str(), thing["id"].as_u64()) {
+            (Some(url), _) => Ok(url.to_string()),
+            (None, Some(id)) => Ok(format!("https://www.thingiverse.com/thing:{}", id)),
+            (None, None) => Err(Error::Publish(
+                "Thingiverse did not say where the listing is".to_string(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Keeping entry names exactly as they were stored.
+//!
+//! The zip crate decodes entry names to strings when reading, as UTF-8 when
+//! the UTF-8 flag is set and as code page 437 otherwise, and writes strings
+//! back as UTF-8, setting the flag for anything that isn't ASCII. Names
+//! stored in another code page, or not valid UTF-8 despite the flag, would
+//! come out changed, so such entries are written under a placeholder of the
+//! same length instead. The stored bytes and flag are put back in place of
+//! the placeholder once the archive is complete.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use zip::read::ZipFile;
+
+use crate::{observer, Result};
+
+/// First byte of every placeholder. Entry names don't start with control
+/// characters, so only the placeholders are remembered.
+const MARKER: u8 = 0x01;
+
+/// The UTF-8 bit of the general purpose flags.
+const UTF8_FLAG: u16 = 1 << 11;
+
+/// Offsets of the general purpose flags from the start of the name, in the
+/// local file header and the central directory header.
+const LOCAL_FLAGS_OFFSET: u64 = 30 - 6;
+const CENTRAL_FLAGS_OFFSET: u64 = 46 - 8;
+
+/// The entries written under a placeholder, see [`RawNameWriter`].
+#[derive(Debug, Default)]
+pub struct RawNames {
+    names: Vec<RawName>,
+}
+
+#[derive(Debug)]
+struct RawName {
+    placeholder: Vec<u8>,
+    raw: Vec<u8>,
+    utf8: bool,
+}
+
+impl RawNames {
+    /// Returns the name to write `file` under: its own name when the zip
+    /// crate writes that back as stored, or else a placeholder.
+    pub(crate) fn name_for(&mut self, file: &ZipFile) -> String {
+        let raw = file.name_raw();
+        if file.name().as_bytes() == raw {
+            return file.name().to_string();
+        }
+        let index = format!("{:x}", self.names.len());
+        if raw.len() < 1 + index.len() {
+            observer::warning(format_args!(
+                "writing entry {} with its name in UTF-8",
+                file.name()
+            ));
+            return file.name().to_string();
+        }
+        let mut placeholder = vec![MARKER];
+        placeholder.extend_from_slice(index.as_bytes());
+        placeholder.resize(raw.len(), MARKER);
+        self.names.push(RawName {
+            placeholder: placeholder.clone(),
+            raw: raw.to_vec(),
+            // Names without the UTF-8 flag are decoded as code page 437,
+            // which never needs the replacement character
+            utf8: file.name().contains(char::REPLACEMENT_CHARACTER),
+        });
+        String::from_utf8(placeholder).expect("placeholders are ASCII")
+    }
+}
+
+/// A writer for a [`zip::ZipWriter`] that remembers where placeholder names
+/// end up, so that [`RawNameWriter::restore`] can replace them.
+pub struct RawNameWriter<W> {
+    inner: W,
+    position: u64,
+    placeholders: Vec<(u64, Vec<u8>)>,
+}
+
+impl<W: Write + Seek> RawNameWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let position = inner.stream_position()?;
+        Ok(RawNameWriter {
+            inner,
+            position,
+            placeholders: Vec::new(),
+        })
+    }
+
+    /// Puts the stored names back in place of their placeholders in the
+    /// finished archive, along with their UTF-8 flags, and returns the
+    /// wrapped writer.
+    pub fn restore(mut self, names: &RawNames) -> Result<W> {
+        for name in &names.names {
+            let mut found = self
+                .placeholders
+                .iter()
+                .filter(|(_, placeholder)| *placeholder == name.placeholder)
+                .map(|(position, _)| *position);
+            // The local header comes first, the central directory last
+            let (local, central) = match (found.next(), found.next_back()) {
+                (Some(local), Some(central)) => (local, central),
+                _ => return Err(io::Error::other("entry name not found in the output").into()),
+            };
+            let flags: u16 = if name.utf8 { UTF8_FLAG } else { 0 };
+            for (start, flags_offset) in
+                [(local, LOCAL_FLAGS_OFFSET), (central, CENTRAL_FLAGS_OFFSET)]
+            {
+                self.inner.seek(SeekFrom::Start(start - flags_offset))?;
+                self.inner.write_all(&flags.to_le_bytes())?;
+                self.inner.seek(SeekFrom::Start(start))?;
+                self.inner.write_all(&name.raw)?;
+            }
+        }
+        self.inner.seek(SeekFrom::End(0))?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for RawNameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        // Names are written in one go, and never longer than 64 KiB
+        if written == buf.len() && buf.len() <= 0xFFFF && is_placeholder(buf) {
+            self.placeholders.push((self.position, buf.to_vec()));
+        }
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn is_placeholder(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&MARKER)
+        && bytes[1..]
+            .iter()
+            .all(|byte| *byte == MARKER || byte.is_ascii_hexdigit())
+}
+
+impl<W: Seek> Seek for RawNameWriter<W> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(position)?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,58 @@
+//! Retrying network requests that fail for a reason that may pass.
+//!
+//! A batch run makes a request for each file, for `publish`, and one at the
+//! end, for the notifications. A server that answers with a 5xx status for
+//! a moment, or a connection that drops, should not fail the run, so such
+//! requests are tried again a few times, waiting longer each time.
+
+use std::thread;
+use std::time::Duration;
+
+/// How often a request is tried before its error is returned.
+pub(crate) const ATTEMPTS: u32 = 3;
+
+/// The wait before the second attempt, doubled before each one after it.
+const FIRST_DELAY: Duration = Duration::from_millis(500);
+
+/// Calls `attempt` until it succeeds, fails with an error `transient`
+/// doesn't accept, or has been called [`ATTEMPTS`] times.
+pub(crate) fn retry<T, E>(
+    mut attempt: impl FnMut() -> Result<T, E>,
+    transient: impl Fn(&E) -> bool,
+) -> Result<T, E> {
+    let mut delay = FIRST_DELAY;
+    let mut attempts = 1;
+    loop {
+        match attempt() {
+            Err(error) if attempts < ATTEMPTS && transient(&error) => {
+                thread::sleep(delay);
+                delay *= 2;
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether an HTTP request failed in a way that trying again may fix: a
+/// server error, too many requests, or no answer at all.
+pub(crate) fn transient(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(status, _) => *status >= 500 || *status == 429,
+        ureq::Error::Transport(_) => true,
+    }
+}
+
+/// Like [`transient`], for requests that must not reach the server twice,
+/// like one that creates something. Only answers that say the request was
+/// not handled, 503 and 429, and failures to connect are tried again: after
+/// other errors the request may have been handled already.
+pub(crate) fn transient_unsent(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(status, _) => matches!(status, 429 | 503),
+        ureq::Error::Transport(transport) => matches!(
+            transport.kind(),
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed
+        ),
+    }
+}
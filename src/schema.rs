@@ -0,0 +1,865 @@
+//! Validation of model parts against the schemas of the 3MF core
+//! specification and of its common extensions: production, materials and
+//! properties, beam lattice and slice.
+//!
+//! The rules of these schemas (which elements may appear where, in what
+//! order and how often, and which attributes they take with which types)
+//! are built in, so no schema files are needed at run time. As in the
+//! schemas, elements of an extension may appear among those of the core,
+//! where they are checked against the rules of the extension. Elements and
+//! attributes of other namespaces, such as those slicers add, are not
+//! checked.
+//!
+//! ```
+//! use metadata_3mf::schema;
+//!
+//! let model = r#"<model unit="inches" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+//!   <resources/>
+//!   <build/>
+//! </model>"#;
+//! let errors = schema::validate_model(model.as_bytes())?;
+//! assert_eq!(errors.len(), 1);
+//! assert_eq!(errors[0].to_string(), "1:1: invalid value \"inches\" for attribute unit of model");
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+
+use std::fmt;
+use std::io::Read;
+
+use xml::common::Position;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use xmltree::ParseError;
+
+use crate::Result;
+
+/// The namespace of the 3MF core specification.
+pub const CORE_NAMESPACE: &str = "http://schemas.microsoft.com/3dmanufacturing/core/2015/02";
+
+/// The namespace of the production extension, whose `path` attributes refer
+/// to objects in other model parts.
+pub const PRODUCTION_NAMESPACE: &str =
+    "http://schemas.microsoft.com/3dmanufacturing/production/2015/06";
+
+/// The namespace of the materials and properties extension.
+pub const MATERIALS_NAMESPACE: &str =
+    "http://schemas.microsoft.com/3dmanufacturing/material/2015/02";
+
+/// The namespace of the beam lattice extension.
+pub const BEAM_LATTICE_NAMESPACE: &str =
+    "http://schemas.microsoft.com/3dmanufacturing/beamlattice/2017/02";
+
+/// The namespace of the slice extension.
+pub const SLICE_NAMESPACE: &str = "http://schemas.microsoft.com/3dmanufacturing/slice/2015/07";
+
+/// Where a model part breaks the schema, and how.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaError {
+    /// The line of the element, counting from 1.
+    pub line: u64,
+    /// The column of the element, counting from 1.
+    pub column: u64,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// The simple types of the attributes.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    String,
+    Boolean,
+    Number,
+    /// A positive integer below 2^31.
+    ResourceId,
+    /// A non-negative integer below 2^31.
+    ResourceIndex,
+    Matrix3D,
+    Color,
+    Unit,
+    Uuid,
+    /// An absolute path of a part in the package, like `/3D/object.model`.
+    Path,
+    /// One of the names.
+    Choice(&'static [&'static str]),
+    /// Values of a kind separated by whitespace, at least one.
+    List(&'static Kind),
+}
+
+/// Elements that may appear in a content model at this point; more than
+/// one name is a choice between them.
+struct Particle {
+    names: &'static [&'static str],
+    min: usize,
+    max: usize,
+}
+
+const UNBOUNDED: usize = usize::MAX;
+
+struct Rule {
+    name: &'static str,
+    required: &'static [&'static str],
+    attributes: &'static [(&'static str, Kind)],
+    content: &'static [Particle],
+    /// Whether text may appear between the children.
+    text: bool,
+}
+
+const fn particle(names: &'static [&'static str], min: usize, max: usize) -> Particle {
+    Particle { names, min, max }
+}
+
+const CORE_RULES: &[Rule] = &[
+    Rule {
+        name: "model",
+        required: &[],
+        attributes: &[
+            ("unit", Kind::Unit),
+            ("xml:lang", Kind::String),
+            ("requiredextensions", Kind::String),
+            ("recommendedextensions", Kind::String),
+        ],
+        content: &[
+            particle(&["metadata"], 0, UNBOUNDED),
+            particle(&["resources"], 1, 1),
+            particle(&["build"], 1, 1),
+        ],
+        text: false,
+    },
+    Rule {
+        name: "metadata",
+        required: &["name"],
+        attributes: &[
+            ("name", Kind::String),
+            ("preserve", Kind::Boolean),
+            ("type", Kind::String),
+        ],
+        content: &[],
+        text: true,
+    },
+    Rule {
+        name: "resources",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["basematerials", "object"], 0, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "basematerials",
+        required: &["id"],
+        attributes: &[("id", Kind::ResourceId)],
+        content: &[particle(&["base"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "base",
+        required: &["name", "displaycolor"],
+        attributes: &[("name", Kind::String), ("displaycolor", Kind::Color)],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "object",
+        required: &["id"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            (
+                "type",
+                Kind::Choice(&["model", "solidsupport", "support", "surface", "other"]),
+            ),
+            ("thumbnail", Kind::String),
+            ("partnumber", Kind::String),
+            ("name", Kind::String),
+            ("pid", Kind::ResourceId),
+            ("pindex", Kind::ResourceIndex),
+        ],
+        content: &[
+            particle(&["metadatagroup"], 0, 1),
+            particle(&["mesh", "components"], 1, 1),
+        ],
+        text: false,
+    },
+    Rule {
+        name: "metadatagroup",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["metadata"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "mesh",
+        required: &[],
+        attributes: &[],
+        content: &[
+            particle(&["vertices"], 1, 1),
+            particle(&["triangles"], 1, 1),
+        ],
+        text: false,
+    },
+    Rule {
+        name: "vertices",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["vertex"], 3, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "vertex",
+        required: &["x", "y", "z"],
+        attributes: &[
+            ("x", Kind::Number),
+            ("y", Kind::Number),
+            ("z", Kind::Number),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "triangles",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["triangle"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "triangle",
+        required: &["v1", "v2", "v3"],
+        attributes: &[
+            ("v1", Kind::ResourceIndex),
+            ("v2", Kind::ResourceIndex),
+            ("v3", Kind::ResourceIndex),
+            ("p1", Kind::ResourceIndex),
+            ("p2", Kind::ResourceIndex),
+            ("p3", Kind::ResourceIndex),
+            ("pid", Kind::ResourceId),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "components",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["component"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "component",
+        required: &["objectid"],
+        attributes: &[
+            ("objectid", Kind::ResourceId),
+            ("transform", Kind::Matrix3D),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "build",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["item"], 0, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "item",
+        required: &["objectid"],
+        attributes: &[
+            ("objectid", Kind::ResourceId),
+            ("transform", Kind::Matrix3D),
+            ("partnumber", Kind::String),
+            ("printable", Kind::Boolean),
+        ],
+        content: &[],
+        text: false,
+    },
+];
+
+const CAP_MODES: &[&str] = &["sphere", "hemisphere", "butt"];
+
+const TILE_STYLES: &[&str] = &["wrap", "mirror", "clamp", "none"];
+
+const MATERIALS_RULES: &[Rule] = &[
+    Rule {
+        name: "texture2d",
+        required: &["id", "path", "contenttype"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("path", Kind::Path),
+            ("contenttype", Kind::Choice(&["image/png", "image/jpeg"])),
+            ("tilestyleu", Kind::Choice(TILE_STYLES)),
+            ("tilestylev", Kind::Choice(TILE_STYLES)),
+            ("filter", Kind::Choice(&["auto", "linear", "nearest"])),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "colorgroup",
+        required: &["id"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("displaypropertiesid", Kind::ResourceId),
+        ],
+        content: &[particle(&["color"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "color",
+        required: &["color"],
+        attributes: &[("color", Kind::Color)],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "texture2dgroup",
+        required: &["id", "texid"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("texid", Kind::ResourceId),
+            ("displaypropertiesid", Kind::ResourceId),
+        ],
+        content: &[particle(&["tex2coord"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "tex2coord",
+        required: &["u", "v"],
+        attributes: &[("u", Kind::Number), ("v", Kind::Number)],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "compositematerials",
+        required: &["id", "matid", "matindices"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("matid", Kind::ResourceId),
+            ("matindices", Kind::List(&Kind::ResourceIndex)),
+            ("displaypropertiesid", Kind::ResourceId),
+        ],
+        content: &[particle(&["composite"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "composite",
+        required: &["values"],
+        attributes: &[("values", Kind::List(&Kind::Number))],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "multiproperties",
+        required: &["id", "pids"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("pids", Kind::List(&Kind::ResourceId)),
+            (
+                "blendmethods",
+                Kind::List(&Kind::Choice(&["mix", "multiply"])),
+            ),
+        ],
+        content: &[particle(&["multi"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "multi",
+        required: &["pindices"],
+        attributes: &[("pindices", Kind::List(&Kind::ResourceIndex))],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "pbspeculardisplayproperties",
+        required: &["id"],
+        attributes: &[("id", Kind::ResourceId)],
+        content: &[particle(&["pbspecular"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "pbspecular",
+        required: &["name"],
+        attributes: &[
+            ("name", Kind::String),
+            ("specularcolor", Kind::Color),
+            ("glossiness", Kind::Number),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "pbmetallicdisplayproperties",
+        required: &["id"],
+        attributes: &[("id", Kind::ResourceId)],
+        content: &[particle(&["pbmetallic"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "pbmetallic",
+        required: &["name"],
+        attributes: &[
+            ("name", Kind::String),
+            ("metallicness", Kind::Number),
+            ("roughness", Kind::Number),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "pbspeculartexturedisplayproperties",
+        required: &["id", "name", "speculartextureid", "glossinesstextureid"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("name", Kind::String),
+            ("speculartextureid", Kind::ResourceId),
+            ("glossinesstextureid", Kind::ResourceId),
+            ("diffusefactor", Kind::Color),
+            ("specularfactor", Kind::Color),
+            ("glossinessfactor", Kind::Number),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "pbmetallictexturedisplayproperties",
+        required: &["id", "name", "metallictextureid", "roughnesstextureid"],
+        attributes: &[
+            ("id", Kind::ResourceId),
+            ("name", Kind::String),
+            ("metallictextureid", Kind::ResourceId),
+            ("roughnesstextureid", Kind::ResourceId),
+            ("basecolorfactor", Kind::Color),
+            ("metallicfactor", Kind::Number),
+            ("roughnessfactor", Kind::Number),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "translucentdisplayproperties",
+        required: &["id"],
+        attributes: &[("id", Kind::ResourceId)],
+        content: &[particle(&["translucent"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "translucent",
+        required: &["name", "attenuation"],
+        attributes: &[
+            ("name", Kind::String),
+            ("attenuation", Kind::List(&Kind::Number)),
+            ("refractiveindex", Kind::List(&Kind::Number)),
+            ("roughness", Kind::Number),
+        ],
+        content: &[],
+        text: false,
+    },
+];
+
+const BEAM_LATTICE_RULES: &[Rule] = &[
+    Rule {
+        name: "beamlattice",
+        required: &["minlength", "radius"],
+        attributes: &[
+            ("minlength", Kind::Number),
+            ("radius", Kind::Number),
+            ("clippingmode", Kind::Choice(&["none", "inside", "outside"])),
+            ("clippingmesh", Kind::ResourceId),
+            ("representationmesh", Kind::ResourceId),
+            ("cap", Kind::Choice(CAP_MODES)),
+        ],
+        content: &[particle(&["beams"], 1, 1), particle(&["beamsets"], 0, 1)],
+        text: false,
+    },
+    Rule {
+        name: "beams",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["beam"], 0, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "beam",
+        required: &["v1", "v2"],
+        attributes: &[
+            ("v1", Kind::ResourceIndex),
+            ("v2", Kind::ResourceIndex),
+            ("r1", Kind::Number),
+            ("r2", Kind::Number),
+            ("cap1", Kind::Choice(CAP_MODES)),
+            ("cap2", Kind::Choice(CAP_MODES)),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "beamsets",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["beamset"], 0, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "beamset",
+        required: &[],
+        attributes: &[("name", Kind::String), ("identifier", Kind::String)],
+        content: &[particle(&["ref"], 0, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "ref",
+        required: &["index"],
+        attributes: &[("index", Kind::ResourceIndex)],
+        content: &[],
+        text: false,
+    },
+];
+
+const SLICE_RULES: &[Rule] = &[
+    Rule {
+        name: "slicestack",
+        required: &["id"],
+        attributes: &[("id", Kind::ResourceId), ("zbottom", Kind::Number)],
+        content: &[
+            particle(&["slice"], 0, UNBOUNDED),
+            particle(&["sliceref"], 0, UNBOUNDED),
+        ],
+        text: false,
+    },
+    Rule {
+        name: "slice",
+        required: &["ztop"],
+        attributes: &[("ztop", Kind::Number)],
+        content: &[
+            particle(&["vertices"], 0, 1),
+            particle(&["polygon"], 0, UNBOUNDED),
+        ],
+        text: false,
+    },
+    Rule {
+        name: "vertices",
+        required: &[],
+        attributes: &[],
+        content: &[particle(&["vertex"], 2, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "vertex",
+        required: &["x", "y"],
+        attributes: &[("x", Kind::Number), ("y", Kind::Number)],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "polygon",
+        required: &["startv"],
+        attributes: &[("startv", Kind::ResourceIndex)],
+        content: &[particle(&["segment"], 1, UNBOUNDED)],
+        text: false,
+    },
+    Rule {
+        name: "segment",
+        required: &["v2"],
+        attributes: &[
+            ("v2", Kind::ResourceIndex),
+            ("p1", Kind::ResourceIndex),
+            ("p2", Kind::ResourceIndex),
+            ("pid", Kind::ResourceId),
+        ],
+        content: &[],
+        text: false,
+    },
+    Rule {
+        name: "sliceref",
+        required: &["slicestackid", "slicepath"],
+        attributes: &[
+            ("slicestackid", Kind::ResourceId),
+            ("slicepath", Kind::Path),
+        ],
+        content: &[],
+        text: false,
+    },
+];
+
+/// The rules of each schema that is checked, by namespace. The production
+/// extension has no elements of its own, only attributes.
+const SCHEMAS: &[(&str, &[Rule])] = &[
+    (CORE_NAMESPACE, CORE_RULES),
+    (PRODUCTION_NAMESPACE, &[]),
+    (MATERIALS_NAMESPACE, MATERIALS_RULES),
+    (BEAM_LATTICE_NAMESPACE, BEAM_LATTICE_RULES),
+    (SLICE_NAMESPACE, SLICE_RULES),
+];
+
+/// The attributes that extensions add to elements of the core: the
+/// namespace of the extension, the element, the attribute and its type.
+const EXTENSION_ATTRIBUTES: &[(&str, &str, &str, Kind)] = &[
+    (PRODUCTION_NAMESPACE, "build", "UUID", Kind::Uuid),
+    (PRODUCTION_NAMESPACE, "item", "UUID", Kind::Uuid),
+    (PRODUCTION_NAMESPACE, "item", "path", Kind::Path),
+    (PRODUCTION_NAMESPACE, "object", "UUID", Kind::Uuid),
+    (PRODUCTION_NAMESPACE, "component", "UUID", Kind::Uuid),
+    (PRODUCTION_NAMESPACE, "component", "path", Kind::Path),
+    (SLICE_NAMESPACE, "object", "slicestackid", Kind::ResourceId),
+    (
+        SLICE_NAMESPACE,
+        "object",
+        "meshresolution",
+        Kind::Choice(&["fullres", "lowres"]),
+    ),
+];
+
+fn rule(namespace: Option<&str>, name: &str) -> Option<&'static Rule> {
+    let (_, rules) = SCHEMAS
+        .iter()
+        .find(|(schema, _)| Some(*schema) == namespace)?;
+    rules.iter().find(|rule| rule.name == name)
+}
+
+fn is_checked(namespace: &str) -> bool {
+    SCHEMAS.iter().any(|(schema, _)| *schema == namespace)
+}
+
+/// An element being validated: where it is in its content model.
+struct Frame {
+    /// `None` for elements of namespaces that aren't checked, whose content
+    /// isn't either.
+    rule: Option<&'static Rule>,
+    /// The namespace of the element, whose elements its content model is
+    /// made of.
+    namespace: Option<String>,
+    particle: usize,
+    count: usize,
+}
+
+impl Frame {
+    /// Moves on to the particle that allows `name`, and counts it. Fails
+    /// with the names that would have been allowed instead.
+    fn child(&mut self, name: &str) -> std::result::Result<(), Vec<&'static str>> {
+        let Some(rule) = self.rule else {
+            return Ok(());
+        };
+        let mut expected = Vec::new();
+        let mut index = self.particle;
+        let mut count = self.count;
+        while let Some(particle) = rule.content.get(index) {
+            if particle.names.contains(&name) && count < particle.max {
+                self.particle = index;
+                self.count = count + 1;
+                return Ok(());
+            }
+            if count < particle.max {
+                expected.extend(particle.names);
+            }
+            if count < particle.min {
+                break;
+            }
+            index += 1;
+            count = 0;
+        }
+        Err(expected)
+    }
+
+    /// The elements that are still missing at the end of the element.
+    fn missing(&self) -> Option<&'static [&'static str]> {
+        let rule = self.rule?;
+        let mut count = self.count;
+        for particle in &rule.content[self.particle.min(rule.content.len())..] {
+            if count < particle.min {
+                return Some(particle.names);
+            }
+            count = 0;
+        }
+        None
+    }
+}
+
+/// Validates a model part against the core schema and those of the
+/// extensions, and returns every problem found. Fails only when the part isn't well-formed XML.
+pub fn validate_model<R: Read>(reader: R) -> Result<Vec<SchemaError>> {
+    let mut reader = EventReader::new_with_config(reader, ParserConfig::new());
+    let mut errors = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    loop {
+        let event = reader.next().map_err(ParseError::MalformedXml)?;
+        let position = reader.position();
+        let mut error = |message: String| {
+            errors.push(SchemaError {
+                line: position.row + 1,
+                column: position.column + 1,
+                message,
+            })
+        };
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let namespace = name.namespace.as_deref();
+                let core = namespace == Some(CORE_NAMESPACE);
+                let element = name.local_name.as_str();
+                let rule = match stack.last_mut() {
+                    None if core && element == "model" => rule(namespace, element),
+                    None => {
+                        error(format!(
+                            "the root element must be model in namespace {}",
+                            CORE_NAMESPACE
+                        ));
+                        None
+                    }
+                    Some(parent)
+                        if parent.rule.is_some() && parent.namespace.as_deref() == namespace =>
+                    {
+                        if let Err(expected) = parent.child(element) {
+                            let parent = parent.rule.unwrap().name;
+                            error(unexpected(element, parent, &expected));
+                        }
+                        rule(namespace, element)
+                    }
+                    // Elements of other namespaces may appear anywhere, and
+                    // are checked if they belong to a known extension
+                    Some(parent) if parent.rule.is_some() => rule(namespace, element),
+                    Some(_) => None,
+                };
+                if let Some(rule) = rule {
+                    for required in rule.required {
+                        if !attributes
+                            .iter()
+                            .any(|attribute| attribute.name.borrow().to_repr() == *required)
+                        {
+                            error(format!("{} lacks attribute {}", element, required));
+                        }
+                    }
+                    for attribute in &attributes {
+                        let attribute_name = attribute.name.borrow().to_repr();
+                        let kind = match attribute.name.namespace.as_deref() {
+                            Some(extension) if attribute_name != "xml:lang" => {
+                                // Attributes of namespaces that aren't
+                                // checked are left to them
+                                if !is_checked(extension) {
+                                    continue;
+                                }
+                                // Extensions only add attributes to the core
+                                EXTENSION_ATTRIBUTES
+                                    .iter()
+                                    .filter(|_| core)
+                                    .find(|(namespace, on, name, _)| {
+                                        *namespace == extension
+                                            && *on == element
+                                            && *name == attribute.name.local_name
+                                    })
+                                    .map(|(_, _, _, kind)| *kind)
+                            }
+                            _ => rule
+                                .attributes
+                                .iter()
+                                .find(|(name, _)| *name == attribute_name)
+                                .map(|(_, kind)| *kind),
+                        };
+                        match kind {
+                            Some(kind) if !valid(kind, &attribute.value) => error(format!(
+                                "invalid value \"{}\" for attribute {} of {}",
+                                attribute.value, attribute_name, element
+                            )),
+                            Some(_) => (),
+                            None => error(format!(
+                                "unexpected attribute {} on {}",
+                                attribute_name, element
+                            )),
+                        }
+                    }
+                }
+                stack.push(Frame {
+                    rule,
+                    namespace: name.namespace,
+                    particle: 0,
+                    count: 0,
+                });
+            }
+            XmlEvent::EndElement { .. } => {
+                let frame = stack.pop().unwrap();
+                if let (Some(rule), Some(missing)) = (frame.rule, frame.missing()) {
+                    error(format!("{} lacks {}", rule.name, missing.join(" or ")));
+                }
+            }
+            XmlEvent::Characters(_) | XmlEvent::CData(_) => {
+                if let Some(rule) = stack.last().and_then(|frame| frame.rule) {
+                    if !rule.text {
+                        error(format!("unexpected text in {}", rule.name));
+                    }
+                }
+            }
+            XmlEvent::EndDocument => return Ok(errors),
+            _ => (),
+        }
+    }
+}
+
+fn unexpected(element: &str, parent: &str, expected: &[&str]) -> String {
+    if expected.is_empty() {
+        format!("unexpected element {} in {}", element, parent)
+    } else {
+        format!(
+            "unexpected element {} in {}, expected {}",
+            element,
+            parent,
+            expected.join(" or ")
+        )
+    }
+}
+
+fn valid(kind: Kind, value: &str) -> bool {
+    match kind {
+        Kind::String => true,
+        Kind::Boolean => matches!(value, "true" | "false" | "1" | "0"),
+        Kind::Number => is_number(value),
+        Kind::ResourceId => is_index(value) && !value.trim_start_matches('0').is_empty(),
+        Kind::ResourceIndex => is_index(value),
+        Kind::Matrix3D => {
+            let numbers: Vec<_> = value.split(' ').collect();
+            numbers.len() == 12 && numbers.iter().all(|number| is_number(number))
+        }
+        Kind::Color => {
+            let digits = value.strip_prefix('#').unwrap_or_default();
+            matches!(digits.len(), 6 | 8) && digits.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        Kind::Unit => crate::model::Unit::NAMES.contains(&value),
+        Kind::Uuid => {
+            let groups: Vec<_> = value.split('-').map(str::len).collect();
+            groups == [8, 4, 4, 4, 12] && value.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+        }
+        Kind::Path => value.starts_with('/') && value.len() > 1,
+        Kind::Choice(names) => names.contains(&value),
+        Kind::List(kind) => {
+            value.split_whitespace().next().is_some()
+                && value.split_whitespace().all(|item| valid(*kind, item))
+        }
+    }
+}
+
+/// A non-negative integer below 2^31.
+fn is_index(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_digit())
+        && value.parse::<u64>().is_ok_and(|index| index < 1 << 31)
+}
+
+/// The number format of the schema: an optional sign, digits with an
+/// optional fraction, or just a fraction, and an optional exponent.
+fn is_number(value: &str) -> bool {
+    let digits = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_digit());
+    let value = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let (mantissa, exponent) = match value.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (value, None),
+    };
+    let mantissa_valid = match mantissa.split_once('.') {
+        Some(("", fraction)) => digits(fraction),
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(mantissa),
+    };
+    let exponent_valid = exponent
+        .is_none_or(|exponent| digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)));
+    mantissa_valid && exponent_valid
+}
@@ -0,0 +1,156 @@
+//! Settings that slicers only keep in their own parts of a project, such as
+//! the printer and filament profiles, turned into 3MF metadata that any
+//! viewer can show.
+//!
+//! PrusaSlicer keeps them in `Metadata/Slic3r_PE.config`, Bambu Studio and
+//! OrcaSlicer in `Metadata/project_settings.config`, and Cura in a stack of
+//! `Cura/*.cfg` files. The settings become metadata entries in a namespace
+//! of their own, named after what they hold:
+//!
+//! | Setting | Metadata name |
+//! | --- | --- |
+//! | `printer_model` | `slicer:PrinterModel` |
+//! | `printer_settings_id`, Cura's printer name | `slicer:PrinterProfile` |
+//! | `print_settings_id` | `slicer:PrintProfile` |
+//! | `filament_settings_id` | `slicer:FilamentProfile` |
+//! | `filament_type` | `slicer:FilamentType` |
+//!
+//! Settings with one value per extruder have their values joined with
+//! `; `. PrusaSlicer also names itself and its version, which becomes the
+//! well-known `Application`.
+
+use std::io::{Read, Seek};
+
+use crate::package::Package;
+use crate::{well_known, Error, MetadataSet, Result};
+
+/// The namespace prefix of the entries, unless another one is asked for.
+pub const DEFAULT_PREFIX: &str = "slicer";
+
+/// The settings that are read, with the local name of the entry each
+/// becomes.
+const FIELDS: [(&str, &str); 5] = [
+    ("printer_model", "PrinterModel"),
+    ("printer_settings_id", "PrinterProfile"),
+    ("print_settings_id", "PrintProfile"),
+    ("filament_settings_id", "FilamentProfile"),
+    ("filament_type", "FilamentType"),
+];
+
+const PRUSA_CONFIG: &str = "Metadata/Slic3r_PE.config";
+const BAMBU_CONFIG: &str = "Metadata/project_settings.config";
+
+/// Reads the slicer settings of the package as metadata, with names in the
+/// `prefix` namespace. Packages that no known slicer wrote give an empty
+/// set.
+pub fn read_slicer_metadata<R: Read + Seek>(
+    package: &mut Package<R>,
+    prefix: &str,
+) -> Result<MetadataSet> {
+    let mut settings = Vec::new();
+    if let Some(text) = read_part(package, PRUSA_CONFIG)? {
+        settings.extend(prusa_settings(&text));
+    } else if let Some(text) = read_part(package, BAMBU_CONFIG)? {
+        settings.extend(bambu_settings(&text)?);
+    } else {
+        let names: Vec<String> = package
+            .archive()
+            .file_names()
+            .filter(|name| name.starts_with("Cura/") && name.ends_with(".global.cfg"))
+            .map(str::to_string)
+            .collect();
+        for name in names {
+            if let Some(text) = read_part(package, &name)? {
+                settings.extend(cura_settings(&text));
+            }
+        }
+    }
+    let mut metadata = MetadataSet::new();
+    for (setting, value) in settings {
+        if value.is_empty() {
+            continue;
+        }
+        if setting == well_known::APPLICATION {
+            metadata.set(well_known::APPLICATION, value);
+        } else if let Some((_, local)) = FIELDS.iter().find(|(name, _)| *name == setting) {
+            metadata.set(&format!("{}:{}", prefix, local), value);
+        }
+    }
+    Ok(metadata)
+}
+
+fn read_part<R: Read + Seek>(package: &mut Package<R>, name: &str) -> Result<Option<String>> {
+    let Some(index) = package.index_of(name) else {
+        return Ok(None);
+    };
+    let mut text = String::new();
+    package.by_index(index)?.read_to_string(&mut text)?;
+    Ok(Some(text))
+}
+
+/// Lines like `; printer_model = MK3S`, after a first line like
+/// `; generated by PrusaSlicer 2.6.0+win64 on 2023-05-04 at 10:12:31 UTC`.
+fn prusa_settings(text: &str) -> Vec<(String, String)> {
+    let mut settings = Vec::new();
+    for line in text.lines() {
+        let Some(line) = line.strip_prefix("; ") else {
+            continue;
+        };
+        if let Some(generator) = line.strip_prefix("generated by ") {
+            let application = generator.split(" on ").next().unwrap_or(generator);
+            settings.push((well_known::APPLICATION.to_string(), application.to_string()));
+        } else if let Some((name, value)) = line.split_once(" = ") {
+            // One quoted value per extruder, separated by semicolons
+            let values: Vec<_> = value
+                .split(';')
+                .map(|value| value.trim().trim_matches('"'))
+                .collect();
+            settings.push((name.to_string(), values.join("; ")));
+        }
+    }
+    settings
+}
+
+/// A JSON object of strings, and arrays of them for settings with a value
+/// per extruder.
+fn bambu_settings(text: &str) -> Result<Vec<(String, String)>> {
+    let json: serde_json::Value = serde_json::from_str(text)
+        .map_err(|error| Error::MetadataFile(format!("{}: {}", BAMBU_CONFIG, error)))?;
+    let Some(object) = json.as_object() else {
+        return Ok(Vec::new());
+    };
+    let mut settings = Vec::new();
+    for (name, value) in object {
+        let value = match value {
+            serde_json::Value::String(value) => value.clone(),
+            serde_json::Value::Array(values) => values
+                .iter()
+                .filter_map(|value| value.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+            _ => continue,
+        };
+        settings.push((name.clone(), value));
+    }
+    Ok(settings)
+}
+
+/// The name in the `[general]` section of the global stack is the printer.
+fn cura_settings(text: &str) -> Vec<(String, String)> {
+    let mut section = "";
+    let mut settings = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = name;
+        } else if let Some((name, value)) = line.split_once('=') {
+            if section == "general" && name.trim() == "name" {
+                settings.push(("printer_settings_id".to_string(), value.trim().to_string()));
+            }
+        }
+    }
+    settings
+}
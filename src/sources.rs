@@ -0,0 +1,116 @@
+//! All the places a package keeps metadata in, read into one listing.
+//!
+//! Besides the metadata of the root model part, packages may carry OPC core
+//! properties, as Office documents do, a comment on the ZIP archive, and
+//! the settings of the slicer that saved them. Each source gives a set of
+//! entries of its own; together they are the complete picture of a file:
+//!
+//! | Source | Entries |
+//! | --- | --- |
+//! | `model` | the metadata of the root model part |
+//! | `core` | the elements of the core properties part, as `dc:title` and so on |
+//! | `archive` | the archive comment, as `comment` |
+//! | `slicer` | the settings [`slicer::read_slicer_metadata`] reads, without a prefix |
+
+use std::io::{Read, Seek};
+
+use xmltree::{Element, XMLNode};
+
+use crate::package::{self, Package};
+use crate::{slicer, MetadataSet, Result};
+
+/// A place a package keeps metadata in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Model,
+    CoreProperties,
+    Archive,
+    Slicer,
+}
+
+impl Source {
+    /// Every source, in the order they are read.
+    pub const ALL: [Source; 4] = [
+        Source::Model,
+        Source::CoreProperties,
+        Source::Archive,
+        Source::Slicer,
+    ];
+
+    /// The name that namespaces the entries of the source.
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Model => "model",
+            Source::CoreProperties => "core",
+            Source::Archive => "archive",
+            Source::Slicer => "slicer",
+        }
+    }
+}
+
+/// Reads the metadata of every source of the package, leaving out sources
+/// without any.
+pub fn read_all_sources<R: Read + Seek>(
+    package: &mut Package<R>,
+) -> Result<Vec<(Source, MetadataSet)>> {
+    let mut sources = Vec::new();
+    for source in Source::ALL {
+        let metadata = match source {
+            Source::Model => package::read_root_metadata(package)?,
+            Source::CoreProperties => read_core_properties(package)?,
+            Source::Archive => {
+                let mut metadata = MetadataSet::new();
+                let comment = String::from_utf8_lossy(package.archive().comment()).into_owned();
+                if !comment.is_empty() {
+                    metadata.set("comment", comment);
+                }
+                metadata
+            }
+            Source::Slicer => {
+                let prefix = format!("{}:", slicer::DEFAULT_PREFIX);
+                let mut metadata = MetadataSet::new();
+                for entry in &slicer::read_slicer_metadata(package, slicer::DEFAULT_PREFIX)? {
+                    let name = entry.name.strip_prefix(&prefix).unwrap_or(&entry.name);
+                    metadata.set(name, entry.value.clone());
+                }
+                metadata
+            }
+        };
+        if !metadata.is_empty() {
+            sources.push((source, metadata));
+        }
+    }
+    Ok(sources)
+}
+
+/// The elements of the core properties part with their text, named with
+/// their prefix. The part is found through its package relationship, or
+/// else by its content type.
+fn read_core_properties<R: Read + Seek>(package: &mut Package<R>) -> Result<MetadataSet> {
+    let mut names = package.relationship_targets(package::CORE_PROPERTIES_RELATIONSHIP_TYPE)?;
+    let mut entries = package.entries();
+    while let Some(entry) = entries.next_entry() {
+        let entry = entry?;
+        if entry.content_type() == Some(package::CORE_PROPERTIES_CONTENT_TYPE) {
+            names.push(entry.name().to_string());
+        }
+    }
+    let mut metadata = MetadataSet::new();
+    let Some(index) = names.iter().find_map(|name| package.index_of(name)) else {
+        return Ok(metadata);
+    };
+    let xml = Element::parse(package.by_index(index)?)?;
+    for element in xml.children.iter().filter_map(XMLNode::as_element) {
+        let value = element.get_text().unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let name = match &element.prefix {
+            Some(prefix) => format!("{}:{}", prefix, element.name),
+            None => element.name.clone(),
+        };
+        metadata.set(&name, value);
+    }
+    Ok(metadata)
+}
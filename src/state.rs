@@ -0,0 +1,99 @@
+//! The state file of a batch run, which lists the input files that are
+//! done, so that running the same command again skips them.
+//!
+//! The first line names the subcommand the run was for, and each further
+//! line is the absolute path of a file that was finished:
+//!
+//! ```text
+//! # metadata_3mf state for add
+//! /srv/library/a.3mf
+//! /srv/library/b.3mf
+//! ```
+//!
+//! Each path is written as soon as its file is done, so an interrupted run
+//! only repeats the file it was working on.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{Error, Result};
+
+/// An open state file.
+#[derive(Debug)]
+pub struct State {
+    done: HashSet<String>,
+    file: File,
+}
+
+fn header(subcommand: &str) -> String {
+    format!("# metadata_3mf state for {}", subcommand)
+}
+
+/// The form a path is listed in, so that the same file under another name
+/// is still found.
+fn key(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl State {
+    /// Opens the state file of a run of `subcommand`, creating it when it
+    /// doesn't exist yet. With `reset`, the files it lists are forgotten.
+    /// Fails when the file is for another subcommand.
+    pub fn open(path: &Path, subcommand: &str, reset: bool) -> Result<Self> {
+        let context =
+            |error: io::Error| Error::State(format!("state file {}: {}", path.display(), error));
+        let text = match fs::read_to_string(path) {
+            Ok(_) if reset => String::new(),
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(context(error)),
+        };
+        let mut lines = text.lines();
+        let mut done = HashSet::new();
+        match lines.next() {
+            None => {
+                let mut file = File::create(path).map_err(context)?;
+                writeln!(file, "{}", header(subcommand)).map_err(context)?;
+                return Ok(State { done, file });
+            }
+            Some(line) if line == header(subcommand) => (),
+            Some(line) => {
+                let other = line
+                    .strip_prefix("# metadata_3mf state for ")
+                    .map(|other| format!("is for {}", other))
+                    .unwrap_or_else(|| "is not a state file".to_string());
+                return Err(Error::State(format!(
+                    "state file {} {}, not {}, use --reset-state to start over",
+                    path.display(),
+                    other,
+                    subcommand
+                )));
+            }
+        }
+        done.extend(lines.filter(|line| !line.is_empty()).map(str::to_string));
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(context)?;
+        Ok(State { done, file })
+    }
+
+    /// Whether the state file lists `path` as done.
+    pub fn is_done(&self, path: &Path) -> bool {
+        self.done.contains(&key(path))
+    }
+
+    /// Lists `path` as done, right away.
+    pub fn mark_done(&mut self, path: &Path) -> Result<()> {
+        let key = key(path);
+        writeln!(self.file, "{}", key)?;
+        self.file.flush()?;
+        self.done.insert(key);
+        Ok(())
+    }
+}
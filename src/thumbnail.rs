@@ -0,0 +1,288 @@
+//! Checking the thumbnails of a package, and resizing them.
+//!
+//! The 3MF specification allows PNG and JPEG thumbnails, and the content
+//! type of the part has to say which of the two it is. Some exporters write
+//! JPEG data into a part named `.png`, which the `[Content_Types].xml` then
+//! gets wrong, and viewers that trust it show nothing. Slicers also want
+//! their own sizes, such as 512×512 for Bambu Studio.
+//!
+//! ```
+//! use metadata_3mf::thumbnail::{self, ImageFormat, Size};
+//!
+//! let size: Size = "512x512".parse()?;
+//! assert_eq!(size.to_string(), "512x512");
+//! assert_eq!(ImageFormat::detect(b"\xFF\xD8\xFF\xE0"), Some(ImageFormat::Jpeg));
+//! assert_eq!(thumbnail::image_size(b"GIF89a"), None);
+//! # Ok::<(), metadata_3mf::Error>(())
+//! ```
+
+use std::fmt;
+#[cfg(feature = "thumbnails")]
+use std::io::Write;
+use std::io::{Read, Seek};
+use std::str::FromStr;
+
+#[cfg(feature = "thumbnails")]
+use zip::read::ZipFile;
+#[cfg(feature = "thumbnails")]
+use zip::write::FileOptions;
+#[cfg(feature = "thumbnails")]
+use zip::{CompressionMethod, ZipWriter};
+
+#[cfg(feature = "thumbnails")]
+use crate::observer;
+use crate::package::{self, Package};
+use crate::{Error, Result};
+
+/// The image formats the specification allows for thumbnails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// The format of an image, going by its first bytes.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(PNG_SIGNATURE) {
+            Some(ImageFormat::Png)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "PNG",
+            ImageFormat::Jpeg => "JPEG",
+        }
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// The width and height of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = Error;
+
+    /// Parses a size as `WIDTHxHEIGHT`, like `512x512`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidValue {
+            name: "size".to_string(),
+            value: s.to_string(),
+        };
+        let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+        let width: u32 = width.parse().map_err(|_| invalid())?;
+        let height: u32 = height.parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(Size { width, height })
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// What a thumbnail of a package is, and what it claims to be.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// The entry name of the part.
+    pub name: String,
+    /// The content type `[Content_Types].xml` gives the part.
+    pub content_type: Option<String>,
+    /// The format of the image, when it is one the specification allows.
+    pub format: Option<ImageFormat>,
+    pub size: Option<Size>,
+}
+
+impl Thumbnail {
+    /// What is wrong with the thumbnail, if anything: an image of another
+    /// format than PNG or JPEG, or a content type that names another format
+    /// than the image has.
+    pub fn problem(&self) -> Option<String> {
+        let content_type = self.content_type.as_deref().unwrap_or("none");
+        match self.format {
+            None => Some(format!(
+                "thumbnail {} is not a PNG or JPEG image",
+                self.name
+            )),
+            Some(format) if !content_type.eq_ignore_ascii_case(format.content_type()) => {
+                Some(format!(
+                    "thumbnail {} is a {} image, but its content type is {}",
+                    self.name,
+                    format.name(),
+                    content_type
+                ))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// Reads the thumbnails of the package, that its relationships point at.
+/// Thumbnails that are missing from the archive are left out.
+pub fn read_thumbnails<R: Read + Seek>(package: &mut Package<R>) -> Result<Vec<Thumbnail>> {
+    let mut thumbnails = Vec::new();
+    for name in package.relationship_targets(package::THUMBNAIL_RELATIONSHIP_TYPE)? {
+        let Some(index) = package.index_of(&name) else {
+            continue;
+        };
+        let mut bytes = Vec::new();
+        package.by_index(index)?.read_to_end(&mut bytes)?;
+        thumbnails.push(Thumbnail {
+            content_type: package.content_types().get(&name).map(str::to_string),
+            format: ImageFormat::detect(&bytes),
+            size: image_size(&bytes),
+            name,
+        });
+    }
+    Ok(thumbnails)
+}
+
+/// The size of a PNG or JPEG image, read from its header.
+pub fn image_size(bytes: &[u8]) -> Option<Size> {
+    let (width, height) = match ImageFormat::detect(bytes)? {
+        // The IHDR chunk always comes first
+        ImageFormat::Png if bytes.get(12..16) == Some(b"IHDR") => {
+            (be32(bytes.get(16..20)?), be32(bytes.get(20..24)?))
+        }
+        ImageFormat::Png => return None,
+        ImageFormat::Jpeg => jpeg_size(bytes)?,
+    };
+    Some(Size { width, height })
+}
+
+/// Walks the segments of a JPEG image up to the start of frame, which has
+/// the size.
+fn jpeg_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut position = 2;
+    loop {
+        if *bytes.get(position)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(position + 1)?;
+        match marker {
+            // Fill bytes before a marker
+            0xFF => position += 1,
+            // Markers without a segment
+            0x01 | 0xD0..=0xD7 => position += 2,
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let segment = bytes.get(position + 5..position + 9)?;
+                let height = u16::from_be_bytes([segment[0], segment[1]]);
+                let width = u16::from_be_bytes([segment[2], segment[3]]);
+                return Some((width.into(), height.into()));
+            }
+            _ => {
+                let length = bytes.get(position + 2..position + 4)?;
+                position += 2 + u16::from_be_bytes([length[0], length[1]]) as usize;
+            }
+        }
+    }
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Scales a PNG image to `size`, averaging the pixels that make up each new
+/// one. Palette and low bit depth images come out as 8 bit color.
+#[cfg(feature = "thumbnails")]
+pub fn resize_png(bytes: &[u8], size: Size) -> Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(image_error)?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut pixels).map_err(image_error)?;
+    let (color, _) = reader.output_color_type();
+    let channels = color.samples();
+    let (old_width, old_height) = (frame.width as usize, frame.height as usize);
+    let (width, height) = (size.width as usize, size.height as usize);
+
+    let mut resized = Vec::with_capacity(width * height * channels);
+    for y in 0..height {
+        let top = y * old_height / height;
+        let bottom = ((y + 1) * old_height / height).max(top + 1);
+        for x in 0..width {
+            let left = x * old_width / width;
+            let right = ((x + 1) * old_width / width).max(left + 1);
+            let mut sums = [0usize; 4];
+            for row in top..bottom {
+                let start = row * frame.line_size;
+                for column in left..right {
+                    let pixel = start + column * channels;
+                    for (sum, value) in sums.iter_mut().zip(&pixels[pixel..pixel + channels]) {
+                        *sum += *value as usize;
+                    }
+                }
+            }
+            let count = (bottom - top) * (right - left);
+            resized.extend(sums[..channels].iter().map(|sum| (sum / count) as u8));
+        }
+    }
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, size.width, size.height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(image_error)?;
+    writer.write_image_data(&resized).map_err(image_error)?;
+    writer.finish().map_err(image_error)?;
+    Ok(output)
+}
+
+/// Writes the thumbnail `file` to `output` as `name`, scaled to `size`,
+/// for the `update` of [`package::copy_entries`]. Returns false, so that it
+/// is copied as it is, when it has that size already, and for JPEG
+/// thumbnails, which can't be scaled, with a warning.
+#[cfg(feature = "thumbnails")]
+pub fn copy_resized<W: Write + Seek>(
+    mut file: ZipFile,
+    name: &str,
+    size: Size,
+    output: &mut ZipWriter<W>,
+) -> Result<bool> {
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    if image_size(&bytes) == Some(size) {
+        return Ok(false);
+    }
+    if ImageFormat::detect(&bytes) != Some(ImageFormat::Png) {
+        observer::warning(format_args!(
+            "only PNG thumbnails can be resized, copying {} as it is",
+            name
+        ));
+        return Ok(false);
+    }
+    let resized = resize_png(&bytes, size)?;
+    // PNG is compressed already
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .last_modified_time(package::entry_time()?);
+    output.start_file(name, options)?;
+    output.write_all(&resized)?;
+    Ok(true)
+}
+
+#[cfg(feature = "thumbnails")]
+fn image_error(error: impl fmt::Display) -> Error {
+    Error::Image(error.to_string())
+}
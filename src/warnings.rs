@@ -0,0 +1,301 @@
+//! Warnings about packages that work, but may cause trouble elsewhere, with
+//! levels to pick which of them are reported.
+//!
+//! Like compiler lints, each warning can be allowed, which means it isn't
+//! looked for, reported, or denied, which turns it into an error:
+//!
+//! | Warning | Found when | Default |
+//! | --- | --- | --- |
+//! | `non-conformant-name` | a metadata name is neither well-known nor namespaced | warn |
+//! | `deprecated-key` | a metadata name is an old spelling of a well-known name | warn |
+//! | `missing-thumbnail` | the package has no thumbnail | allow |
+//! | `huge-uncompressed-part` | a part over 16 MiB is stored without compression | warn |
+//! | `duplicate-entry` | the archive has more than one entry with the same name | warn |
+//! | `thumbnail-format` | a thumbnail is not PNG or JPEG, or not what its content type says | warn |
+//! | `conflicting-metadata` | model parts have different values for a metadata name | warn |
+//!
+//! ```
+//! use metadata_3mf::warnings::{Level, Levels, Warning};
+//!
+//! let mut levels = Levels::default();
+//! levels.set_all(Level::Deny);
+//! levels.set(Warning::MissingThumbnail, Level::Allow);
+//! assert_eq!(levels.level(Warning::DeprecatedKey), Level::Deny);
+//! assert_eq!(levels.level(Warning::MissingThumbnail), Level::Allow);
+//! ```
+
+use std::fmt;
+use std::io::{Read, Seek};
+use std::str::FromStr;
+
+use zip::CompressionMethod;
+
+use crate::doctor::Fix;
+use crate::package::{self, Package};
+use crate::{diagnostics, metadata, thumbnail, well_known, Error, Result};
+
+/// Something worth knowing about a package.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Warning {
+    NonConformantName,
+    DeprecatedKey,
+    MissingThumbnail,
+    HugeUncompressedPart,
+    DuplicateEntry,
+    ThumbnailFormat,
+    ConflictingMetadata,
+}
+
+impl Warning {
+    pub const ALL: [Warning; 7] = [
+        Warning::NonConformantName,
+        Warning::DeprecatedKey,
+        Warning::MissingThumbnail,
+        Warning::HugeUncompressedPart,
+        Warning::DuplicateEntry,
+        Warning::ThumbnailFormat,
+        Warning::ConflictingMetadata,
+    ];
+
+    /// The names of all warnings, and `all` for all of them, as given on
+    /// the command line.
+    pub const SELECTORS: [&'static str; 8] = [
+        "all",
+        "non-conformant-name",
+        "deprecated-key",
+        "missing-thumbnail",
+        "huge-uncompressed-part",
+        "duplicate-entry",
+        "thumbnail-format",
+        "conflicting-metadata",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::NonConformantName => "non-conformant-name",
+            Warning::DeprecatedKey => "deprecated-key",
+            Warning::MissingThumbnail => "missing-thumbnail",
+            Warning::HugeUncompressedPart => "huge-uncompressed-part",
+            Warning::DuplicateEntry => "duplicate-entry",
+            Warning::ThumbnailFormat => "thumbnail-format",
+            Warning::ConflictingMetadata => "conflicting-metadata",
+        }
+    }
+
+    /// The stable code of the warning, see [`diagnostics`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::NonConformantName => diagnostics::NON_CONFORMANT_NAME,
+            Warning::DeprecatedKey => diagnostics::DEPRECATED_KEY,
+            Warning::MissingThumbnail => diagnostics::MISSING_THUMBNAIL,
+            Warning::HugeUncompressedPart => diagnostics::HUGE_UNCOMPRESSED_PART,
+            Warning::DuplicateEntry => diagnostics::DUPLICATE_ENTRY,
+            Warning::ThumbnailFormat => diagnostics::THUMBNAIL_FORMAT,
+            Warning::ConflictingMetadata => diagnostics::CONFLICTING_METADATA,
+        }
+    }
+
+    /// The level of the warning unless another one is asked for. Many
+    /// producers write no thumbnail, so that is only looked for on request.
+    pub fn default_level(&self) -> Level {
+        match self {
+            Warning::MissingThumbnail => Level::Allow,
+            _ => Level::Warn,
+        }
+    }
+}
+
+impl FromStr for Warning {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Warning::ALL
+            .into_iter()
+            .find(|warning| warning.name() == s)
+            .ok_or_else(|| Error::InvalidValue {
+                name: "warning".to_string(),
+                value: s.to_string(),
+            })
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What happens when a warning is found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// Don't look for it.
+    Allow,
+    /// Report it.
+    Warn,
+    /// Report it as an error.
+    Deny,
+}
+
+/// The level of each warning.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Levels {
+    levels: [Level; 7],
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels {
+            levels: Warning::ALL.map(|warning| warning.default_level()),
+        }
+    }
+}
+
+impl Levels {
+    pub fn level(&self, warning: Warning) -> Level {
+        self.levels[warning as usize]
+    }
+
+    pub fn set(&mut self, warning: Warning, level: Level) {
+        self.levels[warning as usize] = level;
+    }
+
+    pub fn set_all(&mut self, level: Level) {
+        self.levels = [level; 7];
+    }
+}
+
+/// A warning found in a package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Found {
+    pub warning: Warning,
+    pub level: Level,
+    pub message: String,
+    /// The safe fix that `doctor --fix` applies, if there is one.
+    pub fix: Option<Fix>,
+}
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.warning)
+    }
+}
+
+/// Old spellings of well-known names that producers still write, with the
+/// name that replaced them.
+const DEPRECATED_KEYS: [(&str, &str); 3] = [
+    ("Author", well_known::DESIGNER),
+    ("License", well_known::LICENSE_TERMS),
+    ("CreationTime", well_known::CREATION_DATE),
+];
+
+/// Parts stored without compression above this size are reported.
+pub const HUGE_PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Looks for the warnings that aren't allowed in the package. Only the
+/// metadata of the root model part is read, and that of the other model
+/// parts when there are any, to compare it.
+pub fn check<R: Read + Seek>(package: &mut Package<R>, levels: &Levels) -> Result<Vec<Found>> {
+    let mut found = Vec::new();
+    let mut report = |warning: Warning, message: String, fix: Option<Fix>| {
+        let level = levels.level(warning);
+        if level != Level::Allow {
+            found.push(Found {
+                warning,
+                level,
+                message,
+                fix,
+            });
+        }
+    };
+    let wanted = |warning: Warning| levels.level(warning) != Level::Allow;
+
+    if wanted(Warning::NonConformantName) || wanted(Warning::DeprecatedKey) {
+        let metadata = package::read_root_metadata(package)?;
+        for entry in &metadata {
+            if let Some((_, name)) = DEPRECATED_KEYS.iter().find(|(old, _)| *old == entry.name) {
+                // Renaming would drop one of the values
+                let fix = match metadata.contains(name) {
+                    true => None,
+                    false => Some(Fix::RenameKey {
+                        from: entry.name.clone(),
+                        to: name.to_string(),
+                    }),
+                };
+                report(
+                    Warning::DeprecatedKey,
+                    format!(
+                        "metadata name \"{}\" is deprecated, use {}",
+                        entry.name, name
+                    ),
+                    fix,
+                );
+            } else if well_known::validate_name(&entry.name).is_err() {
+                report(
+                    Warning::NonConformantName,
+                    format!(
+                        "metadata name \"{}\" is neither well-known nor namespaced",
+                        entry.name
+                    ),
+                    None,
+                );
+            }
+        }
+    }
+    if wanted(Warning::MissingThumbnail)
+        && package
+            .relationship_targets(package::THUMBNAIL_RELATIONSHIP_TYPE)?
+            .is_empty()
+    {
+        report(
+            Warning::MissingThumbnail,
+            "package has no thumbnail".to_string(),
+            None,
+        );
+    }
+    if wanted(Warning::HugeUncompressedPart) {
+        for index in 0..package.len() {
+            let file = package.archive().by_index_raw(index)?;
+            if file.compression() == CompressionMethod::Stored && file.size() > HUGE_PART_SIZE {
+                report(
+                    Warning::HugeUncompressedPart,
+                    format!(
+                        "part {} is stored without compression, with {} bytes",
+                        file.name(),
+                        file.size()
+                    ),
+                    Some(Fix::Compress(file.name().to_string())),
+                );
+            }
+        }
+    }
+    if wanted(Warning::DuplicateEntry) {
+        for name in package.duplicate_entry_names()? {
+            report(
+                Warning::DuplicateEntry,
+                format!(
+                    "entry {} is in the archive more than once, see repair --dedupe-entries",
+                    name
+                ),
+                Some(Fix::DedupeEntries),
+            );
+        }
+    }
+    if wanted(Warning::ThumbnailFormat) {
+        for thumbnail in thumbnail::read_thumbnails(package)? {
+            if let Some(problem) = thumbnail.problem() {
+                let fix = thumbnail.format.map(|format| Fix::ContentType {
+                    part: thumbnail.name.clone(),
+                    content_type: format.content_type().to_string(),
+                });
+                report(Warning::ThumbnailFormat, problem, fix);
+            }
+        }
+    }
+    if wanted(Warning::ConflictingMetadata) && package.model_part_names()?.len() > 1 {
+        let parts = package::read_model_parts_metadata(package)?;
+        for conflict in metadata::conflicts(&parts) {
+            report(Warning::ConflictingMetadata, conflict.to_string(), None);
+        }
+    }
+    Ok(found)
+}
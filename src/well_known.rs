@@ -0,0 +1,321 @@
+//! Typed access to the metadata names defined by the 3MF core
+//! specification.
+
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+use crate::{Error, MetadataSet, Result};
+
+pub const TITLE: &str = "Title";
+pub const DESIGNER: &str = "Designer";
+pub const DESCRIPTION: &str = "Description";
+pub const COPYRIGHT: &str = "Copyright";
+pub const LICENSE_TERMS: &str = "LicenseTerms";
+pub const RATING: &str = "Rating";
+pub const CREATION_DATE: &str = "CreationDate";
+pub const MODIFICATION_DATE: &str = "ModificationDate";
+pub const APPLICATION: &str = "Application";
+
+/// All names defined by the 3MF core specification.
+pub const ALL: [&str; 9] = [
+    TITLE,
+    DESIGNER,
+    DESCRIPTION,
+    COPYRIGHT,
+    LICENSE_TERMS,
+    RATING,
+    CREATION_DATE,
+    MODIFICATION_DATE,
+    APPLICATION,
+];
+
+pub fn is_well_known(name: &str) -> bool {
+    ALL.contains(&name)
+}
+
+/// Names that other programs and formats, such as OPC core properties and
+/// older slicers, use for the well-known names, in lowercase.
+pub const ALIASES: [(&str, &str); 16] = [
+    ("title", TITLE),
+    ("dc:title", TITLE),
+    ("author", DESIGNER),
+    ("creator", DESIGNER),
+    ("dc:creator", DESIGNER),
+    ("dc:description", DESCRIPTION),
+    ("rights", COPYRIGHT),
+    ("dc:rights", COPYRIGHT),
+    ("license", LICENSE_TERMS),
+    ("licence", LICENSE_TERMS),
+    ("creationtime", CREATION_DATE),
+    ("created", CREATION_DATE),
+    ("dcterms:created", CREATION_DATE),
+    ("modified", MODIFICATION_DATE),
+    ("dcterms:modified", MODIFICATION_DATE),
+    ("generator", APPLICATION),
+];
+
+/// The well-known name that `name` stands for, when it is one of the
+/// [`ALIASES`] or a well-known name in another case, ignoring case. A
+/// well-known name is not an alias of itself.
+pub fn unalias(name: &str) -> Option<&'static str> {
+    if is_well_known(name) {
+        return None;
+    }
+    ALL.iter()
+        .copied()
+        .find(|known| known.eq_ignore_ascii_case(name))
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+                .map(|(_, known)| *known)
+        })
+}
+
+/// What kind of value a well-known name takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    /// A non-negative whole number.
+    Integer,
+    /// A date as `YYYY-MM-DD`, or an RFC 3339 date-time.
+    Date,
+}
+
+impl Format {
+    /// Whether `value` is in this format.
+    pub fn accepts(&self, value: &str) -> bool {
+        match self {
+            Format::Text => true,
+            Format::Integer => value.trim().parse::<u32>().is_ok(),
+            Format::Date => parse_date(value).is_some(),
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Format::Text => "any text",
+            Format::Integer => "a whole number, like 3",
+            Format::Date => "a date like 2024-05-04, or a date-time like 2024-05-04T12:30:00Z",
+        }
+    }
+}
+
+/// What a well-known name means, as described by the 3MF core
+/// specification, for `metadata_3mf explain` and for error messages.
+#[derive(Clone, Copy, Debug)]
+pub struct Explanation {
+    pub name: &'static str,
+    pub meaning: &'static str,
+    pub format: Format,
+    pub examples: &'static [&'static str],
+}
+
+/// The explanations of all well-known names, in the order of [`ALL`].
+pub const EXPLANATIONS: [Explanation; 9] = [
+    Explanation {
+        name: TITLE,
+        meaning: "A title for the 3MF document.",
+        format: Format::Text,
+        examples: &["Benchy"],
+    },
+    Explanation {
+        name: DESIGNER,
+        meaning: "The name of a designer of the document.",
+        format: Format::Text,
+        examples: &["Jane Doe"],
+    },
+    Explanation {
+        name: DESCRIPTION,
+        meaning: "A description of the document.",
+        format: Format::Text,
+        examples: &["A boat to benchmark 3D printers"],
+    },
+    Explanation {
+        name: COPYRIGHT,
+        meaning: "The copyright notice of the document.",
+        format: Format::Text,
+        examples: &["Copyright (c) 2024 Jane Doe"],
+    },
+    Explanation {
+        name: LICENSE_TERMS,
+        meaning: "The license under which the document may be used, best given as an \
+                  SPDX license id.",
+        format: Format::Text,
+        examples: &["CC-BY-4.0", "All rights reserved"],
+    },
+    Explanation {
+        name: RATING,
+        meaning: "An industry rating of the document.",
+        format: Format::Integer,
+        examples: &["3"],
+    },
+    Explanation {
+        name: CREATION_DATE,
+        meaning: "When the document was created by the application that made it.",
+        format: Format::Date,
+        examples: &["2024-05-04", "2024-05-04T12:30:00Z"],
+    },
+    Explanation {
+        name: MODIFICATION_DATE,
+        meaning: "When the document was last changed.",
+        format: Format::Date,
+        examples: &["2024-05-04", "2024-05-04T12:30:00Z"],
+    },
+    Explanation {
+        name: APPLICATION,
+        meaning: "The name of the application that created the document.",
+        format: Format::Text,
+        examples: &["PrusaSlicer-2.6.0+win64"],
+    },
+];
+
+/// The explanation of a well-known name, ignoring case.
+pub fn explain(name: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS
+        .iter()
+        .find(|explanation| explanation.name.eq_ignore_ascii_case(name))
+}
+
+/// Checks that the value of a well-known name is in its format. Other
+/// names take any value, and empty values are taken as not set, as slicers
+/// write them for names they have no value for.
+pub fn check_value(name: &str, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        return Ok(());
+    }
+    match EXPLANATIONS
+        .iter()
+        .find(|explanation| explanation.name == name)
+    {
+        Some(explanation) if !explanation.format.accepts(value) => Err(Error::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a name is usable for metadata: either one of the well-known
+/// names, or a name with a namespace prefix like `m:Foo`.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = match name.split_once(':') {
+        Some((prefix, local)) => {
+            !prefix.is_empty()
+                && !local.is_empty()
+                && !local.contains(':')
+                && !name.contains(char::is_whitespace)
+        }
+        None => is_well_known(name),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName(name.to_string()))
+    }
+}
+
+/// Checks that a value only has characters XML 1.0 allows. Control
+/// characters other than tab, newline and carriage return can't be written
+/// to a model part at all, escaped or not, and slicers refuse such files.
+pub fn validate_characters(name: &str, value: &str) -> Result<()> {
+    match value.chars().find(|&character| !is_xml_char(character)) {
+        Some(character) => Err(Error::IllegalCharacter {
+            name: name.to_string(),
+            character,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The `Char` production of XML 1.0. Surrogates can't occur in a `char`.
+fn is_xml_char(character: char) -> bool {
+    matches!(character, '\t' | '\n' | '\r' | '\u{20}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+macro_rules! text_accessors {
+    ($($getter:ident, $setter:ident, $name:ident;)*) => {
+        $(
+            #[doc = concat!("Returns the `", stringify!($name), "` metadata value.")]
+            pub fn $getter(&self) -> Option<&str> {
+                self.value($name)
+            }
+
+            #[doc = concat!("Sets the `", stringify!($name), "` metadata value.")]
+            pub fn $setter(&mut self, value: impl Into<String>) {
+                self.set($name, value)
+            }
+        )*
+    };
+}
+
+impl MetadataSet {
+    text_accessors! {
+        title, set_title, TITLE;
+        designer, set_designer, DESIGNER;
+        description, set_description, DESCRIPTION;
+        copyright, set_copyright, COPYRIGHT;
+        license_terms, set_license_terms, LICENSE_TERMS;
+        application, set_application, APPLICATION;
+    }
+
+    /// Returns the `Rating` as a number. Fails if the value is present but
+    /// is not a non-negative integer.
+    pub fn rating(&self) -> Result<Option<u32>> {
+        self.parsed(RATING, |value| value.trim().parse().ok())
+    }
+
+    pub fn set_rating(&mut self, rating: u32) {
+        self.set(RATING, rating.to_string())
+    }
+
+    /// Returns the `CreationDate`. Accepts both RFC 3339 date-times and
+    /// plain dates, which are taken to be midnight UTC.
+    pub fn creation_date(&self) -> Result<Option<OffsetDateTime>> {
+        self.parsed(CREATION_DATE, parse_date)
+    }
+
+    /// Sets the `CreationDate` as an RFC 3339 date-time.
+    pub fn set_creation_date(&mut self, date: OffsetDateTime) -> Result<()> {
+        self.set(CREATION_DATE, format_date(date)?);
+        Ok(())
+    }
+
+    /// Returns the `ModificationDate`, see [`MetadataSet::creation_date`].
+    pub fn modification_date(&self) -> Result<Option<OffsetDateTime>> {
+        self.parsed(MODIFICATION_DATE, parse_date)
+    }
+
+    /// Sets the `ModificationDate` as an RFC 3339 date-time.
+    pub fn set_modification_date(&mut self, date: OffsetDateTime) -> Result<()> {
+        self.set(MODIFICATION_DATE, format_date(date)?);
+        Ok(())
+    }
+
+    fn parsed<T>(&self, name: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>> {
+        match self.value(name) {
+            Some(value) => parse(value).map(Some).ok_or_else(|| Error::InvalidValue {
+                name: name.to_string(),
+                value: value.to_string(),
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<OffsetDateTime> {
+    let value = value.trim();
+    OffsetDateTime::parse(value, &Rfc3339).ok().or_else(|| {
+        Date::parse(value, format_description!("[year]-[month]-[day]"))
+            .ok()
+            .map(|date| date.midnight().assume_utc())
+    })
+}
+
+fn format_date(date: OffsetDateTime) -> Result<String> {
+    date.format(&Rfc3339).map_err(|_| Error::InvalidValue {
+        name: "date".to_string(),
+        value: date.to_string(),
+    })
+}
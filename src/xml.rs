@@ -0,0 +1,234 @@
+//! XML parsing that keeps the prefixes of attribute names.
+//!
+//! `xmltree` keys attributes by their local name only, which turns
+//! `p:UUID` into `UUID` and `xml:lang` into `lang` when a model is written
+//! back. This builds the same `Element` trees, but with qualified names as
+//! the attribute keys, which `xmltree` then writes out as they are.
+//!
+//! Documents that are not well-formed fail with [`Error::Syntax`], with the
+//! line and column of the problem and the line it is on.
+
+use std::io::{self, Read};
+
+use xml::common::Position;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use xmltree::{Element, ParseError, XMLNode};
+
+use crate::{Error, Result, SyntaxError};
+
+/// How deeply elements may be nested. 3MF documents need only a handful of
+/// levels; the limit keeps hostile input from exhausting the stack when the
+/// tree is dropped.
+const MAX_DEPTH: usize = 256;
+
+/// How many characters of the line of a syntax error its excerpt has,
+/// before and after the column.
+const EXCERPT_BEFORE: usize = 60;
+const EXCERPT_AFTER: usize = 20;
+
+/// How much of the line the parser is on is kept for an excerpt, in bytes.
+const MAX_LINE: usize = 4096;
+
+/// Passes a document through to the parser, keeping the line it is on for
+/// the excerpt of a syntax error. The parser reads one byte at a time, so
+/// what it has read ends where it is.
+struct Recorder<R> {
+    inner: R,
+    /// The offset of the start of `line` in the document.
+    line_offset: u64,
+    /// The line so far, without its start when it is long.
+    line: Vec<u8>,
+    /// The number of characters before `line` on the line.
+    dropped_chars: u64,
+    /// The number of line breaks so far.
+    line_breaks: u64,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for &byte in &buf[..read] {
+            if byte == b'\n' {
+                self.line_offset += self.line.len() as u64 + 1;
+                self.line.clear();
+                self.dropped_chars = 0;
+                self.line_breaks += 1;
+                continue;
+            }
+            if self.line.len() == MAX_LINE {
+                let dropped = self.line.drain(..MAX_LINE / 2);
+                self.dropped_chars += dropped.filter(|byte| is_char_start(*byte)).count() as u64;
+                self.line_offset += MAX_LINE as u64 / 2;
+            }
+            self.line.push(byte);
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Read> Recorder<R> {
+    /// The syntax error with the place the parser reports, and an excerpt
+    /// of the line when the parser is still on it.
+    fn syntax_error(mut self, error: &xml::reader::Error) -> SyntaxError {
+        let position = error.position();
+        let mut syntax = SyntaxError {
+            line: position.row + 1,
+            column: position.column + 1,
+            offset: self.line_offset + self.line.len() as u64,
+            excerpt: String::new(),
+            excerpt_column: 0,
+            message: error.msg().to_string(),
+        };
+        if position.row != self.line_breaks || position.column < self.dropped_chars {
+            return syntax;
+        }
+        let chars = (position.column - self.dropped_chars) as usize;
+        let index = char_index(&self.line, chars);
+        syntax.offset = self.line_offset + index as u64;
+        // The rest of the line, which the parser has not read
+        let mut byte = [0];
+        while self.line.len() < index + EXCERPT_AFTER * 4
+            && matches!(self.inner.read(&mut byte), Ok(1))
+            && byte[0] != b'\n'
+        {
+            self.line.push(byte[0]);
+        }
+        (syntax.excerpt, syntax.excerpt_column) = excerpt(&self.line, index);
+        syntax
+    }
+}
+
+fn is_char_start(byte: u8) -> bool {
+    byte & 0xC0 != 0x80
+}
+
+/// The index of the byte that starts character `chars` of `line`.
+fn char_index(line: &[u8], chars: usize) -> usize {
+    line.iter()
+        .enumerate()
+        .filter(|(_, byte)| is_char_start(**byte))
+        .map(|(index, _)| index)
+        .nth(chars)
+        .unwrap_or(line.len())
+}
+
+/// The part of `line` around the byte `index`, and the column of that byte
+/// in it, counting from 1.
+fn excerpt(line: &[u8], index: usize) -> (String, usize) {
+    let before = String::from_utf8_lossy(&line[..index]);
+    let after = String::from_utf8_lossy(&line[index..]);
+    let before: Vec<char> = before.chars().collect();
+    let start = before.len().saturating_sub(EXCERPT_BEFORE);
+    let mut text: String = before[start..].iter().collect();
+    text.extend(after.chars().take(EXCERPT_AFTER));
+    (text.trim_end().to_string(), before.len() - start + 1)
+}
+
+/// The syntax error at the byte `offset` of a document, with the line and
+/// column of that byte.
+pub(crate) fn syntax_error_at(document: &[u8], offset: usize, message: String) -> SyntaxError {
+    let offset = offset.min(document.len());
+    let line_start = document[..offset]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |index| index + 1);
+    let line_end = document[offset..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(document.len(), |index| offset + index);
+    let line = &document[line_start..line_end];
+    let (excerpt, excerpt_column) = excerpt(line, offset - line_start);
+    SyntaxError {
+        line: document[..line_start]
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count() as u64
+            + 1,
+        column: line[..offset - line_start]
+            .iter()
+            .filter(|byte| is_char_start(**byte))
+            .count() as u64
+            + 1,
+        offset: offset as u64,
+        excerpt,
+        excerpt_column,
+        message,
+    }
+}
+
+/// Parses the root element of a document.
+pub(crate) fn parse<R: Read>(reader: R) -> Result<Element> {
+    let config = ParserConfig::new().ignore_comments(false);
+    let mut reader = EventReader::new_with_config(
+        Recorder {
+            inner: reader,
+            line_offset: 0,
+            line: Vec::new(),
+            dropped_chars: 0,
+            line_breaks: 0,
+        },
+        config,
+    );
+    // Elements that have been started but not ended yet, innermost last.
+    let mut stack: Vec<Element> = Vec::new();
+    loop {
+        let event = match reader.next() {
+            Ok(event) => event,
+            Err(error) => {
+                return Err(Error::Syntax(reader.into_inner().syntax_error(&error)));
+            }
+        };
+        match event {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let mut element = Element::new(&name.local_name);
+                element.prefix = name.prefix;
+                element.namespace = name.namespace;
+                if !namespace.is_essentially_empty() {
+                    element.namespaces = Some(namespace);
+                }
+                for attribute in attributes {
+                    element
+                        .attributes
+                        .insert(attribute.name.borrow().to_repr(), attribute.value);
+                }
+                if stack.len() >= MAX_DEPTH {
+                    return Err(ParseError::CannotParse.into());
+                }
+                stack.push(element);
+            }
+            XmlEvent::EndElement { .. } => {
+                // xml-rs has already checked that the names match
+                let element = stack
+                    .pop()
+                    .ok_or(Error::XmlParse(ParseError::CannotParse))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(XMLNode::Element(element)),
+                    None => return Ok(element),
+                }
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(XMLNode::Text(text));
+                }
+            }
+            XmlEvent::Comment(comment) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(XMLNode::Comment(comment));
+                }
+            }
+            XmlEvent::ProcessingInstruction { name, data } => {
+                if let Some(parent) = stack.last_mut() {
+                    parent
+                        .children
+                        .push(XMLNode::ProcessingInstruction(name, data));
+                }
+            }
+            XmlEvent::StartDocument { .. } | XmlEvent::Whitespace(_) => (),
+            XmlEvent::EndDocument => return Err(ParseError::CannotParse.into()),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Writing the headers of deflated ZIP entries by hand.
+//!
+//! The zip crate writes the entries it compresses itself, but
+//! [`deflate`](crate::deflate) compresses on several threads and wraps the
+//! result in an archive of its own, and [`append`](crate::append) writes
+//! entries after those of an archive without rewriting it. Both write the
+//! local file header and the central directory header of a deflated entry
+//! here, with the ZIP64 extension for the fields that don't fit 32 bits.
+
+/// The sizes and offsets above which the ZIP64 extension is needed.
+pub(crate) const ZIP64_THRESHOLD: u64 = 0xFFFF_FFFF;
+
+/// The length of a central directory header without its name, extra field
+/// and comment.
+pub(crate) const CENTRAL_HEADER_LENGTH: usize = 46;
+
+/// The signature of a local file header.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+
+/// The signature of a central directory header.
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+
+/// The compression method of deflated entries.
+const DEFLATED: u16 = 8;
+
+/// A deflated entry, as its headers describe it.
+pub(crate) struct DeflatedEntry<'a> {
+    pub(crate) name: &'a [u8],
+    /// The general purpose flags.
+    pub(crate) flags: u16,
+    pub(crate) modified: zip::DateTime,
+    pub(crate) crc: u32,
+    pub(crate) compressed_size: u64,
+    pub(crate) size: u64,
+    /// Where the local file header starts in the archive.
+    pub(crate) offset: u64,
+}
+
+impl DeflatedEntry<'_> {
+    /// Writes the local file header. Its sizes are only read from the
+    /// central directory, so it has no ZIP64 extra field of its own.
+    pub(crate) fn write_local_header(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        output.extend_from_slice(&self.version().to_le_bytes());
+        output.extend_from_slice(&self.flags.to_le_bytes());
+        output.extend_from_slice(&DEFLATED.to_le_bytes());
+        output.extend_from_slice(&self.modified.timepart().to_le_bytes());
+        output.extend_from_slice(&self.modified.datepart().to_le_bytes());
+        output.extend_from_slice(&self.crc.to_le_bytes());
+        output.extend_from_slice(&field(self.compressed_size).to_le_bytes());
+        output.extend_from_slice(&field(self.size).to_le_bytes());
+        output.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        output.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        output.extend_from_slice(self.name);
+    }
+
+    /// The central directory header, with the same permissions
+    /// `ZipWriter::start_file` would use.
+    pub(crate) fn central_header(&self) -> Vec<u8> {
+        let extra = self.zip64_extra();
+        let version = self.version();
+        let mut header = Vec::with_capacity(CENTRAL_HEADER_LENGTH + self.name.len() + extra.len());
+        header.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        header.extend_from_slice(&(3 << 8 | version).to_le_bytes()); // made by Unix
+        header.extend_from_slice(&version.to_le_bytes());
+        header.extend_from_slice(&self.flags.to_le_bytes());
+        header.extend_from_slice(&DEFLATED.to_le_bytes());
+        header.extend_from_slice(&self.modified.timepart().to_le_bytes());
+        header.extend_from_slice(&self.modified.datepart().to_le_bytes());
+        header.extend_from_slice(&self.crc.to_le_bytes());
+        header.extend_from_slice(&field(self.compressed_size).to_le_bytes());
+        header.extend_from_slice(&field(self.size).to_le_bytes());
+        header.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        header.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        header.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        header.extend_from_slice(&(0o100644u32 << 16).to_le_bytes());
+        header.extend_from_slice(&field(self.offset).to_le_bytes());
+        header.extend_from_slice(self.name);
+        header.extend_from_slice(&extra);
+        header
+    }
+
+    /// The fields that don't fit 32 bits, in the order of the ZIP64 extra
+    /// field.
+    fn zip64_fields(&self) -> Vec<u64> {
+        [self.size, self.compressed_size, self.offset]
+            .into_iter()
+            .filter(|&value| value >= ZIP64_THRESHOLD)
+            .collect()
+    }
+
+    /// The version needed to extract the entry: 4.5 with the ZIP64
+    /// extension, 2.0 otherwise.
+    fn version(&self) -> u16 {
+        if self.zip64_fields().is_empty() {
+            20
+        } else {
+            45
+        }
+    }
+
+    /// The ZIP64 extra field of the central directory header, if needed.
+    fn zip64_extra(&self) -> Vec<u8> {
+        let fields = self.zip64_fields();
+        if fields.is_empty() {
+            return Vec::new();
+        }
+        let mut extra = Vec::with_capacity(4 + 8 * fields.len());
+        extra.extend_from_slice(&1u16.to_le_bytes());
+        extra.extend_from_slice(&(8 * fields.len() as u16).to_le_bytes());
+        for value in fields {
+            extra.extend_from_slice(&value.to_le_bytes());
+        }
+        extra
+    }
+}
+
+/// A size or offset as a header field, which holds all ones when the value
+/// is in the ZIP64 extra field instead.
+fn field(value: u64) -> u32 {
+    value.min(ZIP64_THRESHOLD) as u32
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use metadata_3mf::{add_metadata_to_vec, asynchronous, read_metadata_from_bytes, MetadataSet};
+
+#[tokio::test]
+async fn async_functions_match_the_blocking_ones() {
+    let metadata = MetadataSet::builder()
+        .designer("BASt")
+        .title("Async")
+        .build()
+        .unwrap();
+    for name in fixture_names() {
+        let original = fixture(&name);
+        let read = asynchronous::read_metadata(&mut Cursor::new(&original))
+            .await
+            .unwrap();
+        assert_eq!(
+            read,
+            read_metadata_from_bytes(&original).unwrap(),
+            "{}",
+            name
+        );
+
+        let added = asynchronous::add_metadata(&mut Cursor::new(&original), &metadata, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            added,
+            add_metadata_to_vec(&original, &metadata, false).unwrap(),
+            "{}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn async_errors_are_returned() {
+    let error = asynchronous::read_metadata(&mut Cursor::new(b"not a zip".to_vec()))
+        .await
+        .unwrap_err();
+    assert!(!error.to_string().is_empty());
+}
@@ -0,0 +1,266 @@
+//! Fixture generation and comparison helpers shared by the integration
+//! tests.
+//!
+//! Fixtures live unpacked in `tests/fixtures/<name>/`, so that they can be
+//! reviewed and edited as plain files, and are zipped up in memory when a
+//! test asks for them.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use metadata_3mf::model;
+use xmltree::{Element, XMLNode};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const CORE_NAMESPACE: &str = "http://schemas.microsoft.com/3dmanufacturing/core/2015/02";
+
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// The names of all fixture packages.
+pub fn fixture_names() -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().unwrap().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type().unwrap().is_dir() {
+            collect_files(&entry.path(), &format!("{}/", name), files);
+        } else {
+            files.push((name, entry.path()));
+        }
+    }
+}
+
+/// Zips up the fixture with the given name. Like most producers, this puts
+/// `[Content_Types].xml` first and stores images without compression.
+pub fn fixture(name: &str) -> Vec<u8> {
+    let mut files = Vec::new();
+    collect_files(&fixtures_dir().join(name), "", &mut files);
+    files.sort_by_key(|(name, _)| (name != "[Content_Types].xml", name.clone()));
+    let entries: Vec<_> = files
+        .into_iter()
+        .map(|(name, path)| (name, fs::read(path).unwrap()))
+        .collect();
+    zip_entries(&entries)
+}
+
+pub fn zip_entries(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in entries {
+        let method = if name.ends_with(".png") {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        writer
+            .start_file(
+                name.as_str(),
+                FileOptions::default().compression_method(method),
+            )
+            .unwrap();
+        writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Generates a minimal package with a single object made of a fan of
+/// triangles over the given vertices.
+pub fn generated_package(metadata: &[(String, String)], vertices: &[[f64; 3]]) -> Vec<u8> {
+    let mut model = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<model unit=\"millimeter\" xml:lang=\"en-US\" xmlns=\"{}\">\n",
+        CORE_NAMESPACE
+    );
+    for (name, value) in metadata {
+        model += &format!(
+            " <metadata name=\"{}\">{}</metadata>\n",
+            escape(name),
+            escape(value)
+        );
+    }
+    model += " <resources>\n  <object id=\"1\" type=\"model\">\n   <mesh>\n    <vertices>\n";
+    for [x, y, z] in vertices {
+        model += &format!("     <vertex x=\"{}\" y=\"{}\" z=\"{}\"/>\n", x, y, z);
+    }
+    model += "    </vertices>\n    <triangles>\n";
+    for index in 1..vertices.len().saturating_sub(1) {
+        model += &format!(
+            "     <triangle v1=\"0\" v2=\"{}\" v3=\"{}\"/>\n",
+            index,
+            index + 1
+        );
+    }
+    model += "    </triangles>\n   </mesh>\n  </object>\n </resources>\n";
+    model += " <build>\n  <item objectid=\"1\"/>\n </build>\n</model>\n";
+
+    let fusion = fixtures_dir().join("fusion360");
+    zip_entries(&[
+        (
+            "[Content_Types].xml".to_string(),
+            fs::read(fusion.join("[Content_Types].xml")).unwrap(),
+        ),
+        (
+            "_rels/.rels".to_string(),
+            fs::read(fusion.join("_rels/.rels")).unwrap(),
+        ),
+        ("3D/3dmodel.model".to_string(), model.into_bytes()),
+    ])
+}
+
+/// The package with every entry stored without compression, as some tools
+/// write them.
+pub fn stored_package(package: &[u8]) -> Vec<u8> {
+    let mut archive = ZipArchive::new(Cursor::new(package)).unwrap();
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).unwrap();
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file(file.name(), options).unwrap();
+        std::io::copy(&mut file, &mut writer).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// An entry as stored: compression method, CRC, and compressed bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RawEntry {
+    pub method: CompressionMethod,
+    pub crc32: u32,
+    pub data: Vec<u8>,
+}
+
+pub fn raw_entries(package: &[u8]) -> BTreeMap<String, RawEntry> {
+    let mut archive = ZipArchive::new(Cursor::new(package)).unwrap();
+    (0..archive.len())
+        .map(|index| {
+            let mut file = archive.by_index_raw(index).unwrap();
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            let entry = RawEntry {
+                method: file.compression(),
+                crc32: file.crc32(),
+                data,
+            };
+            (file.name().to_string(), entry)
+        })
+        .collect()
+}
+
+/// The decompressed contents of an entry.
+pub fn entry(package: &[u8], name: &str) -> Vec<u8> {
+    let mut archive = ZipArchive::new(Cursor::new(package)).unwrap();
+    let mut contents = Vec::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    contents
+}
+
+pub fn model_parts(package: &[u8]) -> Vec<String> {
+    let archive = ZipArchive::new(Cursor::new(package)).unwrap();
+    let mut names: Vec<_> = archive
+        .file_names()
+        .filter(|name| name.ends_with(".model"))
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn parse_model(package: &[u8], name: &str) -> Element {
+    model::read_model(entry(package, name).as_slice()).unwrap()
+}
+
+/// The model with its top-level metadata removed, which is everything an
+/// edit of the metadata should leave alone.
+pub fn geometry(mut xml: Element) -> Element {
+    xml.children.retain(|child| match child {
+        XMLNode::Element(element) => element.name != "metadata",
+        _ => true,
+    });
+    xml
+}
+
+/// Asserts that every entry other than the model parts was copied without
+/// even being recompressed.
+pub fn assert_entries_preserved(before: &[u8], after: &[u8]) {
+    let before = raw_entries(before);
+    let after = raw_entries(after);
+    assert_eq!(
+        before.keys().collect::<Vec<_>>(),
+        after.keys().collect::<Vec<_>>()
+    );
+    for (name, entry) in &before {
+        if !name.ends_with(".model") {
+            assert_eq!(entry, &after[name], "entry {} changed", name);
+        }
+    }
+}
+
+/// Asserts that the rewritten model is still valid per the 3MF core spec,
+/// as far as a metadata edit can affect that: same root, same root
+/// attributes, metadata first and uniquely named with declared prefixes.
+pub fn assert_spec_valid(original: &Element, rewritten: &Element) {
+    assert_eq!(rewritten.name, "model");
+    assert_eq!(rewritten.namespace.as_deref(), Some(CORE_NAMESPACE));
+    assert_eq!(
+        original.attributes.keys().collect::<Vec<_>>(),
+        rewritten.attributes.keys().collect::<Vec<_>>()
+    );
+    let elements: Vec<_> = rewritten
+        .children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .collect();
+    let metadata_count = elements
+        .iter()
+        .take_while(|element| element.name == "metadata")
+        .count();
+    assert!(
+        elements[metadata_count..]
+            .iter()
+            .all(|element| element.name != "metadata"),
+        "metadata after other elements"
+    );
+    let mut names = Vec::new();
+    for element in &elements[..metadata_count] {
+        let name = element
+            .attributes
+            .get("name")
+            .expect("metadata without name");
+        if let Some((prefix, _)) = name.split_once(':') {
+            let namespaces = rewritten.namespaces.as_ref().unwrap();
+            assert!(
+                namespaces.get(prefix).is_some(),
+                "undeclared prefix in {}",
+                name
+            );
+        }
+        assert!(!names.contains(name), "duplicate metadata {}", name);
+        names.push(name.clone());
+    }
+}
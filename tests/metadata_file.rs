@@ -0,0 +1,365 @@
+use std::path::Path;
+
+use metadata_3mf::metadata::{read_metadata_file, MetadataFile};
+use metadata_3mf::{Error, MetadataSet};
+
+fn read(text: &str) -> metadata_3mf::Result<Vec<(String, String)>> {
+    Ok(read_metadata_file(text.as_bytes())?
+        .iter()
+        .map(|entry| (entry.name.clone(), entry.value.clone()))
+        .collect())
+}
+
+fn error(text: &str) -> String {
+    match read(text) {
+        Err(Error::MetadataFile(message)) => message,
+        other => panic!("expected a metadata file error, got {:?}", other),
+    }
+}
+
+#[test]
+fn hand_edited_files_are_read() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Licensing for the shop -->
+<v1>
+    <!-- who made it -->
+    <metadata name="Designer">BASt</metadata>
+
+    <?editor keep-formatting?>
+    <metadata name="Copyright">Copyright <!-- year? -->(c) 2022</metadata>
+    <metadata name="Description"><![CDATA[<b>bold</b>]]></metadata>
+</v1>
+"#;
+    assert_eq!(
+        read(text).unwrap(),
+        [
+            ("Designer".to_string(), "BASt".to_string()),
+            ("Copyright".to_string(), "Copyright (c) 2022".to_string()),
+            ("Description".to_string(), "<b>bold</b>".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn stray_content_is_a_polite_error() {
+    assert_eq!(
+        error(r#"<v1><metadata name="Title">a</metadata> stray text </v1>"#),
+        "Metadata file contains text \"stray text\" outside of <metadata> elements"
+    );
+    assert_eq!(
+        error(r#"<v1><title>a</title></v1>"#),
+        "Metadata file contains a <title> element, only <metadata> elements may be in <v1>"
+    );
+    assert_eq!(
+        error(r#"<v1><metadata name="Title"><b>a</b></metadata></v1>"#),
+        "Metadata element Title contains a <b> element, values must be text"
+    );
+    assert_eq!(
+        error("<v1>\n  <!-- nothing yet -->\n</v1>"),
+        "Metadata file has no metadata elements"
+    );
+}
+
+#[test]
+fn syntax_errors_say_where_they_are() {
+    let syntax = |text: &str| match read(text) {
+        Err(Error::Syntax(syntax)) => syntax,
+        other => panic!("expected a syntax error, got {:?}", other),
+    };
+    let error = syntax("<v1>\n  <metadata name=\"Title\">a</metadat>\n</v1>");
+    assert_eq!((error.line, error.column), (2, 36));
+    assert_eq!(error.excerpt, "  <metadata name=\"Title\">a</metadat>");
+    assert_eq!(error.excerpt_column, 36);
+    assert_eq!(error.offset, 40);
+    assert!(error.message.contains("metadat"), "{}", error.message);
+
+    // Lists of elements are wrapped in a root to parse them, which the place
+    // leaves out
+    let error = syntax("<metadata name=\"Title\">a</metadata><metadata name=\"A\">b</metadatx>");
+    assert_eq!((error.line, error.column), (1, 66));
+    assert_eq!(error.offset, 65);
+    assert!(!error.excerpt.contains("metadata-list"));
+}
+
+#[test]
+fn other_layouts_are_accepted() {
+    let expected = [
+        ("Title".to_string(), "tetrahedron".to_string()),
+        ("Designer".to_string(), "BASt".to_string()),
+    ];
+    let entries = r#"<metadata name="Title">tetrahedron</metadata>
+<metadata name="Designer">BASt</metadata>"#;
+    assert_eq!(read(entries).unwrap(), expected);
+    assert_eq!(
+        read(&format!("<?xml version=\"1.0\"?>\n{}\n", entries)).unwrap(),
+        expected
+    );
+    assert_eq!(
+        read(&format!("<metadata-list>{}</metadata-list>", entries)).unwrap(),
+        expected
+    );
+    let model = format!(
+        r#"<model unit="millimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+ {}
+ <resources><object id="1" type="model"><metadatagroup><metadata name="Title">part</metadata></metadatagroup></object></resources>
+ <build/>
+</model>"#,
+        entries
+    );
+    assert_eq!(read(&model).unwrap(), expected);
+
+    assert_eq!(
+        error("<licenses><metadata name=\"Title\">a</metadata></licenses>"),
+        "Metadata file has a <licenses> root element, expected <v1>, <v2>, <metadata-list>, <model> or a list of <metadata> elements"
+    );
+    assert_eq!(
+        error("<metadata name=\"Title\">a</metadata><title>b</title>"),
+        "Metadata file contains a <title> element, only <metadata> elements may be in <metadata-list>"
+    );
+    assert_eq!(
+        error("<model><resources/><build/></model>"),
+        "Model in metadata file has no metadata elements"
+    );
+}
+
+#[test]
+fn v2_files_have_attributes_and_presets() {
+    let file = MetadataFile::read(
+        r#"<v2>
+    <metadata name="Designer">BASt</metadata>
+    <metadata name="Sku" prefix="shop" preserve="1">A-1</metadata>
+    <preset name="personal">
+        <metadata name="LicenseTerms">CC-BY-4.0</metadata>
+    </preset>
+    <preset name="commercial">
+        <metadata name="LicenseTerms" xml:lang="en-US">All rights reserved</metadata>
+        <metadata name="Designer">BASt Inc.</metadata>
+    </preset>
+</v2>"#
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(
+        file.preset_names().collect::<Vec<_>>(),
+        ["personal", "commercial"]
+    );
+    let sku = file.entries.get("shop:Sku").unwrap();
+    assert_eq!(sku.attributes, [("preserve".to_string(), "1".to_string())]);
+    assert!(!file.entries.contains("LicenseTerms"));
+
+    let commercial = file.preset("commercial").unwrap();
+    assert_eq!(commercial.designer(), Some("BASt Inc."));
+    assert_eq!(
+        commercial.get("LicenseTerms").unwrap().attributes,
+        [("xml:lang".to_string(), "en-US".to_string())]
+    );
+    assert_eq!(commercial.value("shop:Sku"), Some("A-1"));
+    assert!(file.preset("missing").is_none());
+
+    assert_eq!(
+        error(r#"<v2><metadata name="Title" color="red">a</metadata></v2>"#),
+        "Metadata element Title has unknown attribute color, expected preserve, type, xml:lang or prefix"
+    );
+    assert_eq!(
+        error(r#"<v2><metadata name="Title" preserve="yes">a</metadata></v2>"#),
+        "Metadata element Title has preserve=\"yes\", expected 0, 1, true or false"
+    );
+    assert_eq!(
+        error(r#"<v2><preset name="a"/><preset name="a"/></v2>"#),
+        "Preset a is defined twice"
+    );
+    // Version 1 files keep their attributes as they are
+    assert_eq!(
+        read(r#"<v1><metadata name="Title" color="red">a</metadata></v1>"#).unwrap(),
+        [("Title".to_string(), "a".to_string())]
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn toml_files_are_read_like_v2_files() {
+    let file = MetadataFile::read_toml(
+        r#"
+Designer = "BASt"
+Rating = 4
+"shop:Sku" = { value = "A-1", preserve = true }
+CreationDate = 2024-05-01T12:00:00Z
+
+[preset.commercial]
+LicenseTerms = { value = "All rights reserved", "xml:lang" = "en-US" }
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        file.entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.value.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            ("Designer", "BASt"),
+            ("Rating", "4"),
+            ("shop:Sku", "A-1"),
+            ("CreationDate", "2024-05-01T12:00:00Z"),
+        ]
+    );
+    let sku = file.entries.get("shop:Sku").unwrap();
+    assert_eq!(
+        sku.attributes,
+        [("preserve".to_string(), "true".to_string())]
+    );
+    let commercial = file.preset("commercial").unwrap();
+    assert_eq!(commercial.license_terms(), Some("All rights reserved"));
+
+    let toml_error = |text: &str| match MetadataFile::read_toml(text) {
+        Err(Error::MetadataFile(message)) => message,
+        other => panic!("expected a metadata file error, got {:?}", other),
+    };
+    assert_eq!(
+        toml_error("Title = { value = \"a\", color = \"red\" }"),
+        "Metadata element Title has unknown attribute color, expected preserve, type, xml:lang or prefix"
+    );
+    assert_eq!(
+        toml_error("Title = [\"a\"]"),
+        "Metadata entry Title has an array, values must be text, numbers, booleans or dates"
+    );
+    assert_eq!(
+        toml_error("Title = { preserve = 1 }"),
+        "Metadata entry Title has no value"
+    );
+    assert!(toml_error("Title = \"a\"\nDesigner = ")
+        .starts_with("Could not parse metadata file: line 2, column 12:"));
+}
+
+#[test]
+fn later_fragments_win() {
+    let mut file = MetadataFile::read(
+        r#"<v2>
+    <metadata name="Designer">BASt</metadata>
+    <metadata name="Copyright">Copyright (c) 2022, BASt</metadata>
+    <preset name="commercial">
+        <metadata name="LicenseTerms">All rights reserved</metadata>
+    </preset>
+</v2>"#
+            .as_bytes(),
+    )
+    .unwrap();
+    file.merge(
+        MetadataFile::read(
+            r#"<v2>
+    <metadata name="Copyright">Copyright (c) 2024, BASt</metadata>
+    <preset name="commercial">
+        <metadata name="Rating">5</metadata>
+    </preset>
+    <preset name="personal">
+        <metadata name="LicenseTerms">CC-BY-4.0</metadata>
+    </preset>
+</v2>"#
+                .as_bytes(),
+        )
+        .unwrap(),
+    );
+    assert_eq!(file.entries.designer(), Some("BASt"));
+    assert_eq!(file.entries.copyright(), Some("Copyright (c) 2024, BASt"));
+    assert_eq!(
+        file.preset_names().collect::<Vec<_>>(),
+        ["commercial", "personal"]
+    );
+    let commercial = file.preset("commercial").unwrap();
+    assert_eq!(commercial.license_terms(), Some("All rights reserved"));
+    assert_eq!(commercial.value("Rating"), Some("5"));
+}
+
+#[test]
+fn conditions_pick_the_files_entries_are_for() {
+    let file = MetadataFile::read(
+        r#"<v2>
+    <metadata name="Designer">BASt</metadata>
+    <when name="Designer" equals="Our Studio">
+        <metadata name="Copyright">Copyright (c) 2024, Our Studio</metadata>
+    </when>
+    <when name="Designer" not-equals="Our Studio" path="/library/prototypes/*.3mf">
+        <metadata name="LicenseTerms">CC-BY-NC-4.0</metadata>
+    </when>
+    <when name="Rating">
+        <metadata name="Description">Rated</metadata>
+    </when>
+</v2>"#
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(file.entries.designer(), Some("BASt"));
+    let [ours, prototypes, rated] = &file.conditional[..] else {
+        panic!("expected three conditionals, got {:?}", file.conditional);
+    };
+    let studio = MetadataSet::builder()
+        .designer("Our Studio")
+        .build()
+        .unwrap();
+    let other = MetadataSet::builder()
+        .designer("Someone")
+        .rating(3)
+        .build()
+        .unwrap();
+    let prototype = Path::new("/library/prototypes/clip.3mf");
+    assert!(ours.applies(&studio, prototype));
+    assert!(!ours.applies(&other, prototype));
+    assert!(prototypes.applies(&other, prototype));
+    assert!(prototypes.applies(&MetadataSet::new(), prototype));
+    assert!(!prototypes.applies(&studio, prototype));
+    assert!(!prototypes.applies(&other, Path::new("/library/prototypes/old/clip.3mf")));
+    assert!(rated.applies(&other, prototype));
+    assert!(!rated.applies(&studio, prototype));
+
+    assert_eq!(
+        error(r#"<v2><when equals="a"><metadata name="Title">a</metadata></when></v2>"#),
+        "Condition has equals but no name to compare"
+    );
+    assert_eq!(
+        error(r#"<v2><when><metadata name="Title">a</metadata></when></v2>"#),
+        "Condition has neither a name nor a path"
+    );
+    assert_eq!(
+        error(r#"<v2><when size="2"><metadata name="Title">a</metadata></when></v2>"#),
+        "Condition has unknown attribute size, expected name, equals, not-equals or path"
+    );
+    #[cfg(feature = "toml")]
+    assert!(matches!(
+        MetadataFile::read_toml("[[when]]\npath = \"a\"\n"),
+        Err(Error::MetadataFile(message)) if message == "Condition has no metadata table"
+    ));
+}
+
+#[test]
+fn aliases_become_well_known_names() {
+    let mut metadata = read_metadata_file(
+        r#"<v1>
+            <metadata name="creator">Jane Doe</metadata>
+            <metadata name="dc:title">Benchy</metadata>
+            <metadata name="LICENSE">CC-BY-4.0</metadata>
+            <metadata name="Author">Someone else</metadata>
+            <metadata name="Designer">BASt</metadata>
+            <metadata name="shop:Sku">A-1</metadata>
+        </v1>"#
+            .as_bytes(),
+    )
+    .unwrap();
+    metadata.unalias();
+    let entries: Vec<_> = metadata
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.value.as_str()))
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            ("Title", "Benchy"),
+            ("LicenseTerms", "CC-BY-4.0"),
+            ("Designer", "BASt"),
+            ("shop:Sku", "A-1"),
+        ]
+    );
+    assert_eq!(metadata_3mf::well_known::unalias("Designer"), None);
+    assert_eq!(
+        metadata_3mf::well_known::unalias("designer"),
+        Some("Designer")
+    );
+}
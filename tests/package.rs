@@ -0,0 +1,584 @@
+mod common;
+
+use std::fs;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use common::*;
+use metadata_3mf::append;
+use metadata_3mf::diagnostics::Severity;
+use metadata_3mf::doctor::{self, Fix};
+use metadata_3mf::observer::{self, ProcessObserver};
+use metadata_3mf::package::{self, KeepEntry, Package, UnsafeNames};
+use metadata_3mf::producer::{self, Evidence};
+use metadata_3mf::thumbnail::{self, ImageFormat, Size};
+use metadata_3mf::{add_metadata_to_vec, Error, MetadataSet};
+use zip::{ZipArchive, ZipWriter};
+
+fn copy(package: &[u8], unsafe_names: UnsafeNames) -> metadata_3mf::Result<Vec<String>> {
+    let mut input = Package::from_bytes(package)?;
+    let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+    package::copy_archive(&mut input, &mut output, unsafe_names, |_, _, _| Ok(false))?;
+    let output = ZipArchive::new(output.finish()?)?;
+    Ok(output.file_names().map(str::to_string).collect())
+}
+
+fn with_unsafe_entry(name: &str) -> Vec<u8> {
+    zip_entries(&[
+        ("3D/3dmodel.model".to_string(), b"<model/>".to_vec()),
+        (name.to_string(), b"escaped".to_vec()),
+    ])
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_reads_the_same_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in fixture_names() {
+        let bytes = fixture(&name);
+        let path = dir.path().join(format!("{}.3mf", name));
+        fs::write(&path, &bytes).unwrap();
+
+        let mut expected = Package::from_bytes(&bytes).unwrap();
+        let mut mapped = Package::open_mmap(&path).unwrap();
+        assert_eq!(mapped.len(), expected.len());
+        let mut expected = expected.entries();
+        let mut mapped = mapped.entries();
+        while let Some(entry) = expected.next_entry() {
+            let expected = entry.unwrap();
+            let actual = mapped.next_entry().unwrap().unwrap();
+            assert_eq!(actual.name(), expected.name());
+            assert_eq!(actual.content_type(), expected.content_type());
+        }
+        assert!(mapped.next_entry().is_none());
+    }
+}
+
+/// A reader that gets the byte at `offset` wrong the first `flaky` times it
+/// is read, like flaky network storage.
+struct FlakyReader {
+    inner: Cursor<Vec<u8>>,
+    offset: u64,
+    flaky: usize,
+}
+
+impl Read for FlakyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = self.inner.position();
+        let read = self.inner.read(buf)?;
+        if (start..start + read as u64).contains(&self.offset) && self.flaky > 0 {
+            self.flaky -= 1;
+            buf[(self.offset - start) as usize] ^= 0xFF;
+        }
+        Ok(read)
+    }
+}
+
+impl Seek for FlakyReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn verified_copies_are_read_again_until_intact() {
+    let thumbnail = b"not really a PNG, but stored like one".to_vec();
+    let bytes = zip_entries(&[
+        ("3D/3dmodel.model".to_string(), b"<model/>".to_vec()),
+        ("Metadata/thumbnail.png".to_string(), thumbnail.clone()),
+    ]);
+    let offset = bytes
+        .windows(thumbnail.len())
+        .position(|window| window == thumbnail)
+        .unwrap() as u64;
+    let copy = |flaky: usize, verify: bool| {
+        let reader = FlakyReader {
+            inner: Cursor::new(bytes.clone()),
+            offset,
+            flaky,
+        };
+        let mut input = Package::new(reader)?;
+        if verify {
+            input = input.with_verified_copies();
+        }
+        let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+        package::copy_archive(&mut input, &mut output, UnsafeNames::Warn, |_, _, _| {
+            Ok(false)
+        })?;
+        Ok::<_, Error>(output.finish()?.into_inner())
+    };
+
+    // Without checking, the damage is copied along unnoticed
+    let copied = copy(1, false).unwrap();
+    let mut archive = ZipArchive::new(Cursor::new(copied)).unwrap();
+    let mut data = Vec::new();
+    assert!(archive
+        .by_name("Metadata/thumbnail.png")
+        .unwrap()
+        .read_to_end(&mut data)
+        .is_err());
+
+    let copied = copy(package::VERIFY_ATTEMPTS - 1, true).unwrap();
+    let mut archive = ZipArchive::new(Cursor::new(copied)).unwrap();
+    let mut data = Vec::new();
+    archive
+        .by_name("Metadata/thumbnail.png")
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, thumbnail);
+
+    match copy(package::VERIFY_ATTEMPTS, true) {
+        Err(Error::CopyCheck { entry, .. }) => assert_eq!(entry, "Metadata/thumbnail.png"),
+        other => panic!("expected a copy check error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn entries_that_cant_be_copied_as_they_are_are_compressed_again() {
+    let notes = b"Printed in PETG at 240 degrees".to_vec();
+    let mut bytes = zip_entries(&[
+        ("3D/3dmodel.model".to_string(), b"<model/>".to_vec()),
+        ("Metadata/notes.txt".to_string(), notes.clone()),
+    ]);
+    // Overstate the compressed size in the central directory, so that the
+    // data would run past the end of the archive
+    let name = b"Metadata/notes.txt";
+    let header = bytes
+        .windows(4)
+        .enumerate()
+        .position(|(offset, window)| {
+            window == b"PK\x01\x02" && bytes[offset + 46..].starts_with(name)
+        })
+        .unwrap();
+    bytes[header + 20..header + 24].copy_from_slice(&100_000u32.to_le_bytes());
+
+    let mut input = Package::from_bytes(&bytes).unwrap();
+    let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+    package::copy_archive(&mut input, &mut output, UnsafeNames::Warn, |_, _, _| {
+        Ok(false)
+    })
+    .unwrap();
+    let copied = output.finish().unwrap().into_inner();
+    let mut archive = ZipArchive::new(Cursor::new(&copied)).unwrap();
+    let mut file = archive.by_name("Metadata/notes.txt").unwrap();
+    assert!(file.data_start() + file.compressed_size() <= copied.len() as u64);
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data, notes);
+}
+
+#[test]
+fn sanitize_name_keeps_the_relative_part() {
+    assert_eq!(
+        package::sanitize_name("../../etc/passwd").unwrap(),
+        "etc/passwd"
+    );
+    assert_eq!(
+        package::sanitize_name("/3D/./a.model").unwrap(),
+        "3D/a.model"
+    );
+    assert_eq!(
+        package::sanitize_name("C:\\Windows\\a.dll").unwrap(),
+        "Windows/a.dll"
+    );
+    assert_eq!(package::sanitize_name("../.."), None);
+}
+
+#[test]
+fn unsafe_names_are_handled_as_asked() {
+    let package = with_unsafe_entry("../evil.txt");
+    let mut names = copy(&package, UnsafeNames::Warn).unwrap();
+    names.sort();
+    assert_eq!(names, ["../evil.txt", "3D/3dmodel.model"]);
+
+    let mut names = copy(&package, UnsafeNames::Sanitize).unwrap();
+    names.sort();
+    assert_eq!(names, ["3D/3dmodel.model", "evil.txt"]);
+
+    assert!(matches!(
+        copy(&package, UnsafeNames::Reject),
+        Err(Error::UnsafeEntryName(name)) if name == "../evil.txt"
+    ));
+}
+
+#[derive(Default)]
+struct Events(Mutex<Vec<(Option<PathBuf>, String)>>);
+
+impl ProcessObserver for Events {
+    fn progress(&self, path: Option<&Path>, part: &str, done: usize, total: usize) {
+        let event = format!("{} {}/{}", part, done, total);
+        self.0
+            .lock()
+            .unwrap()
+            .push((path.map(Path::to_path_buf), event));
+    }
+
+    fn warning(&self, path: Option<&Path>, message: &str) {
+        let event = message.to_string();
+        self.0
+            .lock()
+            .unwrap()
+            .push((path.map(Path::to_path_buf), event));
+    }
+}
+
+#[test]
+fn observers_hear_about_the_file_being_processed() {
+    let events = Arc::new(Events::default());
+    observer::set_observer(events.clone());
+    let package = with_unsafe_entry("../evil.txt");
+    let path = Path::new("observed.3mf");
+    observer::observe(path, 0, 1, || copy(&package, UnsafeNames::Warn)).unwrap();
+
+    // Other tests run on other threads at the same time
+    let events: Vec<_> = events
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(file, _)| file.as_deref() == Some(path))
+        .map(|(_, event)| event.clone())
+        .collect();
+    assert_eq!(
+        events,
+        [
+            "3D/3dmodel.model 1/2",
+            "copying entry with unsafe name \"../evil.txt\"",
+            "../evil.txt 2/2",
+        ]
+    );
+}
+
+#[test]
+fn sanitizing_does_not_shadow_existing_entries() {
+    let package = with_unsafe_entry("/3D/3dmodel.model");
+    assert!(matches!(
+        copy(&package, UnsafeNames::Sanitize),
+        Err(Error::UnsafeEntryName(_))
+    ));
+}
+
+#[test]
+fn encrypted_entries_need_the_password() {
+    let encrypted = fs::read(fixtures_dir().join("encrypted.3mf")).unwrap();
+    let mut package = Package::from_bytes(&encrypted).unwrap();
+    assert_eq!(package.encrypted_entry_names().unwrap().len(), 3);
+    let error = package.entries().next_entry().unwrap().err().unwrap();
+    assert!(matches!(error, Error::Encrypted(name) if name == "[Content_Types].xml"));
+
+    let wrong = Package::from_bytes(&encrypted)
+        .unwrap()
+        .with_password("wrong")
+        .err()
+        .unwrap();
+    assert!(matches!(wrong, Error::InvalidPassword(_)));
+
+    let mut package = Package::from_bytes(&encrypted)
+        .unwrap()
+        .with_password("secret")
+        .unwrap();
+    assert!(package.content_types().get("3D/3dmodel.model").is_some());
+    let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+    package::copy_archive(&mut package, &mut output, UnsafeNames::Warn, |_, _, _| {
+        Ok(false)
+    })
+    .unwrap();
+    let decrypted = output.finish().unwrap().into_inner();
+    let original = fixture("fusion360");
+    for name in ["[Content_Types].xml", "_rels/.rels", "3D/3dmodel.model"] {
+        assert_eq!(entry(&decrypted, name), entry(&original, name), "{}", name);
+    }
+}
+
+#[test]
+fn names_are_kept_as_stored() {
+    // Names stored without the UTF-8 flag, as Windows tools write them in
+    // their code page: "あ" in Shift JIS, which isn't valid UTF-8, and "é"
+    // in UTF-8, which is but should be read as code page 437
+    let stored: [(&[u8], &[u8]); 3] = [
+        (b"3D/xx.model", b"3D/\x82\xa0.model"),
+        (b"yy.bin", b"\xc3\xa9.bin"),
+        (b"zz.bin", b"\x82\xa0.bin"),
+    ];
+    let mut package = zip_entries(&[
+        ("3D/3dmodel.model".to_string(), b"<model/>".to_vec()),
+        ("3D/xx.model".to_string(), b"<model/>".to_vec()),
+        ("yy.bin".to_string(), b"data".to_vec()),
+        ("zz.bin".to_string(), b"data".to_vec()),
+    ]);
+    for (placeholder, name) in stored {
+        for start in 0..package.len() - name.len() {
+            if &package[start..start + name.len()] == placeholder {
+                package[start..start + name.len()].copy_from_slice(name);
+            }
+        }
+    }
+
+    let metadata = MetadataSet::builder().title("a").build().unwrap();
+    let added = add_metadata_to_vec(&package, &metadata, false).unwrap();
+    let mut before = ZipArchive::new(Cursor::new(package)).unwrap();
+    let mut after = ZipArchive::new(Cursor::new(added.clone())).unwrap();
+    for (index, (_, name)) in stored.iter().enumerate() {
+        let before = before.by_index_raw(index + 1).unwrap();
+        let after = after.by_index_raw(index + 1).unwrap();
+        assert_eq!(after.name_raw(), *name);
+        // Decoded the same way, so the UTF-8 flag is still clear
+        assert_eq!(after.name(), before.name());
+    }
+    // The renamed model part was rewritten, not just copied
+    let read = metadata_3mf::read_metadata_from_bytes(&added).unwrap();
+    assert_eq!(read[1].1.title(), Some("a"));
+}
+
+#[test]
+fn duplicate_entries_are_found_and_deduped() {
+    let package = zip_entries(&[
+        ("3D/3dmodel.model".to_string(), b"<model/>".to_vec()),
+        ("Metadata/notes.txt".to_string(), b"first".to_vec()),
+        ("Metadata/notes.txt".to_string(), b"second".to_vec()),
+    ]);
+    let mut input = Package::from_bytes(&package).unwrap();
+    assert_eq!(
+        input.duplicate_entry_names().unwrap(),
+        vec!["Metadata/notes.txt"]
+    );
+
+    let dedupe = |keep| {
+        let mut input = Package::from_bytes(&package).unwrap();
+        let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+        package::dedupe_entries(&mut input, &mut output, UnsafeNames::Warn, keep).unwrap();
+        let mut output = ZipArchive::new(output.finish().unwrap()).unwrap();
+        assert_eq!(output.len(), 2);
+        let mut notes = String::new();
+        output
+            .by_name("Metadata/notes.txt")
+            .unwrap()
+            .read_to_string(&mut notes)
+            .unwrap();
+        notes
+    };
+    assert_eq!(dedupe(KeepEntry::First), "first");
+    assert_eq!(dedupe(KeepEntry::Last), "second");
+}
+
+#[test]
+fn archive_comment_is_carried_over() {
+    let mut archive = ZipArchive::new(Cursor::new(fixture("bambu"))).unwrap();
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for index in 0..archive.len() {
+        writer
+            .raw_copy_file(archive.by_index_raw(index).unwrap())
+            .unwrap();
+    }
+    writer.set_comment("BambuStudio-01.07.04.52");
+    let package = writer.finish().unwrap().into_inner();
+
+    let output = add_metadata_to_vec(&package, &MetadataSet::new(), false).unwrap();
+    let output = ZipArchive::new(Cursor::new(output)).unwrap();
+    assert_eq!(output.comment(), b"BambuStudio-01.07.04.52");
+}
+
+#[test]
+fn appending_replaces_only_the_root_model_part() {
+    let original = fixture("prusaslicer");
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&original).unwrap();
+    let metadata = MetadataSet::builder()
+        .title("Appended")
+        .designer("BASt")
+        .build()
+        .unwrap();
+    let appended = append::append_metadata(&mut file, &metadata, false).unwrap();
+    assert_eq!(appended.part, "3D/3dmodel.model");
+    assert!(appended.superseded > 0);
+    // Appending again supersedes the appended part
+    let metadata = MetadataSet::builder().title("Twice").build().unwrap();
+    append::append_metadata(&mut file, &metadata, false).unwrap();
+
+    let mut bytes = Vec::new();
+    file.rewind().unwrap();
+    file.read_to_end(&mut bytes).unwrap();
+    let read = metadata_3mf::read_metadata_from_bytes(&bytes).unwrap();
+    let (_, root) = read
+        .iter()
+        .find(|(part, _)| part == "3D/3dmodel.model")
+        .unwrap();
+    assert_eq!(root.title(), Some("Twice"));
+    assert_eq!(root.designer(), Some("BASt"));
+
+    let before = raw_entries(&original);
+    let after = raw_entries(&bytes);
+    assert_eq!(
+        before.keys().collect::<Vec<_>>(),
+        after.keys().collect::<Vec<_>>()
+    );
+    for (name, entry) in before {
+        if name != "3D/3dmodel.model" {
+            assert_eq!(entry, after[&name], "{}", name);
+        }
+    }
+}
+
+#[test]
+fn undo_puts_back_what_an_append_replaced() {
+    let original = fixture("prusaslicer");
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&original).unwrap();
+    let appended = append::append_root_model(&mut file, None, true, |bytes| {
+        Ok(String::from_utf8_lossy(bytes)
+            .replace("PrusaSlicer", "Appended")
+            .into_bytes())
+    })
+    .unwrap();
+    let backup = appended.backup.unwrap();
+    assert!(backup.starts_with(append::BACKUP_DIRECTORY));
+    assert!(backup.ends_with("/3dmodel.model"));
+
+    let undone = append::undo(&mut file, None).unwrap();
+    assert_eq!(undone.backup, Some(backup));
+    let mut bytes = Vec::new();
+    file.rewind().unwrap();
+    file.read_to_end(&mut bytes).unwrap();
+    assert_eq!(
+        metadata_3mf::read_metadata_from_bytes(&bytes).unwrap(),
+        metadata_3mf::read_metadata_from_bytes(&original).unwrap()
+    );
+    assert_eq!(raw_entries(&bytes).len(), raw_entries(&original).len());
+    assert!(matches!(
+        append::undo(&mut file, None),
+        Err(metadata_3mf::Error::NoBackup(_))
+    ));
+}
+
+/// The start of a 64x32 JPEG image, up to its size.
+const JPEG_HEADER: &[u8] = b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00\xFF\xC0\x00\x11\x08\x00\x20\x00\x40\x03";
+
+#[test]
+fn thumbnails_are_checked_and_resized() {
+    let original = fixture("cura");
+    let mut input = Package::from_bytes(&original).unwrap();
+    let thumbnails = thumbnail::read_thumbnails(&mut input).unwrap();
+    assert_eq!(thumbnails.len(), 1);
+    assert_eq!(thumbnails[0].name, "Metadata/thumbnail.png");
+    assert_eq!(thumbnails[0].format, Some(ImageFormat::Png));
+    assert_eq!(thumbnails[0].problem(), None);
+
+    assert_eq!(
+        thumbnail::image_size(JPEG_HEADER),
+        Some(Size {
+            width: 64,
+            height: 32
+        })
+    );
+    let entries: Vec<_> = raw_entries(&original)
+        .into_keys()
+        .map(|name| match name.as_str() {
+            "Metadata/thumbnail.png" => (name, JPEG_HEADER.to_vec()),
+            _ => (name.clone(), entry(&original, &name)),
+        })
+        .collect();
+    let mislabeled = zip_entries(&entries);
+    let mut input = Package::from_bytes(&mislabeled).unwrap();
+    let thumbnails = thumbnail::read_thumbnails(&mut input).unwrap();
+    assert_eq!(
+        thumbnails[0].problem().unwrap(),
+        "thumbnail Metadata/thumbnail.png is a JPEG image, but its content type is image/png"
+    );
+    assert!("0x512".parse::<Size>().is_err());
+    assert!("512".parse::<Size>().is_err());
+}
+
+#[cfg(feature = "thumbnails")]
+#[test]
+fn png_thumbnails_are_resized() {
+    let png = entry(&fixture("cura"), "Metadata/thumbnail.png");
+    let size = thumbnail::image_size(&png).unwrap();
+    let half = Size {
+        width: size.width / 2,
+        height: size.height / 2,
+    };
+    let resized = thumbnail::resize_png(&png, half).unwrap();
+    assert_eq!(ImageFormat::detect(&resized), Some(ImageFormat::Png));
+    assert_eq!(thumbnail::image_size(&resized), Some(half));
+}
+
+#[test]
+fn doctor_gives_parts_their_content_types() {
+    let bytes = fixture("prusaslicer");
+    let mut package = Package::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        package.content_types().get("_rels/.rels"),
+        Some(package::RELATIONSHIPS_CONTENT_TYPE)
+    );
+    assert!(doctor::verify_entries(&mut package).unwrap().is_empty());
+    // Settings parts have no content type, which slicers don't mind
+    let findings = doctor::check_content_types(&mut package).unwrap();
+    assert!(!findings.is_empty());
+    assert!(findings
+        .iter()
+        .all(|finding| finding.severity == Severity::Warning && finding.fix.is_none()));
+
+    let fix = Fix::ContentType {
+        part: "Metadata/Slic3r_PE.config".to_string(),
+        content_type: "text/plain".to_string(),
+    };
+    let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+    doctor::apply_fixes(&mut package, &mut output, UnsafeNames::Warn, &[fix]).unwrap();
+    let fixed = output.finish().unwrap().into_inner();
+    let mut package = Package::from_bytes(&fixed).unwrap();
+    assert_eq!(
+        package.content_types().get("Metadata/Slic3r_PE.config"),
+        Some("text/plain")
+    );
+    let findings = doctor::check_content_types(&mut package).unwrap();
+    assert_eq!(findings.len(), 1);
+    assert_eq!(
+        findings[0].message,
+        "part Metadata/Slic3r_PE_model.config has no content type"
+    );
+}
+
+#[test]
+fn producer_is_told_within_the_memory_limit() {
+    let fusion = fixtures_dir().join("fusion360");
+    let mut settings = "; generated by PrusaSlicer 2.6.0+win64 on 2023-08-01\n".to_string();
+    settings += &"; layer_height = 0.2\n".repeat(100);
+    let bytes = zip_entries(&[
+        (
+            "[Content_Types].xml".to_string(),
+            fs::read(fusion.join("[Content_Types].xml")).unwrap(),
+        ),
+        (
+            "_rels/.rels".to_string(),
+            fs::read(fusion.join("_rels/.rels")).unwrap(),
+        ),
+        (
+            "3D/3dmodel.model".to_string(),
+            format!("<model xmlns=\"{}\"><resources/></model>", CORE_NAMESPACE).into_bytes(),
+        ),
+        (
+            "Metadata/Slic3r_PE.config".to_string(),
+            settings.into_bytes(),
+        ),
+    ]);
+    let mut package = Package::from_bytes(&bytes).unwrap();
+    let producer = producer::identify(&mut package, Some(1 << 20))
+        .unwrap()
+        .unwrap();
+    assert_eq!(producer.name, "PrusaSlicer");
+    assert_eq!(producer.version.as_deref(), Some("2.6.0+win64"));
+    assert_eq!(producer.evidence, Evidence::PrivatePart);
+
+    match producer::identify(&mut package, Some(1000)) {
+        Err(Error::TooLarge { part, limit }) => {
+            assert_eq!(part, "Metadata/Slic3r_PE.config");
+            assert_eq!(limit, 1000);
+        }
+        other => panic!(
+            "expected the settings part to be too large, got {:?}",
+            other
+        ),
+    }
+}
@@ -0,0 +1,432 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use metadata_3mf::model::{self, MetadataEdit, MetadataPatch};
+use metadata_3mf::package;
+use metadata_3mf::{
+    add_metadata_to_vec, read_metadata_from_bytes, remove_metadata, Error, MetadataSet, Spdx,
+};
+use proptest::prelude::*;
+use zip::ZipWriter;
+
+fn added_metadata() -> MetadataSet {
+    MetadataSet::builder()
+        .copyright("Copyright (c) 2022, BASt")
+        .license(Spdx::CcBy40)
+        .description("fixture & <round trip>")
+        .build()
+        .unwrap()
+}
+
+fn remove(package: &[u8], names: &[String]) -> Vec<u8> {
+    remove_metadata(
+        std::io::Cursor::new(package),
+        std::io::Cursor::new(Vec::new()),
+        names,
+    )
+    .unwrap()
+    .into_inner()
+}
+
+#[test]
+fn add_preserves_fixtures() {
+    let metadata = added_metadata();
+    for name in fixture_names() {
+        let original = fixture(&name);
+        let added = add_metadata_to_vec(&original, &metadata, false).unwrap();
+        assert_entries_preserved(&original, &added);
+        for part in model_parts(&original) {
+            let before = parse_model(&original, &part);
+            let after = parse_model(&added, &part);
+            assert_spec_valid(&before, &after);
+            assert_eq!(geometry(before), geometry(after), "{} {}", name, part);
+        }
+        for (part, read) in read_metadata_from_bytes(&added).unwrap() {
+            for entry in &metadata {
+                assert_eq!(read.get(&entry.name), Some(entry), "{} {}", name, part);
+            }
+        }
+    }
+}
+
+#[test]
+fn add_only_patches_the_metadata() {
+    let metadata = added_metadata();
+    for name in fixture_names() {
+        let original = fixture(&name);
+        let added = add_metadata_to_vec(&original, &metadata, false).unwrap();
+        for part in model_parts(&original) {
+            let before = entry(&original, &part);
+            let after = entry(&added, &part);
+            let resources = |model: &[u8]| {
+                let text = String::from_utf8(model.to_vec()).unwrap();
+                text[text.find("<resources").unwrap()..].to_string()
+            };
+            assert_eq!(resources(&before), resources(&after), "{} {}", name, part);
+        }
+    }
+}
+
+#[test]
+fn unusual_metadata_falls_back_to_full_parse() {
+    let model = "<?xml version=\"1.0\"?>\n<model xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">\n <metadata name=\"Title\">a<!-- b --></metadata>\n <resources/>\n <build/>\n</model>\n";
+    assert!(MetadataPatch::new(model.as_bytes()).unwrap().is_none());
+
+    let mut edit = MetadataEdit::new(model.as_bytes()).unwrap();
+    assert!(matches!(edit, MetadataEdit::Full(_)));
+    let metadata = MetadataSet::builder().title("c").build().unwrap();
+    model::merge_metadata(edit.xml_mut(), &metadata, false);
+    let mut written = Vec::new();
+    edit.write(&mut written).unwrap();
+    let xml = model::read_model(written.as_slice()).unwrap();
+    assert_eq!(MetadataSet::from_model(&xml).title(), Some("c"));
+    assert!(xml.get_child("resources").is_some());
+}
+
+#[test]
+fn patch_keeps_formatting() {
+    let model = "<model xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\" a='>'>\n  <metadata name=\"Title\" >a</metadata >\n  <metadata name=\"Designer\"/>\n  <resources/>\n</model>";
+    let mut patch = MetadataPatch::new(model.as_bytes()).unwrap().unwrap();
+    model::remove_metadata(&mut patch.xml, &["Designer".to_string()]);
+    let mut written = Vec::new();
+    patch.write(&mut written).unwrap();
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "<model xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\" a='>'>\n  <metadata name=\"Title\">a</metadata>\n  <resources/>\n</model>"
+    );
+}
+
+#[test]
+fn parallel_compression_round_trips() {
+    let vertices: Vec<_> = (0..100_000)
+        .map(|index| [index as f64, (index % 7) as f64, 0.5])
+        .collect();
+    let model = entry(&generated_package(&[], &vertices), "3D/3dmodel.model");
+    assert!(model.len() > 3 << 20);
+
+    let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+    package::write_model_part_with(&mut output, "3D/3dmodel.model", 4, |writer| {
+        Ok(writer.write_all(&model)?)
+    })
+    .unwrap();
+    let package = output.finish().unwrap().into_inner();
+    assert_eq!(entry(&package, "3D/3dmodel.model"), model);
+}
+
+#[test]
+fn keep_existing_does_not_overwrite() {
+    let original = fixture("prusaslicer");
+    let metadata = MetadataSet::builder().title("replaced").build().unwrap();
+    let kept = add_metadata_to_vec(&original, &metadata, true).unwrap();
+    let replaced = add_metadata_to_vec(&original, &metadata, false).unwrap();
+    assert_eq!(
+        read_metadata_from_bytes(&kept).unwrap()[0].1.title(),
+        Some("tetrahedron")
+    );
+    assert_eq!(
+        read_metadata_from_bytes(&replaced).unwrap()[0].1.title(),
+        Some("replaced")
+    );
+}
+
+#[test]
+fn diff_previews_an_add() {
+    let original = fixture("prusaslicer");
+    let mut metadata = MetadataSet::builder().title("replaced").build().unwrap();
+    metadata.set("example:Printer", "MK4");
+    let added = add_metadata_to_vec(&original, &metadata, false).unwrap();
+    let before = &read_metadata_from_bytes(&original).unwrap()[0].1;
+    let after = &read_metadata_from_bytes(&added).unwrap()[0].1;
+    let diff = before.diff(after);
+    assert_eq!(
+        diff.added,
+        vec![metadata.get("example:Printer").unwrap().clone()]
+    );
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].old.value, "tetrahedron");
+    assert_eq!(diff.changed[0].new.value, "replaced");
+    assert_eq!(
+        diff.to_string(),
+        "+ example:Printer = MK4\n~ Title = tetrahedron -> replaced\n"
+    );
+    assert_eq!(after.diff(before).removed, diff.added);
+    assert!(after.diff(after).is_empty());
+    // What check reports is the same comparison
+    assert!(after.differences(&metadata).is_empty());
+    assert_eq!(before.differences(&metadata).len(), 2);
+}
+
+#[test]
+fn remove_undoes_add() {
+    let metadata = added_metadata();
+    let names: Vec<_> = metadata.iter().map(|entry| entry.name.clone()).collect();
+    for name in fixture_names() {
+        let original = fixture(&name);
+        let added = add_metadata_to_vec(&original, &metadata, false).unwrap();
+        let removed = remove(&added, &names);
+        assert_entries_preserved(&original, &removed);
+        let expected = read_metadata_from_bytes(&original).unwrap();
+        let actual = read_metadata_from_bytes(&removed).unwrap();
+        for ((part, mut expected), (_, actual)) in expected.into_iter().zip(actual) {
+            for name in &names {
+                expected.remove(name);
+            }
+            assert_eq!(expected, actual, "{} {}", name, part);
+        }
+    }
+}
+
+#[test]
+fn remove_without_matches_copies_models_unchanged() {
+    let original = fixture("cura");
+    let removed = remove(&original, &["Designer".to_string()]);
+    assert_eq!(raw_entries(&original), raw_entries(&removed));
+}
+
+fn metadata_strategy() -> impl Strategy<Value = Vec<(String, String)>> {
+    let names = prop::sample::subsequence(
+        vec![
+            "Title",
+            "Designer",
+            "Description",
+            "Copyright",
+            "LicenseTerms",
+        ],
+        0..=5,
+    );
+    names.prop_flat_map(|names| {
+        let values = prop::collection::vec(
+            "[ -~éß中\n]{1,40}".prop_filter("whitespace only", |value| !value.trim().is_empty()),
+            names.len(),
+        );
+        values.prop_map(move |values| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        })
+    })
+}
+
+fn vertices_strategy() -> impl Strategy<Value = Vec<[f64; 3]>> {
+    prop::collection::vec(prop::array::uniform3(-1000.0..1000.0f64), 3..20)
+}
+
+proptest! {
+    #[test]
+    fn generated_packages_round_trip(
+        existing in metadata_strategy(),
+        added in metadata_strategy(),
+        vertices in vertices_strategy(),
+        keep_existing: bool,
+    ) {
+        let original = generated_package(&existing, &vertices);
+        let added: MetadataSet = added
+            .into_iter()
+            .map(|(name, value)| metadata_3mf::MetadataEntry::new(name, value))
+            .collect();
+        let rewritten = add_metadata_to_vec(&original, &added, keep_existing).unwrap();
+
+        let mut expected: MetadataSet = existing
+            .into_iter()
+            .map(|(name, value)| metadata_3mf::MetadataEntry::new(name, value))
+            .collect();
+        for entry in &added {
+            if !(keep_existing && expected.contains(&entry.name)) {
+                expected.insert(entry.clone());
+            }
+        }
+        let actual = read_metadata_from_bytes(&rewritten).unwrap().remove(0).1;
+        prop_assert_eq!(expected, actual);
+
+        let before = parse_model(&original, "3D/3dmodel.model");
+        let after = parse_model(&rewritten, "3D/3dmodel.model");
+        assert_spec_valid(&before, &after);
+        prop_assert_eq!(geometry(before), geometry(after));
+        assert_entries_preserved(&original, &rewritten);
+    }
+}
+
+#[test]
+fn special_characters_round_trip() {
+    let values = [
+        "fish & chips",
+        "<b>bold</b>",
+        "a \"quoted\" 'name'",
+        "]]> and <![CDATA[",
+        "two\nlines",
+        "windows\r\nline",
+        "old mac\rline",
+        "\ttabbed\t",
+        "  spaced  ",
+        "&amp; already escaped",
+        "émoji 🚢 and 中文",
+    ];
+    let original = fixture("cura");
+    for value in values {
+        let metadata = MetadataSet::builder().description(value).build().unwrap();
+        let added = add_metadata_to_vec(&original, &metadata, false).unwrap();
+        let read = read_metadata_from_bytes(&added).unwrap().remove(0).1;
+        assert_eq!(read.description(), Some(value), "{:?}", value);
+    }
+}
+
+#[test]
+fn builder_checks_well_known_values() {
+    let error = MetadataSet::builder()
+        .title("Benchy")
+        .custom("Rating", "five")
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, Error::InvalidValue { ref name, .. } if name == "Rating"));
+
+    // Namespaced names take any value, and empty values are not set
+    let metadata = MetadataSet::builder()
+        .custom("shop:Rating", "five")
+        .custom("Rating", "")
+        .build()
+        .unwrap();
+    assert_eq!(metadata.value("shop:Rating"), Some("five"));
+}
+
+#[test]
+fn characters_illegal_in_xml_are_refused() {
+    let error = MetadataSet::builder()
+        .description("bell\u{7}")
+        .build()
+        .unwrap_err();
+    assert!(error.to_string().contains("U+0007"), "{}", error);
+
+    let mut metadata = MetadataSet::new();
+    metadata.set_title("nul\0");
+    let error = add_metadata_to_vec(&fixture("cura"), &metadata, false).unwrap_err();
+    assert!(matches!(
+        error,
+        Error::IllegalCharacter { ref name, character: '\0' } if name == "Title"
+    ));
+}
+
+const LENGTHS_MODEL: &str = r#"<model unit="millimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02" xmlns:b="http://schemas.microsoft.com/3dmanufacturing/beamlattice/2017/02" xmlns:s="http://schemas.microsoft.com/3dmanufacturing/slice/2015/07">
+    <resources>
+        <s:slicestack id="3" zbottom="2.54">
+            <s:slice ztop="5.08" />
+        </s:slicestack>
+        <object id="1" type="model">
+            <mesh>
+                <vertices>
+                    <vertex x="0" y="25.4" z="-12.7" />
+                </vertices>
+                <b:beamlattice radius="1.27" minlength="0.254">
+                    <b:beams>
+                        <b:beam v1="0" v2="0" r1="2.54" r2="0.127" />
+                    </b:beams>
+                </b:beamlattice>
+            </mesh>
+        </object>
+        <object id="2" type="model">
+            <components>
+                <component objectid="1" transform="2 0 0 0 2 0 0 0 2 25.4 0 -2.54" />
+            </components>
+        </object>
+    </resources>
+    <build>
+        <item objectid="2" transform="1 0 0 0 1 0 0 0 1 127 254 0" />
+    </build>
+</model>"#;
+
+/// The value of an attribute of the first element with the name.
+fn attribute(xml: &xmltree::Element, element: &str, name: &str) -> String {
+    let mut stack = vec![xml];
+    while let Some(current) = stack.pop() {
+        if current.name == element {
+            return current.attributes[name].clone();
+        }
+        stack.extend(
+            current
+                .children
+                .iter()
+                .filter_map(|child| child.as_element()),
+        );
+    }
+    panic!("no {} element", element);
+}
+
+#[test]
+fn convert_units_scales_every_length() {
+    let mut xml = xmltree::Element::parse(LENGTHS_MODEL.as_bytes()).unwrap();
+    let from = model::convert_units(&mut xml, model::Unit::Inch).unwrap();
+    assert_eq!(from, model::Unit::Millimeter);
+    assert_eq!(xml.attributes["unit"], "inch");
+    let scaled = [
+        ("vertex", "x", "0"),
+        ("vertex", "y", "1"),
+        ("vertex", "z", "-0.5"),
+        ("beamlattice", "radius", "0.05"),
+        ("beamlattice", "minlength", "0.01"),
+        ("beam", "r1", "0.1"),
+        ("beam", "r2", "0.005"),
+        ("beam", "v1", "0"),
+        ("slicestack", "zbottom", "0.1"),
+        ("slice", "ztop", "0.2"),
+        // Only the translation of a transform is a length
+        ("component", "transform", "2 0 0 0 2 0 0 0 2 1 0 -0.1"),
+        ("item", "transform", "1 0 0 0 1 0 0 0 1 5 10 0"),
+    ];
+    for (element, name, value) in scaled {
+        assert_eq!(
+            attribute(&xml, element, name),
+            value,
+            "{} {}",
+            element,
+            name
+        );
+    }
+
+    // Converting to the unit the model already has changes nothing
+    let before = xml.clone();
+    assert_eq!(
+        model::convert_units(&mut xml, model::Unit::Inch).unwrap(),
+        model::Unit::Inch
+    );
+    assert_eq!(xml, before);
+}
+
+#[test]
+fn converted_numbers_round_trip() {
+    let original = xmltree::Element::parse(LENGTHS_MODEL.as_bytes()).unwrap();
+    for unit in [
+        model::Unit::Micron,
+        model::Unit::Centimeter,
+        model::Unit::Inch,
+        model::Unit::Meter,
+    ] {
+        let mut xml = original.clone();
+        model::convert_units(&mut xml, unit).unwrap();
+        model::convert_units(&mut xml, model::Unit::Millimeter).unwrap();
+        // Float noise past the twelfth digit is cut off, and trailing zeros
+        // and negative zero are not written
+        assert_eq!(xml, original, "{}", unit);
+    }
+
+    // Lengths in feet don't end, so they come back within the digits kept
+    let mut xml = original.clone();
+    model::convert_units(&mut xml, model::Unit::Foot).unwrap();
+    assert_eq!(attribute(&xml, "vertex", "y"), "0.0833333333333");
+    model::convert_units(&mut xml, model::Unit::Millimeter).unwrap();
+    for (element, name) in [("vertex", "z"), ("slice", "ztop"), ("beam", "r2")] {
+        let before: f64 = attribute(&original, element, name).parse().unwrap();
+        let after: f64 = attribute(&xml, element, name).parse().unwrap();
+        assert!((after - before).abs() < 1e-9, "{} {}", element, name);
+    }
+
+    let mut xml = original;
+    model::convert_units(&mut xml, model::Unit::Meter).unwrap();
+    assert_eq!(attribute(&xml, "vertex", "y"), "0.0254");
+    assert_eq!(attribute(&xml, "beam", "r2"), "0.000127");
+    model::convert_units(&mut xml, model::Unit::Micron).unwrap();
+    assert_eq!(attribute(&xml, "vertex", "z"), "-12700");
+}